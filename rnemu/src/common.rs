@@ -1,7 +1,17 @@
+// 64bit wins when the default riscv32 is still on next to riscv64
 cfg_if::cfg_if! {
-    if #[cfg(feature = "32bit")] {
+    if #[cfg(feature = "64bit")] {
+        pub type Word = u64;
+        pub type SWord = i64;
+        pub type DWord = u128;
+        pub type SDWord = i128;
+        pub type Vaddr = Word;
+        pub type Paddr = u64;
+    } else if #[cfg(feature = "32bit")] {
         pub type Word = u32;
         pub type SWord = i32;
+        pub type DWord = u64;
+        pub type SDWord = i64;
         pub type Vaddr = Word;
         pub type Paddr = u32;
     }
@@ -54,6 +64,6 @@ mod tests {
     #[test]
     fn sext_test() {
         let x = sext!(1, 1);
-        assert_eq!(u32::MAX,x);
+        assert_eq!(crate::common::Word::MAX, x);
     }
 }
@@ -4,17 +4,27 @@ use num_format::{Locale, ToFormattedString};
 use spin::mutex::SpinMutex;
 use std::{fmt, slice::Windows, sync::OnceLock};
 
+#[cfg(feature = "trace")]
+use crate::trace::{FTrace, ITrace, ITraceConfig, MTrace, MTraceConfig};
 use crate::{
-    common::{Vaddr, Word},
-    isa::{Executer, Riscv32, ISA, ISA_LOGO},
+    common::{Paddr, Vaddr, Word},
+    elf::Symbol,
+    isa::{disas_line, Execute, Guest, GuestException, TlbConfig, ISA},
+    loader::{load, LoadConfig},
     log,
-    memory::{MemoryBank, RESET_VECTOR},
+    memory::{MemError, MemoryBank, MemoryMap},
     time::now,
 };
-cfg_if::cfg_if! {
-    if #[cfg(feature="riscv32")] {
-        static NEMU: OnceLock<SpinMutex<Nemu<Riscv32>>> = OnceLock::new();
-    }
+static NEMU: OnceLock<SpinMutex<Nemu<Guest>>> = OnceLock::new();
+
+/// What happens when the guest raises an exception.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum TrapMode {
+    /// bare-metal tests: the breakpoint instruction is nemu_trap and any other exception aborts
+    /// the machine
+    Abort,
+    /// deliver every exception to the trap handler of the guest
+    Deliver,
 }
 
 #[derive(PartialEq, Eq)]
@@ -29,11 +39,11 @@ enum NemuState {
 impl fmt::Display for NemuState {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            NemuState::Running => write!(f, "{}", "RUNNING"),
-            NemuState::Stop => write!(f, "{}", "STOP"),
-            NemuState::Quit => write!(f, "{}", "QUIT"),
-            NemuState::Abort => write!(f, "{}", "ABORT"),
-            NemuState::End => write!(f, "{}", "END"),
+            NemuState::Running => write!(f, "RUNNING"),
+            NemuState::Stop => write!(f, "STOP"),
+            NemuState::Quit => write!(f, "QUIT"),
+            NemuState::Abort => write!(f, "ABORT"),
+            NemuState::End => write!(f, "END"),
         }
     }
 }
@@ -41,34 +51,79 @@ impl fmt::Display for NemuState {
 struct Nemu<T: ISA> {
     state: NemuState,
     halt_pc: Vaddr,
-    halt_ret: u32,
+    halt_ret: Word,
     cpu: T,
+    /// kept across runs, it may cache decoded code
+    executer: T::Executer,
     // statistic
     timer: TimeDelta,
     nr_guest_inst: u64,
     mem: MemoryBank,
+    trap_mode: TrapMode,
+    /// the functions of the image, sorted by address
+    symbols: Vec<Symbol>,
+    /// the physical memory access that aborted the guest
+    mem_error: Option<MemError>,
+    #[cfg(feature = "trace")]
+    itrace: ITrace,
+    #[cfg(feature = "trace")]
+    ftrace: FTrace,
 }
 
-impl Nemu<Riscv32> {
-    fn new(cpu: Riscv32, img: Option<String>) -> Self {
-        let default_img = Riscv32::default_img();
-        let mem = MemoryBank::new(&default_img);
-        Self {
+impl<T: ISA> Nemu<T> {
+    /// the guest on memory laid out by `map` with the images of `images` loaded over the
+    /// default image at the reset vector, starting at the entry point of the images unless
+    /// the reset vector was given
+    fn new(
+        mut cpu: T,
+        map: &MemoryMap,
+        images: &LoadConfig,
+        trap_mode: TrapMode,
+    ) -> Result<Self, String> {
+        let mut mem = MemoryBank::with_map(map);
+        let img = T::default_img();
+        if !mem.load(map.reset_vector, img, img.len()) {
+            return Err(format!(
+                "the default image does not fit at the reset vector 0x{:x}",
+                map.reset_vector
+            ));
+        }
+        let image = load::<T>(&mut mem, images)?;
+        if let Some(entry) = image.entry.filter(|_| !map.reset_given) {
+            cpu.set_pc(entry);
+        }
+        Ok(Self {
             state: NemuState::Stop,
             halt_pc: 0,
             halt_ret: 0,
             cpu,
+            executer: T::executer(),
             timer: TimeDelta::default(),
             nr_guest_inst: 0,
             mem,
-        }
+            trap_mode,
+            symbols: image.symbols,
+            mem_error: None,
+            #[cfg(feature = "trace")]
+            itrace: ITrace::default(),
+            #[cfg(feature = "trace")]
+            ftrace: FTrace::default(),
+        })
     }
 
-    fn execute(&mut self, n: u64) -> Result<(), Vaddr> {
-        let mut executer = Riscv32::executer();
-        for _ in 0..n {
-            self.nr_guest_inst += 1;
-            self.exec_once(&mut executer, self.cpu.pc())?;
+    fn execute(&mut self, n: u64) {
+        let mut left = n;
+        while left > 0 {
+            #[cfg(feature = "trace")]
+            let ran = if self.tracing() {
+                self.trace_once()
+            } else {
+                self.exec_block(left)
+            };
+            #[cfg(not(feature = "trace"))]
+            let ran = self.exec_block(left);
+            self.nr_guest_inst += ran;
+            left -= ran;
             if self.state != NemuState::Running {
                 break;
             }
@@ -76,14 +131,74 @@ impl Nemu<Riscv32> {
                 todo!()
             }
         }
-        Ok(())
     }
-    fn exec_once(&mut self, executer: &mut Executer, pc: Vaddr) -> Result<(), Vaddr> {
-        executer.set_pc(pc);
-        executer.set_snpc(pc);
-        executer.exec_once(&mut self.cpu, &mut self.mem)?;
-        self.cpu.set_pc(executer.dnpc());
-        Ok(())
+
+    /// run at most `limit` instructions without coming back here, return how many
+    fn exec_block(&mut self, limit: u64) -> u64 {
+        let ran = self
+            .executer
+            .exec_block(&mut self.cpu, &mut self.mem, limit);
+        if let Some(e) = self.executer.take_exception() {
+            self.exception(self.executer.pc(), e);
+        }
+        if let Some(e) = self.mem.take_error(self.executer.pc()) {
+            self.mem_abort(self.executer.pc(), e);
+        }
+        ran
+    }
+
+    /// abort on the access `e` made by the instruction at `pc`
+    fn mem_abort(&mut self, pc: Vaddr, e: MemError) {
+        log!("nemu: {}", e);
+        self.mem_error = Some(e);
+        self.set_state(NemuState::Abort, pc, Word::MAX);
+    }
+
+    /// whether a trace needs the instructions one by one
+    #[cfg(feature = "trace")]
+    fn tracing(&self) -> bool {
+        self.itrace.on() || self.mem.mtrace_on() || self.ftrace.on()
+    }
+
+    /// run one instruction and trace it
+    #[cfg(feature = "trace")]
+    fn trace_once(&mut self) -> u64 {
+        let pc = self.cpu.pc();
+        // looked up before the instruction changes the translation
+        let inst = self.peek_inst(pc);
+        self.itrace.begin(&self.cpu);
+        self.mem.mtrace_begin(pc, self.nr_guest_inst);
+        let ran = self.exec_block(1);
+        self.itrace.end(self.nr_guest_inst, pc, inst, &self.cpu);
+        if let Some(jump) = self.executer.take_jump() {
+            self.ftrace.trace(pc, jump);
+        }
+        ran
+    }
+
+    fn exception(&mut self, pc: Vaddr, e: T::Exception) {
+        match self.trap_mode {
+            TrapMode::Deliver => {
+                let handler = self.cpu.raise_exception(&mut self.mem, pc, e);
+                if e.is_fetch() && handler == pc {
+                    // the handler itself can not be fetched, the guest would spin here forever
+                    log!("nemu: trap handler at pc = 0x{:x} can not be fetched", pc);
+                    self.set_state(NemuState::Abort, pc, Word::MAX);
+                }
+                self.cpu.set_pc(handler);
+            }
+            TrapMode::Abort if e.is_breakpoint() => {
+                self.set_state(NemuState::End, pc, self.cpu.halt_ret());
+            }
+            TrapMode::Abort if e.is_illegal() => self.invalid(pc),
+            TrapMode::Abort => match e.is_access_fault().then(|| self.mem.take_denied(pc)) {
+                Some(Some(denied)) => self.mem_abort(pc, denied),
+                _ => {
+                    log!("nemu: unhandled exception {:?} at pc = 0x{:x}", e, pc);
+                    self.set_state(NemuState::Abort, pc, Word::MAX);
+                }
+            },
+        }
     }
     fn exec(&mut self, n: u64) {
         use NemuState::*;
@@ -99,9 +214,7 @@ impl Nemu<Riscv32> {
         }
 
         let start_time = now();
-        if let Err(pc) = self.execute(n) {
-            self.invalid(pc);
-        }
+        self.execute(n);
         let end_time = now();
         self.timer += end_time - start_time;
         match &self.state {
@@ -115,7 +228,11 @@ impl Nemu<Riscv32> {
                 } else {
                     "HIT BAD TRAP".green().bold()
                 };
-                log!("nemu: {} at pc = 0x{:x}", x, self.halt_pc)
+                log!("nemu: {} at pc = 0x{:x}", x, self.halt_pc);
+                #[cfg(feature = "trace")]
+                if self.halt_ret != 0 {
+                    self.itrace.dump::<T>();
+                }
             }
             Abort => {
                 log!(
@@ -123,6 +240,8 @@ impl Nemu<Riscv32> {
                     format!("{}", self.state).red().bold(),
                     self.halt_pc
                 );
+                #[cfg(feature = "trace")]
+                self.itrace.dump::<T>();
             }
             _ => {}
         }
@@ -134,8 +253,7 @@ impl Nemu<Riscv32> {
         log!("host time spent = {} us", time);
         log!("total guest instructions = {}", self.nr_guest_inst);
 
-        if time > 0 {
-            let val = self.nr_guest_inst * 1000000 / time;
+        if let Some(val) = (self.nr_guest_inst * 1000000).checked_div(time) {
             log!(
                 "simulation frequency = {} inst/s",
                 val.to_formatted_string(&Locale::en)
@@ -143,64 +261,247 @@ impl Nemu<Riscv32> {
         } else {
             log!("Finish running in less than 1 us and can not calculate the simulation frequency");
         }
+        self.cpu.statistic();
     }
 
-    fn set_state(&mut self, state: NemuState, halt_pc: Vaddr, halt_ret: u32) {
+    fn set_state(&mut self, state: NemuState, halt_pc: Vaddr, halt_ret: Word) {
         self.state = state;
         self.halt_pc = halt_pc;
         self.halt_ret = halt_ret;
     }
 
     fn invalid(&mut self, this_pc: Vaddr) {
-        let mut pc = this_pc;
-        let mut tmp: [Word; 2] = [0; 2];
-        tmp[0] = self.mem.inst_fetch(&mut pc, 4);
-        tmp[1] = self.mem.inst_fetch(&mut pc, 4);
-
-        let ptr = tmp.as_ptr() as *const u8;
-        let len = tmp.len() * std::mem::size_of::<u32>();
-        let p = unsafe { std::slice::from_raw_parts(ptr, len) };
-        println!("invalid opcode(PC = 0x{:x}):", this_pc);
-        println!(
-            "\t{:02x} {:02x} {:02x} {:02x} {:02x} {:02x} {:02x} {:02x} ...",
-            p[0], p[1], p[2], p[3], p[4], p[5], p[6], p[7]
-        );
-        println!("\t{:08x} {:08x} ...", tmp[0], tmp[1]);
-        println!("There are two cases which will trigger this unexpected exception:");
-        println!(
-            "1. The instruction at PC =  0x{:x}  is not implemented.",
-            this_pc
-        );
-        println!("2. Something is implemented incorrectly.");
-        println!(
-            "Find this PC(0x{:x}) in the disassembling result to distinguish which case it is.\n",
-            this_pc
-        );
-        let x = format!(
-            "If it is the first case, see\n{}\n\
-    for more details.\n\nIf it is the second case, remember:\n\
-    * The machine is always right!\n\
-    * Every line of untested code is always wrong!",
-            ISA_LOGO
-        );
+        T::invalid(&self.mem, this_pc);
+        self.set_state(NemuState::Abort, this_pc, Word::MAX);
+    }
+
+    /// the 32 bits at `pc` as the hart would fetch them, `None` if the fetch would fault or
+    /// go outside of memory. The upper half is 0 if only the lower one can be fetched
+    fn peek_inst(&self, pc: Vaddr) -> Option<Word> {
+        // parcel by parcel, an instruction may straddle a page boundary
+        let parcel = |pc: Vaddr| {
+            let paddr = self.cpu.inst_paddr(&self.mem, pc)?;
+            self.mem.in_pmem(paddr, 2).then(|| self.mem.peek(paddr, 2))
+        };
+        let lo = parcel(pc)?;
+        Some(lo | (parcel(pc.wrapping_add(2)).unwrap_or(0) << 16))
+    }
 
-        println!("{}", x.red().bold());
-        self.set_state(NemuState::Abort, this_pc, u32::MAX);
+    /// the instruction at `pc` as a line of the listing and where the next one starts, `None`
+    /// if it can not be fetched
+    fn disas_line(&self, pc: Vaddr) -> Option<(String, Vaddr)> {
+        let (line, len) = disas_line::<T>(pc, self.peek_inst(pc)?);
+        Some((line, pc.wrapping_add(len as Vaddr)))
+    }
+
+    /// print `n` instructions from `addr`, the current pc by default
+    #[allow(clippy::unnecessary_cast)]
+    fn disas(&self, addr: Option<Vaddr>, n: usize) {
+        let mut pc = addr.unwrap_or_else(|| self.cpu.pc());
+        for _ in 0..n {
+            let Some((line, next)) = self.disas_line(pc) else {
+                println!("0x{:x} is not mapped or outside of memory", pc);
+                break;
+            };
+            if let Some(f) = self.symbols.iter().find(|f| f.addr == pc as u64) {
+                println!("{:08x} <{}>:", pc, f.name);
+            }
+            println!("{}", line);
+            pc = next;
+        }
     }
 }
 
-impl Nemu<Riscv32> {}
+/// the machine on `map` with `images` loaded, why they could not be loaded otherwise
+pub fn init_nemu(
+    map: &MemoryMap,
+    images: &LoadConfig,
+    trap_mode: TrapMode,
+    tlb: TlbConfig,
+) -> Result<(), String> {
+    let cpu = Guest::init(map.reset_vector as Vaddr, tlb);
+    let nemu = Nemu::new(cpu, map, images, trap_mode)?;
+    for r in nemu.mem.regions() {
+        log!(
+            "physical memory region {} [0x{:08x}, 0x{:08x}) {}",
+            r.name,
+            r.base,
+            r.end(),
+            r.perm
+        );
+    }
+    NEMU.get_or_init(|| SpinMutex::new(nemu));
+    Ok(())
+}
 
-pub fn init_nemu(img: Option<String>) {
-    if cfg!(feature = "riscv32") {
-        NEMU.get_or_init(|| {
-            let cpu = Riscv32::new(RESET_VECTOR as Vaddr);
-            let nemu = Nemu::new(cpu, img);
-            SpinMutex::new(nemu)
-        });
+#[cfg(feature = "trace")]
+pub fn init_itrace(config: ITraceConfig) {
+    NEMU.get().unwrap().lock().itrace = ITrace::new(config);
+}
+
+#[cfg(feature = "trace")]
+pub fn init_mtrace(config: MTraceConfig) {
+    NEMU.get()
+        .unwrap()
+        .lock()
+        .mem
+        .set_mtrace(MTrace::new(config));
+}
+
+#[cfg(feature = "trace")]
+pub fn init_etrace(on: bool) {
+    NEMU.get().unwrap().lock().cpu.set_etrace(on);
+}
+
+/// trace the calls and returns to `funcs`, those of the image by default, from the start if
+/// `on`
+#[cfg(feature = "trace")]
+pub fn init_ftrace(funcs: Option<Vec<Symbol>>, on: bool) {
+    let mut nemu = NEMU.get().unwrap().lock();
+    let funcs = funcs.unwrap_or_else(|| nemu.symbols.clone());
+    nemu.ftrace = FTrace::new(funcs, on);
+}
+
+/// turn the function trace on or off, return whether it is on
+#[cfg(feature = "trace")]
+pub fn nemu_ftrace(on: Option<bool>) -> bool {
+    let ftrace = &mut NEMU.get().unwrap().lock().ftrace;
+    if let Some(on) = on {
+        ftrace.set_on(on);
     }
+    ftrace.on()
 }
 
 pub fn nemu_exec(n: u64) {
-    NEMU.get().unwrap().lock().exec(u64::MAX);
+    NEMU.get().unwrap().lock().exec(n);
+}
+
+pub fn nemu_disas(addr: Option<Vaddr>, n: usize) {
+    NEMU.get().unwrap().lock().disas(addr, n);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::{Perm, Region};
+
+    /// the default image on a read-only `rom` of 4 KiB at the reset vector, in abort mode
+    fn on_rom() -> Nemu<Guest> {
+        let rom = Region::parse("rom:80000000:1000:rx").unwrap();
+        let map = MemoryMap::new(vec![rom], None).unwrap();
+        let cpu = Guest::init(map.reset_vector as Vaddr, TlbConfig::default());
+        Nemu::new(cpu, &map, &LoadConfig::default(), TrapMode::Abort).unwrap()
+    }
+
+    /// run `nemu` from `pc` until it stops
+    fn run(nemu: &mut Nemu<Guest>, pc: Vaddr) {
+        nemu.cpu.set_pc(pc);
+        nemu.state = NemuState::Running;
+        nemu.execute(100);
+    }
+
+    #[test]
+    fn mem_abort_test() {
+        // running off the end of the rom
+        let mut nemu = on_rom();
+        run(&mut nemu, 0x8000_1000);
+        assert!(nemu.state == NemuState::Abort);
+        assert!(matches!(
+            nemu.mem_error,
+            Some(MemError::OutOfBound { addr: 0x8000_1000, range, pc: 0x8000_1000, .. })
+                if range == (0x8000_0000..0x8000_1000)
+        ));
+        // the default image stores next to itself
+        let mut nemu = on_rom();
+        run(&mut nemu, 0x8000_0000);
+        assert!(nemu.state == NemuState::Abort);
+        let Some(MemError::Permission {
+            access,
+            region,
+            allowed,
+            pc,
+            ..
+        }) = nemu.mem_error
+        else {
+            panic!("{:?}", nemu.mem_error);
+        };
+        assert!(access.contains(Perm::W));
+        assert_eq!((region.as_str(), allowed), ("rom", Perm::R | Perm::X));
+        assert_eq!(pc, nemu.halt_pc);
+    }
+
+    #[test]
+    fn invalid_test() {
+        // the report reads past the unknown instruction, not out of the rom
+        let mut nemu = on_rom();
+        assert!(nemu.mem.load(0x8000_0ffc, &[0xff; 4], 4));
+        run(&mut nemu, 0x8000_0ffc);
+        assert!(nemu.state == NemuState::Abort);
+        assert_eq!(nemu.halt_pc, 0x8000_0ffc);
+        assert_eq!(nemu.mem_error, None);
+    }
+
+    #[test]
+    fn peek_inst_test() {
+        let mut nemu = on_rom();
+        assert!(nemu.mem.load(0x8000_0ffc, &[1, 2, 3, 4], 4));
+        assert_eq!(nemu.peek_inst(0x8000_0ffc), Some(0x0403_0201));
+        // half of it past the end of the rom
+        assert_eq!(nemu.peek_inst(0x8000_0ffe), Some(0x0403));
+        assert_eq!(nemu.peek_inst(0x8000_1000), None);
+        assert_eq!(nemu.disas_line(0x8000_1000), None);
+    }
+
+    #[cfg(feature = "trace")]
+    #[test]
+    fn tracing_test() {
+        // the ring is kept by default
+        let mut nemu = on_rom();
+        assert!(nemu.tracing());
+        nemu.itrace = ITrace::new(ITraceConfig {
+            ring: 0,
+            ..Default::default()
+        });
+        // unless another test has opened the log, it only ever gets opened
+        assert!(!nemu.tracing() || crate::debug::LOG_FILE.get().is_some());
+        run(&mut nemu, 0x8000_0000);
+        assert!(nemu.state == NemuState::Abort);
+        nemu.ftrace.set_on(true);
+        assert!(nemu.tracing());
+    }
+
+    #[cfg(not(feature = "64bit"))]
+    #[test]
+    fn reset_vector_test() {
+        use crate::{elf::tests::elf32_with, loader::ImageFile};
+
+        // an ELF image entered past the default image
+        let data = elf32_with(
+            Guest::ELF_MACHINE,
+            0x8000_0100,
+            &[(0x8000_0100, &[0; 4], 4)],
+            &[],
+        );
+        let path = std::env::temp_dir().join("rnemu-reset-vector-test.elf");
+        std::fs::write(&path, data).unwrap();
+        let images = LoadConfig {
+            files: vec![ImageFile {
+                path: path.to_string_lossy().into(),
+                addr: None,
+            }],
+            ..Default::default()
+        };
+        let pc = |reset_vector| {
+            let map = MemoryMap::new(vec![], reset_vector).unwrap();
+            let cpu = Guest::init(map.reset_vector as Vaddr, TlbConfig::default());
+            Nemu::new(cpu, &map, &images, TrapMode::Abort)
+                .unwrap()
+                .cpu
+                .pc()
+        };
+        assert_eq!(pc(None), 0x8000_0100);
+        // given on the command line or in the map file
+        assert_eq!(pc(Some(0x8000_0000)), 0x8000_0000);
+        std::fs::remove_file(path).unwrap();
+    }
 }
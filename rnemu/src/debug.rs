@@ -84,10 +84,10 @@ mod tests {
     #[test]
     fn write_test() {
         let mut f = File::create("foo.txt").unwrap();
-        write!(f, "{}", "World").unwrap();
-        write!(f, "{}", "World").unwrap();
-        write!(f, "{}", "World").unwrap();
-        write!(f, "{}", "World").unwrap();
+        write!(f, "World").unwrap();
+        write!(f, "World").unwrap();
+        write!(f, "World").unwrap();
+        write!(f, "World").unwrap();
     }
 
     #[test]
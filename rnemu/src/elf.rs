@@ -0,0 +1,406 @@
+use std::fmt;
+
+const PT_LOAD: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const STT_FUNC: u8 = 2;
+
+/// Why a file could not be read as an ELF.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ElfError {
+    /// the file does not start with the ELF magic
+    NotElf,
+    /// a big-endian file or an unknown class
+    Unsupported(&'static str),
+    /// `.0` points past the end of the file
+    Truncated(&'static str),
+    /// `.0` is placed past the largest offset there can be
+    Malformed(&'static str),
+}
+
+impl fmt::Display for ElfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ElfError::NotElf => write!(f, "not an ELF file"),
+            ElfError::Unsupported(what) => write!(f, "unsupported ELF: {what}"),
+            ElfError::Truncated(what) => write!(f, "truncated ELF: {what} is past the end"),
+            ElfError::Malformed(what) => write!(f, "malformed ELF: {what} is out of range"),
+        }
+    }
+}
+
+/// A function from the symbol table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: String,
+    pub addr: u64,
+    pub size: u64,
+}
+
+/// A PT_LOAD segment, `data` is what the file holds and the rest of `memsz` is zero.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment<'a> {
+    pub paddr: u64,
+    pub data: &'a [u8],
+    pub memsz: u64,
+}
+
+/// A little-endian ELF file, 32 or 64-bit, read in place.
+pub struct Elf<'a> {
+    data: &'a [u8],
+    is64: bool,
+    pub machine: u16,
+    pub entry: u64,
+    phoff: u64,
+    phentsize: u64,
+    phnum: u64,
+    shoff: u64,
+    shentsize: u64,
+    shnum: u64,
+}
+
+impl<'a> Elf<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Self, ElfError> {
+        if data.get(..4) != Some(b"\x7fELF") {
+            return Err(ElfError::NotElf);
+        }
+        let is64 = match data.get(4) {
+            Some(1) => false,
+            Some(2) => true,
+            _ => return Err(ElfError::Unsupported("unknown class")),
+        };
+        if data.get(5) != Some(&1) {
+            return Err(ElfError::Unsupported("big-endian"));
+        }
+        let mut elf = Self {
+            data,
+            is64,
+            machine: 0,
+            entry: 0,
+            phoff: 0,
+            phentsize: 0,
+            phnum: 0,
+            shoff: 0,
+            shentsize: 0,
+            shnum: 0,
+        };
+        elf.machine = elf.read(18, 2, "the header")? as u16;
+        // the fields after e_entry are as wide as an address
+        let (entry, phoff, shoff, ph, sh) = match is64 {
+            true => ((24, 8), (32, 8), (40, 8), 54, 58),
+            false => ((24, 4), (28, 4), (32, 4), 42, 46),
+        };
+        elf.entry = elf.read(entry.0, entry.1, "the header")?;
+        elf.phoff = elf.read(phoff.0, phoff.1, "the header")?;
+        elf.shoff = elf.read(shoff.0, shoff.1, "the header")?;
+        elf.phentsize = elf.read(ph, 2, "the header")?;
+        elf.phnum = elf.read(ph + 2, 2, "the header")?;
+        elf.shentsize = elf.read(sh, 2, "the header")?;
+        elf.shnum = elf.read(sh + 2, 2, "the header")?;
+        Ok(elf)
+    }
+
+    /// whether it is a 64-bit file
+    pub fn is64(&self) -> bool {
+        self.is64
+    }
+
+    /// the PT_LOAD segments in the order of the program headers
+    pub fn segments(&self) -> Result<Vec<Segment<'a>>, ElfError> {
+        let mut segments = vec![];
+        for n in 0..self.phnum {
+            let ph = entry(self.phoff, n, self.phentsize, "a program header")?;
+            if self.read(ph, 4, "a program header")? as u32 != PT_LOAD {
+                continue;
+            }
+            // p_offset, p_paddr, p_filesz and p_memsz
+            let fields = match self.is64 {
+                true => [8, 24, 32, 40],
+                false => [4, 12, 16, 20],
+            };
+            let mut values = [0; 4];
+            for (value, field) in values.iter_mut().zip(fields) {
+                let field = entry(ph, 1, field, "a program header")?;
+                *value = self.read_word(field, 8, "a program header")?;
+            }
+            let [offset, paddr, filesz, memsz] = values;
+            let data = usize::try_from(offset)
+                .ok()
+                .zip(usize::try_from(filesz).ok())
+                .and_then(|(start, len)| self.data.get(start..start.checked_add(len)?))
+                .ok_or(ElfError::Truncated("a segment"))?;
+            segments.push(Segment {
+                paddr,
+                data,
+                memsz: memsz.max(filesz),
+            });
+        }
+        Ok(segments)
+    }
+
+    /// the `len`-byte little-endian field at `offset` of the file, `what` names it in the error
+    fn read(&self, offset: u64, len: usize, what: &'static str) -> Result<u64, ElfError> {
+        let bytes = usize::try_from(offset)
+            .ok()
+            .and_then(|start| self.data.get(start..start.checked_add(len)?))
+            .ok_or(ElfError::Truncated(what))?;
+        Ok(bytes.iter().rev().fold(0, |val, &b| (val << 8) | b as u64))
+    }
+
+    /// a field that is 4 bytes wide in a 32-bit file and `len64` in a 64-bit one
+    fn read_word(&self, offset: u64, len64: usize, what: &'static str) -> Result<u64, ElfError> {
+        self.read(offset, if self.is64 { len64 } else { 4 }, what)
+    }
+
+    /// the NUL-terminated string at `offset` of the string table at `table`
+    fn string(&self, table: u64, offset: u64) -> Result<String, ElfError> {
+        let start = entry(table, 1, offset, "a name")?;
+        let start = usize::try_from(start).map_err(|_| ElfError::Truncated("a name"))?;
+        let bytes = self
+            .data
+            .get(start..)
+            .ok_or(ElfError::Truncated("a name"))?;
+        let end = bytes
+            .iter()
+            .position(|&b| b == 0)
+            .ok_or(ElfError::Truncated("a name"))?;
+        Ok(String::from_utf8_lossy(&bytes[..end]).into_owned())
+    }
+
+    /// the functions in `.symtab` sorted by address, none if it was stripped
+    pub fn functions(&self) -> Result<Vec<Symbol>, ElfError> {
+        let mut funcs = vec![];
+        for n in 0..self.shnum {
+            let sh = entry(self.shoff, n, self.shentsize, "a section header")?;
+            let field = |offset| entry(sh, 1, offset, "a section header");
+            if self.read(field(4)?, 4, "a section header")? as u32 != SHT_SYMTAB {
+                continue;
+            }
+            // sh_offset, sh_size, sh_link and sh_entsize
+            let fields = match self.is64 {
+                true => [24, 32, 40, 56],
+                false => [16, 20, 24, 36],
+            };
+            let offset = self.read_word(field(fields[0])?, 8, "a section header")?;
+            let size = self.read_word(field(fields[1])?, 8, "a section header")?;
+            let link = self.read(field(fields[2])?, 4, "a section header")?;
+            let entsize = self.read_word(field(fields[3])?, 8, "a section header")?;
+            let strtab = entry(self.shoff, link, self.shentsize, "the string table")?;
+            let strtab = entry(strtab, 1, fields[0], "the string table")?;
+            let strtab = self.read_word(strtab, 8, "the string table")?;
+            let end = entry(offset, 1, size, "the symbol table")?;
+            for sym in (offset..end).step_by(entsize.max(1) as usize) {
+                let (info, value, size) = match self.is64 {
+                    true => (4, 8, 16),
+                    false => (12, 4, 8),
+                };
+                let field = |offset| entry(sym, 1, offset, "a symbol");
+                if self.read(field(info)?, 1, "a symbol")? as u8 & 0xf != STT_FUNC {
+                    continue;
+                }
+                funcs.push(Symbol {
+                    name: self.string(strtab, self.read(sym, 4, "a symbol")?)?,
+                    addr: self.read_word(field(value)?, 8, "a symbol")?,
+                    size: self.read_word(field(size)?, 8, "a symbol")?,
+                });
+            }
+        }
+        funcs.sort_by_key(|f| f.addr);
+        Ok(funcs)
+    }
+}
+
+/// offset of entry `n` of the table of `size`-byte entries at `table`, `what` names it in
+/// the error when it does not fit in 64 bits
+fn entry(table: u64, n: u64, size: u64, what: &'static str) -> Result<u64, ElfError> {
+    n.checked_mul(size)
+        .and_then(|offset| table.checked_add(offset))
+        .ok_or(ElfError::Malformed(what))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// A 32-bit ELF for `machine` with a symbol table of `(name, addr, size, type)`.
+    pub fn elf32(machine: u16, entry: u32, symbols: &[(&str, u32, u32, u8)]) -> Vec<u8> {
+        elf32_with(machine, entry, &[], symbols)
+    }
+
+    /// A 32-bit ELF for `machine` with the PT_LOAD segments `(paddr, data, memsz)` and a
+    /// symbol table of `(name, addr, size, type)`.
+    pub fn elf32_with(
+        machine: u16,
+        entry: u32,
+        segments: &[(u32, &[u8], u32)],
+        symbols: &[(&str, u32, u32, u8)],
+    ) -> Vec<u8> {
+        let mut strtab = vec![0u8];
+        let mut symtab = vec![0u8; 16];
+        for &(name, addr, size, typ) in symbols {
+            let name_off = strtab.len() as u32;
+            strtab.extend(name.bytes().chain([0]));
+            symtab.extend(name_off.to_le_bytes());
+            symtab.extend(addr.to_le_bytes());
+            symtab.extend(size.to_le_bytes());
+            symtab.extend([typ, 0, 1, 0]);
+        }
+        // the header, the program headers, the segments, .symtab, .strtab, the section headers
+        let phoff = 52u32;
+        let mut offset = phoff + 32 * segments.len() as u32;
+        let mut phdrs = vec![];
+        for &(paddr, bytes, memsz) in segments {
+            for field in [
+                PT_LOAD,
+                offset,
+                paddr,
+                paddr,
+                bytes.len() as u32,
+                memsz,
+                0,
+                0,
+            ] {
+                phdrs.extend(field.to_le_bytes());
+            }
+            offset += bytes.len() as u32;
+        }
+        let symtab_off = offset;
+        let strtab_off = symtab_off + symtab.len() as u32;
+        let shoff = strtab_off + strtab.len() as u32;
+        let mut data = vec![0u8; 52];
+        data[..6].copy_from_slice(b"\x7fELF\x01\x01");
+        data[18..20].copy_from_slice(&machine.to_le_bytes());
+        data[24..28].copy_from_slice(&entry.to_le_bytes());
+        data[28..32].copy_from_slice(&phoff.to_le_bytes());
+        data[32..36].copy_from_slice(&shoff.to_le_bytes());
+        data[42..44].copy_from_slice(&32u16.to_le_bytes());
+        data[44..46].copy_from_slice(&(segments.len() as u16).to_le_bytes());
+        data[46..48].copy_from_slice(&40u16.to_le_bytes());
+        data[48..50].copy_from_slice(&3u16.to_le_bytes());
+        data.extend(phdrs);
+        for (_, bytes, _) in segments {
+            data.extend(*bytes);
+        }
+        data.extend(symtab.iter().chain(&strtab));
+        // null, .symtab linked to .strtab, .strtab
+        let section = |typ: u32, offset: u32, size: u32, link: u32, entsize: u32| {
+            let mut sh = vec![0u8; 40];
+            sh[4..8].copy_from_slice(&typ.to_le_bytes());
+            sh[16..20].copy_from_slice(&offset.to_le_bytes());
+            sh[20..24].copy_from_slice(&size.to_le_bytes());
+            sh[24..28].copy_from_slice(&link.to_le_bytes());
+            sh[36..40].copy_from_slice(&entsize.to_le_bytes());
+            sh
+        };
+        data.extend(section(0, 0, 0, 0, 0));
+        data.extend(section(SHT_SYMTAB, symtab_off, symtab.len() as u32, 2, 16));
+        data.extend(section(3, strtab_off, strtab.len() as u32, 0, 0));
+        data
+    }
+
+    #[test]
+    fn functions_test() {
+        let data = elf32(
+            0xf3,
+            0x8000_0000,
+            &[
+                ("main", 0x8000_0100, 0x40, STT_FUNC),
+                ("data", 0x8000_1000, 4, 1),
+                ("_start", 0x8000_0000, 0x10, STT_FUNC),
+            ],
+        );
+        let elf = Elf::parse(&data).unwrap();
+        assert_eq!((elf.machine, elf.entry), (0xf3, 0x8000_0000));
+        let names: Vec<_> = elf
+            .functions()
+            .unwrap()
+            .into_iter()
+            .map(|f| (f.name, f.addr, f.size))
+            .collect();
+        assert_eq!(
+            names,
+            [
+                ("_start".to_string(), 0x8000_0000, 0x10),
+                ("main".to_string(), 0x8000_0100, 0x40)
+            ]
+        );
+    }
+
+    #[test]
+    fn segments_test() {
+        let data = elf32_with(
+            0xf3,
+            0x8000_0000,
+            &[(0x8000_0000, &[1, 2, 3, 4], 4), (0x8000_1000, &[5], 0x10)],
+            &[],
+        );
+        let elf = Elf::parse(&data).unwrap();
+        let segments = elf.segments().unwrap();
+        assert_eq!(
+            segments,
+            [
+                Segment {
+                    paddr: 0x8000_0000,
+                    data: &[1, 2, 3, 4],
+                    memsz: 4
+                },
+                Segment {
+                    paddr: 0x8000_1000,
+                    data: &[5],
+                    memsz: 0x10
+                }
+            ]
+        );
+        assert!(elf.functions().unwrap().is_empty());
+    }
+
+    #[test]
+    fn error_test() {
+        let cases: [(&[u8], ElfError); 4] = [
+            (b"\x7fELG", ElfError::NotElf),
+            (b"\x7fELF\x03\x01", ElfError::Unsupported("unknown class")),
+            (b"\x7fELF\x01\x02", ElfError::Unsupported("big-endian")),
+            (b"\x7fELF\x01\x01", ElfError::Truncated("the header")),
+        ];
+        for (data, e) in cases {
+            assert_eq!(Elf::parse(data).err(), Some(e), "{data:?}");
+        }
+    }
+
+    #[test]
+    fn table_error_test() {
+        let data = elf32(0xf3, 0, &[("main", 0, 0, STT_FUNC)]);
+        let shoff = u32::from_le_bytes(data[32..36].try_into().unwrap()) as usize;
+        // without the header of .strtab
+        let short = data[..data.len() - 40].to_vec();
+        // .symtab at an offset past the end of the file
+        let mut far = data.clone();
+        far[shoff + 56..shoff + 60].copy_from_slice(&u32::MAX.to_le_bytes());
+        // a 64-bit file with e_phoff past the end and a .symtab so large that it wraps
+        let mut wraps = vec![0u8; 128];
+        wraps[..6].copy_from_slice(b"\x7fELF\x02\x01");
+        wraps[32..40].copy_from_slice(&u64::MAX.to_le_bytes());
+        wraps[40..48].copy_from_slice(&64u64.to_le_bytes());
+        wraps[54..56].copy_from_slice(&56u16.to_le_bytes());
+        wraps[56..58].copy_from_slice(&1u16.to_le_bytes());
+        wraps[58..60].copy_from_slice(&64u16.to_le_bytes());
+        wraps[60..62].copy_from_slice(&1u16.to_le_bytes());
+        wraps[68..72].copy_from_slice(&SHT_SYMTAB.to_le_bytes());
+        wraps[88..96].copy_from_slice(&1u64.to_le_bytes());
+        wraps[96..104].copy_from_slice(&u64::MAX.to_le_bytes());
+        // what segments and functions fail with
+        let cases = [
+            (short, None, ElfError::Truncated("the string table")),
+            (far, None, ElfError::Truncated("a symbol")),
+            (
+                wraps,
+                Some(ElfError::Truncated("a program header")),
+                ElfError::Malformed("the symbol table"),
+            ),
+        ];
+        for (n, (data, segments, functions)) in cases.into_iter().enumerate() {
+            let elf = Elf::parse(&data).unwrap();
+            assert_eq!(elf.segments().err(), segments, "case {n}");
+            assert_eq!(elf.functions().err(), Some(functions), "case {n}");
+        }
+    }
+}
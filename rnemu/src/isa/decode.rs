@@ -0,0 +1,295 @@
+use std::fmt;
+
+/// An instruction pattern such as `"??????? ????? ????? 000 ????? 00100 11"`: '0' and '1' are
+/// bits the instruction must have, '?' are operand bits and spaces are only for reading. The
+/// pattern is aligned to bit 0 of the instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pattern {
+    pub name: &'static str,
+    pub key: u32,
+    pub mask: u32,
+    /// number of bits in the pattern
+    pub len: u32,
+}
+
+impl Pattern {
+    /// parse `pattern`, panics on anything but '0', '1', '?' and ' ' or more than 32 bits
+    pub fn new(name: &'static str, pattern: &str) -> Self {
+        let mut key = 0u32;
+        let mut mask = 0u32;
+        let mut len = 0;
+        for c in pattern.chars() {
+            let (k, m) = match c {
+                '0' => (0, 1),
+                '1' => (1, 1),
+                '?' => (0, 0),
+                ' ' => continue,
+                _ => panic!("bad character {c:?} in the pattern of {name}"),
+            };
+            assert!(
+                len < u32::BITS,
+                "the pattern of {name} is longer than 32 bits"
+            );
+            key = (key << 1) | k;
+            mask = (mask << 1) | m;
+            len += 1;
+        }
+        Self {
+            name,
+            key,
+            mask,
+            len,
+        }
+    }
+
+    pub fn matches(&self, inst: u32) -> bool {
+        inst & self.mask == self.key
+    }
+
+    /// some instruction matches both
+    fn overlaps(&self, other: &Pattern) -> bool {
+        (self.key ^ other.key) & self.mask & other.mask == 0
+    }
+
+    /// every instruction that matches `other` also matches `self`
+    fn covers(&self, other: &Pattern) -> bool {
+        self.mask & !other.mask == 0 && self.overlaps(other)
+    }
+}
+
+/// Two patterns of a table that can not both be right, found when the table is built.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Conflict {
+    /// `.1` comes later but every instruction it matches is taken by `.0`
+    Shadowed(&'static str, &'static str),
+    /// the two share some instructions but neither is a special case of the other, which one
+    /// wins depends on their order alone
+    Overlap(&'static str, &'static str),
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Conflict::Shadowed(a, b) => write!(f, "pattern {b} is shadowed by {a}"),
+            Conflict::Overlap(a, b) => write!(f, "patterns {a} and {b} overlap"),
+        }
+    }
+}
+
+/// at most this many candidates are tried one by one, larger sets are split further
+const LEAF_SIZE: usize = 4;
+/// widest field a node switches on
+const MAX_FIELD: u32 = 8;
+
+#[derive(Debug)]
+enum Node {
+    /// candidates in table order
+    Leaf(Vec<usize>),
+    /// switch on the `width` bits at `shift`, every candidate below has them fixed
+    Switch {
+        shift: u32,
+        width: u32,
+        children: Vec<Node>,
+    },
+}
+
+/// A decision tree compiled from the patterns of a guest. Each node switches on a field that
+/// all the patterns below it fix, the leaves are short lists checked in table order. The only
+/// overlaps allowed are special cases listed before the general pattern, e.g. an instruction
+/// with an operand hardwired to zero before the one that takes any.
+pub struct DecodeTable<D> {
+    patterns: Vec<Pattern>,
+    entries: Vec<D>,
+    root: Node,
+}
+
+impl<D> DecodeTable<D> {
+    /// compile `entries`, `Err` with the first conflict between two of them
+    pub fn try_new(entries: Vec<(Pattern, D)>) -> Result<Self, Conflict> {
+        let (patterns, entries): (Vec<_>, Vec<_>) = entries.into_iter().unzip();
+        for (i, a) in patterns.iter().enumerate() {
+            for b in &patterns[i + 1..] {
+                if !a.overlaps(b) {
+                    continue;
+                }
+                if a.covers(b) {
+                    return Err(Conflict::Shadowed(a.name, b.name));
+                }
+                if !b.covers(a) {
+                    return Err(Conflict::Overlap(a.name, b.name));
+                }
+            }
+        }
+        let root = build(&patterns, (0..patterns.len()).collect(), 0);
+        Ok(Self {
+            patterns,
+            entries,
+            root,
+        })
+    }
+
+    /// compile `entries`, panics on a conflict as the table of a guest is fixed
+    pub fn new(entries: Vec<(Pattern, D)>) -> Self {
+        Self::try_new(entries).unwrap_or_else(|c| panic!("bad decode table: {c}"))
+    }
+
+    /// the entry whose pattern matches `inst`
+    pub fn lookup(&self, inst: u32) -> Option<&D> {
+        self.find(inst).map(|n| &self.entries[n])
+    }
+
+    /// the pattern that matches `inst`
+    pub fn pattern(&self, inst: u32) -> Option<&Pattern> {
+        self.find(inst).map(|n| &self.patterns[n])
+    }
+
+    /// the pattern that matches `inst` and its entry
+    pub fn entry(&self, inst: u32) -> Option<(&Pattern, &D)> {
+        self.find(inst)
+            .map(|n| (&self.patterns[n], &self.entries[n]))
+    }
+
+    /// every pattern, in table order
+    pub fn patterns(&self) -> &[Pattern] {
+        &self.patterns
+    }
+
+    fn find(&self, inst: u32) -> Option<usize> {
+        let mut node = &self.root;
+        loop {
+            match node {
+                Node::Switch {
+                    shift,
+                    width,
+                    children,
+                } => node = &children[((inst >> shift) & ((1 << width) - 1)) as usize],
+                Node::Leaf(candidates) => {
+                    return candidates
+                        .iter()
+                        .copied()
+                        .find(|&n| self.patterns[n].matches(inst))
+                }
+            }
+        }
+    }
+}
+
+/// the tree over `candidates`, switching only on bits outside `used`
+fn build(patterns: &[Pattern], candidates: Vec<usize>, used: u32) -> Node {
+    if candidates.len() <= LEAF_SIZE {
+        return Node::Leaf(candidates);
+    }
+    let common = candidates.iter().fold(!used, |m, &n| m & patterns[n].mask);
+    let Some((shift, width)) = widest_field(common) else {
+        return Node::Leaf(candidates);
+    };
+    let field = ((1u64 << width) - 1) as u32;
+    let children = (0..=field)
+        .map(|v| {
+            let below = candidates
+                .iter()
+                .copied()
+                .filter(|&n| (patterns[n].key >> shift) & field == v)
+                .collect();
+            build(patterns, below, used | (field << shift))
+        })
+        .collect();
+    Node::Switch {
+        shift,
+        width,
+        children,
+    }
+}
+
+/// shift and width of the longest run of ones in `mask`, at most `MAX_FIELD` wide
+fn widest_field(mask: u32) -> Option<(u32, u32)> {
+    let mut best: Option<(u32, u32)> = None;
+    let mut bit = 0;
+    while bit < u32::BITS {
+        let run = (mask >> bit).trailing_ones();
+        if run == 0 {
+            bit += 1;
+            continue;
+        }
+        if best.is_none_or(|(_, w)| run > w) {
+            best = Some((bit, run));
+        }
+        bit += run;
+    }
+    // keep the top of the run, opcodes sit in the high bits of a field more often than not
+    best.map(|(shift, width)| {
+        let w = width.min(MAX_FIELD);
+        (shift + width - w, w)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(patterns: &[(&'static str, &str)]) -> Result<DecodeTable<usize>, Conflict> {
+        DecodeTable::try_new(
+            patterns
+                .iter()
+                .enumerate()
+                .map(|(n, (name, p))| (Pattern::new(name, p), n))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn pattern_test() {
+        let p = Pattern::new("addi", "??????? ????? ????? 000 ????? 00100 11");
+        assert_eq!((p.key, p.mask, p.len), (0x13, 0x707f, 32));
+        assert!(p.matches(0x00100093));
+        assert!(!p.matches(0x00101093));
+        let p = Pattern::new("mov", "1000 10?1");
+        assert_eq!((p.key, p.mask, p.len), (0x89, 0xfd, 8));
+    }
+
+    #[test]
+    fn conflict_test() {
+        // a special case before the general pattern is fine, the other way round it is dead
+        assert!(table(&[("nop", "0000 0000"), ("any", "0000 ????")]).is_ok());
+        assert_eq!(
+            table(&[("any", "0000 ????"), ("nop", "0000 0000")]).err(),
+            Some(Conflict::Shadowed("any", "nop"))
+        );
+        assert_eq!(
+            table(&[("a", "0000 ???0"), ("b", "0000 0???")]).err(),
+            Some(Conflict::Overlap("a", "b"))
+        );
+        assert!(table(&[("a", "0000 ???0"), ("b", "0000 ???1")]).is_ok());
+    }
+
+    #[test]
+    fn lookup_test() {
+        // enough entries to switch on the high nibble, the special case has to come first
+        let names: Vec<String> = (0..16).map(|n| format!("{:04b} ????", n)).collect();
+        let mut entries = vec![(Pattern::new("zero", "0000 0000 0000"), 16)];
+        entries.extend(
+            names
+                .iter()
+                .enumerate()
+                .map(|(n, p)| (Pattern::new("op", p), n)),
+        );
+        let table = DecodeTable::new(entries);
+        assert!(matches!(table.root, Node::Switch { .. }));
+        assert_eq!(table.lookup(0x000), Some(&16));
+        assert_eq!(table.lookup(0x0a5), Some(&10));
+        // bits above a pattern are free
+        assert_eq!(table.lookup(0x100), Some(&0));
+        assert_eq!(table.pattern(0x0ff).map(|p| p.name), Some("op"));
+        let table = DecodeTable::new(vec![(Pattern::new("nop", "0000 0000"), ())]);
+        assert_eq!(table.lookup(0x01), None);
+    }
+
+    #[test]
+    fn widest_field_test() {
+        assert_eq!(widest_field(0x7f), Some((0, 7)));
+        assert_eq!(widest_field(0xfc00_707f), Some((0, 7)));
+        assert_eq!(widest_field(0xfc00_7000), Some((26, 6)));
+        assert_eq!(widest_field(0xffff_0000), Some((24, 8)));
+        assert_eq!(widest_field(0), None);
+    }
+}
@@ -0,0 +1,169 @@
+use crate::common::{Vaddr, Word};
+
+pub const CRMD: usize = 0x0;
+pub const PRMD: usize = 0x1;
+pub const ESTAT: usize = 0x5;
+pub const ERA: usize = 0x6;
+pub const BADV: usize = 0x7;
+pub const EENTRY: usize = 0xc;
+pub const SAVE0: usize = 0x30;
+pub const TID: usize = 0x40;
+/// SAVE0..SAVE3, scratch registers for the handlers
+const NR_SAVE: usize = 4;
+
+/// PLV and IE, the part of CRMD that PRMD saves
+pub const CRMD_PLV: Word = 0b11;
+pub const CRMD_IE: Word = 1 << 2;
+pub const CRMD_DA: Word = 1 << 3;
+/// PLV, IE, DA, PG, DATF and DATM. Only direct address translation is modelled, the paging
+/// fields are kept but not acted upon.
+const CRMD_MASK: Word = 0x1ff;
+const PRMD_MASK: Word = CRMD_PLV | CRMD_IE;
+/// the two software interrupt bits of ESTAT.IS are the only writable part
+const ESTAT_MASK: Word = 0b11;
+/// the entry is 64-byte aligned
+const EENTRY_MASK: Word = !0x3f;
+
+pub const CSR_NAMES: [(usize, &str); 11] = [
+    (CRMD, "crmd"),
+    (PRMD, "prmd"),
+    (ESTAT, "estat"),
+    (ERA, "era"),
+    (BADV, "badv"),
+    (EENTRY, "eentry"),
+    (SAVE0, "save0"),
+    (SAVE0 + 1, "save1"),
+    (SAVE0 + 2, "save2"),
+    (SAVE0 + 3, "save3"),
+    (TID, "tid"),
+];
+
+/// name of the CSR at `addr`
+pub fn csr_name(addr: usize) -> Option<&'static str> {
+    CSR_NAMES.iter().find(|(a, _)| *a == addr).map(|(_, n)| *n)
+}
+
+/// The CSRs taking part in exception handling. The hart comes out of reset at PLV0 with
+/// interrupts off in direct address mode.
+#[derive(Debug)]
+pub struct CsrFile {
+    crmd: Word,
+    prmd: Word,
+    estat: Word,
+    era: Word,
+    badv: Word,
+    eentry: Word,
+    save: [Word; NR_SAVE],
+    tid: Word,
+}
+
+impl Default for CsrFile {
+    fn default() -> Self {
+        Self {
+            crmd: CRMD_DA,
+            prmd: 0,
+            estat: 0,
+            era: 0,
+            badv: 0,
+            eentry: 0,
+            save: [0; NR_SAVE],
+            tid: 0,
+        }
+    }
+}
+
+impl CsrFile {
+    /// read the CSR at `addr`, `None` if it does not exist
+    pub fn read(&self, addr: usize) -> Option<Word> {
+        let val = match addr {
+            CRMD => self.crmd,
+            PRMD => self.prmd,
+            ESTAT => self.estat,
+            ERA => self.era,
+            BADV => self.badv,
+            EENTRY => self.eentry,
+            TID => self.tid,
+            _ if (SAVE0..SAVE0 + NR_SAVE).contains(&addr) => self.save[addr - SAVE0],
+            _ => return None,
+        };
+        Some(val)
+    }
+
+    /// write the CSR at `addr`, `false` if it does not exist
+    pub fn write(&mut self, addr: usize, val: Word) -> bool {
+        match addr {
+            CRMD => self.crmd = val & CRMD_MASK,
+            PRMD => self.prmd = val & PRMD_MASK,
+            ESTAT => self.estat = (self.estat & !ESTAT_MASK) | (val & ESTAT_MASK),
+            ERA => self.era = val,
+            BADV => self.badv = val,
+            EENTRY => self.eentry = val & EENTRY_MASK,
+            TID => self.tid = val,
+            _ if (SAVE0..SAVE0 + NR_SAVE).contains(&addr) => self.save[addr - SAVE0] = val,
+            _ => return false,
+        }
+        true
+    }
+
+    /// current privilege level, 0 is the most privileged
+    pub fn plv(&self) -> Word {
+        self.crmd & CRMD_PLV
+    }
+
+    /// enter the exception `ecode.subcode` raised at `pc`, return the handler address
+    pub fn trap_enter(
+        &mut self,
+        pc: Vaddr,
+        ecode: Word,
+        subcode: Word,
+        badv: Option<Vaddr>,
+    ) -> Vaddr {
+        self.prmd = self.crmd & PRMD_MASK;
+        self.crmd &= !PRMD_MASK;
+        self.era = pc;
+        self.estat = (self.estat & 0x1fff) | ((ecode & 0x3f) << 16) | ((subcode & 0x1ff) << 22);
+        if let Some(addr) = badv {
+            self.badv = addr;
+        }
+        self.eentry
+    }
+
+    /// ertn, restore PLV and IE and return where to continue
+    pub fn trap_return(&mut self) -> Vaddr {
+        self.crmd = (self.crmd & !PRMD_MASK) | self.prmd;
+        self.era
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trap_test() {
+        let mut csr = CsrFile::default();
+        csr.write(EENTRY, 0x8000_1234);
+        assert_eq!(csr.read(EENTRY), Some(0x8000_1200));
+        csr.write(CRMD, CRMD_DA | CRMD_IE | 3);
+        assert_eq!(csr.trap_enter(0x8000_0010, 0x8, 1, Some(0x10)), 0x8000_1200);
+        assert_eq!(csr.plv(), 0);
+        assert_eq!(csr.read(CRMD), Some(CRMD_DA));
+        assert_eq!(csr.read(PRMD), Some(CRMD_IE | 3));
+        assert_eq!(csr.read(ESTAT), Some((1 << 22) | (0x8 << 16)));
+        assert_eq!(csr.read(BADV), Some(0x10));
+        assert_eq!(csr.trap_return(), 0x8000_0010);
+        assert_eq!(csr.read(CRMD), Some(CRMD_DA | CRMD_IE | 3));
+    }
+
+    #[test]
+    fn warl_test() {
+        let mut csr = CsrFile::default();
+        assert!(csr.write(ESTAT, Word::MAX));
+        assert_eq!(csr.read(ESTAT), Some(ESTAT_MASK));
+        assert!(csr.write(SAVE0 + 3, 7));
+        assert_eq!(csr.read(SAVE0 + 3), Some(7));
+        assert!(!csr.write(SAVE0 + 4, 7));
+        assert_eq!(csr.read(0x4), None);
+        assert_eq!(csr_name(ERA), Some("era"));
+    }
+}
@@ -0,0 +1,725 @@
+use operand::{
+    Args, BOperand, IOperand, JOperand, NOperand, OperandHelper, OperandType, ROperand, UOperand,
+    WOperand, ZOperand,
+};
+
+use super::Loongarch32r;
+use crate::{
+    common::{DWord, SDWord, SWord, Vaddr, Word},
+    isa::{decode::DecodeTable, Execute, GuestException, ISA},
+    memory::{MemoryBank, Perm},
+};
+
+mod operand;
+struct Decoder<A>
+where
+    A: Fn(&mut Executer, &mut Loongarch32r, &mut MemoryBank, Args),
+{
+    apply: A,
+    helper: Box<dyn OperandHelper>,
+}
+
+impl<A> Decoder<A>
+where
+    A: Fn(&mut Executer, &mut Loongarch32r, &mut MemoryBank, Args),
+{
+    pub fn new(apply: A, typ: OperandType) -> Self {
+        let helper: Box<dyn OperandHelper> = match typ {
+            OperandType::R => Box::new(ROperand),
+            OperandType::I => Box::new(IOperand),
+            OperandType::Z => Box::new(ZOperand),
+            OperandType::W => Box::new(WOperand),
+            OperandType::U => Box::new(UOperand),
+            OperandType::B => Box::new(BOperand),
+            OperandType::J => Box::new(JOperand),
+            OperandType::N => Box::new(NOperand),
+        };
+        Self { apply, helper }
+    }
+}
+trait Decode: Send + Sync {
+    fn apply(&self, s: &mut Executer, cpu: &mut Loongarch32r, mem: &mut MemoryBank);
+}
+
+impl<A> Decode for Decoder<A>
+where
+    A: Fn(&mut Executer, &mut Loongarch32r, &mut MemoryBank, Args) + Send + Sync,
+{
+    fn apply(&self, s: &mut Executer, cpu: &mut Loongarch32r, mem: &mut MemoryBank) {
+        let args = self.helper.decode_operand(s.inst, cpu);
+        (self.apply)(s, cpu, mem, args)
+    }
+}
+
+macro_rules! pat {
+    ($p:literal,$name: ident,$o:expr, $b:expr) => {
+        (
+            $crate::isa::decode::Pattern::new(stringify!($name), $p),
+            Box::new($crate::isa::loongarch32r::executer::Decoder::new($b, $o))
+                as Box<dyn $crate::isa::loongarch32r::executer::Decode>,
+        )
+    };
+}
+
+/// Synchronous exceptions raised by an instruction, carrying what goes to BADV.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exception {
+    /// ADEF, fetch from a pc that is not 4-byte aligned or outside physical memory
+    FetchAddressError(Vaddr),
+    /// ADEM, load or store outside physical memory
+    AddressErrorMemory(Vaddr),
+    /// ALE, load or store that is not naturally aligned
+    Misaligned(Vaddr),
+    Syscall,
+    /// `break`, also used as nemu_trap
+    Breakpoint,
+    /// INE, with the instruction
+    IllegalInstruction(Word),
+    /// IPE, a privileged instruction above PLV0
+    PrivilegedInstruction(Word),
+}
+
+impl Exception {
+    /// Ecode and EsubCode written to ESTAT
+    pub fn code(&self) -> (Word, Word) {
+        match self {
+            Exception::FetchAddressError(_) => (0x8, 0),
+            Exception::AddressErrorMemory(_) => (0x8, 1),
+            Exception::Misaligned(_) => (0x9, 0),
+            Exception::Syscall => (0xb, 0),
+            Exception::Breakpoint => (0xc, 0),
+            Exception::IllegalInstruction(_) => (0xd, 0),
+            Exception::PrivilegedInstruction(_) => (0xe, 0),
+        }
+    }
+
+    /// value written to BADV, only address exceptions set it
+    pub fn badv(&self) -> Option<Vaddr> {
+        match *self {
+            Exception::FetchAddressError(addr)
+            | Exception::AddressErrorMemory(addr)
+            | Exception::Misaligned(addr) => Some(addr),
+            _ => None,
+        }
+    }
+}
+
+impl GuestException for Exception {
+    /// whether the instruction could not even be fetched
+    fn is_fetch(&self) -> bool {
+        matches!(self, Exception::FetchAddressError(_))
+    }
+
+    fn is_breakpoint(&self) -> bool {
+        matches!(self, Exception::Breakpoint)
+    }
+
+    fn is_illegal(&self) -> bool {
+        matches!(self, Exception::IllegalInstruction(_))
+    }
+
+    fn is_access_fault(&self) -> bool {
+        matches!(
+            self,
+            Exception::FetchAddressError(_) | Exception::AddressErrorMemory(_)
+        )
+    }
+}
+
+pub struct Executer {
+    pc: Vaddr,
+    snpc: Vaddr,
+    dnpc: Vaddr,
+    inst: Word,
+    exception: Option<Exception>,
+    decoders: &'static DecodeTable<Box<dyn Decode>>,
+}
+
+impl Executer {
+    pub fn new() -> Self {
+        Self {
+            pc: 0,
+            snpc: 0,
+            dnpc: 0,
+            inst: 0,
+            exception: None,
+            decoders: &DECODERS,
+        }
+    }
+
+    fn fetch(&mut self, mem: &MemoryBank) -> Option<Word> {
+        if !self.pc.is_multiple_of(4) || !mem.allows(self.snpc, 4, Perm::X) {
+            self.raise(Exception::FetchAddressError(self.pc));
+            return None;
+        }
+        Some(mem.inst_fetch(&mut self.snpc, 4))
+    }
+
+    fn raise(&mut self, e: Exception) {
+        self.exception = Some(e);
+    }
+}
+
+impl Execute<Loongarch32r> for Executer {
+    /// execute the instruction at pc, an exception it raises is left for `take_exception`
+    fn exec_once(&mut self, cpu: &mut Loongarch32r, mem: &mut MemoryBank) {
+        cpu.counter = cpu.counter.wrapping_add(1);
+        self.dnpc = self.snpc;
+        let Some(inst) = self.fetch(mem) else {
+            return;
+        };
+        self.inst = inst;
+        self.dnpc = self.snpc;
+        match self.decoders.lookup(inst) {
+            Some(d) => {
+                d.apply(self, cpu, mem);
+                cpu.set_reg(0, 0);
+            }
+            None => self.raise(Exception::IllegalInstruction(inst)),
+        }
+    }
+
+    fn pc(&self) -> Vaddr {
+        self.pc
+    }
+
+    fn set_pc(&mut self, pc: Vaddr) {
+        self.pc = pc;
+    }
+
+    fn set_snpc(&mut self, snpc: Vaddr) {
+        self.snpc = snpc;
+    }
+
+    fn dnpc(&self) -> Vaddr {
+        self.dnpc
+    }
+
+    /// take the exception raised by the last executed instruction
+    fn take_exception(&mut self) -> Option<Exception> {
+        self.exception.take()
+    }
+}
+
+/// whether the hart is at PLV0, raising IPE if it is not
+fn privileged(s: &mut Executer, cpu: &Loongarch32r) -> bool {
+    if cpu.csr.plv() != 0 {
+        s.raise(Exception::PrivilegedInstruction(s.inst));
+        return false;
+    }
+    true
+}
+
+/// CSRRD/CSRWR/CSRXCHG, `rd = csr; csr = (rd & mask) | (csr & !mask)`. Unimplemented CSRs read
+/// as zero and ignore writes.
+fn csr_xchg(s: &mut Executer, cpu: &mut Loongarch32r, args: &Args, mask: Option<Word>) {
+    if !privileged(s, cpu) {
+        return;
+    }
+    let addr = bits!(s.inst, 23, 10) as usize;
+    let old = cpu.csr(addr).unwrap_or(0);
+    if let Some(mask) = mask {
+        cpu.set_csr(addr, (args.src2 & mask) | (old & !mask));
+    }
+    cpu.set_reg(args.rd, old);
+}
+
+/// load `len` bytes at `addr`, `None` after raising the exception if it can not be done
+fn load(s: &mut Executer, mem: &MemoryBank, addr: Vaddr, len: usize) -> Option<Word> {
+    if !addr.is_multiple_of(len as Vaddr) {
+        s.raise(Exception::Misaligned(addr));
+        return None;
+    }
+    if !mem.allows(addr, len, Perm::R) {
+        s.raise(Exception::AddressErrorMemory(addr));
+        return None;
+    }
+    Some(mem.paddr_read(addr, len))
+}
+
+fn store(s: &mut Executer, mem: &mut MemoryBank, addr: Vaddr, len: usize, data: Word) -> bool {
+    if !addr.is_multiple_of(len as Vaddr) {
+        s.raise(Exception::Misaligned(addr));
+        return false;
+    }
+    if !mem.allows(addr, len, Perm::W) {
+        s.raise(Exception::AddressErrorMemory(addr));
+        return false;
+    }
+    mem.paddr_write(addr, len, data);
+    true
+}
+
+lazy_static::lazy_static! {
+    static ref DECODERS: DecodeTable<Box<dyn Decode>> = {
+        let decoders = vec! {
+            // rdcntvl.w rd and rdcntid rj share the opcode, the one with the other field zero
+            // writes r0 so a single entry does both
+            pat!("0000000000000000011000 ????? ?????", rdcntvl_w, OperandType::R, |s, cpu, mem, args| {
+                let rj = bits!(s.inst, 9, 5) as usize;
+                cpu.set_reg(rj, cpu.csr.read(super::csr::TID).unwrap_or(0));
+                cpu.set_reg(args.rd, cpu.counter as Word);
+            }),
+            pat!("0000000000000000011001 00000 ?????", rdcntvh_w, OperandType::R, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, (cpu.counter >> Word::BITS) as Word);
+            }),
+            pat!("00000000000100000 ????? ????? ?????", add_w, OperandType::R, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, args.src1.wrapping_add(args.src2));
+            }),
+            pat!("00000000000100010 ????? ????? ?????", sub_w, OperandType::R, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, args.src1.wrapping_sub(args.src2));
+            }),
+            pat!("00000000000100100 ????? ????? ?????", slt, OperandType::R, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, ((args.src1 as SWord) < (args.src2 as SWord)) as Word);
+            }),
+            pat!("00000000000100101 ????? ????? ?????", sltu, OperandType::R, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, (args.src1 < args.src2) as Word);
+            }),
+            pat!("00000000000101000 ????? ????? ?????", nor, OperandType::R, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, !(args.src1 | args.src2));
+            }),
+            pat!("00000000000101001 ????? ????? ?????", and, OperandType::R, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, args.src1 & args.src2);
+            }),
+            pat!("00000000000101010 ????? ????? ?????", or, OperandType::R, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, args.src1 | args.src2);
+            }),
+            pat!("00000000000101011 ????? ????? ?????", xor, OperandType::R, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, args.src1 ^ args.src2);
+            }),
+            pat!("00000000000101110 ????? ????? ?????", sll_w, OperandType::R, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, args.src1 << (args.src2 & 0x1f));
+            }),
+            pat!("00000000000101111 ????? ????? ?????", srl_w, OperandType::R, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, args.src1 >> (args.src2 & 0x1f));
+            }),
+            pat!("00000000000110000 ????? ????? ?????", sra_w, OperandType::R, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, ((args.src1 as SWord) >> (args.src2 & 0x1f)) as Word);
+            }),
+            pat!("00000000000111000 ????? ????? ?????", mul_w, OperandType::R, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, args.src1.wrapping_mul(args.src2));
+            }),
+            pat!("00000000000111001 ????? ????? ?????", mulh_w, OperandType::R, |s, cpu, mem, args| {
+                let prod = (args.src1 as SWord as SDWord) * (args.src2 as SWord as SDWord);
+                cpu.set_reg(args.rd, (prod >> Word::BITS) as Word);
+            }),
+            pat!("00000000000111010 ????? ????? ?????", mulh_wu, OperandType::R, |s, cpu, mem, args| {
+                let prod = (args.src1 as DWord) * (args.src2 as DWord);
+                cpu.set_reg(args.rd, (prod >> Word::BITS) as Word);
+            }),
+            // a zero divisor does not trap and leaves the result unspecified: the quotient
+            // reads all ones and the remainder the dividend
+            pat!("00000000001000000 ????? ????? ?????", div_w, OperandType::R, |s, cpu, mem, args| {
+                let val = match args.src2 {
+                    0 => Word::MAX,
+                    _ => (args.src1 as SWord).wrapping_div(args.src2 as SWord) as Word,
+                };
+                cpu.set_reg(args.rd, val);
+            }),
+            pat!("00000000001000001 ????? ????? ?????", mod_w, OperandType::R, |s, cpu, mem, args| {
+                let val = match args.src2 {
+                    0 => args.src1,
+                    _ => (args.src1 as SWord).wrapping_rem(args.src2 as SWord) as Word,
+                };
+                cpu.set_reg(args.rd, val);
+            }),
+            pat!("00000000001000010 ????? ????? ?????", div_wu, OperandType::R, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, args.src1.checked_div(args.src2).unwrap_or(Word::MAX));
+            }),
+            pat!("00000000001000011 ????? ????? ?????", mod_wu, OperandType::R, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, args.src1.checked_rem(args.src2).unwrap_or(args.src1));
+            }),
+            pat!("00000000001010100 ???????????????", break, OperandType::N, |s, cpu, mem, args| {
+                s.raise(Exception::Breakpoint);
+            }),
+            pat!("00000000001010110 ???????????????", syscall, OperandType::N, |s, cpu, mem, args| {
+                s.raise(Exception::Syscall);
+            }),
+            pat!("00000000010000001 ????? ????? ?????", slli_w, OperandType::R, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, args.src1 << args.imm);
+            }),
+            pat!("00000000010001001 ????? ????? ?????", srli_w, OperandType::R, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, args.src1 >> args.imm);
+            }),
+            pat!("00000000010010001 ????? ????? ?????", srai_w, OperandType::R, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, ((args.src1 as SWord) >> args.imm) as Word);
+            }),
+            pat!("0000001000 ???????????? ????? ?????", slti, OperandType::I, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, ((args.src1 as SWord) < (args.imm as SWord)) as Word);
+            }),
+            // the immediate is sign-extended, then compared unsigned
+            pat!("0000001001 ???????????? ????? ?????", sltui, OperandType::I, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, (args.src1 < args.imm) as Word);
+            }),
+            pat!("0000001010 ???????????? ????? ?????", addi_w, OperandType::I, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, args.src1.wrapping_add(args.imm));
+            }),
+            pat!("0000001101 ???????????? ????? ?????", andi, OperandType::Z, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, args.src1 & args.imm);
+            }),
+            pat!("0000001110 ???????????? ????? ?????", ori, OperandType::Z, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, args.src1 | args.imm);
+            }),
+            pat!("0000001111 ???????????? ????? ?????", xori, OperandType::Z, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, args.src1 ^ args.imm);
+            }),
+            // rj = 0 reads, rj = 1 writes, any other rj holds the write mask
+            pat!("00000100 ?????????????? 00000 ?????", csrrd, OperandType::I, |s, cpu, mem, args| {
+                csr_xchg(s, cpu, &args, None);
+            }),
+            pat!("00000100 ?????????????? 00001 ?????", csrwr, OperandType::I, |s, cpu, mem, args| {
+                csr_xchg(s, cpu, &args, Some(Word::MAX));
+            }),
+            pat!("00000100 ?????????????? ????? ?????", csrxchg, OperandType::I, |s, cpu, mem, args| {
+                csr_xchg(s, cpu, &args, Some(args.src1));
+            }),
+            // no caches to maintain
+            pat!("0000011000 ???????????? ????? ?????", cacop, OperandType::N, |s, cpu, mem, args| {
+                privileged(s, cpu);
+            }),
+            pat!("0000011001001000001110 0000000000", ertn, OperandType::N, |s, cpu, mem, args| {
+                if privileged(s, cpu) {
+                    s.dnpc = cpu.csr.trap_return();
+                    cpu.llbit = false;
+                }
+            }),
+            // there is nothing to wake the hart up, so it does not go to sleep
+            pat!("00000110010010001 ???????????????", idle, OperandType::N, |s, cpu, mem, args| {
+                privileged(s, cpu);
+            }),
+            pat!("0001010 ???????????????????? ?????", lu12i_w, OperandType::U, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, args.imm);
+            }),
+            pat!("0001110 ???????????????????? ?????", pcaddu12i, OperandType::U, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, s.pc.wrapping_add(args.imm));
+            }),
+            pat!("00100000 ?????????????? ????? ?????", ll_w, OperandType::W, |s, cpu, mem, args| {
+                let Some(val) = load(s, mem, args.src1.wrapping_add(args.imm), 4) else {
+                    return;
+                };
+                cpu.set_reg(args.rd, val);
+                cpu.llbit = true;
+            }),
+            pat!("00100001 ?????????????? ????? ?????", sc_w, OperandType::W, |s, cpu, mem, args| {
+                let done = cpu.llbit && store(s, mem, args.src1.wrapping_add(args.imm), 4, args.src2);
+                if s.exception.is_none() {
+                    cpu.set_reg(args.rd, done as Word);
+                    cpu.llbit = false;
+                }
+            }),
+            pat!("0010100000 ???????????? ????? ?????", ld_b, OperandType::I, |s, cpu, mem, args| {
+                let Some(val) = load(s, mem, args.src1.wrapping_add(args.imm), 1) else {
+                    return;
+                };
+                cpu.set_reg(args.rd, sext!(val, 8));
+            }),
+            pat!("0010100001 ???????????? ????? ?????", ld_h, OperandType::I, |s, cpu, mem, args| {
+                let Some(val) = load(s, mem, args.src1.wrapping_add(args.imm), 2) else {
+                    return;
+                };
+                cpu.set_reg(args.rd, sext!(val, 16));
+            }),
+            pat!("0010100010 ???????????? ????? ?????", ld_w, OperandType::I, |s, cpu, mem, args| {
+                let Some(val) = load(s, mem, args.src1.wrapping_add(args.imm), 4) else {
+                    return;
+                };
+                cpu.set_reg(args.rd, val);
+            }),
+            pat!("0010100100 ???????????? ????? ?????", st_b, OperandType::I, |s, cpu, mem, args| {
+                store(s, mem, args.src1.wrapping_add(args.imm), 1, args.src2);
+            }),
+            pat!("0010100101 ???????????? ????? ?????", st_h, OperandType::I, |s, cpu, mem, args| {
+                store(s, mem, args.src1.wrapping_add(args.imm), 2, args.src2);
+            }),
+            pat!("0010100110 ???????????? ????? ?????", st_w, OperandType::I, |s, cpu, mem, args| {
+                store(s, mem, args.src1.wrapping_add(args.imm), 4, args.src2);
+            }),
+            pat!("0010101000 ???????????? ????? ?????", ld_bu, OperandType::I, |s, cpu, mem, args| {
+                let Some(val) = load(s, mem, args.src1.wrapping_add(args.imm), 1) else {
+                    return;
+                };
+                cpu.set_reg(args.rd, val);
+            }),
+            pat!("0010101001 ???????????? ????? ?????", ld_hu, OperandType::I, |s, cpu, mem, args| {
+                let Some(val) = load(s, mem, args.src1.wrapping_add(args.imm), 2) else {
+                    return;
+                };
+                cpu.set_reg(args.rd, val);
+            }),
+            pat!("0010101011 ???????????? ????? ?????", preld, OperandType::N, |s, cpu, mem, args| {}),
+            // single hart without caches, every memory ordering is already satisfied
+            pat!("00111000011100100 ???????????????", dbar, OperandType::N, |s, cpu, mem, args| {}),
+            pat!("00111000011100101 ???????????????", ibar, OperandType::N, |s, cpu, mem, args| {}),
+            pat!("010011 ???????????????? ????? ?????", jirl, OperandType::B, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, s.snpc);
+                s.dnpc = args.src1.wrapping_add(args.imm);
+            }),
+            pat!("010100 ??????????????????????????", b, OperandType::J, |s, cpu, mem, args| {
+                s.dnpc = s.pc.wrapping_add(args.imm);
+            }),
+            pat!("010101 ??????????????????????????", bl, OperandType::J, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, s.snpc);
+                s.dnpc = s.pc.wrapping_add(args.imm);
+            }),
+            pat!("010110 ???????????????? ????? ?????", beq, OperandType::B, |s, cpu, mem, args| {
+                if args.src1 == args.src2 {
+                    s.dnpc = s.pc.wrapping_add(args.imm);
+                }
+            }),
+            pat!("010111 ???????????????? ????? ?????", bne, OperandType::B, |s, cpu, mem, args| {
+                if args.src1 != args.src2 {
+                    s.dnpc = s.pc.wrapping_add(args.imm);
+                }
+            }),
+            pat!("011000 ???????????????? ????? ?????", blt, OperandType::B, |s, cpu, mem, args| {
+                if (args.src1 as SWord) < (args.src2 as SWord) {
+                    s.dnpc = s.pc.wrapping_add(args.imm);
+                }
+            }),
+            pat!("011001 ???????????????? ????? ?????", bge, OperandType::B, |s, cpu, mem, args| {
+                if (args.src1 as SWord) >= (args.src2 as SWord) {
+                    s.dnpc = s.pc.wrapping_add(args.imm);
+                }
+            }),
+            pat!("011010 ???????????????? ????? ?????", bltu, OperandType::B, |s, cpu, mem, args| {
+                if args.src1 < args.src2 {
+                    s.dnpc = s.pc.wrapping_add(args.imm);
+                }
+            }),
+            pat!("011011 ???????????????? ????? ?????", bgeu, OperandType::B, |s, cpu, mem, args| {
+                if args.src1 >= args.src2 {
+                    s.dnpc = s.pc.wrapping_add(args.imm);
+                }
+            }),
+        };
+        DecodeTable::new(decoders)
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::isa::loongarch32r::csr::{CRMD, ERA, ESTAT, PRMD, SAVE0};
+    use crate::isa::{Loongarch32r, ISA};
+    use crate::memory::{MemoryBank, RESET_VECTOR};
+
+    use super::*;
+
+    const PC: Vaddr = RESET_VECTOR as Vaddr;
+    const DATA: Vaddr = PC + 0x1000;
+
+    fn r3(op: u32, rk: u32, rj: u32, rd: u32) -> Word {
+        ((op << 15) | (rk << 10) | (rj << 5) | rd) as Word
+    }
+    fn i12(op: u32, imm: i32, rj: u32, rd: u32) -> Word {
+        ((op << 22) | ((imm as u32 & 0xfff) << 10) | (rj << 5) | rd) as Word
+    }
+    /// `offs` counts words
+    fn i16(op: u32, offs: i32, rj: u32, rd: u32) -> Word {
+        ((op << 26) | ((offs as u32 & 0xffff) << 10) | (rj << 5) | rd) as Word
+    }
+    fn i26(op: u32, offs: i32) -> Word {
+        let offs = offs as u32;
+        ((op << 26) | ((offs & 0xffff) << 10) | bits!(offs, 25, 16)) as Word
+    }
+    fn csr(rj: u32, csr: usize, rd: u32) -> Word {
+        ((0x04 << 24) | ((csr as u32) << 10) | (rj << 5) | rd) as Word
+    }
+
+    fn machine() -> (Loongarch32r, MemoryBank) {
+        (Loongarch32r::new(PC), MemoryBank::new(&[]))
+    }
+
+    /// place `inst` at the current pc and execute it
+    fn step(cpu: &mut Loongarch32r, mem: &mut MemoryBank, inst: Word) -> Option<Exception> {
+        let pc = cpu.pc();
+        mem.paddr_write(pc, 4, inst);
+        let mut executer = Executer::new();
+        executer.set_pc(pc);
+        executer.set_snpc(pc);
+        executer.exec_once(cpu, mem);
+        cpu.set_pc(executer.dnpc());
+        executer.take_exception()
+    }
+
+    /// run a 3R op with `opcode` on (a, b) and return rd
+    fn alu_r(op: u32, a: Word, b: Word) -> Word {
+        let (mut cpu, mut mem) = machine();
+        cpu.set_reg(5, a);
+        cpu.set_reg(6, b);
+        step(&mut cpu, &mut mem, r3(op, 6, 5, 4));
+        cpu.reg(4)
+    }
+
+    /// run a 2RI12 op with `opcode` on (a, imm) and return rd
+    fn alu_i(op: u32, a: Word, imm: i32) -> Word {
+        let (mut cpu, mut mem) = machine();
+        cpu.set_reg(5, a);
+        step(&mut cpu, &mut mem, i12(op, imm, 5, 4));
+        cpu.reg(4)
+    }
+
+    #[test]
+    fn default_img_test() {
+        let (mut cpu, mut mem) = machine();
+        let img = Loongarch32r::default_img();
+        for (n, w) in img.chunks(4).enumerate() {
+            let w = u32::from_le_bytes(w.try_into().unwrap());
+            mem.paddr_write(PC + 4 * n as Vaddr, 4, w as Word);
+        }
+        let mut executer = Executer::new();
+        for _ in 0..3 {
+            executer.set_pc(cpu.pc());
+            executer.set_snpc(cpu.pc());
+            executer.exec_once(&mut cpu, &mut mem);
+            cpu.set_pc(executer.dnpc());
+        }
+        assert_eq!(mem.paddr_read(PC + 16, 4), 0);
+        cpu.set_reg(4, 1);
+        executer.set_pc(cpu.pc());
+        executer.set_snpc(cpu.pc());
+        executer.exec_once(&mut cpu, &mut mem);
+        assert_eq!(executer.take_exception(), Some(Exception::Breakpoint));
+        assert_eq!(cpu.halt_ret(), 1);
+    }
+
+    #[test]
+    fn alu_test() {
+        assert_eq!(alu_r(0x20, Word::MAX, 2), 1);
+        assert_eq!(alu_r(0x22, 0, 1), Word::MAX);
+        assert_eq!(alu_r(0x24, Word::MAX, 0), 1);
+        assert_eq!(alu_r(0x25, Word::MAX, 0), 0);
+        assert_eq!(alu_r(0x28, 0xf0, 0x0f), 0xffff_ff00);
+        assert_eq!(alu_r(0x30, 0x8000_0000, 33), 0xc000_0000);
+        // andi zero-extends, sltui sign-extends
+        assert_eq!(alu_i(0xd, Word::MAX, -1), 0xfff);
+        assert_eq!(alu_i(0x9, 5, -1), 1);
+        assert_eq!(alu_i(0xa, 5, -6), Word::MAX);
+        let (mut cpu, mut mem) = machine();
+        // the ui5 of srai.w sits where rk would be
+        cpu.set_reg(5, 0x8000_0000);
+        step(&mut cpu, &mut mem, r3(0x91, 4, 5, 4));
+        assert_eq!(cpu.reg(4), 0xf800_0000);
+        // pcaddu12i r4, 1
+        step(&mut cpu, &mut mem, 0x1c00_0024);
+        assert_eq!(cpu.reg(4), PC + 4 + 0x1000);
+    }
+
+    #[test]
+    fn mul_div_test() {
+        assert_eq!(alu_r(0x38, Word::MAX, 3), Word::MAX - 2);
+        assert_eq!(alu_r(0x39, Word::MAX, Word::MAX), 0);
+        assert_eq!(alu_r(0x3a, Word::MAX, Word::MAX), Word::MAX - 1);
+        assert_eq!(alu_r(0x40, -7i32 as Word, 2), -3i32 as Word);
+        assert_eq!(alu_r(0x41, -7i32 as Word, 2), Word::MAX);
+        assert_eq!(alu_r(0x40, 0x8000_0000, Word::MAX), 0x8000_0000);
+        assert_eq!(alu_r(0x42, 7, 0), Word::MAX);
+        assert_eq!(alu_r(0x43, 7, 0), 7);
+    }
+
+    #[test]
+    fn branch_test() {
+        let (mut cpu, mut mem) = machine();
+        // b with an offset that needs the high half of offs26
+        step(&mut cpu, &mut mem, i26(0x14, 0x10000));
+        assert_eq!(cpu.pc(), PC + 0x40000);
+        // bl links into $ra, backwards
+        step(&mut cpu, &mut mem, i26(0x15, -4));
+        assert_eq!(cpu.reg(1), PC + 0x40004);
+        assert_eq!(cpu.pc(), PC + 0x40000 - 16);
+        // beq compares rj with rd
+        cpu.set_reg(5, 3);
+        cpu.set_reg(4, 3);
+        let pc = cpu.pc();
+        step(&mut cpu, &mut mem, i16(0x16, 2, 5, 4));
+        assert_eq!(cpu.pc(), pc + 8);
+        step(&mut cpu, &mut mem, i16(0x1a, 2, 5, 4));
+        assert_eq!(cpu.pc(), pc + 12);
+        // jirl r1, r5, 4 words
+        cpu.set_reg(5, PC);
+        step(&mut cpu, &mut mem, i16(0x13, 4, 5, 1));
+        assert_eq!(cpu.reg(1), pc + 16);
+        assert_eq!(cpu.pc(), PC + 16);
+    }
+
+    #[test]
+    fn load_store_test() {
+        let (mut cpu, mut mem) = machine();
+        cpu.set_reg(5, DATA);
+        cpu.set_reg(4, 0x8765_4321);
+        step(&mut cpu, &mut mem, i12(0xa6, 0, 5, 4));
+        assert_eq!(mem.paddr_read(DATA, 4), 0x8765_4321);
+        step(&mut cpu, &mut mem, i12(0xa1, 2, 5, 6));
+        assert_eq!(cpu.reg(6), 0xffff_8765);
+        step(&mut cpu, &mut mem, i12(0xa8, 3, 5, 6));
+        assert_eq!(cpu.reg(6), 0x87);
+        assert_eq!(
+            step(&mut cpu, &mut mem, i12(0xa2, 2, 5, 6)),
+            Some(Exception::Misaligned(DATA + 2))
+        );
+        cpu.set_reg(5, 0x1000);
+        assert_eq!(
+            step(&mut cpu, &mut mem, i12(0xa4, 0, 5, 6)),
+            Some(Exception::AddressErrorMemory(0x1000))
+        );
+        // ll.w/sc.w, the offset counts words
+        cpu.set_reg(5, DATA - 4);
+        step(&mut cpu, &mut mem, (0x20 << 24) | (1 << 10) | (5 << 5) | 6);
+        assert_eq!(cpu.reg(6), 0x8765_4321);
+        cpu.set_reg(7, 1);
+        step(&mut cpu, &mut mem, (0x21 << 24) | (1 << 10) | (5 << 5) | 7);
+        assert_eq!((cpu.reg(7), mem.paddr_read(DATA, 4)), (1, 1));
+        step(&mut cpu, &mut mem, (0x21 << 24) | (1 << 10) | (5 << 5) | 7);
+        assert_eq!(cpu.reg(7), 0);
+    }
+
+    #[test]
+    fn csr_test() {
+        let (mut cpu, mut mem) = machine();
+        cpu.set_reg(4, 0x8000_0100);
+        // csrwr swaps
+        step(&mut cpu, &mut mem, csr(1, ERA, 4));
+        assert_eq!((cpu.reg(4), cpu.csr(ERA)), (0, Some(0x8000_0100)));
+        // csrxchg writes the bits under the mask in rj
+        cpu.set_reg(4, Word::MAX);
+        cpu.set_reg(5, 0xf0);
+        step(&mut cpu, &mut mem, csr(5, SAVE0, 4));
+        assert_eq!((cpu.reg(4), cpu.csr(SAVE0)), (0, Some(0xf0)));
+        step(&mut cpu, &mut mem, csr(0, SAVE0, 6));
+        assert_eq!(cpu.reg(6), 0xf0);
+        // ertn back to PLV3 at ERA, where CSRs are out of reach
+        cpu.set_csr(PRMD, 3);
+        step(&mut cpu, &mut mem, 0x0648_3800);
+        assert_eq!(cpu.pc(), 0x8000_0100);
+        assert_eq!(cpu.csr(CRMD).map(|v| v & 3), Some(3));
+        let inst = csr(0, ESTAT, 4);
+        assert_eq!(
+            step(&mut cpu, &mut mem, inst),
+            Some(Exception::PrivilegedInstruction(inst))
+        );
+    }
+
+    #[test]
+    fn counter_test() {
+        let (mut cpu, mut mem) = machine();
+        cpu.counter = 0x1_ffff_fffe;
+        step(&mut cpu, &mut mem, 0x0000_6004);
+        assert_eq!(cpu.reg(4), Word::MAX);
+        step(&mut cpu, &mut mem, 0x0000_6404);
+        assert_eq!(cpu.reg(4), 2);
+    }
+
+    #[test]
+    fn fetch_test() {
+        let (mut cpu, mut mem) = machine();
+        cpu.set_pc(PC + 2);
+        assert_eq!(
+            step(&mut cpu, &mut mem, 0),
+            Some(Exception::FetchAddressError(PC + 2))
+        );
+        let (mut cpu, mut mem) = machine();
+        assert_eq!(
+            step(&mut cpu, &mut mem, 0xffff_ffff),
+            Some(Exception::IllegalInstruction(0xffff_ffff))
+        );
+    }
+}
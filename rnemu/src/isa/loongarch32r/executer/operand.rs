@@ -0,0 +1,121 @@
+use crate::{
+    common::Word,
+    isa::{Loongarch32r, ISA},
+};
+
+pub trait OperandHelper: Send + Sync {
+    fn decode_operand(&self, inst: Word, isa: &Loongarch32r) -> Args;
+}
+pub enum OperandType {
+    /// 3R, `imm` is the ui5 of the immediate shifts
+    R,
+    /// 2RI12 with a signed immediate, stores read rd as src2
+    I,
+    /// 2RI12 with an unsigned immediate, the logical immediates
+    Z,
+    /// 2RI14, the word offset of LL.W/SC.W
+    W,
+    /// 1RI20
+    U,
+    /// 2RI16, branches compare rj with rd
+    B,
+    /// I26
+    J,
+    N,
+}
+
+pub struct Args {
+    pub rd: usize,
+    pub src1: Word,
+    pub src2: Word,
+    pub imm: Word,
+}
+
+impl Args {
+    pub fn new(rd: usize, src1: Word, src2: Word, imm: Word) -> Self {
+        Self {
+            rd,
+            src1,
+            src2,
+            imm,
+        }
+    }
+}
+
+pub struct ROperand;
+impl OperandHelper for ROperand {
+    fn decode_operand(&self, inst: Word, isa: &Loongarch32r) -> Args {
+        let rk = bits!(inst, 14, 10);
+        let rj = bits!(inst, 9, 5);
+        let rd = bits!(inst, 4, 0);
+        let src1 = isa.reg(rj as usize);
+        let src2 = isa.reg(rk as usize);
+        Args::new(rd as usize, src1, src2, rk)
+    }
+}
+pub struct IOperand;
+impl OperandHelper for IOperand {
+    fn decode_operand(&self, inst: Word, isa: &Loongarch32r) -> Args {
+        let rj = bits!(inst, 9, 5);
+        let rd = bits!(inst, 4, 0);
+        let src1 = isa.reg(rj as usize);
+        let src2 = isa.reg(rd as usize);
+        let imm = sext!(bits!(inst, 21, 10), 12);
+        Args::new(rd as usize, src1, src2, imm)
+    }
+}
+pub struct ZOperand;
+impl OperandHelper for ZOperand {
+    fn decode_operand(&self, inst: Word, isa: &Loongarch32r) -> Args {
+        let rj = bits!(inst, 9, 5);
+        let rd = bits!(inst, 4, 0);
+        let src1 = isa.reg(rj as usize);
+        let imm = bits!(inst, 21, 10);
+        Args::new(rd as usize, src1, 0, imm)
+    }
+}
+pub struct WOperand;
+impl OperandHelper for WOperand {
+    fn decode_operand(&self, inst: Word, isa: &Loongarch32r) -> Args {
+        let rj = bits!(inst, 9, 5);
+        let rd = bits!(inst, 4, 0);
+        let src1 = isa.reg(rj as usize);
+        let src2 = isa.reg(rd as usize);
+        let imm = sext!(bits!(inst, 23, 10), 14) << 2;
+        Args::new(rd as usize, src1, src2, imm)
+    }
+}
+pub struct UOperand;
+impl OperandHelper for UOperand {
+    fn decode_operand(&self, inst: Word, isa: &Loongarch32r) -> Args {
+        let rd = bits!(inst, 4, 0);
+        let imm = bits!(inst, 24, 5) << 12;
+        Args::new(rd as usize, 0, 0, imm)
+    }
+}
+pub struct BOperand;
+impl OperandHelper for BOperand {
+    fn decode_operand(&self, inst: Word, isa: &Loongarch32r) -> Args {
+        let rj = bits!(inst, 9, 5);
+        let rd = bits!(inst, 4, 0);
+        let src1 = isa.reg(rj as usize);
+        let src2 = isa.reg(rd as usize);
+        let imm = sext!(bits!(inst, 25, 10), 16) << 2;
+        Args::new(rd as usize, src1, src2, imm)
+    }
+}
+pub struct JOperand;
+impl OperandHelper for JOperand {
+    fn decode_operand(&self, inst: Word, isa: &Loongarch32r) -> Args {
+        // offs[15:0] sits above offs[25:16]
+        let offs = (bits!(inst, 9, 0) << 16) | bits!(inst, 25, 10);
+        let imm = sext!(offs, 26) << 2;
+        Args::new(1, 0, 0, imm)
+    }
+}
+pub struct NOperand;
+impl OperandHelper for NOperand {
+    fn decode_operand(&self, inst: Word, isa: &Loongarch32r) -> Args {
+        Args::new(0, 0, 0, 0)
+    }
+}
@@ -0,0 +1,129 @@
+use crate::{
+    common::{DWord, Vaddr, Word},
+    memory::MemoryBank,
+};
+
+use super::{TlbConfig, ISA};
+
+mod csr;
+mod executer;
+use csr::CsrFile;
+pub use executer::{Exception, Executer};
+pub const GUEST_ISA: &str = "loongarch32r";
+
+/// LA32R hart in direct address mode, virtual addresses are physical.
+#[derive(Debug, Default)]
+pub struct Loongarch32r {
+    gpr: [Word; 32],
+    pc: Vaddr,
+    csr: CsrFile,
+    /// set by LL.W, cleared by SC.W and ERTN
+    llbit: bool,
+    /// stable counter read by RDCNTV{L,H}.W, it ticks once per instruction
+    counter: DWord,
+}
+
+impl Loongarch32r {
+    pub fn new(pc: Vaddr) -> Self {
+        Self {
+            pc,
+            ..Self::default()
+        }
+    }
+}
+
+impl ISA for Loongarch32r {
+    type Executer = executer::Executer;
+    type Exception = executer::Exception;
+    const NAME: &'static str = GUEST_ISA;
+    const LOGO: &'static str = ISA_LOGO;
+    const NR_GPR: usize = 32;
+    const ELF_MACHINE: u16 = 258;
+
+    fn init(pc: Vaddr, tlb: TlbConfig) -> Self {
+        Self::new(pc)
+    }
+
+    /// take exception `e` raised by the instruction at `pc`, return the handler address
+    fn raise_exception(&mut self, mem: &mut MemoryBank, pc: Vaddr, e: Exception) -> Vaddr {
+        let (ecode, subcode) = e.code();
+        self.csr.trap_enter(pc, ecode, subcode, e.badv())
+    }
+
+    /// $a0 holds the exit code of nemu_trap
+    fn halt_ret(&self) -> Word {
+        self.gpr[4]
+    }
+
+    fn set_pc(&mut self, next: Vaddr) {
+        self.pc = next;
+    }
+
+    fn pc(&self) -> Vaddr {
+        self.pc
+    }
+
+    fn reg(&self, idx: usize) -> Word {
+        self.gpr[idx]
+    }
+
+    fn set_reg(&mut self, idx: usize, val: Word) {
+        self.gpr[idx] = val;
+    }
+
+    fn csr(&self, addr: usize) -> Option<Word> {
+        self.csr.read(addr)
+    }
+
+    fn set_csr(&mut self, addr: usize, val: Word) -> bool {
+        self.csr.write(addr, val)
+    }
+
+    fn default_img() -> &'static [u8] {
+        let ptr = IMG.as_ptr() as *const u8;
+        let len = IMG.len() * std::mem::size_of::<u32>();
+        unsafe { std::slice::from_raw_parts(ptr, len) }
+    }
+
+    fn executer() -> Self::Executer {
+        executer::Executer::new()
+    }
+}
+
+pub const ISA_LOGO: &str = r"  _                                            _    _________
+ | |    ___   ___  _ __   __ _  __ _ _ __ ___| |__|___ /___ \ _ __
+ | |   / _ \ / _ \| '_ \ / _` |/ _` | '__/ __| '_ \ |_ \ __) | '__|
+ | |__| (_) | (_) | | | | (_| | (_| | | | (__| | | |__) / __/| |
+ |_____\___/ \___/|_| |_|\__, |\__,_|_|  \___|_| |_|___/_____|_|
+                         |___/
+";
+
+const IMG: [u32; 5] = [
+    0x15000004, // lu12i.w a0,-0x80000
+    0x29804080, // st.w    zero,a0,16
+    0x28804084, // ld.w    a0,a0,16
+    0x002a0000, // break   0 (used as nemu_trap)
+    0xdeadbeef, // some data
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn raise_exception_test() {
+        let mut cpu = Loongarch32r::new(0x8000_0000);
+        let mut mem = MemoryBank::new(&[]);
+        cpu.set_csr(csr::EENTRY, 0x8000_0100);
+        let handler =
+            cpu.raise_exception(&mut mem, 0x8000_0004, Exception::IllegalInstruction(0xffff));
+        assert_eq!(handler, 0x8000_0100);
+        assert_eq!(cpu.csr(csr::ERA), Some(0x8000_0004));
+        assert_eq!(cpu.csr(csr::ESTAT), Some(0xd << 16));
+        let handler =
+            cpu.raise_exception(&mut mem, 0x8000_0100, Exception::Misaligned(0x8000_0002));
+        assert_eq!(handler, 0x8000_0100);
+        assert_eq!(cpu.csr(csr::ESTAT), Some(0x9 << 16));
+        assert_eq!(cpu.csr(csr::BADV), Some(0x8000_0002));
+    }
+}
@@ -0,0 +1,162 @@
+use crate::common::{Vaddr, Word};
+
+/// CP0 registers are addressed as `reg << 3 | sel`, the way mfc0/mtc0 name them
+pub const fn cp0_addr(reg: usize, sel: usize) -> usize {
+    (reg << 3) | sel
+}
+
+pub const BADVADDR: usize = cp0_addr(8, 0);
+pub const STATUS: usize = cp0_addr(12, 0);
+pub const CAUSE: usize = cp0_addr(13, 0);
+pub const EPC: usize = cp0_addr(14, 0);
+pub const PRID: usize = cp0_addr(15, 0);
+pub const CONFIG: usize = cp0_addr(16, 0);
+
+pub const STATUS_IE: Word = 1 << 0;
+pub const STATUS_EXL: Word = 1 << 1;
+pub const STATUS_ERL: Word = 1 << 2;
+pub const STATUS_BEV: Word = 1 << 22;
+/// IE, EXL, ERL, KSU, IM and BEV
+const STATUS_MASK: Word = 0x0040_ff1f;
+
+pub const CAUSE_BD: Word = 1 << 31;
+/// the two software interrupt pending bits are the only writable part of Cause
+const CAUSE_MASK: Word = 0b11 << 8;
+
+/// MIPS Technologies as the company, 4Kc as the processor
+const PRID_VALUE: Word = 0x0001_8000;
+/// Config1 follows, little-endian MIPS32 R1 without an MMU, kseg0 cacheable
+const CONFIG_VALUE: Word = 0x8000_0003;
+
+/// general exception vector with Status.BEV clear and set
+const VECTOR: Vaddr = 0x8000_0180;
+const BOOT_VECTOR: Vaddr = 0xbfc0_0380;
+
+pub const CP0_NAMES: [(usize, &str); 6] = [
+    (BADVADDR, "badvaddr"),
+    (STATUS, "status"),
+    (CAUSE, "cause"),
+    (EPC, "epc"),
+    (PRID, "prid"),
+    (CONFIG, "config"),
+];
+
+/// name of the CP0 register at `addr`
+pub fn cp0_name(addr: usize) -> Option<&'static str> {
+    CP0_NAMES.iter().find(|(a, _)| *a == addr).map(|(_, n)| *n)
+}
+
+/// The system control coprocessor, cut down to what exception handling needs. Status comes
+/// out of reset with BEV clear so that the vector lies in memory.
+#[derive(Debug, Default)]
+pub struct Cp0 {
+    badvaddr: Word,
+    status: Word,
+    cause: Word,
+    epc: Word,
+}
+
+impl Cp0 {
+    /// read the register at `addr`, `None` if it does not exist
+    pub fn read(&self, addr: usize) -> Option<Word> {
+        let val = match addr {
+            BADVADDR => self.badvaddr,
+            STATUS => self.status,
+            CAUSE => self.cause,
+            EPC => self.epc,
+            PRID => PRID_VALUE,
+            CONFIG => CONFIG_VALUE,
+            _ => return None,
+        };
+        Some(val)
+    }
+
+    /// write the register at `addr`, `false` if it does not exist or is read-only
+    pub fn write(&mut self, addr: usize, val: Word) -> bool {
+        match addr {
+            BADVADDR | PRID | CONFIG => return false,
+            STATUS => self.status = val & STATUS_MASK,
+            CAUSE => self.cause = (self.cause & !CAUSE_MASK) | (val & CAUSE_MASK),
+            EPC => self.epc = val,
+            _ => return false,
+        }
+        true
+    }
+
+    /// enter the exception with `code` raised at `pc`, `in_delay` if that was a delay slot,
+    /// return the vector. A nested exception keeps EPC and Cause.BD of the first one.
+    pub fn trap_enter(
+        &mut self,
+        pc: Vaddr,
+        in_delay: bool,
+        code: Word,
+        badvaddr: Option<Vaddr>,
+    ) -> Vaddr {
+        if self.status & STATUS_EXL == 0 {
+            // the branch owning the slot runs again after eret
+            let (epc, bd) = if in_delay {
+                (pc.wrapping_sub(4), CAUSE_BD)
+            } else {
+                (pc, 0)
+            };
+            self.epc = epc;
+            self.cause = (self.cause & !CAUSE_BD) | bd;
+        }
+        self.cause = (self.cause & !(0x1f << 2)) | ((code & 0x1f) << 2);
+        if let Some(addr) = badvaddr {
+            self.badvaddr = addr;
+        }
+        self.status |= STATUS_EXL;
+        if self.status & STATUS_BEV != 0 {
+            BOOT_VECTOR
+        } else {
+            VECTOR
+        }
+    }
+
+    /// eret, return where to continue. ERL takes precedence over EXL as the error level uses
+    /// ErrorEPC, which is not modelled, so it goes back to EPC too.
+    pub fn trap_return(&mut self) -> Vaddr {
+        if self.status & STATUS_ERL != 0 {
+            self.status &= !STATUS_ERL;
+        } else {
+            self.status &= !STATUS_EXL;
+        }
+        self.epc
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn warl_test() {
+        let mut cp0 = Cp0::default();
+        assert!(cp0.write(STATUS, Word::MAX));
+        assert_eq!(cp0.read(STATUS), Some(STATUS_MASK));
+        assert!(cp0.write(CAUSE, Word::MAX));
+        assert_eq!(cp0.read(CAUSE), Some(CAUSE_MASK));
+        assert!(!cp0.write(PRID, 0));
+        assert_eq!(cp0.read(PRID), Some(PRID_VALUE));
+        assert_eq!(cp0.read(cp0_addr(9, 0)), None);
+        assert_eq!(cp0_name(EPC), Some("epc"));
+    }
+
+    #[test]
+    fn trap_test() {
+        let mut cp0 = Cp0::default();
+        assert_eq!(cp0.trap_enter(0x8000_0010, true, 10, None), VECTOR);
+        assert_eq!(cp0.read(EPC), Some(0x8000_000c));
+        assert_eq!(cp0.read(CAUSE), Some(CAUSE_BD | (10 << 2)));
+        // nested, EPC stays
+        cp0.trap_enter(0x8000_0180, false, 4, Some(0x1));
+        assert_eq!(cp0.read(EPC), Some(0x8000_000c));
+        assert_eq!(cp0.read(CAUSE), Some(CAUSE_BD | (4 << 2)));
+        assert_eq!(cp0.read(BADVADDR), Some(0x1));
+        assert_eq!(cp0.trap_return(), 0x8000_000c);
+        assert_eq!(cp0.read(STATUS), Some(0));
+        cp0.write(STATUS, STATUS_BEV);
+        assert_eq!(cp0.trap_enter(0x8000_0000, false, 8, None), BOOT_VECTOR);
+    }
+}
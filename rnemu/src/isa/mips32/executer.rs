@@ -0,0 +1,852 @@
+use operand::{Args, IOperand, JOperand, NOperand, OperandHelper, OperandType, ROperand, ZOperand};
+
+use super::{cp0, Mips32};
+use crate::{
+    common::{DWord, SDWord, SWord, Vaddr, Word},
+    isa::{decode::DecodeTable, Execute, GuestException, ISA},
+    memory::{MemoryBank, Perm},
+};
+
+mod operand;
+struct Decoder<A>
+where
+    A: Fn(&mut Executer, &mut Mips32, &mut MemoryBank, Args),
+{
+    apply: A,
+    helper: Box<dyn OperandHelper>,
+}
+
+impl<A> Decoder<A>
+where
+    A: Fn(&mut Executer, &mut Mips32, &mut MemoryBank, Args),
+{
+    pub fn new(apply: A, typ: OperandType) -> Self {
+        let helper: Box<dyn OperandHelper> = match typ {
+            OperandType::R => Box::new(ROperand),
+            OperandType::I => Box::new(IOperand),
+            OperandType::Z => Box::new(ZOperand),
+            OperandType::J => Box::new(JOperand),
+            OperandType::N => Box::new(NOperand),
+        };
+        Self { apply, helper }
+    }
+}
+trait Decode: Send + Sync {
+    fn apply(&self, s: &mut Executer, cpu: &mut Mips32, mem: &mut MemoryBank);
+}
+
+impl<A> Decode for Decoder<A>
+where
+    A: Fn(&mut Executer, &mut Mips32, &mut MemoryBank, Args) + Send + Sync,
+{
+    fn apply(&self, s: &mut Executer, cpu: &mut Mips32, mem: &mut MemoryBank) {
+        let args = self.helper.decode_operand(s.inst, cpu);
+        (self.apply)(s, cpu, mem, args)
+    }
+}
+
+macro_rules! pat {
+    ($p:literal,$name: ident,$o:expr, $b:expr) => {
+        (
+            $crate::isa::decode::Pattern::new(stringify!($name), $p),
+            Box::new($crate::isa::mips32::executer::Decoder::new($b, $o))
+                as Box<dyn $crate::isa::mips32::executer::Decode>,
+        )
+    };
+}
+
+/// Synchronous exceptions raised by an instruction, carrying what goes to BadVAddr.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exception {
+    /// fetch from a pc that is not 4-byte aligned
+    FetchAddressError(Vaddr),
+    /// fetch outside physical memory
+    InstBusError(Vaddr),
+    AddressErrorLoad(Vaddr),
+    AddressErrorStore(Vaddr),
+    /// load or store outside physical memory
+    DataBusError(Vaddr),
+    Syscall,
+    /// `break`, and `sdbbp` used as nemu_trap
+    Breakpoint,
+    /// reserved instruction, with the instruction
+    IllegalInstruction(Word),
+    /// signed overflow of add, addi or sub
+    Overflow,
+    /// condition of a trap instruction held
+    Trap,
+}
+
+impl Exception {
+    /// ExcCode written to Cause
+    pub fn code(&self) -> Word {
+        match self {
+            Exception::FetchAddressError(_) | Exception::AddressErrorLoad(_) => 4,
+            Exception::AddressErrorStore(_) => 5,
+            Exception::InstBusError(_) => 6,
+            Exception::DataBusError(_) => 7,
+            Exception::Syscall => 8,
+            Exception::Breakpoint => 9,
+            Exception::IllegalInstruction(_) => 10,
+            Exception::Overflow => 12,
+            Exception::Trap => 13,
+        }
+    }
+
+    /// value written to BadVAddr, only address errors set it
+    pub fn badvaddr(&self) -> Option<Vaddr> {
+        match *self {
+            Exception::FetchAddressError(addr)
+            | Exception::AddressErrorLoad(addr)
+            | Exception::AddressErrorStore(addr) => Some(addr),
+            _ => None,
+        }
+    }
+}
+
+impl GuestException for Exception {
+    /// whether the instruction could not even be fetched
+    fn is_fetch(&self) -> bool {
+        matches!(
+            self,
+            Exception::FetchAddressError(_) | Exception::InstBusError(_)
+        )
+    }
+
+    fn is_breakpoint(&self) -> bool {
+        matches!(self, Exception::Breakpoint)
+    }
+
+    fn is_illegal(&self) -> bool {
+        matches!(self, Exception::IllegalInstruction(_))
+    }
+
+    fn is_access_fault(&self) -> bool {
+        matches!(
+            self,
+            Exception::InstBusError(_) | Exception::DataBusError(_)
+        )
+    }
+}
+
+pub struct Executer {
+    pc: Vaddr,
+    snpc: Vaddr,
+    dnpc: Vaddr,
+    inst: Word,
+    exception: Option<Exception>,
+    decoders: &'static DecodeTable<Box<dyn Decode>>,
+}
+
+impl Executer {
+    pub fn new() -> Self {
+        Self {
+            pc: 0,
+            snpc: 0,
+            dnpc: 0,
+            inst: 0,
+            exception: None,
+            decoders: &DECODERS,
+        }
+    }
+
+    fn fetch(&mut self, mem: &MemoryBank) -> Option<Word> {
+        if !self.pc.is_multiple_of(4) {
+            self.raise(Exception::FetchAddressError(self.pc));
+            return None;
+        }
+        if !mem.allows(self.snpc, 4, Perm::X) {
+            self.raise(Exception::InstBusError(self.snpc));
+            return None;
+        }
+        Some(mem.inst_fetch(&mut self.snpc, 4))
+    }
+
+    fn raise(&mut self, e: Exception) {
+        self.exception = Some(e);
+    }
+}
+
+impl Execute<Mips32> for Executer {
+    /// execute the instruction at pc, an exception it raises is left for `take_exception`.
+    /// When pc is a delay slot the branch before it has left its target in the hart.
+    fn exec_once(&mut self, cpu: &mut Mips32, mem: &mut MemoryBank) {
+        let target = cpu.delay.take();
+        cpu.in_delay = target.is_some();
+        self.dnpc = self.snpc;
+        let Some(inst) = self.fetch(mem) else {
+            return;
+        };
+        self.inst = inst;
+        self.dnpc = self.snpc;
+        match self.decoders.lookup(inst) {
+            Some(d) => {
+                d.apply(self, cpu, mem);
+                cpu.set_reg(0, 0);
+            }
+            None => self.raise(Exception::IllegalInstruction(inst)),
+        }
+        if let (Some(target), None) = (target, self.exception) {
+            self.dnpc = target;
+        }
+    }
+
+    fn pc(&self) -> Vaddr {
+        self.pc
+    }
+
+    fn set_pc(&mut self, pc: Vaddr) {
+        self.pc = pc;
+    }
+
+    fn set_snpc(&mut self, snpc: Vaddr) {
+        self.snpc = snpc;
+    }
+
+    fn dnpc(&self) -> Vaddr {
+        self.dnpc
+    }
+
+    /// take the exception raised by the last executed instruction
+    fn take_exception(&mut self) -> Option<Exception> {
+        self.exception.take()
+    }
+}
+
+/// branch by `offset` words from the delay slot if `taken`, a likely branch that is not taken
+/// skips its delay slot
+fn branch(s: &mut Executer, cpu: &mut Mips32, taken: bool, offset: Word, likely: bool) {
+    if taken {
+        cpu.delay = Some(s.snpc.wrapping_add(offset << 2));
+    } else if likely {
+        s.dnpc = s.snpc.wrapping_add(4);
+    }
+}
+
+/// the return address of a branch or jump, past its delay slot
+fn link(s: &Executer) -> Word {
+    s.snpc.wrapping_add(4)
+}
+
+fn trap(s: &mut Executer, cond: bool) {
+    if cond {
+        s.raise(Exception::Trap);
+    }
+}
+
+/// signed `op` on (a, b), raising Overflow instead of writing `rd` when it does not fit
+fn checked<F>(s: &mut Executer, cpu: &mut Mips32, rd: usize, a: Word, b: Word, op: F)
+where
+    F: Fn(SWord, SWord) -> Option<SWord>,
+{
+    match op(a as SWord, b as SWord) {
+        Some(val) => cpu.set_reg(rd, val as Word),
+        None => s.raise(Exception::Overflow),
+    }
+}
+
+fn hilo(cpu: &Mips32) -> DWord {
+    ((cpu.hi as DWord) << Word::BITS) | cpu.lo as DWord
+}
+
+fn set_hilo(cpu: &mut Mips32, val: DWord) {
+    cpu.hi = (val >> Word::BITS) as Word;
+    cpu.lo = val as Word;
+}
+
+/// load `len` bytes at `addr`, `None` after raising the exception if it can not be done
+fn load(s: &mut Executer, mem: &MemoryBank, addr: Vaddr, len: usize) -> Option<Word> {
+    if !addr.is_multiple_of(len as Vaddr) {
+        s.raise(Exception::AddressErrorLoad(addr));
+        return None;
+    }
+    if !mem.allows(addr, len, Perm::R) {
+        s.raise(Exception::DataBusError(addr));
+        return None;
+    }
+    Some(mem.paddr_read(addr, len))
+}
+
+fn store(s: &mut Executer, mem: &mut MemoryBank, addr: Vaddr, len: usize, data: Word) -> bool {
+    if !addr.is_multiple_of(len as Vaddr) {
+        s.raise(Exception::AddressErrorStore(addr));
+        return false;
+    }
+    if !mem.allows(addr, len, Perm::W) {
+        s.raise(Exception::DataBusError(addr));
+        return false;
+    }
+    mem.paddr_write(addr, len, data);
+    true
+}
+
+lazy_static::lazy_static! {
+    static ref DECODERS: DecodeTable<Box<dyn Decode>> = {
+        let decoders = vec! {
+            // SPECIAL
+            pat!("000000 00000 ????? ????? ????? 000000", sll, OperandType::R, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, args.src2 << args.imm);
+            }),
+            pat!("000000 00000 ????? ????? ????? 000010", srl, OperandType::R, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, args.src2 >> args.imm);
+            }),
+            pat!("000000 00000 ????? ????? ????? 000011", sra, OperandType::R, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, ((args.src2 as SWord) >> args.imm) as Word);
+            }),
+            pat!("000000 ????? ????? ????? 00000 000100", sllv, OperandType::R, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, args.src2 << (args.src1 & 0x1f));
+            }),
+            pat!("000000 ????? ????? ????? 00000 000110", srlv, OperandType::R, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, args.src2 >> (args.src1 & 0x1f));
+            }),
+            pat!("000000 ????? ????? ????? 00000 000111", srav, OperandType::R, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, ((args.src2 as SWord) >> (args.src1 & 0x1f)) as Word);
+            }),
+            pat!("000000 ????? 00000 00000 ????? 001000", jr, OperandType::R, |s, cpu, mem, args| {
+                cpu.delay = Some(args.src1);
+            }),
+            pat!("000000 ????? 00000 ????? ????? 001001", jalr, OperandType::R, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, link(s));
+                cpu.delay = Some(args.src1);
+            }),
+            pat!("000000 ????? ????? ????? 00000 001010", movz, OperandType::R, |s, cpu, mem, args| {
+                if args.src2 == 0 {
+                    cpu.set_reg(args.rd, args.src1);
+                }
+            }),
+            pat!("000000 ????? ????? ????? 00000 001011", movn, OperandType::R, |s, cpu, mem, args| {
+                if args.src2 != 0 {
+                    cpu.set_reg(args.rd, args.src1);
+                }
+            }),
+            pat!("000000 ????? ????? ????? ????? 001100", syscall, OperandType::N, |s, cpu, mem, args| {
+                s.raise(Exception::Syscall);
+            }),
+            pat!("000000 ????? ????? ????? ????? 001101", break, OperandType::N, |s, cpu, mem, args| {
+                s.raise(Exception::Breakpoint);
+            }),
+            // single hart without caches, every memory ordering is already satisfied
+            pat!("000000 00000 00000 00000 ????? 001111", sync, OperandType::N, |s, cpu, mem, args| {}),
+            pat!("000000 00000 00000 ????? 00000 010000", mfhi, OperandType::R, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, cpu.hi);
+            }),
+            pat!("000000 ????? 00000 00000 00000 010001", mthi, OperandType::R, |s, cpu, mem, args| {
+                cpu.hi = args.src1;
+            }),
+            pat!("000000 00000 00000 ????? 00000 010010", mflo, OperandType::R, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, cpu.lo);
+            }),
+            pat!("000000 ????? 00000 00000 00000 010011", mtlo, OperandType::R, |s, cpu, mem, args| {
+                cpu.lo = args.src1;
+            }),
+            pat!("000000 ????? ????? 00000 00000 011000", mult, OperandType::R, |s, cpu, mem, args| {
+                let prod = (args.src1 as SWord as SDWord) * (args.src2 as SWord as SDWord);
+                set_hilo(cpu, prod as DWord);
+            }),
+            pat!("000000 ????? ????? 00000 00000 011001", multu, OperandType::R, |s, cpu, mem, args| {
+                set_hilo(cpu, (args.src1 as DWord) * (args.src2 as DWord));
+            }),
+            // division never traps, HI and LO keep their values on a zero divisor
+            pat!("000000 ????? ????? 00000 00000 011010", div, OperandType::R, |s, cpu, mem, args| {
+                let (a, b) = (args.src1 as SWord, args.src2 as SWord);
+                if b != 0 {
+                    cpu.lo = a.wrapping_div(b) as Word;
+                    cpu.hi = a.wrapping_rem(b) as Word;
+                }
+            }),
+            pat!("000000 ????? ????? 00000 00000 011011", divu, OperandType::R, |s, cpu, mem, args| {
+                if let (Some(q), Some(r)) = (args.src1.checked_div(args.src2), args.src1.checked_rem(args.src2)) {
+                    cpu.lo = q;
+                    cpu.hi = r;
+                }
+            }),
+            pat!("000000 ????? ????? ????? 00000 100000", add, OperandType::R, |s, cpu, mem, args| {
+                checked(s, cpu, args.rd, args.src1, args.src2, SWord::checked_add);
+            }),
+            pat!("000000 ????? ????? ????? 00000 100001", addu, OperandType::R, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, args.src1.wrapping_add(args.src2));
+            }),
+            pat!("000000 ????? ????? ????? 00000 100010", sub, OperandType::R, |s, cpu, mem, args| {
+                checked(s, cpu, args.rd, args.src1, args.src2, SWord::checked_sub);
+            }),
+            pat!("000000 ????? ????? ????? 00000 100011", subu, OperandType::R, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, args.src1.wrapping_sub(args.src2));
+            }),
+            pat!("000000 ????? ????? ????? 00000 100100", and, OperandType::R, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, args.src1 & args.src2);
+            }),
+            pat!("000000 ????? ????? ????? 00000 100101", or, OperandType::R, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, args.src1 | args.src2);
+            }),
+            pat!("000000 ????? ????? ????? 00000 100110", xor, OperandType::R, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, args.src1 ^ args.src2);
+            }),
+            pat!("000000 ????? ????? ????? 00000 100111", nor, OperandType::R, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, !(args.src1 | args.src2));
+            }),
+            pat!("000000 ????? ????? ????? 00000 101010", slt, OperandType::R, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, ((args.src1 as SWord) < (args.src2 as SWord)) as Word);
+            }),
+            pat!("000000 ????? ????? ????? 00000 101011", sltu, OperandType::R, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, (args.src1 < args.src2) as Word);
+            }),
+            pat!("000000 ????? ????? ????? ????? 110000", tge, OperandType::R, |s, cpu, mem, args| {
+                trap(s, args.src1 as SWord >= args.src2 as SWord);
+            }),
+            pat!("000000 ????? ????? ????? ????? 110001", tgeu, OperandType::R, |s, cpu, mem, args| {
+                trap(s, args.src1 >= args.src2);
+            }),
+            pat!("000000 ????? ????? ????? ????? 110010", tlt, OperandType::R, |s, cpu, mem, args| {
+                trap(s, (args.src1 as SWord) < (args.src2 as SWord));
+            }),
+            pat!("000000 ????? ????? ????? ????? 110011", tltu, OperandType::R, |s, cpu, mem, args| {
+                trap(s, args.src1 < args.src2);
+            }),
+            pat!("000000 ????? ????? ????? ????? 110100", teq, OperandType::R, |s, cpu, mem, args| {
+                trap(s, args.src1 == args.src2);
+            }),
+            pat!("000000 ????? ????? ????? ????? 110110", tne, OperandType::R, |s, cpu, mem, args| {
+                trap(s, args.src1 != args.src2);
+            }),
+            // REGIMM, the linking forms write $ra whether the branch is taken or not
+            pat!("000001 ????? 00000 ????????????????", bltz, OperandType::I, |s, cpu, mem, args| {
+                branch(s, cpu, (args.src1 as SWord) < 0, args.imm, false);
+            }),
+            pat!("000001 ????? 00001 ????????????????", bgez, OperandType::I, |s, cpu, mem, args| {
+                branch(s, cpu, args.src1 as SWord >= 0, args.imm, false);
+            }),
+            pat!("000001 ????? 00010 ????????????????", bltzl, OperandType::I, |s, cpu, mem, args| {
+                branch(s, cpu, (args.src1 as SWord) < 0, args.imm, true);
+            }),
+            pat!("000001 ????? 00011 ????????????????", bgezl, OperandType::I, |s, cpu, mem, args| {
+                branch(s, cpu, args.src1 as SWord >= 0, args.imm, true);
+            }),
+            pat!("000001 ????? 01000 ????????????????", tgei, OperandType::I, |s, cpu, mem, args| {
+                trap(s, args.src1 as SWord >= args.imm as SWord);
+            }),
+            pat!("000001 ????? 01001 ????????????????", tgeiu, OperandType::I, |s, cpu, mem, args| {
+                trap(s, args.src1 >= args.imm);
+            }),
+            pat!("000001 ????? 01010 ????????????????", tlti, OperandType::I, |s, cpu, mem, args| {
+                trap(s, (args.src1 as SWord) < (args.imm as SWord));
+            }),
+            pat!("000001 ????? 01011 ????????????????", tltiu, OperandType::I, |s, cpu, mem, args| {
+                trap(s, args.src1 < args.imm);
+            }),
+            pat!("000001 ????? 01100 ????????????????", teqi, OperandType::I, |s, cpu, mem, args| {
+                trap(s, args.src1 == args.imm);
+            }),
+            pat!("000001 ????? 01110 ????????????????", tnei, OperandType::I, |s, cpu, mem, args| {
+                trap(s, args.src1 != args.imm);
+            }),
+            pat!("000001 ????? 10000 ????????????????", bltzal, OperandType::I, |s, cpu, mem, args| {
+                cpu.set_reg(31, link(s));
+                branch(s, cpu, (args.src1 as SWord) < 0, args.imm, false);
+            }),
+            pat!("000001 ????? 10001 ????????????????", bgezal, OperandType::I, |s, cpu, mem, args| {
+                cpu.set_reg(31, link(s));
+                branch(s, cpu, args.src1 as SWord >= 0, args.imm, false);
+            }),
+            pat!("000001 ????? 10010 ????????????????", bltzall, OperandType::I, |s, cpu, mem, args| {
+                cpu.set_reg(31, link(s));
+                branch(s, cpu, (args.src1 as SWord) < 0, args.imm, true);
+            }),
+            pat!("000001 ????? 10011 ????????????????", bgezall, OperandType::I, |s, cpu, mem, args| {
+                cpu.set_reg(31, link(s));
+                branch(s, cpu, args.src1 as SWord >= 0, args.imm, true);
+            }),
+            // jumps stay in the 256MB region of the delay slot
+            pat!("000010 ??????????????????????????", j, OperandType::J, |s, cpu, mem, args| {
+                cpu.delay = Some((s.snpc & 0xf000_0000) | args.imm);
+            }),
+            pat!("000011 ??????????????????????????", jal, OperandType::J, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, link(s));
+                cpu.delay = Some((s.snpc & 0xf000_0000) | args.imm);
+            }),
+            pat!("000100 ????? ????? ????????????????", beq, OperandType::I, |s, cpu, mem, args| {
+                branch(s, cpu, args.src1 == args.src2, args.imm, false);
+            }),
+            pat!("000101 ????? ????? ????????????????", bne, OperandType::I, |s, cpu, mem, args| {
+                branch(s, cpu, args.src1 != args.src2, args.imm, false);
+            }),
+            pat!("000110 ????? 00000 ????????????????", blez, OperandType::I, |s, cpu, mem, args| {
+                branch(s, cpu, args.src1 as SWord <= 0, args.imm, false);
+            }),
+            pat!("000111 ????? 00000 ????????????????", bgtz, OperandType::I, |s, cpu, mem, args| {
+                branch(s, cpu, args.src1 as SWord > 0, args.imm, false);
+            }),
+            pat!("010100 ????? ????? ????????????????", beql, OperandType::I, |s, cpu, mem, args| {
+                branch(s, cpu, args.src1 == args.src2, args.imm, true);
+            }),
+            pat!("010101 ????? ????? ????????????????", bnel, OperandType::I, |s, cpu, mem, args| {
+                branch(s, cpu, args.src1 != args.src2, args.imm, true);
+            }),
+            pat!("010110 ????? 00000 ????????????????", blezl, OperandType::I, |s, cpu, mem, args| {
+                branch(s, cpu, args.src1 as SWord <= 0, args.imm, true);
+            }),
+            pat!("010111 ????? 00000 ????????????????", bgtzl, OperandType::I, |s, cpu, mem, args| {
+                branch(s, cpu, args.src1 as SWord > 0, args.imm, true);
+            }),
+            pat!("001000 ????? ????? ????????????????", addi, OperandType::I, |s, cpu, mem, args| {
+                checked(s, cpu, args.rd, args.src1, args.imm, SWord::checked_add);
+            }),
+            pat!("001001 ????? ????? ????????????????", addiu, OperandType::I, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, args.src1.wrapping_add(args.imm));
+            }),
+            pat!("001010 ????? ????? ????????????????", slti, OperandType::I, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, ((args.src1 as SWord) < (args.imm as SWord)) as Word);
+            }),
+            // the immediate is sign-extended, then compared unsigned
+            pat!("001011 ????? ????? ????????????????", sltiu, OperandType::I, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, (args.src1 < args.imm) as Word);
+            }),
+            pat!("001100 ????? ????? ????????????????", andi, OperandType::Z, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, args.src1 & args.imm);
+            }),
+            pat!("001101 ????? ????? ????????????????", ori, OperandType::Z, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, args.src1 | args.imm);
+            }),
+            pat!("001110 ????? ????? ????????????????", xori, OperandType::Z, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, args.src1 ^ args.imm);
+            }),
+            pat!("001111 00000 ????? ????????????????", lui, OperandType::Z, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, args.imm << 16);
+            }),
+            // COP0, the register is `rd << 3 | sel`. Unimplemented ones read as zero and
+            // ignore writes.
+            pat!("010000 00000 ????? ????? 00000000 ???", mfc0, OperandType::R, |s, cpu, mem, args| {
+                let addr = cp0::cp0_addr(args.rd, bits!(s.inst, 2, 0) as usize);
+                let rt = bits!(s.inst, 20, 16) as usize;
+                cpu.set_reg(rt, cpu.csr(addr).unwrap_or(0));
+            }),
+            pat!("010000 00100 ????? ????? 00000000 ???", mtc0, OperandType::R, |s, cpu, mem, args| {
+                let addr = cp0::cp0_addr(args.rd, bits!(s.inst, 2, 0) as usize);
+                cpu.set_csr(addr, args.src2);
+            }),
+            // no delay slot
+            pat!("010000 1 0000000000000000000 011000", eret, OperandType::N, |s, cpu, mem, args| {
+                s.dnpc = cpu.cp0.trap_return();
+                cpu.llbit = false;
+            }),
+            // SPECIAL2
+            pat!("011100 ????? ????? 00000 00000 000000", madd, OperandType::R, |s, cpu, mem, args| {
+                let prod = (args.src1 as SWord as SDWord) * (args.src2 as SWord as SDWord);
+                set_hilo(cpu, hilo(cpu).wrapping_add(prod as DWord));
+            }),
+            pat!("011100 ????? ????? 00000 00000 000001", maddu, OperandType::R, |s, cpu, mem, args| {
+                let prod = (args.src1 as DWord) * (args.src2 as DWord);
+                set_hilo(cpu, hilo(cpu).wrapping_add(prod));
+            }),
+            pat!("011100 ????? ????? ????? 00000 000010", mul, OperandType::R, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, args.src1.wrapping_mul(args.src2));
+            }),
+            pat!("011100 ????? ????? 00000 00000 000100", msub, OperandType::R, |s, cpu, mem, args| {
+                let prod = (args.src1 as SWord as SDWord) * (args.src2 as SWord as SDWord);
+                set_hilo(cpu, hilo(cpu).wrapping_sub(prod as DWord));
+            }),
+            pat!("011100 ????? ????? 00000 00000 000101", msubu, OperandType::R, |s, cpu, mem, args| {
+                let prod = (args.src1 as DWord) * (args.src2 as DWord);
+                set_hilo(cpu, hilo(cpu).wrapping_sub(prod));
+            }),
+            pat!("011100 ????? ????? ????? 00000 100000", clz, OperandType::R, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, args.src1.leading_zeros() as Word);
+            }),
+            pat!("011100 ????? ????? ????? 00000 100001", clo, OperandType::R, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, args.src1.leading_ones() as Word);
+            }),
+            pat!("011100 ???????????????????? 111111", sdbbp, OperandType::N, |s, cpu, mem, args| {
+                s.raise(Exception::Breakpoint);
+            }),
+            pat!("100000 ????? ????? ????????????????", lb, OperandType::I, |s, cpu, mem, args| {
+                let Some(val) = load(s, mem, args.src1.wrapping_add(args.imm), 1) else {
+                    return;
+                };
+                cpu.set_reg(args.rd, sext!(val, 8));
+            }),
+            pat!("100001 ????? ????? ????????????????", lh, OperandType::I, |s, cpu, mem, args| {
+                let Some(val) = load(s, mem, args.src1.wrapping_add(args.imm), 2) else {
+                    return;
+                };
+                cpu.set_reg(args.rd, sext!(val, 16));
+            }),
+            // lwl/lwr and swl/swr access the bytes of the aligned word up to (from) the address,
+            // little-endian: lwl fills rt from the top, lwr from the bottom
+            pat!("100010 ????? ????? ????????????????", lwl, OperandType::I, |s, cpu, mem, args| {
+                let addr = args.src1.wrapping_add(args.imm);
+                let Some(word) = load(s, mem, addr & !3, 4) else {
+                    return;
+                };
+                let shift = (3 - (addr & 3)) * 8;
+                let keep = !(Word::MAX << shift);
+                cpu.set_reg(args.rd, (args.src2 & keep) | (word << shift));
+            }),
+            pat!("100011 ????? ????? ????????????????", lw, OperandType::I, |s, cpu, mem, args| {
+                let Some(val) = load(s, mem, args.src1.wrapping_add(args.imm), 4) else {
+                    return;
+                };
+                cpu.set_reg(args.rd, val);
+            }),
+            pat!("100100 ????? ????? ????????????????", lbu, OperandType::I, |s, cpu, mem, args| {
+                let Some(val) = load(s, mem, args.src1.wrapping_add(args.imm), 1) else {
+                    return;
+                };
+                cpu.set_reg(args.rd, val);
+            }),
+            pat!("100101 ????? ????? ????????????????", lhu, OperandType::I, |s, cpu, mem, args| {
+                let Some(val) = load(s, mem, args.src1.wrapping_add(args.imm), 2) else {
+                    return;
+                };
+                cpu.set_reg(args.rd, val);
+            }),
+            pat!("100110 ????? ????? ????????????????", lwr, OperandType::I, |s, cpu, mem, args| {
+                let addr = args.src1.wrapping_add(args.imm);
+                let Some(word) = load(s, mem, addr & !3, 4) else {
+                    return;
+                };
+                let shift = (addr & 3) * 8;
+                let fill = Word::MAX >> shift;
+                cpu.set_reg(args.rd, (args.src2 & !fill) | (word >> shift));
+            }),
+            pat!("101000 ????? ????? ????????????????", sb, OperandType::I, |s, cpu, mem, args| {
+                store(s, mem, args.src1.wrapping_add(args.imm), 1, args.src2);
+            }),
+            pat!("101001 ????? ????? ????????????????", sh, OperandType::I, |s, cpu, mem, args| {
+                store(s, mem, args.src1.wrapping_add(args.imm), 2, args.src2);
+            }),
+            pat!("101010 ????? ????? ????????????????", swl, OperandType::I, |s, cpu, mem, args| {
+                let addr = args.src1.wrapping_add(args.imm);
+                let Some(word) = load(s, mem, addr & !3, 4) else {
+                    return;
+                };
+                let shift = (3 - (addr & 3)) * 8;
+                let fill = Word::MAX >> shift;
+                store(s, mem, addr & !3, 4, (word & !fill) | (args.src2 >> shift));
+            }),
+            pat!("101011 ????? ????? ????????????????", sw, OperandType::I, |s, cpu, mem, args| {
+                store(s, mem, args.src1.wrapping_add(args.imm), 4, args.src2);
+            }),
+            pat!("101110 ????? ????? ????????????????", swr, OperandType::I, |s, cpu, mem, args| {
+                let addr = args.src1.wrapping_add(args.imm);
+                let Some(word) = load(s, mem, addr & !3, 4) else {
+                    return;
+                };
+                let shift = (addr & 3) * 8;
+                let keep = !(Word::MAX << shift);
+                store(s, mem, addr & !3, 4, (word & keep) | (args.src2 << shift));
+            }),
+            // no caches to maintain or prefetch into
+            pat!("101111 ????? ????? ????????????????", cache, OperandType::N, |s, cpu, mem, args| {}),
+            pat!("110011 ????? ????? ????????????????", pref, OperandType::N, |s, cpu, mem, args| {}),
+            // a single hart only loses the link to eret
+            pat!("110000 ????? ????? ????????????????", ll, OperandType::I, |s, cpu, mem, args| {
+                let Some(val) = load(s, mem, args.src1.wrapping_add(args.imm), 4) else {
+                    return;
+                };
+                cpu.set_reg(args.rd, val);
+                cpu.llbit = true;
+            }),
+            pat!("111000 ????? ????? ????????????????", sc, OperandType::I, |s, cpu, mem, args| {
+                let done = cpu.llbit && store(s, mem, args.src1.wrapping_add(args.imm), 4, args.src2);
+                if s.exception.is_none() {
+                    cpu.set_reg(args.rd, done as Word);
+                }
+            }),
+        };
+        DecodeTable::new(decoders)
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::isa::mips32::cp0::{CAUSE, CAUSE_BD, EPC, STATUS};
+    use crate::isa::{Mips32, ISA};
+    use crate::memory::{MemoryBank, RESET_VECTOR};
+
+    use super::*;
+
+    const PC: Vaddr = RESET_VECTOR as Vaddr;
+    const DATA: Vaddr = PC + 0x1000;
+    const NOP: Word = 0;
+
+    fn r(rs: u32, rt: u32, rd: u32, shamt: u32, funct: u32) -> Word {
+        ((rs << 21) | (rt << 16) | (rd << 11) | (shamt << 6) | funct) as Word
+    }
+    fn i(op: u32, rs: u32, rt: u32, imm: i32) -> Word {
+        ((op << 26) | (rs << 21) | (rt << 16) | (imm as u32 & 0xffff)) as Word
+    }
+
+    fn machine() -> (Mips32, MemoryBank) {
+        (Mips32::new(PC), MemoryBank::new(&[]))
+    }
+
+    /// place `inst` at the current pc and execute it
+    fn step(cpu: &mut Mips32, mem: &mut MemoryBank, inst: Word) -> Option<Exception> {
+        let pc = cpu.pc();
+        mem.paddr_write(pc, 4, inst);
+        let mut executer = Executer::new();
+        executer.set_pc(pc);
+        executer.set_snpc(pc);
+        executer.exec_once(cpu, mem);
+        cpu.set_pc(executer.dnpc());
+        executer.take_exception()
+    }
+
+    #[test]
+    fn default_img_test() {
+        let (mut cpu, mut mem) = machine();
+        let img = Mips32::default_img();
+        for (n, w) in img.chunks(4).enumerate() {
+            let w = u32::from_le_bytes(w.try_into().unwrap());
+            mem.paddr_write(PC + 4 * n as Vaddr, 4, w as Word);
+        }
+        let mut executer = Executer::new();
+        for _ in 0..4 {
+            executer.set_pc(cpu.pc());
+            executer.set_snpc(cpu.pc());
+            executer.exec_once(&mut cpu, &mut mem);
+            cpu.set_pc(executer.dnpc());
+        }
+        assert_eq!(executer.take_exception(), Some(Exception::Breakpoint));
+        assert_eq!(cpu.reg(4), PC);
+        assert_eq!(cpu.halt_ret(), 0);
+    }
+
+    #[test]
+    fn overflow_test() {
+        let (mut cpu, mut mem) = machine();
+        cpu.set_reg(1, 0x7fff_ffff);
+        cpu.set_reg(3, 5);
+        // add traps and leaves rd alone, addu wraps
+        assert_eq!(
+            step(&mut cpu, &mut mem, r(1, 1, 3, 0, 0x20)),
+            Some(Exception::Overflow)
+        );
+        assert_eq!(cpu.reg(3), 5);
+        step(&mut cpu, &mut mem, r(1, 1, 3, 0, 0x21));
+        assert_eq!(cpu.reg(3), 0xffff_fffe);
+        assert_eq!(
+            step(&mut cpu, &mut mem, i(0x08, 1, 3, 1)),
+            Some(Exception::Overflow)
+        );
+        // andi zero-extends, sltiu sign-extends
+        step(&mut cpu, &mut mem, i(0x0c, 1, 3, -1));
+        assert_eq!(cpu.reg(3), 0xffff);
+        step(&mut cpu, &mut mem, i(0x0b, 1, 3, -1));
+        assert_eq!(cpu.reg(3), 1);
+    }
+
+    #[test]
+    fn hi_lo_test() {
+        let (mut cpu, mut mem) = machine();
+        cpu.set_reg(1, Word::MAX);
+        cpu.set_reg(2, 2);
+        step(&mut cpu, &mut mem, r(1, 2, 0, 0, 0x18));
+        assert_eq!((cpu.hi(), cpu.lo()), (Word::MAX, 0xffff_fffe));
+        step(&mut cpu, &mut mem, r(1, 2, 0, 0, 0x19));
+        assert_eq!((cpu.hi(), cpu.lo()), (1, 0xffff_fffe));
+        // madd accumulates -2 onto HI:LO
+        step(
+            &mut cpu,
+            &mut mem,
+            (0x1c << 26) as Word | r(1, 2, 0, 0, 0x00),
+        );
+        assert_eq!((cpu.hi(), cpu.lo()), (1, 0xffff_fffc));
+        cpu.set_reg(1, -7i32 as Word);
+        step(&mut cpu, &mut mem, r(1, 2, 0, 0, 0x1a));
+        assert_eq!((cpu.hi(), cpu.lo()), (-1i32 as Word, -3i32 as Word));
+        // a zero divisor leaves them be
+        step(&mut cpu, &mut mem, r(1, 0, 0, 0, 0x1a));
+        assert_eq!((cpu.hi(), cpu.lo()), (-1i32 as Word, -3i32 as Word));
+        step(&mut cpu, &mut mem, r(0, 0, 3, 0, 0x12));
+        assert_eq!(cpu.reg(3), -3i32 as Word);
+    }
+
+    #[test]
+    fn unaligned_test() {
+        let (mut cpu, mut mem) = machine();
+        mem.paddr_write(DATA, 4, 0x4433_2211);
+        mem.paddr_write(DATA + 4, 4, 0x8877_6655);
+        cpu.set_reg(1, DATA);
+        // lwr 1(a), lwl 4(a) loads the word at DATA + 1
+        step(&mut cpu, &mut mem, i(0x26, 1, 2, 1));
+        assert_eq!(cpu.reg(2), 0x0044_3322);
+        step(&mut cpu, &mut mem, i(0x22, 1, 2, 4));
+        assert_eq!(cpu.reg(2), 0x5544_3322);
+        // swr 1(a), swl 4(a) stores it back shifted
+        cpu.set_reg(2, 0xaabb_ccdd);
+        step(&mut cpu, &mut mem, i(0x2e, 1, 2, 1));
+        step(&mut cpu, &mut mem, i(0x2a, 1, 2, 4));
+        assert_eq!(mem.paddr_read(DATA, 4), 0xbbcc_dd11);
+        assert_eq!(mem.paddr_read(DATA + 4, 4), 0x8877_66aa);
+        assert_eq!(
+            step(&mut cpu, &mut mem, i(0x23, 1, 2, 2)),
+            Some(Exception::AddressErrorLoad(DATA + 2))
+        );
+    }
+
+    #[test]
+    fn delay_slot_test() {
+        let (mut cpu, mut mem) = machine();
+        // beq $0,$0,+4 words, the slot still runs
+        step(&mut cpu, &mut mem, i(0x04, 0, 0, 4));
+        assert_eq!(cpu.pc(), PC + 4);
+        step(&mut cpu, &mut mem, i(0x09, 0, 1, 1));
+        assert_eq!(cpu.reg(1), 1);
+        assert_eq!(cpu.pc(), PC + 20);
+        // jal links past the slot
+        step(&mut cpu, &mut mem, (0x03 << 26) as Word | (0x100 >> 2));
+        step(&mut cpu, &mut mem, NOP);
+        assert_eq!(cpu.reg(31), PC + 28);
+        assert_eq!(cpu.pc(), PC + 0x100);
+        // beql not taken skips its slot
+        cpu.set_reg(1, 1);
+        step(&mut cpu, &mut mem, i(0x14, 0, 1, 4));
+        assert_eq!(cpu.pc(), PC + 0x108);
+    }
+
+    #[test]
+    fn delay_slot_exception_test() {
+        let (mut cpu, mut mem) = machine();
+        cpu.set_reg(1, 0x7fff_ffff);
+        step(&mut cpu, &mut mem, i(0x04, 0, 0, 8));
+        let pc = cpu.pc();
+        let e = step(&mut cpu, &mut mem, i(0x08, 1, 2, 1)).unwrap();
+        let handler = cpu.raise_exception(&mut mem, pc, e);
+        assert_eq!(handler, 0x8000_0180);
+        assert_eq!(cpu.csr(EPC), Some(PC));
+        assert_eq!(cpu.csr(CAUSE), Some(CAUSE_BD | (12 << 2)));
+        // eret goes back to the branch and leaves the exception level
+        cpu.set_pc(handler);
+        step(&mut cpu, &mut mem, 0x4200_0018);
+        assert_eq!(cpu.pc(), PC);
+        assert_eq!(cpu.csr(STATUS), Some(0));
+    }
+
+    #[test]
+    fn cp0_test() {
+        let (mut cpu, mut mem) = machine();
+        cpu.set_reg(1, 0x0040_0001);
+        step(&mut cpu, &mut mem, 0x4081_6000);
+        assert_eq!(cpu.csr(STATUS), Some(0x0040_0001));
+        step(&mut cpu, &mut mem, 0x4002_7800);
+        assert_eq!(cpu.reg(2), 0x0001_8000);
+        // unimplemented registers read as zero
+        step(&mut cpu, &mut mem, 0x4002_4800);
+        assert_eq!(cpu.reg(2), 0);
+    }
+
+    #[test]
+    fn fetch_test() {
+        let (mut cpu, mut mem) = machine();
+        cpu.set_pc(PC + 2);
+        assert_eq!(
+            step(&mut cpu, &mut mem, NOP),
+            Some(Exception::FetchAddressError(PC + 2))
+        );
+        let (mut cpu, mut mem) = machine();
+        assert_eq!(
+            step(&mut cpu, &mut mem, 0xfc00_0000),
+            Some(Exception::IllegalInstruction(0xfc00_0000))
+        );
+    }
+}
@@ -0,0 +1,84 @@
+use crate::{
+    common::Word,
+    isa::{Mips32, ISA},
+};
+
+pub trait OperandHelper: Send + Sync {
+    fn decode_operand(&self, inst: Word, isa: &Mips32) -> Args;
+}
+pub enum OperandType {
+    /// rd = rs op rt, `imm` is the shift amount
+    R,
+    /// rt = rs op sext(imm16), branches and stores read rt as src2
+    I,
+    /// rt = rs op zext(imm16), the logical immediates
+    Z,
+    J,
+    N,
+}
+
+pub struct Args {
+    /// rd for R, rt for I and Z
+    pub rd: usize,
+    pub src1: Word,
+    pub src2: Word,
+    pub imm: Word,
+}
+
+impl Args {
+    pub fn new(rd: usize, src1: Word, src2: Word, imm: Word) -> Self {
+        Self {
+            rd,
+            src1,
+            src2,
+            imm,
+        }
+    }
+}
+
+pub struct ROperand;
+impl OperandHelper for ROperand {
+    fn decode_operand(&self, inst: Word, isa: &Mips32) -> Args {
+        let rs = bits!(inst, 25, 21);
+        let rt = bits!(inst, 20, 16);
+        let rd = bits!(inst, 15, 11);
+        let src1 = isa.reg(rs as usize);
+        let src2 = isa.reg(rt as usize);
+        let imm = bits!(inst, 10, 6);
+        Args::new(rd as usize, src1, src2, imm)
+    }
+}
+pub struct IOperand;
+impl OperandHelper for IOperand {
+    fn decode_operand(&self, inst: Word, isa: &Mips32) -> Args {
+        let rs = bits!(inst, 25, 21);
+        let rt = bits!(inst, 20, 16);
+        let src1 = isa.reg(rs as usize);
+        let src2 = isa.reg(rt as usize);
+        let imm = sext!(bits!(inst, 15, 0), 16);
+        Args::new(rt as usize, src1, src2, imm)
+    }
+}
+pub struct ZOperand;
+impl OperandHelper for ZOperand {
+    fn decode_operand(&self, inst: Word, isa: &Mips32) -> Args {
+        let rs = bits!(inst, 25, 21);
+        let rt = bits!(inst, 20, 16);
+        let src1 = isa.reg(rs as usize);
+        let imm = bits!(inst, 15, 0);
+        Args::new(rt as usize, src1, 0, imm)
+    }
+}
+pub struct JOperand;
+impl OperandHelper for JOperand {
+    fn decode_operand(&self, inst: Word, isa: &Mips32) -> Args {
+        let imm = bits!(inst, 25, 0) << 2;
+        Args::new(31, 0, 0, imm)
+    }
+}
+pub struct NOperand;
+impl OperandHelper for NOperand {
+    fn decode_operand(&self, inst: Word, isa: &Mips32) -> Args {
+        Args::new(0, 0, 0, 0)
+    }
+}
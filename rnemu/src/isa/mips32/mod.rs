@@ -0,0 +1,149 @@
+use crate::{
+    common::{Vaddr, Word},
+    memory::MemoryBank,
+};
+
+use super::{TlbConfig, ISA};
+
+mod cp0;
+mod executer;
+use cp0::Cp0;
+pub use executer::{Exception, Executer};
+pub const GUEST_ISA: &str = "mips32";
+
+/// MIPS32 R1 hart in kernel mode. There is no TLB, kseg0 and kuseg addresses are physical.
+#[derive(Debug, Default)]
+pub struct Mips32 {
+    gpr: [Word; 32],
+    pc: Vaddr,
+    hi: Word,
+    lo: Word,
+    cp0: Cp0,
+    /// target of the branch whose delay slot executes next
+    delay: Option<Vaddr>,
+    /// the instruction being executed sits in a delay slot
+    in_delay: bool,
+    /// set by LL, cleared by ERET
+    llbit: bool,
+}
+
+impl Mips32 {
+    pub fn new(pc: Vaddr) -> Self {
+        Self {
+            pc,
+            ..Self::default()
+        }
+    }
+
+    pub fn hi(&self) -> Word {
+        self.hi
+    }
+
+    pub fn lo(&self) -> Word {
+        self.lo
+    }
+}
+
+impl ISA for Mips32 {
+    type Executer = executer::Executer;
+    type Exception = executer::Exception;
+    const NAME: &'static str = GUEST_ISA;
+    const LOGO: &'static str = ISA_LOGO;
+    const NR_GPR: usize = 32;
+    const ELF_MACHINE: u16 = 8;
+
+    fn init(pc: Vaddr, tlb: TlbConfig) -> Self {
+        Self::new(pc)
+    }
+
+    /// take exception `e` raised by the instruction at `pc`, return the handler address
+    fn raise_exception(&mut self, mem: &mut MemoryBank, pc: Vaddr, e: Exception) -> Vaddr {
+        let in_delay = std::mem::take(&mut self.in_delay);
+        // the branch of an excepting delay slot is not followed
+        self.delay = None;
+        self.llbit = false;
+        self.cp0.trap_enter(pc, in_delay, e.code(), e.badvaddr())
+    }
+
+    /// $v0 holds the exit code of nemu_trap
+    fn halt_ret(&self) -> Word {
+        self.gpr[2]
+    }
+
+    fn set_pc(&mut self, next: Vaddr) {
+        self.pc = next;
+    }
+
+    fn pc(&self) -> Vaddr {
+        self.pc
+    }
+
+    fn reg(&self, idx: usize) -> Word {
+        self.gpr[idx]
+    }
+
+    fn set_reg(&mut self, idx: usize, val: Word) {
+        self.gpr[idx] = val;
+    }
+
+    /// CP0 register `reg << 3 | sel`
+    fn csr(&self, addr: usize) -> Option<Word> {
+        self.cp0.read(addr)
+    }
+
+    fn set_csr(&mut self, addr: usize, val: Word) -> bool {
+        self.cp0.write(addr, val)
+    }
+
+    fn default_img() -> &'static [u8] {
+        let ptr = IMG.as_ptr() as *const u8;
+        let len = IMG.len() * std::mem::size_of::<u32>();
+        unsafe { std::slice::from_raw_parts(ptr, len) }
+    }
+
+    fn executer() -> Self::Executer {
+        executer::Executer::new()
+    }
+}
+
+pub const ISA_LOGO: &str = r"            _              _________
+  _ __ ___ (_)_ __  ___   |___ /___ \
+ | '_ ` _ \| | '_ \/ __|    |_ \ __) |
+ | | | | | | | |_) \__ \   ___) / __/
+ |_| |_| |_|_| .__/|___/  |____/_____|
+             |_|
+";
+
+const IMG: [u32; 5] = [
+    0x3c048000, // lui   a0,0x8000
+    0xac800010, // sw    zero,16(a0)
+    0x8c820010, // lw    v0,16(a0)
+    0x7000003f, // sdbbp (used as nemu_trap)
+    0xdeadbeef, // some data
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn raise_exception_test() {
+        let mut cpu = Mips32::new(0x8000_0000);
+        let mut mem = MemoryBank::new(&[]);
+        cpu.delay = Some(0x8000_0100);
+        cpu.in_delay = true;
+        let handler = cpu.raise_exception(&mut mem, 0x8000_0008, Exception::Overflow);
+        assert_eq!(handler, 0x8000_0180);
+        assert_eq!(cpu.csr(cp0::EPC), Some(0x8000_0004));
+        assert_eq!(cpu.csr(cp0::CAUSE), Some(cp0::CAUSE_BD | (12 << 2)));
+        assert_eq!(cpu.delay, None);
+        let handler = cpu.raise_exception(
+            &mut mem,
+            0x8000_0180,
+            Exception::AddressErrorLoad(0x8000_0001),
+        );
+        assert_eq!(handler, 0x8000_0180);
+        assert_eq!(cpu.csr(cp0::BADVADDR), Some(0x8000_0001));
+        assert_eq!(cpu.csr(cp0::EPC), Some(0x8000_0004));
+    }
+}
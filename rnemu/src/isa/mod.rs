@@ -1,12 +1,138 @@
-mod riscv32;
-mod x86;
-// pub use riscv32::GUEST_ISA;
-// pub use riscv32::ISA_LOGO;
+// any other guest wins when the default riscv32 is still on next to it
+cfg_if::cfg_if! {
+    if #[cfg(feature = "riscv64")] {
+        mod riscv64;
+        pub use riscv64::Riscv64;
+        /// the guest the emulator is built for
+        pub type Guest = Riscv64;
+    } else if #[cfg(feature = "x86")] {
+        mod x86;
+        pub use x86::X86;
+        pub type Guest = X86;
+    } else if #[cfg(feature = "mips32")] {
+        mod mips32;
+        pub use mips32::Mips32;
+        pub type Guest = Mips32;
+    } else if #[cfg(feature = "loongarch32r")] {
+        mod loongarch32r;
+        pub use loongarch32r::Loongarch32r;
+        pub type Guest = Loongarch32r;
+    } else if #[cfg(feature = "riscv32")] {
+        mod riscv32;
+        pub use riscv32::Riscv32;
+        pub type Guest = Riscv32;
+    }
+}
+
+pub mod decode;
+
+use std::fmt;
+
+use colored::Colorize;
+
+use crate::{
+    common::{Paddr, Vaddr, Word},
+    memory::MemoryBank,
+};
+
+/// Geometry of a software TLB, `ways` is 1 for a direct-mapped one. Guests without
+/// translation ignore it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TlbConfig {
+    pub sets: usize,
+    pub ways: usize,
+}
+
+impl Default for TlbConfig {
+    fn default() -> Self {
+        Self { sets: 64, ways: 1 }
+    }
+}
+
+/// Fetches, decodes and executes the instructions of guest `T`. The core runs a block at a
+/// time, one instruction unless the executer caches decoded code.
+pub trait Execute<T: ISA> {
+    /// address of the last executed instruction
+    fn pc(&self) -> Vaddr;
+    /// set the address of the instruction to execute
+    fn set_pc(&mut self, pc: Vaddr);
+    /// set where fetching starts, the same as pc
+    fn set_snpc(&mut self, snpc: Vaddr);
+    /// execute the instruction at pc, an exception it raises is left for `take_exception`
+    fn exec_once(&mut self, cpu: &mut T, mem: &mut MemoryBank);
+    /// address of the next instruction
+    fn dnpc(&self) -> Vaddr;
+    /// take the exception raised by the last executed instruction
+    fn take_exception(&mut self) -> Option<T::Exception>;
+    /// take the call or return made by the last executed instruction, guests that do not tell
+    /// them from other jumps have none
+    fn take_jump(&mut self) -> Option<Jump> {
+        None
+    }
+
+    /// execute at most `limit` instructions from the pc of `cpu` and move it past them, stop
+    /// early where the straight line ends, an instruction raises an exception or makes an
+    /// access out of memory. Return how many were executed, the one that raised counted.
+    fn exec_block(&mut self, cpu: &mut T, mem: &mut MemoryBank, limit: u64) -> u64 {
+        let pc = cpu.pc();
+        self.set_pc(pc);
+        self.set_snpc(pc);
+        self.exec_once(cpu, mem);
+        cpu.set_pc(self.dnpc());
+        1
+    }
+}
+
+/// A jump that follows the calling convention of the guest, for the function trace.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Jump {
+    /// a call to the address
+    Call(Vaddr),
+    /// a return to the address
+    Return(Vaddr),
+}
 
-use crate::common::{Vaddr, Word};
+/// What the core needs to know about an exception when it does not deliver it to the guest.
+pub trait GuestException: Copy + fmt::Debug {
+    /// whether the instruction could not even be fetched
+    fn is_fetch(&self) -> bool;
+    /// whether it is the breakpoint instruction used as nemu_trap
+    fn is_breakpoint(&self) -> bool;
+    /// whether the instruction is not one the guest knows
+    fn is_illegal(&self) -> bool;
+    /// whether physical memory was not there or its region did not allow the access
+    fn is_access_fault(&self) -> bool;
+}
 
-pub trait ISA {
-    type Executer;
+#[allow(clippy::upper_case_acronyms)]
+pub trait ISA: Sized {
+    type Executer: Execute<Self>;
+    type Exception: GuestException;
+    /// name of the guest in the welcome message
+    const NAME: &'static str;
+    /// printed when an instruction is not implemented
+    const LOGO: &'static str;
+    /// number of general purpose registers, `reg` takes 0 up to it
+    const NR_GPR: usize;
+    /// e_machine of the ELF images it runs
+    const ELF_MACHINE: u16;
+    /// the hart out of reset at `pc`, guests without translation ignore `tlb`
+    fn init(pc: Vaddr, tlb: TlbConfig) -> Self;
+    /// take exception `e` raised by the instruction at `pc`, return the handler address
+    fn raise_exception(&mut self, mem: &mut MemoryBank, pc: Vaddr, e: Self::Exception) -> Vaddr;
+    /// exit code of nemu_trap
+    fn halt_ret(&self) -> Word;
+    /// log the counters of the hart next to the instruction count
+    fn statistic(&self) {}
+    /// turn the trace of the traps taken on or off, guests without one ignore it
+    #[cfg(feature = "trace")]
+    fn set_etrace(&mut self, on: bool) {}
+    /// physical address the instruction at `pc` is fetched from, looked up without filling a
+    /// TLB or raising anything, `None` if the fetch would fault. Guests without translation
+    /// fetch from `pc` itself
+    fn inst_paddr(&self, mem: &MemoryBank, pc: Vaddr) -> Option<Paddr> {
+        Some(pc as Paddr)
+    }
     /// set pc to `next``
     fn set_pc(&mut self, next: Vaddr);
     /// get current PC
@@ -15,14 +141,192 @@ pub trait ISA {
     fn reg(&self, idx: usize) -> Word;
     /// set register[idx]
     fn set_reg(&mut self, idx: usize, val: Word);
+    /// get csr[addr], `None` if it does not exist
+    fn csr(&self, addr: usize) -> Option<Word>;
+    /// set csr[addr], `false` if it does not exist or is read-only
+    fn set_csr(&mut self, addr: usize, val: Word) -> bool;
     // get default test img
     fn default_img() -> &'static [u8];
     fn executer() -> Self::Executer;
+    /// the instruction at `pc` whose bytes are the low ones of `inst` as assembly, with its
+    /// length in bytes, `None` if it is unknown or the guest has no disassembler
+    fn disassemble(inst: Word, pc: Vaddr) -> Option<(usize, String)> {
+        None
+    }
+    /// name of register[idx] in traces
+    fn reg_name(idx: usize) -> String {
+        format!("r{}", idx)
+    }
+
+    /// report the invalid instruction at `pc`, by default as the two 32-bit words there, 0
+    /// past the end of memory
+    // instructions are 32-bit whatever the width of Word
+    #[allow(clippy::unnecessary_cast)]
+    fn invalid(mem: &MemoryBank, this_pc: Vaddr) {
+        let word = |pc: Vaddr| match mem.in_pmem(pc as Paddr, 4) {
+            true => mem.peek(pc as Paddr, 4) as u32,
+            false => 0,
+        };
+        let tmp: [u32; 2] = [word(this_pc), word(this_pc.wrapping_add(4))];
+
+        let ptr = tmp.as_ptr() as *const u8;
+        let len = tmp.len() * std::mem::size_of::<u32>();
+        let p = unsafe { std::slice::from_raw_parts(ptr, len) };
+        println!("invalid opcode(PC = 0x{:x}):", this_pc);
+        println!(
+            "\t{:02x} {:02x} {:02x} {:02x} {:02x} {:02x} {:02x} {:02x} ...",
+            p[0], p[1], p[2], p[3], p[4], p[5], p[6], p[7]
+        );
+        println!("\t{:08x} {:08x} ...", tmp[0], tmp[1]);
+        let known = Self::disassemble(tmp[0] as Word, this_pc);
+        if let Some((_, text)) = &known {
+            println!("\t{}", text);
+        }
+        println!("There are two cases which will trigger this unexpected exception:");
+        println!(
+            "1. The instruction at PC =  0x{:x}  is not implemented.",
+            this_pc
+        );
+        println!("2. Something is implemented incorrectly.");
+        match known {
+            Some(_) => println!("The decoder knows this instruction, so it is the second case.\n"),
+            None => println!(
+                "Find this PC(0x{:x}) in the disassembling result to distinguish which case it is.\n",
+                this_pc
+            ),
+        }
+        let x = format!(
+            "If it is the first case, see\n{}\n\
+    for more details.\n\nIf it is the second case, remember:\n\
+    * The machine is always right!\n\
+    * Every line of untested code is always wrong!",
+            Self::LOGO
+        );
+
+        println!("{}", x.red().bold());
+    }
 }
 
-struct Instruction {
-    mask: u32,
-    data: u32,
-    name: &'static str,
-    operation: fn(cpu: &mut RvCpu, word: u32, address: u64) -> Result<(), i32>,
+/// The instruction at `pc` whose bytes are the low ones of `inst` as a line of a listing, with
+/// its length in bytes. What the guest can not disassemble shows as a 4-byte word.
+pub fn disas_line<T: ISA>(pc: Vaddr, inst: Word) -> (String, usize) {
+    let (len, text) = T::disassemble(inst, pc).unwrap_or((4, format!(".word\t0x{:x}", inst)));
+    let raw = inst & (Word::MAX >> (Word::BITS as usize - len * 8));
+    let line = format!("{:8x}:\t{:0width$x}\t{}", pc, raw, text, width = len * 2);
+    (line, len)
+}
+
+/// The suite every guest has to pass. Only the guest the emulator is built for is compiled,
+/// run `cargo test` once per guest feature to cover them all.
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::RESET_VECTOR;
+
+    const PC: Vaddr = RESET_VECTOR as Vaddr;
+
+    /// run on `mem` from `pc` until an exception or `limit` instructions, the way the core does
+    fn run<T: ISA>(
+        cpu: &mut T,
+        mem: &mut MemoryBank,
+        executer: &mut T::Executer,
+        limit: usize,
+    ) -> Option<(Vaddr, T::Exception)> {
+        for _ in 0..limit {
+            let pc = cpu.pc();
+            executer.set_pc(pc);
+            executer.set_snpc(pc);
+            executer.exec_once(cpu, mem);
+            cpu.set_pc(executer.dnpc());
+            if let Some(e) = executer.take_exception() {
+                return Some((pc, e));
+            }
+        }
+        None
+    }
+
+    /// the default image stops at nemu_trap inside itself with a zero exit code, whether the
+    /// executer is fresh or has run before
+    fn default_img<T: ISA>() {
+        let img = T::default_img();
+        assert!(!img.is_empty());
+        let mut executer = T::executer();
+        for _ in 0..2 {
+            let mut mem = MemoryBank::new(img);
+            let mut cpu = T::init(PC, TlbConfig::default());
+            assert_eq!(cpu.pc(), PC);
+            let (pc, e) = run(&mut cpu, &mut mem, &mut executer, 100).expect("no nemu_trap");
+            assert!(e.is_breakpoint(), "{:?}", e);
+            assert!(!e.is_fetch() && !e.is_illegal());
+            assert!((PC..PC + img.len() as Vaddr).contains(&pc));
+            assert_eq!(cpu.halt_ret(), 0);
+        }
+    }
+
+    /// running the default image a block at a time stops at the same nemu_trap after the
+    /// same number of instructions as one at a time
+    fn blocks<T: ISA>() {
+        let mut mem = MemoryBank::new(T::default_img());
+        let mut cpu = T::init(PC, TlbConfig::default());
+        let mut executer = T::executer();
+        let mut steps = 0;
+        let expected = loop {
+            steps += 1;
+            if let Some((pc, _)) = run(&mut cpu, &mut mem, &mut executer, 1) {
+                break pc;
+            }
+            assert!(steps < 100, "no nemu_trap");
+        };
+
+        let mut mem = MemoryBank::new(T::default_img());
+        let mut cpu = T::init(PC, TlbConfig::default());
+        let mut executer = T::executer();
+        let mut ran = 0;
+        let e = loop {
+            ran += executer.exec_block(&mut cpu, &mut mem, u64::MAX);
+            if let Some(e) = executer.take_exception() {
+                break e;
+            }
+            assert!(ran < 100, "no nemu_trap");
+        };
+        assert!(e.is_breakpoint(), "{:?}", e);
+        assert_eq!(executer.pc(), expected);
+        assert_eq!(ran, steps);
+        assert_eq!(cpu.halt_ret(), 0);
+    }
+
+    /// pc and the general purpose registers read back what was written
+    fn registers<T: ISA>() {
+        let mut cpu = T::init(PC, TlbConfig::default());
+        cpu.set_pc(PC + 0x100);
+        assert_eq!(cpu.pc(), PC + 0x100);
+        cpu.set_reg(1, 0x1234_5678);
+        assert_eq!(cpu.reg(1), 0x1234_5678);
+        assert_eq!(cpu.csr(usize::MAX), None);
+        assert!(!cpu.set_csr(usize::MAX, 0));
+    }
+
+    /// running off physical memory is a fetch exception, nothing executes
+    fn fetch<T: ISA>() {
+        let mut mem = MemoryBank::new(&[]);
+        let mut cpu = T::init(0, TlbConfig::default());
+        let (pc, e) = run(&mut cpu, &mut mem, &mut T::executer(), 1).expect("no exception");
+        assert_eq!(pc, 0);
+        assert!(e.is_fetch(), "{:?}", e);
+        assert!(!e.is_breakpoint() && !e.is_illegal());
+    }
+
+    fn conformance<T: ISA>() {
+        assert!(!T::NAME.is_empty());
+        assert!(!T::LOGO.is_empty());
+        default_img::<T>();
+        blocks::<T>();
+        registers::<T>();
+        fetch::<T>();
+    }
+
+    #[test]
+    fn guest_conformance_test() {
+        conformance::<Guest>();
+    }
 }
@@ -0,0 +1,567 @@
+use super::pmp::{self, Pmp};
+use crate::common::Word;
+
+pub const SSTATUS: usize = 0x100;
+pub const SIE: usize = 0x104;
+pub const STVEC: usize = 0x105;
+pub const SSCRATCH: usize = 0x140;
+pub const SEPC: usize = 0x141;
+pub const SCAUSE: usize = 0x142;
+pub const STVAL: usize = 0x143;
+pub const SIP: usize = 0x144;
+pub const SATP: usize = 0x180;
+pub const MVENDORID: usize = 0xf11;
+pub const MARCHID: usize = 0xf12;
+pub const MIMPID: usize = 0xf13;
+pub const MHARTID: usize = 0xf14;
+pub const MSTATUS: usize = 0x300;
+pub const MISA: usize = 0x301;
+pub const MEDELEG: usize = 0x302;
+pub const MIDELEG: usize = 0x303;
+pub const MIE: usize = 0x304;
+pub const MTVEC: usize = 0x305;
+pub const MSCRATCH: usize = 0x340;
+pub const MEPC: usize = 0x341;
+pub const MCAUSE: usize = 0x342;
+pub const MTVAL: usize = 0x343;
+pub const MIP: usize = 0x344;
+pub const PMPCFG0: usize = 0x3a0;
+pub const PMPADDR0: usize = 0x3b0;
+
+pub const MSTATUS_SIE: Word = 1 << 1;
+pub const MSTATUS_MIE: Word = 1 << 3;
+pub const MSTATUS_SPIE: Word = 1 << 5;
+pub const MSTATUS_MPIE: Word = 1 << 7;
+pub const MSTATUS_SPP: Word = 1 << 8;
+pub const MSTATUS_MPP: Word = 0b11 << 11;
+pub const MSTATUS_MPRV: Word = 1 << 17;
+pub const MSTATUS_SUM: Word = 1 << 18;
+pub const MSTATUS_MXR: Word = 1 << 19;
+pub const MSTATUS_TVM: Word = 1 << 20;
+pub const MSTATUS_TW: Word = 1 << 21;
+pub const MSTATUS_TSR: Word = 1 << 22;
+
+/// the mstatus fields visible through sstatus
+const SSTATUS_MASK: Word = MSTATUS_SIE | MSTATUS_SPIE | MSTATUS_SPP | MSTATUS_SUM | MSTATUS_MXR;
+
+/// supervisor software, timer and external interrupt bits of mie/mip
+const SI_MASK: Word = (1 << 1) | (1 << 5) | (1 << 9);
+/// machine software, timer and external interrupt bits of mie/mip
+const MI_MASK: Word = (1 << 3) | (1 << 7) | (1 << 11);
+/// the supervisor software interrupt, the only pending bit S-mode may write
+const SSIP: Word = 1 << 1;
+
+/// exceptions that can be delegated, all but ecall from M and the reserved codes 10 and 14
+const MEDELEG_MASK: Word = 0xb3ff;
+
+/// MXL = 32, with the A, C, I and M extensions and S/U modes
+const MISA_VALUE: Word =
+    (1 << 30) | (1 << 0) | (1 << 2) | (1 << 8) | (1 << 12) | (1 << 18) | (1 << 20);
+
+pub const CSR_NAMES: &[(usize, &str)] = &[
+    (SSTATUS, "sstatus"),
+    (SIE, "sie"),
+    (STVEC, "stvec"),
+    (SSCRATCH, "sscratch"),
+    (SEPC, "sepc"),
+    (SCAUSE, "scause"),
+    (STVAL, "stval"),
+    (SIP, "sip"),
+    (SATP, "satp"),
+    (MVENDORID, "mvendorid"),
+    (MARCHID, "marchid"),
+    (MIMPID, "mimpid"),
+    (MHARTID, "mhartid"),
+    (MSTATUS, "mstatus"),
+    (MISA, "misa"),
+    (MEDELEG, "medeleg"),
+    (MIDELEG, "mideleg"),
+    (MIE, "mie"),
+    (MTVEC, "mtvec"),
+    (MSCRATCH, "mscratch"),
+    (MEPC, "mepc"),
+    (MCAUSE, "mcause"),
+    (MTVAL, "mtval"),
+    (MIP, "mip"),
+];
+
+const PMPCFG_NAMES: [&str; pmp::ENTRIES / 4] = ["pmpcfg0", "pmpcfg1", "pmpcfg2", "pmpcfg3"];
+const PMPADDR_NAMES: [&str; pmp::ENTRIES] = [
+    "pmpaddr0",
+    "pmpaddr1",
+    "pmpaddr2",
+    "pmpaddr3",
+    "pmpaddr4",
+    "pmpaddr5",
+    "pmpaddr6",
+    "pmpaddr7",
+    "pmpaddr8",
+    "pmpaddr9",
+    "pmpaddr10",
+    "pmpaddr11",
+    "pmpaddr12",
+    "pmpaddr13",
+    "pmpaddr14",
+    "pmpaddr15",
+];
+
+/// index of `addr` in the CSR array starting at `base` with `len` elements
+fn index(addr: usize, base: usize, len: usize) -> Option<usize> {
+    addr.checked_sub(base).filter(|&n| n < len)
+}
+
+/// whether `addr` is one of the PMP configuration or address registers
+pub fn is_pmp(addr: usize) -> bool {
+    index(addr, PMPCFG0, pmp::ENTRIES / 4).is_some()
+        || index(addr, PMPADDR0, pmp::ENTRIES).is_some()
+}
+
+/// name of the CSR at `addr`
+pub fn csr_name(addr: usize) -> Option<&'static str> {
+    if let Some(n) = index(addr, PMPCFG0, pmp::ENTRIES / 4) {
+        return Some(PMPCFG_NAMES[n]);
+    }
+    if let Some(n) = index(addr, PMPADDR0, pmp::ENTRIES) {
+        return Some(PMPADDR_NAMES[n]);
+    }
+    CSR_NAMES.iter().find(|(a, _)| *a == addr).map(|(_, n)| *n)
+}
+
+/// Privilege level of the hart, encoded as in mstatus.MPP.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Privilege {
+    User = 0,
+    Supervisor = 1,
+    /// harts come out of reset in M-mode
+    #[default]
+    Machine = 3,
+}
+
+impl Privilege {
+    /// decode a MPP/SPP field, `None` for the reserved encoding 2
+    fn from_bits(bits: Word) -> Option<Self> {
+        match bits {
+            0 => Some(Privilege::User),
+            1 => Some(Privilege::Supervisor),
+            3 => Some(Privilege::Machine),
+            _ => None,
+        }
+    }
+}
+
+/// set or clear `mask` in `val`
+fn with(val: Word, mask: Word, on: bool) -> Word {
+    if on {
+        val | mask
+    } else {
+        val & !mask
+    }
+}
+
+/// `val` with its trap vector mode checked, the reserved modes keep the one of `old`
+fn tvec(old: Word, val: Word) -> Word {
+    // direct and vectored mode
+    let mode = match bits!(val, 1, 0) {
+        m @ (0 | 1) => m,
+        _ => bits!(old, 1, 0),
+    };
+    (val & !0b11) | mode
+}
+
+/// Machine and supervisor CSR file of a single hart.
+#[derive(Debug)]
+pub struct CsrFile {
+    mstatus: Word,
+    mtvec: Word,
+    mepc: Word,
+    mcause: Word,
+    mtval: Word,
+    mscratch: Word,
+    medeleg: Word,
+    mideleg: Word,
+    mie: Word,
+    mip: Word,
+    stvec: Word,
+    sepc: Word,
+    scause: Word,
+    stval: Word,
+    sscratch: Word,
+    satp: Word,
+    pmp: Pmp,
+}
+
+impl Default for CsrFile {
+    fn default() -> Self {
+        Self {
+            mstatus: MSTATUS_MPP,
+            mtvec: 0,
+            mepc: 0,
+            mcause: 0,
+            mtval: 0,
+            mscratch: 0,
+            medeleg: 0,
+            mideleg: 0,
+            mie: 0,
+            mip: 0,
+            stvec: 0,
+            sepc: 0,
+            scause: 0,
+            stval: 0,
+            sscratch: 0,
+            satp: 0,
+            pmp: Pmp::default(),
+        }
+    }
+}
+
+impl CsrFile {
+    /// read the CSR at `addr`, `None` if it does not exist
+    pub fn read(&self, addr: usize) -> Option<Word> {
+        if let Some(n) = index(addr, PMPCFG0, pmp::ENTRIES / 4) {
+            return Some(self.pmp.cfg(n));
+        }
+        if let Some(n) = index(addr, PMPADDR0, pmp::ENTRIES) {
+            return Some(self.pmp.addr(n));
+        }
+        let val = match addr {
+            SSTATUS => self.mstatus & SSTATUS_MASK,
+            SIE => self.mie & self.mideleg,
+            STVEC => self.stvec,
+            SSCRATCH => self.sscratch,
+            SEPC => self.sepc,
+            SCAUSE => self.scause,
+            STVAL => self.stval,
+            SIP => self.mip & self.mideleg,
+            SATP => self.satp,
+            MVENDORID | MARCHID | MIMPID | MHARTID => 0,
+            MSTATUS => self.mstatus,
+            MISA => MISA_VALUE,
+            MEDELEG => self.medeleg,
+            MIDELEG => self.mideleg,
+            MIE => self.mie,
+            MTVEC => self.mtvec,
+            MSCRATCH => self.mscratch,
+            MEPC => self.mepc,
+            MCAUSE => self.mcause,
+            MTVAL => self.mtval,
+            MIP => self.mip,
+            _ => return None,
+        };
+        Some(val)
+    }
+
+    /// write `val` to the CSR at `addr` keeping only the legal values of WARL fields,
+    /// `false` if it does not exist or is read-only
+    pub fn write(&mut self, addr: usize, val: Word) -> bool {
+        // addr[11:10] == 0b11 marks the read-only CSRs
+        if bits!(addr, 11, 10) == 0b11 {
+            return false;
+        }
+        // writes to locked PMP entries are ignored
+        if let Some(n) = index(addr, PMPCFG0, pmp::ENTRIES / 4) {
+            self.pmp.set_cfg(n, val);
+            return true;
+        }
+        if let Some(n) = index(addr, PMPADDR0, pmp::ENTRIES) {
+            self.pmp.set_addr(n, val);
+            return true;
+        }
+        match addr {
+            SSTATUS => self.mstatus = (self.mstatus & !SSTATUS_MASK) | (val & SSTATUS_MASK),
+            SIE => self.mie = (self.mie & !self.mideleg) | (val & self.mideleg),
+            STVEC => self.stvec = tvec(self.stvec, val),
+            SSCRATCH => self.sscratch = val,
+            SEPC => self.sepc = val & !1,
+            SCAUSE => self.scause = val,
+            STVAL => self.stval = val,
+            SIP => {
+                let mask = self.mideleg & SSIP;
+                self.mip = (self.mip & !mask) | (val & mask);
+            }
+            // Sv32 and Bare are the only modes, every value is legal
+            SATP => self.satp = val,
+            MSTATUS => {
+                let mask = SSTATUS_MASK
+                    | MSTATUS_MIE
+                    | MSTATUS_MPIE
+                    | MSTATUS_MPRV
+                    | MSTATUS_TVM
+                    | MSTATUS_TW
+                    | MSTATUS_TSR;
+                // the reserved MPP encoding keeps the old mode
+                let mpp = match Privilege::from_bits(bits!(val, 12, 11)) {
+                    Some(_) => val & MSTATUS_MPP,
+                    None => self.mstatus & MSTATUS_MPP,
+                };
+                self.mstatus = (val & mask) | mpp;
+            }
+            // single fixed configuration, writes are ignored
+            MISA => {}
+            MEDELEG => self.medeleg = val & MEDELEG_MASK,
+            MIDELEG => self.mideleg = val & SI_MASK,
+            MIE => self.mie = val & (MI_MASK | SI_MASK),
+            MTVEC => self.mtvec = tvec(self.mtvec, val),
+            MSCRATCH => self.mscratch = val,
+            // IALIGN = 16, only bit 0 is cleared
+            MEPC => self.mepc = val & !1,
+            MCAUSE => self.mcause = val,
+            MTVAL => self.mtval = val,
+            // the M-mode pending bits are driven by the interrupt sources, the S-mode ones
+            // may be injected by M-mode software
+            MIP => self.mip = (self.mip & !SI_MASK) | (val & SI_MASK),
+            _ => return false,
+        }
+        true
+    }
+
+    /// whether code running in `privilege` may access the CSR at `addr`
+    pub fn accessible(&self, addr: usize, privilege: Privilege) -> bool {
+        // addr[9:8] is the lowest privilege level allowed to access the CSR
+        if (privilege as usize) < bits!(addr, 9, 8) {
+            return false;
+        }
+        // TVM traps S-mode satp accesses so M-mode can emulate the page tables
+        !(addr == SATP && privilege == Privilege::Supervisor && self.mstatus & MSTATUS_TVM != 0)
+    }
+
+    pub fn mstatus(&self) -> Word {
+        self.mstatus
+    }
+
+    pub fn satp(&self) -> Word {
+        self.satp
+    }
+
+    pub fn pmp(&self) -> &Pmp {
+        &self.pmp
+    }
+
+    /// previous privilege saved in mstatus.MPP
+    pub fn mpp(&self) -> Privilege {
+        Privilege::from_bits(bits!(self.mstatus, 12, 11)).unwrap()
+    }
+
+    /// enter the trap handler for exception `cause` taken in `from` at `pc`,
+    /// return the handler privilege and address
+    pub fn trap_enter(
+        &mut self,
+        from: Privilege,
+        pc: Word,
+        cause: Word,
+        tval: Word,
+    ) -> (Privilege, Word) {
+        let mstatus = self.mstatus;
+        // synchronous exceptions always go to BASE, also in vectored mode
+        if from <= Privilege::Supervisor && self.medeleg & (1 << cause) != 0 {
+            self.sepc = pc;
+            self.scause = cause;
+            self.stval = tval;
+            let mstatus = with(mstatus, MSTATUS_SPIE, mstatus & MSTATUS_SIE != 0);
+            let mstatus = with(mstatus, MSTATUS_SPP, from == Privilege::Supervisor);
+            self.mstatus = mstatus & !MSTATUS_SIE;
+            (Privilege::Supervisor, self.stvec & !0b11)
+        } else {
+            self.mepc = pc;
+            self.mcause = cause;
+            self.mtval = tval;
+            let mstatus = with(mstatus, MSTATUS_MPIE, mstatus & MSTATUS_MIE != 0);
+            let mstatus = (mstatus & !MSTATUS_MPP) | ((from as Word) << 11);
+            self.mstatus = mstatus & !MSTATUS_MIE;
+            (Privilege::Machine, self.mtvec & !0b11)
+        }
+    }
+
+    /// leave the M-mode trap handler through MRET, return the privilege and address to resume at
+    pub fn mret(&mut self) -> (Privilege, Word) {
+        let to = self.mpp();
+        let mstatus = with(self.mstatus, MSTATUS_MIE, self.mstatus & MSTATUS_MPIE != 0);
+        // MPP is left at the least privileged mode
+        let mstatus = (mstatus | MSTATUS_MPIE) & !MSTATUS_MPP;
+        self.mstatus = with(
+            mstatus,
+            MSTATUS_MPRV,
+            to == Privilege::Machine && mstatus & MSTATUS_MPRV != 0,
+        );
+        (to, self.mepc)
+    }
+
+    /// leave the S-mode trap handler through SRET, return the privilege and address to resume at
+    pub fn sret(&mut self) -> (Privilege, Word) {
+        let to = if self.mstatus & MSTATUS_SPP != 0 {
+            Privilege::Supervisor
+        } else {
+            Privilege::User
+        };
+        let mstatus = with(self.mstatus, MSTATUS_SIE, self.mstatus & MSTATUS_SPIE != 0);
+        self.mstatus = (mstatus | MSTATUS_SPIE) & !(MSTATUS_SPP | MSTATUS_MPRV);
+        (to, self.sepc)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_only_test() {
+        let mut csr = CsrFile::default();
+        for addr in [MVENDORID, MARCHID, MIMPID, MHARTID] {
+            assert_eq!(csr.read(addr), Some(0));
+            assert!(!csr.write(addr, 1));
+        }
+        assert_eq!(csr.read(0x7ff), None);
+        assert!(!csr.write(0x7ff, 1));
+    }
+
+    #[test]
+    fn warl_test() {
+        let mut csr = CsrFile::default();
+        assert!(csr.write(MSTATUS, Word::MAX));
+        let all = SSTATUS_MASK
+            | MSTATUS_MIE
+            | MSTATUS_MPIE
+            | MSTATUS_MPP
+            | MSTATUS_MPRV
+            | MSTATUS_TVM
+            | MSTATUS_TW
+            | MSTATUS_TSR;
+        assert_eq!(csr.read(MSTATUS), Some(all));
+        assert!(csr.write(MSTATUS, 0));
+        assert_eq!(csr.read(MSTATUS), Some(0));
+        assert_eq!(csr.mpp(), Privilege::User);
+        // MPP = 2 is reserved
+        assert!(csr.write(MSTATUS, 2 << 11));
+        assert_eq!(csr.mpp(), Privilege::User);
+        assert!(csr.write(MSTATUS, 1 << 11));
+        assert_eq!(csr.mpp(), Privilege::Supervisor);
+
+        assert!(csr.write(MISA, 0));
+        assert_eq!(csr.read(MISA), Some(MISA_VALUE));
+
+        assert!(csr.write(MTVEC, 0x8000_0101));
+        assert_eq!(csr.read(MTVEC), Some(0x8000_0101));
+        assert!(csr.write(MTVEC, 0x8000_0202));
+        assert_eq!(csr.read(MTVEC), Some(0x8000_0201));
+        assert!(csr.write(STVEC, 0x8000_0202));
+        assert_eq!(csr.read(STVEC), Some(0x8000_0200));
+
+        assert!(csr.write(MEPC, 0x8000_0003));
+        assert_eq!(csr.read(MEPC), Some(0x8000_0002));
+        assert!(csr.write(SEPC, 0x8000_0003));
+        assert_eq!(csr.read(SEPC), Some(0x8000_0002));
+
+        assert!(csr.write(MIE, Word::MAX));
+        assert_eq!(csr.read(MIE), Some(MI_MASK | SI_MASK));
+        assert!(csr.write(MIP, Word::MAX));
+        assert_eq!(csr.read(MIP), Some(SI_MASK));
+
+        assert!(csr.write(MEDELEG, Word::MAX));
+        assert_eq!(csr.read(MEDELEG), Some(MEDELEG_MASK));
+        assert!(csr.write(MIDELEG, Word::MAX));
+        assert_eq!(csr.read(MIDELEG), Some(SI_MASK));
+    }
+
+    #[test]
+    fn supervisor_view_test() {
+        let mut csr = CsrFile::default();
+        csr.write(MSTATUS, MSTATUS_MIE | MSTATUS_SIE | MSTATUS_SUM);
+        assert_eq!(csr.read(SSTATUS), Some(MSTATUS_SIE | MSTATUS_SUM));
+        // sstatus writes leave the M-mode fields alone
+        csr.write(SSTATUS, Word::MAX);
+        assert_eq!(csr.read(MSTATUS), Some(MSTATUS_MIE | SSTATUS_MASK));
+
+        // sie/sip only see the delegated interrupts
+        csr.write(MIE, MI_MASK);
+        csr.write(SIE, Word::MAX);
+        assert_eq!(csr.read(SIE), Some(0));
+        assert_eq!(csr.read(MIE), Some(MI_MASK));
+        csr.write(MIDELEG, SI_MASK);
+        csr.write(SIE, Word::MAX);
+        assert_eq!(csr.read(SIE), Some(SI_MASK));
+        csr.write(SIP, Word::MAX);
+        assert_eq!(csr.read(SIP), Some(SSIP));
+    }
+
+    #[test]
+    fn accessible_test() {
+        let mut csr = CsrFile::default();
+        assert!(csr.accessible(MSTATUS, Privilege::Machine));
+        assert!(!csr.accessible(MSTATUS, Privilege::Supervisor));
+        assert!(csr.accessible(SSTATUS, Privilege::Supervisor));
+        assert!(!csr.accessible(SSTATUS, Privilege::User));
+        assert!(csr.accessible(SATP, Privilege::Supervisor));
+        csr.write(MSTATUS, MSTATUS_TVM);
+        assert!(!csr.accessible(SATP, Privilege::Supervisor));
+        assert!(csr.accessible(SATP, Privilege::Machine));
+    }
+
+    #[test]
+    fn trap_test() {
+        let mut csr = CsrFile::default();
+        csr.write(MTVEC, 0x8000_1001);
+        csr.write(MSTATUS, MSTATUS_MIE | MSTATUS_MPP);
+        let ret = csr.trap_enter(Privilege::Machine, 0x8000_0010, 2, 0x13);
+        assert_eq!(ret, (Privilege::Machine, 0x8000_1000));
+        assert_eq!(csr.read(MEPC), Some(0x8000_0010));
+        assert_eq!(csr.read(MCAUSE), Some(2));
+        assert_eq!(csr.read(MTVAL), Some(0x13));
+        assert_eq!(csr.read(MSTATUS), Some(MSTATUS_MPIE | MSTATUS_MPP));
+
+        assert_eq!(csr.mret(), (Privilege::Machine, 0x8000_0010));
+        assert_eq!(csr.read(MSTATUS), Some(MSTATUS_MIE | MSTATUS_MPIE));
+    }
+
+    #[test]
+    fn delegation_test() {
+        let mut csr = CsrFile::default();
+        csr.write(MTVEC, 0x8000_1000);
+        csr.write(STVEC, 0x8000_2000);
+        csr.write(MEDELEG, 1 << 8);
+        csr.write(MSTATUS, MSTATUS_SIE);
+
+        // ecall from U is delegated to S
+        let ret = csr.trap_enter(Privilege::User, 0x1000, 8, 0);
+        assert_eq!(ret, (Privilege::Supervisor, 0x8000_2000));
+        assert_eq!(csr.read(SEPC), Some(0x1000));
+        assert_eq!(csr.read(SCAUSE), Some(8));
+        assert_eq!(csr.read(MSTATUS), Some(MSTATUS_SPIE));
+        assert_eq!(csr.sret(), (Privilege::User, 0x1000));
+        assert_eq!(csr.read(MSTATUS), Some(MSTATUS_SIE | MSTATUS_SPIE));
+
+        // delegated causes taken in M stay in M
+        let ret = csr.trap_enter(Privilege::Machine, 0x8000_0000, 8, 0);
+        assert_eq!(ret, (Privilege::Machine, 0x8000_1000));
+
+        // other causes go to M and record the previous mode in MPP
+        csr.write(MSTATUS, 0);
+        let ret = csr.trap_enter(Privilege::Supervisor, 0x2000, 2, 0);
+        assert_eq!(ret, (Privilege::Machine, 0x8000_1000));
+        assert_eq!(csr.mpp(), Privilege::Supervisor);
+        csr.write(MSTATUS, csr.mstatus() | MSTATUS_MPRV);
+        assert_eq!(csr.mret(), (Privilege::Supervisor, 0x2000));
+        assert_eq!(csr.mstatus() & (MSTATUS_MPRV | MSTATUS_MPP), 0);
+    }
+
+    #[test]
+    fn pmp_test() {
+        let mut csr = CsrFile::default();
+        assert!(csr.write(PMPADDR0 + 15, 0x1234));
+        assert_eq!(csr.read(PMPADDR0 + 15), Some(0x1234));
+        assert!(csr.write(PMPCFG0 + 3, 0x9f00_0000));
+        assert_eq!(csr.read(PMPCFG0 + 3), Some(0x9f00_0000));
+        // entry 15 is locked now
+        assert!(csr.write(PMPADDR0 + 15, 0));
+        assert_eq!(csr.read(PMPADDR0 + 15), Some(0x1234));
+        assert_eq!(csr.read(PMPCFG0 + 4), None);
+        assert!(!csr.accessible(PMPCFG0, Privilege::Supervisor));
+    }
+
+    #[test]
+    fn name_test() {
+        assert_eq!(csr_name(MEPC), Some("mepc"));
+        assert_eq!(csr_name(PMPCFG0 + 2), Some("pmpcfg2"));
+        assert_eq!(csr_name(PMPADDR0 + 15), Some("pmpaddr15"));
+        assert_eq!(csr_name(PMPADDR0 + 16), None);
+        assert_eq!(csr_name(0), None);
+    }
+}
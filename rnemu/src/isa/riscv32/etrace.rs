@@ -0,0 +1,113 @@
+use std::collections::BTreeMap;
+
+use super::csr::Privilege;
+use crate::{
+    common::{Vaddr, Word},
+    log,
+};
+
+/// name of exception code `cause` of mcause/scause
+pub fn cause_name(cause: Word) -> &'static str {
+    match cause {
+        0 => "instruction address misaligned",
+        1 => "instruction access fault",
+        2 => "illegal instruction",
+        3 => "breakpoint",
+        4 => "load address misaligned",
+        5 => "load access fault",
+        6 => "store/AMO address misaligned",
+        7 => "store/AMO access fault",
+        8 => "environment call from U-mode",
+        9 => "environment call from S-mode",
+        11 => "environment call from M-mode",
+        12 => "instruction page fault",
+        13 => "load page fault",
+        15 => "store/AMO page fault",
+        _ => "reserved",
+    }
+}
+
+/// The exception trace: each trap taken and each MRET or SRET out of a handler, logged when
+/// it is on. Traps are counted by cause all the same, for the summary at the end of the run.
+#[derive(Debug, Default)]
+pub struct ETrace {
+    on: bool,
+    counts: BTreeMap<Word, u64>,
+}
+
+impl ETrace {
+    pub fn set_on(&mut self, on: bool) {
+        self.on = on;
+    }
+
+    /// the trap for `cause` with `tval` taken at `pc` in `from`, into the handler at `handler`
+    /// in `to`
+    pub fn trap(
+        &mut self,
+        cause: Word,
+        tval: Word,
+        (from, to): (Privilege, Privilege),
+        pc: Vaddr,
+        handler: Vaddr,
+    ) {
+        *self.counts.entry(cause).or_default() += 1;
+        if self.on {
+            log!(
+                "trap {} ({}), tval = 0x{:x}, {:?} -> {:?}, pc = 0x{:08x} -> handler 0x{:08x}",
+                cause,
+                cause_name(cause),
+                tval,
+                from,
+                to,
+                pc,
+                handler
+            );
+        }
+    }
+
+    /// `inst`, MRET or SRET, at `pc` in `from` restored `to` and the pc `target`
+    pub fn ret(&self, inst: &str, (from, to): (Privilege, Privilege), pc: Vaddr, target: Vaddr) {
+        if self.on {
+            log!(
+                "{} at pc = 0x{:08x}, {:?} -> {:?}, restored pc = 0x{:08x}",
+                inst,
+                pc,
+                from,
+                to,
+                target
+            );
+        }
+    }
+
+    /// log the number of traps of each cause taken
+    pub fn statistic(&self) {
+        for (cause, n) in &self.counts {
+            log!("traps of cause {} ({}) = {}", cause, cause_name(*cause), n);
+        }
+    }
+
+    /// number of traps taken for `cause`
+    pub fn count(&self, cause: Word) -> u64 {
+        self.counts.get(&cause).copied().unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn count_test() {
+        let mut etrace = ETrace::default();
+        let priv_ = (Privilege::User, Privilege::Machine);
+        etrace.trap(8, 0, priv_, 0x1000, 0x8000_0100);
+        etrace.trap(8, 0, priv_, 0x1004, 0x8000_0100);
+        etrace.trap(13, 0x4000, priv_, 0x1008, 0x8000_0100);
+        assert_eq!(
+            (etrace.count(8), etrace.count(13), etrace.count(2)),
+            (2, 1, 0)
+        );
+        assert_eq!(cause_name(13), "load page fault");
+        assert_eq!(cause_name(10), "reserved");
+    }
+}
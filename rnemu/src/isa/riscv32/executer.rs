@@ -1,35 +1,42 @@
-use std::{os::unix::net::SocketAddr, sync::Arc};
+use std::os::unix::net::SocketAddr;
 
+use block::Block;
 use operand::{
-    Args, BOperand, IOperand, JOperand, NOperand, OperandHelper, OperandType, ROperand, SOperand,
-    UOperand,
+    Args, BOperand, IOperand, JOperand, NOperand, OperandHelper, OperandType, Operands, ROperand,
+    SOperand, UOperand,
 };
-use spin::mutex::SpinMutex;
 
-use super::Riscv32;
+use super::{
+    csr::{Privilege, MSTATUS_TSR, MSTATUS_TVM},
+    mmu::{self, Access},
+    Riscv32,
+};
 use crate::{
-    common::{Vaddr, Word},
-    isa::ISA,
+    common::{DWord, Paddr, SDWord, SWord, Vaddr, Word},
+    isa::{decode::DecodeTable, Execute, GuestException, Jump, ISA},
     memory::MemoryBank,
 };
 
+mod block;
+mod disasm;
 mod operand;
-struct Decoder<P, A>
+mod rvc;
+pub use block::BlockCache;
+pub use disasm::{disassemble, REG_NAMES};
+struct Decoder<A>
 where
-    P: Fn() -> Option<(u64, u64, u64)>,
-    A: Fn(&mut Riscv32, &MemoryBank, Args),
+    A: Fn(&mut Executer, &mut Riscv32, &mut MemoryBank, Args),
 {
-    pred: P,
     apply: A,
+    typ: OperandType,
     helper: Box<dyn OperandHelper>,
 }
 
-impl<P, A> Decoder<P, A>
+impl<A> Decoder<A>
 where
-    P: Fn() -> Option<(u64, u64, u64)>,
-    A: Fn(&mut Riscv32, &MemoryBank, Args),
+    A: Fn(&mut Executer, &mut Riscv32, &mut MemoryBank, Args),
 {
-    pub fn new(pred: P, apply: A, typ: OperandType) -> Self {
+    pub fn new(apply: A, typ: OperandType) -> Self {
         let helper: Box<dyn OperandHelper> = match typ {
             OperandType::I => Box::new(IOperand),
             OperandType::U => Box::new(UOperand),
@@ -39,76 +46,135 @@ where
             OperandType::J => Box::new(JOperand),
             OperandType::B => Box::new(BOperand),
         };
-        Self {
-            pred,
-            apply,
-            helper,
-        }
+        Self { apply, typ, helper }
     }
 }
-trait Decode: Send {
-    fn decode(&self, inst: Word) -> Option<(u64, u64, u64)>;
-    fn apply(&self, inst: Word, cpu: &mut Riscv32, mem: &MemoryBank);
+trait Decode: Send + Sync {
+    fn operand_type(&self) -> OperandType;
+    fn operands(&self, inst: Word) -> Operands;
+    fn apply(&self, s: &mut Executer, cpu: &mut Riscv32, mem: &mut MemoryBank, args: Args);
 }
 
-impl<P, A> Decode for Decoder<P, A>
+impl<A> Decode for Decoder<A>
 where
-    P: Fn() -> Option<(u64, u64, u64)> + Send,
-    A: Fn(&mut Riscv32, &MemoryBank, Args) + Send,
+    A: Fn(&mut Executer, &mut Riscv32, &mut MemoryBank, Args) + Send + Sync,
 {
-    fn decode(&self, inst: Word) -> Option<(u64, u64, u64)> {
-        (self.pred)()
+    fn operand_type(&self) -> OperandType {
+        self.typ
+    }
+
+    fn operands(&self, inst: Word) -> Operands {
+        self.helper.decode_operand(inst)
     }
 
-    fn apply(&self, inst: Word, cpu: &mut Riscv32, mem: &MemoryBank) {
-        let args = self.helper.decode_operand(inst, cpu);
-        (self.apply)(cpu, mem, args)
+    fn apply(&self, s: &mut Executer, cpu: &mut Riscv32, mem: &mut MemoryBank, args: Args) {
+        (self.apply)(s, cpu, mem, args)
     }
 }
 
-fn pattern_decode(pattern: &str) -> Option<(u64, u64, u64)> {
-    let mut key: u64 = 0;
-    let mut mask: u64 = 0;
-    let mut shift: u64 = 0;
-    let mut temp_shift: u64 = 0;
+macro_rules! pat {
+    ($p:literal,$name: ident,$o:expr, $b:expr) => {
+        (
+            $crate::isa::decode::Pattern::new(stringify!($name), $p),
+            Box::new($crate::isa::riscv32::executer::Decoder::new($b, $o))
+                as Box<dyn $crate::isa::riscv32::executer::Decode>,
+        )
+    };
+}
+
+/// Synchronous exceptions raised by an instruction, carrying what goes to mtval.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exception {
+    /// fetch from a misaligned pc
+    InstMisaligned(Vaddr),
+    /// fetch outside physical memory
+    InstAccessFault(Vaddr),
+    /// illegal instruction, with the instruction
+    IllegalInstruction(Word),
+    /// `ebreak`, also used as nemu_trap
+    Breakpoint,
+    LoadMisaligned(Vaddr),
+    LoadAccessFault(Vaddr),
+    /// store or AMO to a misaligned address
+    StoreMisaligned(Vaddr),
+    /// store or AMO outside physical memory
+    StoreAccessFault(Vaddr),
+    EcallFromU,
+    EcallFromS,
+    EcallFromM,
+    InstPageFault(Vaddr),
+    LoadPageFault(Vaddr),
+    /// store or AMO denied by the page tables
+    StorePageFault(Vaddr),
+}
 
-    for (i, c) in pattern.chars().enumerate() {
-        match c {
-            '0' => {
-                key = key << 1;
-                mask = (mask << 1) | 1;
-                temp_shift = 0;
-            }
-            '1' => {
-                key = (key << 1) | 1;
-                mask = (mask << 1) | 1;
-                temp_shift = 0;
-            }
-            '?' => {
-                key = key << 1;
-                mask = mask << 1;
-                temp_shift += 1;
-            }
-            ' ' => continue, // 忽略空格
-            _ => {
-                return None;
-            }
+impl Exception {
+    /// exception code written to mcause/scause
+    pub fn cause(&self) -> Word {
+        match self {
+            Exception::InstMisaligned(_) => 0,
+            Exception::InstAccessFault(_) => 1,
+            Exception::IllegalInstruction(_) => 2,
+            Exception::Breakpoint => 3,
+            Exception::LoadMisaligned(_) => 4,
+            Exception::LoadAccessFault(_) => 5,
+            Exception::StoreMisaligned(_) => 6,
+            Exception::StoreAccessFault(_) => 7,
+            Exception::EcallFromU => 8,
+            Exception::EcallFromS => 9,
+            Exception::EcallFromM => 11,
+            Exception::InstPageFault(_) => 12,
+            Exception::LoadPageFault(_) => 13,
+            Exception::StorePageFault(_) => 15,
         }
-
-        shift = temp_shift; // 记录最后的连续 '?' 的数量
     }
 
-    // 右移 `key` 和 `mask`，忽略末尾的 '?'
-    Some((key >> shift, mask >> shift, shift))
+    /// value written to mtval/stval when the exception is taken at `pc`
+    pub fn tval(&self, pc: Vaddr) -> Word {
+        match *self {
+            Exception::InstMisaligned(addr)
+            | Exception::InstAccessFault(addr)
+            | Exception::LoadMisaligned(addr)
+            | Exception::LoadAccessFault(addr)
+            | Exception::StoreMisaligned(addr)
+            | Exception::StoreAccessFault(addr)
+            | Exception::InstPageFault(addr)
+            | Exception::LoadPageFault(addr)
+            | Exception::StorePageFault(addr) => addr,
+            Exception::IllegalInstruction(inst) => inst,
+            Exception::Breakpoint => pc,
+            Exception::EcallFromU | Exception::EcallFromS | Exception::EcallFromM => 0,
+        }
+    }
 }
 
-macro_rules! pat {
-    ($p:literal,$_name: ident,$o:expr, $b:expr) => {{
-        let pred = || $crate::isa::riscv32::executer::pattern_decode($p);
-        let a = $b;
-        Box::new($crate::isa::riscv32::executer::Decoder::new(pred, a, $o))
-            as Box<dyn $crate::isa::riscv32::executer::Decode>
-    }};
+impl GuestException for Exception {
+    /// whether the instruction could not even be fetched
+    fn is_fetch(&self) -> bool {
+        matches!(
+            self,
+            Exception::InstMisaligned(_)
+                | Exception::InstAccessFault(_)
+                | Exception::InstPageFault(_)
+        )
+    }
+
+    fn is_breakpoint(&self) -> bool {
+        matches!(self, Exception::Breakpoint)
+    }
+
+    fn is_illegal(&self) -> bool {
+        matches!(self, Exception::IllegalInstruction(_))
+    }
+
+    fn is_access_fault(&self) -> bool {
+        matches!(
+            self,
+            Exception::InstAccessFault(_)
+                | Exception::LoadAccessFault(_)
+                | Exception::StoreAccessFault(_)
+        )
+    }
 }
 
 pub struct Executer {
@@ -116,7 +182,10 @@ pub struct Executer {
     snpc: Vaddr,
     dnpc: Vaddr,
     inst: Word,
-    decoders: Arc<SpinMutex<Vec<Box<dyn Decode>>>>,
+    exception: Option<Exception>,
+    /// the call or return made by the instruction
+    jump: Option<Jump>,
+    decoders: &'static DecodeTable<Box<dyn Decode>>,
 }
 
 impl Executer {
@@ -126,64 +195,679 @@ impl Executer {
             snpc: 0,
             dnpc: 0,
             inst: 0,
-            decoders: DECODERS.clone(),
+            exception: None,
+            jump: None,
+            decoders: &DECODERS,
         }
     }
 
-    pub fn exec_once(&mut self, cpu: &mut Riscv32, mem: &mut MemoryBank) -> Result<(), Vaddr> {
-        self.inst = mem.inst_fetch(&mut self.snpc, 4);
+    fn fetch(&mut self, cpu: &mut Riscv32, mem: &MemoryBank) -> Option<Word> {
+        // the low two bits of the first parcel tell a 16-bit RVC instruction from a 32-bit one,
+        // fetching parcel by parcel also covers a 32-bit instruction straddling a word or
+        // page boundary
+        let lo = self.fetch_parcel(cpu, mem)?;
+        if bits!(lo, 1, 0) == 0b11 {
+            Some(lo | (self.fetch_parcel(cpu, mem)? << 16))
+        } else {
+            let inst = rvc::expand(lo);
+            if inst.is_none() {
+                self.raise(Exception::IllegalInstruction(lo));
+            }
+            inst
+        }
+    }
+
+    fn fetch_parcel(&mut self, cpu: &mut Riscv32, mem: &MemoryBank) -> Option<Word> {
+        let mut paddr = translate(self, cpu, mem, self.snpc, 2, Access::Fetch)?;
+        let parcel = mem.inst_fetch(&mut paddr, 2);
+        self.snpc += 2;
+        Some(parcel)
+    }
+
+    fn raise(&mut self, e: Exception) {
+        self.exception = Some(e);
+    }
+
+    /// execute `inst` decoded by `decoder` into `operands`, pc and snpc are already past it
+    fn execute(
+        &mut self,
+        cpu: &mut Riscv32,
+        mem: &mut MemoryBank,
+        inst: Word,
+        decoder: &dyn Decode,
+        operands: Operands,
+    ) {
+        self.inst = inst;
+        self.dnpc = self.snpc;
+        self.jump = None;
+        decoder.apply(self, cpu, mem, operands.read(cpu));
+        cpu.set_reg(0, 0);
+        if self.exception.is_some() {
+            cpu.reservation = None;
+        }
+    }
+}
+
+impl Execute<Riscv32> for Executer {
+    /// execute the instruction at pc, an exception it raises is left for `take_exception`
+    fn exec_once(&mut self, cpu: &mut Riscv32, mem: &mut MemoryBank) {
         self.dnpc = self.snpc;
-        let guard = self.decoders.lock();
-        for d in guard.iter() {
-            if let Some((key, mask, shift)) = d.decode(self.inst) {
-                if ((self.inst as u64) >> shift) & mask == key {
-                    d.apply(self.inst, cpu, mem);
-                    cpu.set_reg(0, 0);
-                    return Ok(());
+        let Some(inst) = self.fetch(cpu, mem) else {
+            return;
+        };
+        match self.decoders.lookup(inst) {
+            Some(d) => self.execute(cpu, mem, inst, d.as_ref(), d.operands(inst)),
+            None => {
+                self.dnpc = self.snpc;
+                self.raise(Exception::IllegalInstruction(inst));
+                cpu.reservation = None;
+            }
+        }
+    }
+
+    /// run the cached block at pc, decoding it on a miss. An instruction that does not fit in
+    /// a block, one that can not be fetched or decoded, goes the slow way through `exec_once`.
+    fn exec_block(&mut self, cpu: &mut Riscv32, mem: &mut MemoryBank, limit: u64) -> u64 {
+        let pc = cpu.pc();
+        self.pc = pc;
+        self.snpc = pc;
+        self.dnpc = pc;
+        // the entry is translated every time, it is where the page tables and the fetch
+        // permission of the current privilege take effect
+        let Some(paddr) = translate(self, cpu, mem, pc, 2, Access::Fetch) else {
+            return 1;
+        };
+        let block = match cpu.blocks.get(paddr, cpu.privilege) {
+            Some(block) => block,
+            None => {
+                let block = Block::decode(cpu, mem, paddr);
+                if block.insts.is_empty() {
+                    self.exec_once(cpu, mem);
+                    cpu.set_pc(self.dnpc);
+                    return 1;
                 }
+                cpu.blocks.insert(cpu.privilege, block)
+            }
+        };
+        cpu.blocks.take_stale();
+        let mut n = 0;
+        for d in block.insts.iter().take(limit as usize) {
+            n += 1;
+            self.pc = self.snpc;
+            self.snpc += d.len;
+            self.execute(cpu, mem, d.inst, d.decoder, d.operands);
+            cpu.set_pc(self.dnpc);
+            // an instruction that overwrote the rest of the block is the last one run from it, as
+            // is one that went out of memory
+            if self.exception.is_some() || cpu.blocks.take_stale() || mem.has_error() {
+                break;
             }
         }
-        Err(self.pc)
+        n
+    }
+
+    fn pc(&self) -> Vaddr {
+        self.pc
     }
-    pub fn set_pc(&mut self, pc: Vaddr) {
+
+    fn set_pc(&mut self, pc: Vaddr) {
         self.pc = pc;
     }
-    pub fn set_snpc(&mut self, snpc: Vaddr) {
+
+    fn set_snpc(&mut self, snpc: Vaddr) {
         self.snpc = snpc;
     }
 
-    pub fn dnpc(&self) -> Vaddr {
+    fn dnpc(&self) -> Vaddr {
         self.dnpc
     }
+
+    /// take the exception raised by the last executed instruction
+    fn take_exception(&mut self) -> Option<Exception> {
+        self.exception.take()
+    }
+
+    fn take_jump(&mut self) -> Option<Jump> {
+        self.jump.take()
+    }
+}
+
+/// translate an `access` of `len` bytes at `addr` and check it lands in physical memory
+/// that PMP grants, `None` after raising the exception if it can not be done
+fn translate(
+    s: &mut Executer,
+    cpu: &mut Riscv32,
+    mem: &MemoryBank,
+    addr: Vaddr,
+    len: usize,
+    access: Access,
+) -> Option<Paddr> {
+    let paddr = if !addr.is_multiple_of(len as Vaddr) {
+        Err(access.misaligned(addr))
+    } else {
+        // an aligned access never crosses a page, one translation covers all of it
+        mmu::translate(cpu, mem, addr, access).and_then(|paddr| {
+            let privilege = mmu::effective_privilege(cpu, access);
+            match mem.allows(paddr, len, access.perm())
+                && cpu.csr.pmp().check(paddr, len, privilege, access)
+            {
+                true => Ok(paddr),
+                false => Err(access.access_fault(addr)),
+            }
+        })
+    };
+    paddr.map_err(|e| s.raise(e)).ok()
+}
+
+/// load `len` bytes at `addr`, `None` after raising the exception if it can not be done
+fn load(
+    s: &mut Executer,
+    cpu: &mut Riscv32,
+    mem: &MemoryBank,
+    addr: Vaddr,
+    len: usize,
+) -> Option<Word> {
+    let paddr = translate(s, cpu, mem, addr, len, Access::Load)?;
+    Some(mem.paddr_read(paddr, len))
+}
+
+fn store(
+    s: &mut Executer,
+    cpu: &mut Riscv32,
+    mem: &mut MemoryBank,
+    addr: Vaddr,
+    len: usize,
+    data: Word,
+) {
+    if let Some(paddr) = translate(s, cpu, mem, addr, len, Access::Store) {
+        cpu.snoop_store(paddr, len);
+        mem.paddr_write(paddr, len, data);
+    }
+}
+
+/// read-modify-write `rd = M[rs1]; M[rs1] = op(M[rs1], rs2)`
+fn amo<F>(s: &mut Executer, cpu: &mut Riscv32, mem: &mut MemoryBank, args: Args, op: F)
+where
+    F: Fn(Word, Word) -> Word,
+{
+    // AMOs report every failure as a store/AMO exception
+    let Some(paddr) = translate(s, cpu, mem, args.src1, 4, Access::Store) else {
+        return;
+    };
+    let val = mem.paddr_read(paddr, 4);
+    cpu.snoop_store(paddr, 4);
+    mem.paddr_write(paddr, 4, op(val, args.src2));
+    cpu.set_reg(args.rd, val);
+}
+
+/// Zicsr read-modify-write, `rd = csr; csr = op(csr)` where the write is skipped unless `write`
+fn csr_rw<F>(s: &mut Executer, cpu: &mut Riscv32, rd: usize, write: bool, op: F)
+where
+    F: Fn(Word) -> Word,
+{
+    let addr = bits!(s.inst, 31, 20) as usize;
+    if !cpu.csr.accessible(addr, cpu.privilege) {
+        s.raise(Exception::IllegalInstruction(s.inst));
+        return;
+    }
+    let Some(old) = cpu.csr(addr) else {
+        s.raise(Exception::IllegalInstruction(s.inst));
+        return;
+    };
+    if write && !cpu.set_csr(addr, op(old)) {
+        s.raise(Exception::IllegalInstruction(s.inst));
+        return;
+    }
+    cpu.set_reg(rd, old);
+}
+
+/// ra and t0 hold return addresses by the calling convention
+fn is_link(reg: usize) -> bool {
+    reg == 1 || reg == 5
 }
 
 lazy_static::lazy_static! {
-    static ref DECODERS: Arc<SpinMutex<Vec<Box<dyn Decode>>>> = {
-        let decoders =  vec!{
-            // pat!(
-            //     "??????? ????? ????? ??? ????? 00101 11",
-            //     auipc,
-            //     OperandType::U,
-            //     |cpu: &mut Riscv32, mem: &MemoryBank,args:Args| {
-            //         cpu.set_reg(args.rd, cpu.pc + args.imm);
-            //     }
-            // ),
+    static ref DECODERS: DecodeTable<Box<dyn Decode>> = {
+        let decoders = vec! {
+            pat!("??????? ????? ????? ??? ????? 01101 11", lui, OperandType::U, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, args.imm);
+            }),
+            pat!("??????? ????? ????? ??? ????? 00101 11", auipc, OperandType::U, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, s.pc.wrapping_add(args.imm));
+            }),
+            pat!("??????? ????? ????? ??? ????? 11011 11", jal, OperandType::J, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, s.snpc);
+                s.dnpc = s.pc.wrapping_add(args.imm);
+                s.jump = is_link(args.rd).then_some(Jump::Call(s.dnpc));
+            }),
+            pat!("??????? ????? ????? 000 ????? 11001 11", jalr, OperandType::I, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, s.snpc);
+                s.dnpc = args.src1.wrapping_add(args.imm) & !1;
+                // the return address stack hints of the spec, a link in rd pushes whatever
+                // rs1 is
+                let rs1 = bits!(s.inst, 19, 15) as usize;
+                s.jump = match (is_link(args.rd), is_link(rs1)) {
+                    (true, _) => Some(Jump::Call(s.dnpc)),
+                    (false, true) => Some(Jump::Return(s.dnpc)),
+                    (false, false) => None,
+                };
+            }),
+            pat!("??????? ????? ????? 000 ????? 11000 11", beq, OperandType::B, |s, cpu, mem, args| {
+                if args.src1 == args.src2 {
+                    s.dnpc = s.pc.wrapping_add(args.imm);
+                }
+            }),
+            pat!("??????? ????? ????? 001 ????? 11000 11", bne, OperandType::B, |s, cpu, mem, args| {
+                if args.src1 != args.src2 {
+                    s.dnpc = s.pc.wrapping_add(args.imm);
+                }
+            }),
+            pat!("??????? ????? ????? 100 ????? 11000 11", blt, OperandType::B, |s, cpu, mem, args| {
+                if (args.src1 as SWord) < (args.src2 as SWord) {
+                    s.dnpc = s.pc.wrapping_add(args.imm);
+                }
+            }),
+            pat!("??????? ????? ????? 101 ????? 11000 11", bge, OperandType::B, |s, cpu, mem, args| {
+                if (args.src1 as SWord) >= (args.src2 as SWord) {
+                    s.dnpc = s.pc.wrapping_add(args.imm);
+                }
+            }),
+            pat!("??????? ????? ????? 110 ????? 11000 11", bltu, OperandType::B, |s, cpu, mem, args| {
+                if args.src1 < args.src2 {
+                    s.dnpc = s.pc.wrapping_add(args.imm);
+                }
+            }),
+            pat!("??????? ????? ????? 111 ????? 11000 11", bgeu, OperandType::B, |s, cpu, mem, args| {
+                if args.src1 >= args.src2 {
+                    s.dnpc = s.pc.wrapping_add(args.imm);
+                }
+            }),
+            pat!("??????? ????? ????? 000 ????? 00000 11", lb, OperandType::I, |s, cpu, mem, args| {
+                let Some(val) = load(s, cpu, mem, args.src1.wrapping_add(args.imm), 1) else {
+                    return;
+                };
+                cpu.set_reg(args.rd, sext!(val, 8));
+            }),
+            pat!("??????? ????? ????? 001 ????? 00000 11", lh, OperandType::I, |s, cpu, mem, args| {
+                let Some(val) = load(s, cpu, mem, args.src1.wrapping_add(args.imm), 2) else {
+                    return;
+                };
+                cpu.set_reg(args.rd, sext!(val, 16));
+            }),
+            pat!("??????? ????? ????? 010 ????? 00000 11", lw, OperandType::I, |s, cpu, mem, args| {
+                let Some(val) = load(s, cpu, mem, args.src1.wrapping_add(args.imm), 4) else {
+                    return;
+                };
+                cpu.set_reg(args.rd, val);
+            }),
+            pat!("??????? ????? ????? 100 ????? 00000 11", lbu, OperandType::I, |s, cpu, mem, args| {
+                let Some(val) = load(s, cpu, mem, args.src1.wrapping_add(args.imm), 1) else {
+                    return;
+                };
+                cpu.set_reg(args.rd, val);
+            }),
+            pat!("??????? ????? ????? 101 ????? 00000 11", lhu, OperandType::I, |s, cpu, mem, args| {
+                let Some(val) = load(s, cpu, mem, args.src1.wrapping_add(args.imm), 2) else {
+                    return;
+                };
+                cpu.set_reg(args.rd, val);
+            }),
+            pat!("??????? ????? ????? 000 ????? 01000 11", sb, OperandType::S, |s, cpu, mem, args| {
+                store(s, cpu, mem, args.src1.wrapping_add(args.imm), 1, args.src2);
+            }),
+            pat!("??????? ????? ????? 001 ????? 01000 11", sh, OperandType::S, |s, cpu, mem, args| {
+                store(s, cpu, mem, args.src1.wrapping_add(args.imm), 2, args.src2);
+            }),
+            pat!("??????? ????? ????? 010 ????? 01000 11", sw, OperandType::S, |s, cpu, mem, args| {
+                store(s, cpu, mem, args.src1.wrapping_add(args.imm), 4, args.src2);
+            }),
+            pat!("??????? ????? ????? 000 ????? 00100 11", addi, OperandType::I, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, args.src1.wrapping_add(args.imm));
+            }),
+            pat!("??????? ????? ????? 010 ????? 00100 11", slti, OperandType::I, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, ((args.src1 as SWord) < (args.imm as SWord)) as Word);
+            }),
+            pat!("??????? ????? ????? 011 ????? 00100 11", sltiu, OperandType::I, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, (args.src1 < args.imm) as Word);
+            }),
+            pat!("??????? ????? ????? 100 ????? 00100 11", xori, OperandType::I, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, args.src1 ^ args.imm);
+            }),
+            pat!("??????? ????? ????? 110 ????? 00100 11", ori, OperandType::I, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, args.src1 | args.imm);
+            }),
+            pat!("??????? ????? ????? 111 ????? 00100 11", andi, OperandType::I, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, args.src1 & args.imm);
+            }),
+            pat!("0000000 ????? ????? 001 ????? 00100 11", slli, OperandType::I, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, args.src1 << (args.imm & 0x1f));
+            }),
+            pat!("0000000 ????? ????? 101 ????? 00100 11", srli, OperandType::I, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, args.src1 >> (args.imm & 0x1f));
+            }),
+            pat!("0100000 ????? ????? 101 ????? 00100 11", srai, OperandType::I, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, ((args.src1 as SWord) >> (args.imm & 0x1f)) as Word);
+            }),
+            pat!("0000000 ????? ????? 000 ????? 01100 11", add, OperandType::R, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, args.src1.wrapping_add(args.src2));
+            }),
+            pat!("0100000 ????? ????? 000 ????? 01100 11", sub, OperandType::R, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, args.src1.wrapping_sub(args.src2));
+            }),
+            pat!("0000000 ????? ????? 001 ????? 01100 11", sll, OperandType::R, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, args.src1 << (args.src2 & 0x1f));
+            }),
+            pat!("0000000 ????? ????? 010 ????? 01100 11", slt, OperandType::R, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, ((args.src1 as SWord) < (args.src2 as SWord)) as Word);
+            }),
+            pat!("0000000 ????? ????? 011 ????? 01100 11", sltu, OperandType::R, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, (args.src1 < args.src2) as Word);
+            }),
+            pat!("0000000 ????? ????? 100 ????? 01100 11", xor, OperandType::R, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, args.src1 ^ args.src2);
+            }),
+            pat!("0000000 ????? ????? 101 ????? 01100 11", srl, OperandType::R, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, args.src1 >> (args.src2 & 0x1f));
+            }),
+            pat!("0100000 ????? ????? 101 ????? 01100 11", sra, OperandType::R, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, ((args.src1 as SWord) >> (args.src2 & 0x1f)) as Word);
+            }),
+            pat!("0000000 ????? ????? 110 ????? 01100 11", or, OperandType::R, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, args.src1 | args.src2);
+            }),
+            pat!("0000000 ????? ????? 111 ????? 01100 11", and, OperandType::R, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, args.src1 & args.src2);
+            }),
+            pat!("0000001 ????? ????? 000 ????? 01100 11", mul, OperandType::R, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, args.src1.wrapping_mul(args.src2));
+            }),
+            pat!("0000001 ????? ????? 001 ????? 01100 11", mulh, OperandType::R, |s, cpu, mem, args| {
+                let prod = (args.src1 as SWord as SDWord) * (args.src2 as SWord as SDWord);
+                cpu.set_reg(args.rd, (prod >> Word::BITS) as Word);
+            }),
+            pat!("0000001 ????? ????? 010 ????? 01100 11", mulhsu, OperandType::R, |s, cpu, mem, args| {
+                let prod = (args.src1 as SWord as SDWord).wrapping_mul(args.src2 as SDWord);
+                cpu.set_reg(args.rd, (prod >> Word::BITS) as Word);
+            }),
+            pat!("0000001 ????? ????? 011 ????? 01100 11", mulhu, OperandType::R, |s, cpu, mem, args| {
+                let prod = (args.src1 as DWord) * (args.src2 as DWord);
+                cpu.set_reg(args.rd, (prod >> Word::BITS) as Word);
+            }),
+            // division never traps: x/0 is all ones and INT_MIN/-1 overflows back to INT_MIN
+            pat!("0000001 ????? ????? 100 ????? 01100 11", div, OperandType::R, |s, cpu, mem, args| {
+                let val = match args.src2 {
+                    0 => Word::MAX,
+                    _ => (args.src1 as SWord).wrapping_div(args.src2 as SWord) as Word,
+                };
+                cpu.set_reg(args.rd, val);
+            }),
+            pat!("0000001 ????? ????? 101 ????? 01100 11", divu, OperandType::R, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, args.src1.checked_div(args.src2).unwrap_or(Word::MAX));
+            }),
+            // x%0 is x and INT_MIN%-1 is 0
+            pat!("0000001 ????? ????? 110 ????? 01100 11", rem, OperandType::R, |s, cpu, mem, args| {
+                let val = match args.src2 {
+                    0 => args.src1,
+                    _ => (args.src1 as SWord).wrapping_rem(args.src2 as SWord) as Word,
+                };
+                cpu.set_reg(args.rd, val);
+            }),
+            pat!("0000001 ????? ????? 111 ????? 01100 11", remu, OperandType::R, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, args.src1.checked_rem(args.src2).unwrap_or(args.src1));
+            }),
+            // the aq/rl bits are ignored, a single hart always observes its own program order
+            pat!("00010 ?? 00000 ????? 010 ????? 01011 11", lr_w, OperandType::R, |s, cpu, mem, args| {
+                let Some(paddr) = translate(s, cpu, mem, args.src1, 4, Access::Load) else {
+                    return;
+                };
+                cpu.set_reg(args.rd, mem.paddr_read(paddr, 4));
+                cpu.reservation = Some(paddr);
+            }),
+            pat!("00011 ?? ????? ????? 010 ????? 01011 11", sc_w, OperandType::R, |s, cpu, mem, args| {
+                let Some(paddr) = translate(s, cpu, mem, args.src1, 4, Access::Store) else {
+                    return;
+                };
+                let success = cpu.reservation.take() == Some(paddr);
+                if success {
+                    cpu.snoop_store(paddr, 4);
+                    mem.paddr_write(paddr, 4, args.src2);
+                }
+                cpu.set_reg(args.rd, !success as Word);
+            }),
+            pat!("00001 ?? ????? ????? 010 ????? 01011 11", amoswap_w, OperandType::R, |s, cpu, mem, args| {
+                amo(s, cpu, mem, args, |_, b| b);
+            }),
+            pat!("00000 ?? ????? ????? 010 ????? 01011 11", amoadd_w, OperandType::R, |s, cpu, mem, args| {
+                amo(s, cpu, mem, args, |a, b| a.wrapping_add(b));
+            }),
+            pat!("00100 ?? ????? ????? 010 ????? 01011 11", amoxor_w, OperandType::R, |s, cpu, mem, args| {
+                amo(s, cpu, mem, args, |a, b| a ^ b);
+            }),
+            pat!("01100 ?? ????? ????? 010 ????? 01011 11", amoand_w, OperandType::R, |s, cpu, mem, args| {
+                amo(s, cpu, mem, args, |a, b| a & b);
+            }),
+            pat!("01000 ?? ????? ????? 010 ????? 01011 11", amoor_w, OperandType::R, |s, cpu, mem, args| {
+                amo(s, cpu, mem, args, |a, b| a | b);
+            }),
+            pat!("10000 ?? ????? ????? 010 ????? 01011 11", amomin_w, OperandType::R, |s, cpu, mem, args| {
+                amo(s, cpu, mem, args, |a, b| (a as SWord).min(b as SWord) as Word);
+            }),
+            pat!("10100 ?? ????? ????? 010 ????? 01011 11", amomax_w, OperandType::R, |s, cpu, mem, args| {
+                amo(s, cpu, mem, args, |a, b| (a as SWord).max(b as SWord) as Word);
+            }),
+            pat!("11000 ?? ????? ????? 010 ????? 01011 11", amominu_w, OperandType::R, |s, cpu, mem, args| {
+                amo(s, cpu, mem, args, |a, b| a.min(b));
+            }),
+            pat!("11100 ?? ????? ????? 010 ????? 01011 11", amomaxu_w, OperandType::R, |s, cpu, mem, args| {
+                amo(s, cpu, mem, args, |a, b| a.max(b));
+            }),
+            // the immediate forms take the zero-extended rs1 field as uimm, csrrs/csrrc with
+            // rs1 == x0 only read the CSR
+            pat!("??????? ????? ????? 001 ????? 11100 11", csrrw, OperandType::I, |s, cpu, mem, args| {
+                csr_rw(s, cpu, args.rd, true, |_| args.src1);
+            }),
+            pat!("??????? ????? ????? 010 ????? 11100 11", csrrs, OperandType::I, |s, cpu, mem, args| {
+                let write = bits!(s.inst, 19, 15) != 0;
+                csr_rw(s, cpu, args.rd, write, |old| old | args.src1);
+            }),
+            pat!("??????? ????? ????? 011 ????? 11100 11", csrrc, OperandType::I, |s, cpu, mem, args| {
+                let write = bits!(s.inst, 19, 15) != 0;
+                csr_rw(s, cpu, args.rd, write, |old| old & !args.src1);
+            }),
+            pat!("??????? ????? ????? 101 ????? 11100 11", csrrwi, OperandType::I, |s, cpu, mem, args| {
+                let uimm = bits!(s.inst, 19, 15);
+                csr_rw(s, cpu, args.rd, true, |_| uimm);
+            }),
+            pat!("??????? ????? ????? 110 ????? 11100 11", csrrsi, OperandType::I, |s, cpu, mem, args| {
+                let uimm = bits!(s.inst, 19, 15);
+                csr_rw(s, cpu, args.rd, uimm != 0, |old| old | uimm);
+            }),
+            pat!("??????? ????? ????? 111 ????? 11100 11", csrrci, OperandType::I, |s, cpu, mem, args| {
+                let uimm = bits!(s.inst, 19, 15);
+                csr_rw(s, cpu, args.rd, uimm != 0, |old| old & !uimm);
+            }),
+            // single hart without caches, every memory ordering is already satisfied
+            pat!("??????? ????? ????? 000 ????? 00011 11", fence, OperandType::N, |s, cpu, mem, args| {}),
+            // stores already drop the blocks they overwrite, this is for code written behind
+            // the back of the hart
+            pat!("??????? ????? ????? 001 ????? 00011 11", fence_i, OperandType::N, |s, cpu, mem, args| {
+                cpu.blocks.flush();
+            }),
+            pat!("0000000 00000 00000 000 00000 11100 11", ecall, OperandType::N, |s, cpu, mem, args| {
+                s.raise(match cpu.privilege {
+                    Privilege::User => Exception::EcallFromU,
+                    Privilege::Supervisor => Exception::EcallFromS,
+                    Privilege::Machine => Exception::EcallFromM,
+                });
+            }),
+            pat!("0000000 00001 00000 000 00000 11100 11", ebreak, OperandType::N, |s, cpu, mem, args| {
+                s.raise(Exception::Breakpoint);
+            }),
+            pat!("0001000 00010 00000 000 00000 11100 11", sret, OperandType::N, |s, cpu, mem, args| {
+                // TSR traps SRET so M-mode can emulate the supervisor
+                let tsr = cpu.csr.mstatus() & MSTATUS_TSR != 0;
+                if cpu.privilege < Privilege::Supervisor || (cpu.privilege == Privilege::Supervisor && tsr) {
+                    s.raise(Exception::IllegalInstruction(s.inst));
+                    return;
+                }
+                let from = cpu.privilege;
+                (cpu.privilege, s.dnpc) = cpu.csr.sret();
+                #[cfg(feature = "trace")]
+                cpu.etrace.ret("sret", (from, cpu.privilege), s.pc, s.dnpc);
+            }),
+            pat!("0011000 00010 00000 000 00000 11100 11", mret, OperandType::N, |s, cpu, mem, args| {
+                if cpu.privilege < Privilege::Machine {
+                    s.raise(Exception::IllegalInstruction(s.inst));
+                    return;
+                }
+                let from = cpu.privilege;
+                (cpu.privilege, s.dnpc) = cpu.csr.mret();
+                #[cfg(feature = "trace")]
+                cpu.etrace.ret("mret", (from, cpu.privilege), s.pc, s.dnpc);
+            }),
+            // rs1 == x0 fences every address and rs2 == x0 every address space
+            pat!("0001001 ????? ????? 000 00000 11100 11", sfence_vma, OperandType::N, |s, cpu, mem, args| {
+                let tvm = cpu.csr.mstatus() & MSTATUS_TVM != 0;
+                if cpu.privilege < Privilege::Supervisor || (cpu.privilege == Privilege::Supervisor && tvm) {
+                    s.raise(Exception::IllegalInstruction(s.inst));
+                    return;
+                }
+                let (rs1, rs2) = (bits!(s.inst, 19, 15) as usize, bits!(s.inst, 24, 20) as usize);
+                let vaddr = (rs1 != 0).then(|| cpu.reg(rs1));
+                let asid = (rs2 != 0).then(|| bits!(cpu.reg(rs2), 8, 0));
+                cpu.flush_tlb(vaddr, asid);
+            }),
         };
-        Arc::new(SpinMutex::new(decoders))
+        DecodeTable::new(decoders)
     };
 }
+
+#[cfg(test)]
 mod tests {
+    use crate::isa::riscv32::csr::{
+        MEPC, MHARTID, MSCRATCH, MSTATUS, PMPADDR0, PMPCFG0, SATP, SEPC,
+    };
     use crate::isa::{Riscv32, ISA};
-    use crate::memory::MemoryBank;
+    use crate::memory::{MemoryBank, RESET_VECTOR};
 
     use super::*;
+
+    const PC: Vaddr = RESET_VECTOR as Vaddr;
+    const DATA: Vaddr = PC + 0x1000;
+
+    fn r(f7: u32, rs2: u32, rs1: u32, f3: u32, rd: u32, op: u32) -> Word {
+        (f7 << 25) | (rs2 << 20) | (rs1 << 15) | (f3 << 12) | (rd << 7) | op
+    }
+    fn i(imm: i32, rs1: u32, f3: u32, rd: u32, op: u32) -> Word {
+        ((imm as u32 & 0xfff) << 20) | (rs1 << 15) | (f3 << 12) | (rd << 7) | op
+    }
+    fn s(imm: i32, rs2: u32, rs1: u32, f3: u32) -> Word {
+        let imm = imm as u32;
+        (bits!(imm, 11, 5) << 25)
+            | (rs2 << 20)
+            | (rs1 << 15)
+            | (f3 << 12)
+            | (bits!(imm, 4, 0) << 7)
+            | 0x23
+    }
+    fn b(imm: i32, rs2: u32, rs1: u32, f3: u32) -> Word {
+        let imm = imm as u32;
+        (bits!(imm, 12, 12) << 31)
+            | (bits!(imm, 10, 5) << 25)
+            | (rs2 << 20)
+            | (rs1 << 15)
+            | (f3 << 12)
+            | (bits!(imm, 4, 1) << 8)
+            | (bits!(imm, 11, 11) << 7)
+            | 0x63
+    }
+    fn u(imm: u32, rd: u32, op: u32) -> Word {
+        (imm & 0xfffff000) | (rd << 7) | op
+    }
+    fn j(imm: i32, rd: u32) -> Word {
+        let imm = imm as u32;
+        (bits!(imm, 20, 20) << 31)
+            | (bits!(imm, 10, 1) << 21)
+            | (bits!(imm, 11, 11) << 20)
+            | (bits!(imm, 19, 12) << 12)
+            | (rd << 7)
+            | 0x6f
+    }
+
+    fn machine() -> (Riscv32, MemoryBank) {
+        (Riscv32::new(PC), MemoryBank::new(&[]))
+    }
+
+    /// place `inst` at the current pc and execute it
+    fn step(cpu: &mut Riscv32, mem: &mut MemoryBank, inst: Word) -> Option<Exception> {
+        let pc = cpu.pc();
+        mem.paddr_write(pc, 4, inst);
+        let mut executer = Executer::new();
+        executer.set_pc(pc);
+        executer.set_snpc(pc);
+        executer.exec_once(cpu, mem);
+        cpu.set_pc(executer.dnpc());
+        executer.take_exception()
+    }
+
+    /// run an R-type op on (a, b) and return rd
+    fn alu_r(f7: u32, f3: u32, a: Word, b: Word) -> Word {
+        let (mut cpu, mut mem) = machine();
+        cpu.set_reg(1, a);
+        cpu.set_reg(2, b);
+        step(&mut cpu, &mut mem, r(f7, 2, 1, f3, 3, 0x33));
+        cpu.reg(3)
+    }
+
+    /// run an I-type op on (a, imm) and return rd
+    fn alu_i(f3: u32, a: Word, imm: i32) -> Word {
+        let (mut cpu, mut mem) = machine();
+        cpu.set_reg(1, a);
+        step(&mut cpu, &mut mem, i(imm, 1, f3, 3, 0x13));
+        cpu.reg(3)
+    }
+
+    /// run a branch on (a, b) and return whether it was taken
+    fn branch(f3: u32, a: Word, b_: Word) -> bool {
+        let (mut cpu, mut mem) = machine();
+        cpu.set_reg(1, a);
+        cpu.set_reg(2, b_);
+        step(&mut cpu, &mut mem, b(-16, 2, 1, f3));
+        match cpu.pc() {
+            x if x == PC - 16 => true,
+            x if x == PC + 4 => false,
+            x => panic!("unexpected pc {x:#x}"),
+        }
+    }
+
+    /// load from DATA + off holding `val`
+    fn load(f3: u32, val: Word, off: i32) -> Word {
+        let (mut cpu, mut mem) = machine();
+        mem.paddr_write(DATA.wrapping_add(off as Word), 4, val);
+        cpu.set_reg(1, DATA);
+        step(&mut cpu, &mut mem, i(off, 1, f3, 3, 0x03));
+        cpu.reg(3)
+    }
+
+    /// store `val` to DATA + off and read back the surrounding word
+    fn store(f3: u32, val: Word, off: i32) -> Word {
+        let (mut cpu, mut mem) = machine();
+        mem.paddr_write(DATA, 4, 0xaaaa_aaaa);
+        cpu.set_reg(1, DATA.wrapping_sub(off as Word));
+        cpu.set_reg(2, val);
+        step(&mut cpu, &mut mem, s(off, 2, 1, f3));
+        mem.paddr_read(DATA, 4)
+    }
+
     #[test]
     fn macro_test() {
         let x = pat!(
             "00000 00 0 00 0 00 0",
             li,
             OperandType::U,
-            |cpu: &mut Riscv32, mem: &MemoryBank, args: Args| { cpu.set_pc(3) }
+            |s: &mut Executer, cpu: &mut Riscv32, mem: &mut MemoryBank, args: Args| {
+                cpu.set_pc(3)
+            }
         );
         let y = |cpu: &mut Riscv32, mem: &MemoryBank| {
             cpu.set_pc(3);
@@ -195,4 +879,1141 @@ mod tests {
         let executer = Executer::new();
         println!("success");
     }
+
+    #[test]
+    fn encoding_test() {
+        assert_eq!(0x00000297, u(0, 5, 0x17));
+        assert_eq!(0x00028823, s(16, 0, 5, 0));
+        assert_eq!(0x0102c503, i(16, 5, 4, 10, 0x03));
+    }
+
+    #[test]
+    fn default_img_test() {
+        let (mut cpu, mut mem) = machine();
+        let img = Riscv32::default_img();
+        for (n, w) in img.chunks(4).take(3).enumerate() {
+            mem.paddr_write(
+                PC + 4 * n as Vaddr,
+                4,
+                Word::from_le_bytes(w.try_into().unwrap()),
+            );
+        }
+        let mut executer = Executer::new();
+        for _ in 0..3 {
+            executer.set_pc(cpu.pc());
+            executer.set_snpc(cpu.pc());
+            executer.exec_once(&mut cpu, &mut mem);
+            cpu.set_pc(executer.dnpc());
+        }
+        assert_eq!(cpu.reg(5), PC);
+        assert_eq!(cpu.reg(10), 0);
+    }
+
+    #[test]
+    fn lui_test() {
+        let (mut cpu, mut mem) = machine();
+        step(&mut cpu, &mut mem, u(0xfffff000, 1, 0x37));
+        assert_eq!(cpu.reg(1), 0xfffff000);
+        assert_eq!(cpu.pc(), PC + 4);
+    }
+
+    #[test]
+    fn auipc_test() {
+        let (mut cpu, mut mem) = machine();
+        step(&mut cpu, &mut mem, u(0x12345000, 1, 0x17));
+        assert_eq!(cpu.reg(1), PC + 0x12345000);
+    }
+
+    #[test]
+    fn jal_test() {
+        let (mut cpu, mut mem) = machine();
+        step(&mut cpu, &mut mem, j(-8, 1));
+        assert_eq!(cpu.reg(1), PC + 4);
+        assert_eq!(cpu.pc(), PC - 8);
+
+        let (mut cpu, mut mem) = machine();
+        step(&mut cpu, &mut mem, j(0x800, 0));
+        assert_eq!(cpu.reg(0), 0);
+        assert_eq!(cpu.pc(), PC + 0x800);
+    }
+
+    #[test]
+    fn jalr_test() {
+        let (mut cpu, mut mem) = machine();
+        cpu.set_reg(2, PC + 0x101);
+        step(&mut cpu, &mut mem, i(-0x10, 2, 0, 1, 0x67));
+        assert_eq!(cpu.reg(1), PC + 4);
+        assert_eq!(cpu.pc(), PC + 0xf0);
+
+        // rd == rs1 reads the base before writing the link
+        let (mut cpu, mut mem) = machine();
+        cpu.set_reg(1, PC + 0x20);
+        step(&mut cpu, &mut mem, i(0, 1, 0, 1, 0x67));
+        assert_eq!(cpu.pc(), PC + 0x20);
+        assert_eq!(cpu.reg(1), PC + 4);
+    }
+
+    #[test]
+    fn jump_test() {
+        let jump = |inst: Word| {
+            let (mut cpu, mut mem) = machine();
+            cpu.set_reg(1, PC + 0x40);
+            cpu.set_reg(6, PC + 0x80);
+            mem.paddr_write(PC, 4, inst);
+            let mut executer = Executer::new();
+            executer.set_pc(PC);
+            executer.set_snpc(PC);
+            executer.exec_once(&mut cpu, &mut mem);
+            executer.take_jump()
+        };
+        // jal ra, jal zero, call through t1, ret, jr t1
+        assert_eq!(jump(j(0x10, 1)), Some(Jump::Call(PC + 0x10)));
+        assert_eq!(jump(j(0x10, 0)), None);
+        assert_eq!(jump(i(0, 6, 0, 1, 0x67)), Some(Jump::Call(PC + 0x80)));
+        assert_eq!(jump(i(0, 1, 0, 0, 0x67)), Some(Jump::Return(PC + 0x40)));
+        assert_eq!(jump(i(0, 6, 0, 0, 0x67)), None);
+    }
+
+    #[test]
+    fn beq_test() {
+        assert!(branch(0, 5, 5));
+        assert!(!branch(0, 5, 6));
+    }
+
+    #[test]
+    fn bne_test() {
+        assert!(branch(1, 5, 6));
+        assert!(!branch(1, 5, 5));
+    }
+
+    #[test]
+    fn blt_test() {
+        assert!(branch(4, -1i32 as Word, 0));
+        assert!(!branch(4, 0, -1i32 as Word));
+        assert!(!branch(4, 3, 3));
+    }
+
+    #[test]
+    fn bge_test() {
+        assert!(branch(5, 0, -1i32 as Word));
+        assert!(branch(5, 3, 3));
+        assert!(!branch(5, -1i32 as Word, 0));
+    }
+
+    #[test]
+    fn bltu_test() {
+        assert!(branch(6, 0, Word::MAX));
+        assert!(!branch(6, Word::MAX, 0));
+    }
+
+    #[test]
+    fn bgeu_test() {
+        assert!(branch(7, Word::MAX, 0));
+        assert!(branch(7, 7, 7));
+        assert!(!branch(7, 0, Word::MAX));
+    }
+
+    #[test]
+    fn branch_forward_test() {
+        let (mut cpu, mut mem) = machine();
+        step(&mut cpu, &mut mem, b(0xffe, 0, 0, 0));
+        assert_eq!(cpu.pc(), PC + 0xffe);
+    }
+
+    #[test]
+    fn lb_test() {
+        assert_eq!(load(0, 0x0000_0080, 0), 0xffff_ff80);
+        assert_eq!(load(0, 0x0000_7f00, -3), 0);
+    }
+
+    #[test]
+    fn lh_test() {
+        assert_eq!(load(1, 0x0000_8001, 0), 0xffff_8001);
+        assert_eq!(load(1, 0x1234_5678, 4), 0x5678);
+    }
+
+    #[test]
+    fn lw_test() {
+        assert_eq!(load(2, 0xdead_beef, 8), 0xdead_beef);
+        assert_eq!(load(2, 0x0102_0304, -4), 0x0102_0304);
+    }
+
+    #[test]
+    fn lbu_test() {
+        assert_eq!(load(4, 0x0000_0080, 0), 0x80);
+    }
+
+    #[test]
+    fn lhu_test() {
+        assert_eq!(load(5, 0xffff_8001, 0), 0x8001);
+    }
+
+    #[test]
+    fn sb_test() {
+        assert_eq!(store(0, 0x1234_5678, 0), 0xaaaa_aa78);
+        assert_eq!(store(0, 0x1234_5678, -8), 0xaaaa_aa78);
+    }
+
+    #[test]
+    fn sh_test() {
+        assert_eq!(store(1, 0x1234_5678, 4), 0xaaaa_5678);
+    }
+
+    #[test]
+    fn sw_test() {
+        assert_eq!(store(2, 0x1234_5678, 0x7ff), 0x1234_5678);
+    }
+
+    #[test]
+    fn addi_test() {
+        assert_eq!(alu_i(0, 1, -2), Word::MAX);
+        assert_eq!(alu_i(0, Word::MAX, 1), 0);
+    }
+
+    #[test]
+    fn slti_test() {
+        assert_eq!(alu_i(2, -5i32 as Word, -4), 1);
+        assert_eq!(alu_i(2, 5, -4), 0);
+    }
+
+    #[test]
+    fn sltiu_test() {
+        // the immediate is sign-extended, then compared unsigned
+        assert_eq!(alu_i(3, 5, -1), 1);
+        assert_eq!(alu_i(3, 0, 1), 1);
+        assert_eq!(alu_i(3, 1, 1), 0);
+    }
+
+    #[test]
+    fn xori_test() {
+        assert_eq!(alu_i(4, 0x0f0f_0f0f, -1), 0xf0f0_f0f0);
+    }
+
+    #[test]
+    fn ori_test() {
+        assert_eq!(alu_i(6, 0x0f00_0000, 0x0f0), 0x0f00_00f0);
+    }
+
+    #[test]
+    fn andi_test() {
+        assert_eq!(alu_i(7, 0xffff_ffff, 0x7f0), 0x7f0);
+    }
+
+    #[test]
+    fn slli_test() {
+        assert_eq!(alu_i(1, 1, 31), 0x8000_0000);
+    }
+
+    #[test]
+    fn srli_test() {
+        assert_eq!(alu_i(5, 0x8000_0000, 31), 1);
+    }
+
+    #[test]
+    fn srai_test() {
+        assert_eq!(alu_i(5, 0x8000_0000, 0x400 | 31), Word::MAX);
+        assert_eq!(alu_i(5, 0x4000_0000, 0x400 | 30), 1);
+    }
+
+    #[test]
+    fn add_test() {
+        assert_eq!(alu_r(0, 0, Word::MAX, 2), 1);
+    }
+
+    #[test]
+    fn sub_test() {
+        assert_eq!(alu_r(0x20, 0, 1, 2), Word::MAX);
+    }
+
+    #[test]
+    fn sll_test() {
+        // only the low 5 bits of rs2 are the shift amount
+        assert_eq!(alu_r(0, 1, 3, 33), 6);
+    }
+
+    #[test]
+    fn slt_test() {
+        assert_eq!(alu_r(0, 2, -1i32 as Word, 0), 1);
+        assert_eq!(alu_r(0, 2, 0, -1i32 as Word), 0);
+    }
+
+    #[test]
+    fn sltu_test() {
+        assert_eq!(alu_r(0, 3, -1i32 as Word, 0), 0);
+        assert_eq!(alu_r(0, 3, 0, -1i32 as Word), 1);
+    }
+
+    #[test]
+    fn xor_test() {
+        assert_eq!(alu_r(0, 4, 0xff00_ff00, 0x0ff0_0ff0), 0xf0f0_f0f0);
+    }
+
+    #[test]
+    fn srl_test() {
+        assert_eq!(alu_r(0, 5, 0x8000_0000, 35), 0x1000_0000);
+    }
+
+    #[test]
+    fn sra_test() {
+        assert_eq!(alu_r(0x20, 5, 0x8000_0000, 35), 0xf000_0000);
+    }
+
+    #[test]
+    fn or_test() {
+        assert_eq!(alu_r(0, 6, 0xff00_0000, 0x0000_00ff), 0xff00_00ff);
+    }
+
+    #[test]
+    fn and_test() {
+        assert_eq!(alu_r(0, 7, 0xff00_ff00, 0x0ff0_0ff0), 0x0f00_0f00);
+    }
+
+    const INT_MIN: Word = SWord::MIN as Word;
+    const NEG1: Word = -1i32 as Word;
+
+    #[test]
+    fn mul_test() {
+        assert_eq!(alu_r(1, 0, 7, NEG1), -7i32 as Word);
+        assert_eq!(alu_r(1, 0, 0x1_0001, 0x1_0001), 0x0002_0001);
+    }
+
+    #[test]
+    fn mulh_test() {
+        assert_eq!(alu_r(1, 1, NEG1, NEG1), 0);
+        assert_eq!(alu_r(1, 1, INT_MIN, INT_MIN), 0x4000_0000);
+        assert_eq!(alu_r(1, 1, INT_MIN, 2), NEG1);
+    }
+
+    #[test]
+    fn mulhsu_test() {
+        // rs1 signed, rs2 unsigned
+        assert_eq!(alu_r(1, 2, NEG1, Word::MAX), NEG1);
+        assert_eq!(alu_r(1, 2, INT_MIN, Word::MAX), INT_MIN);
+        assert_eq!(alu_r(1, 2, 2, Word::MAX), 1);
+    }
+
+    #[test]
+    fn mulhu_test() {
+        assert_eq!(alu_r(1, 3, Word::MAX, Word::MAX), 0xffff_fffe);
+        assert_eq!(alu_r(1, 3, INT_MIN, 2), 1);
+    }
+
+    #[test]
+    fn div_test() {
+        assert_eq!(alu_r(1, 4, -7i32 as Word, 2), -3i32 as Word);
+        assert_eq!(alu_r(1, 4, 7, 0), NEG1);
+        assert_eq!(alu_r(1, 4, INT_MIN, NEG1), INT_MIN);
+    }
+
+    #[test]
+    fn divu_test() {
+        assert_eq!(alu_r(1, 5, NEG1, 2), 0x7fff_ffff);
+        assert_eq!(alu_r(1, 5, 7, 0), Word::MAX);
+        assert_eq!(alu_r(1, 5, INT_MIN, NEG1), 0);
+    }
+
+    #[test]
+    fn rem_test() {
+        assert_eq!(alu_r(1, 6, -7i32 as Word, 2), NEG1);
+        assert_eq!(alu_r(1, 6, 7, 0), 7);
+        assert_eq!(alu_r(1, 6, INT_MIN, NEG1), 0);
+    }
+
+    #[test]
+    fn remu_test() {
+        assert_eq!(alu_r(1, 7, NEG1, 10), 5);
+        assert_eq!(alu_r(1, 7, INT_MIN, 0), INT_MIN);
+        assert_eq!(alu_r(1, 7, INT_MIN, NEG1), INT_MIN);
+    }
+
+    fn amo_inst(f5: u32, rs2: u32, rs1: u32, rd: u32) -> Word {
+        r(f5 << 2, rs2, rs1, 2, rd, 0x2f)
+    }
+
+    /// run an AMO on memory holding `old` with rs2 = `b`, return (rd, new memory)
+    fn amo_op(f5: u32, old: Word, b_: Word) -> (Word, Word) {
+        let (mut cpu, mut mem) = machine();
+        mem.paddr_write(DATA, 4, old);
+        cpu.set_reg(1, DATA);
+        cpu.set_reg(2, b_);
+        assert_eq!(step(&mut cpu, &mut mem, amo_inst(f5, 2, 1, 3)), None);
+        (cpu.reg(3), mem.paddr_read(DATA, 4))
+    }
+
+    #[test]
+    fn lr_sc_test() {
+        let (mut cpu, mut mem) = machine();
+        mem.paddr_write(DATA, 4, 42);
+        cpu.set_reg(1, DATA);
+        cpu.set_reg(2, 7);
+        step(&mut cpu, &mut mem, amo_inst(0b00010, 0, 1, 3));
+        assert_eq!(cpu.reg(3), 42);
+        step(&mut cpu, &mut mem, amo_inst(0b00011, 2, 1, 4));
+        assert_eq!(cpu.reg(4), 0);
+        assert_eq!(mem.paddr_read(DATA, 4), 7);
+
+        // the reservation is consumed by the first sc
+        cpu.set_reg(2, 9);
+        step(&mut cpu, &mut mem, amo_inst(0b00011, 2, 1, 4));
+        assert_eq!(cpu.reg(4), 1);
+        assert_eq!(mem.paddr_read(DATA, 4), 7);
+    }
+
+    #[test]
+    fn sc_without_lr_test() {
+        let (mut cpu, mut mem) = machine();
+        cpu.set_reg(1, DATA);
+        cpu.set_reg(2, 7);
+        step(&mut cpu, &mut mem, amo_inst(0b00011, 2, 1, 3));
+        assert_eq!(cpu.reg(3), 1);
+        assert_eq!(mem.paddr_read(DATA, 4), 0);
+    }
+
+    #[test]
+    fn sc_after_store_test() {
+        let (mut cpu, mut mem) = machine();
+        cpu.set_reg(1, DATA);
+        cpu.set_reg(2, 7);
+        step(&mut cpu, &mut mem, amo_inst(0b00010, 0, 1, 3));
+        // sb to the last byte of the reserved word
+        step(&mut cpu, &mut mem, s(3, 0, 1, 0));
+        step(&mut cpu, &mut mem, amo_inst(0b00011, 2, 1, 3));
+        assert_eq!(cpu.reg(3), 1);
+
+        // a store to another word keeps the reservation
+        step(&mut cpu, &mut mem, amo_inst(0b00010, 0, 1, 3));
+        step(&mut cpu, &mut mem, s(4, 0, 1, 2));
+        step(&mut cpu, &mut mem, amo_inst(0b00011, 2, 1, 3));
+        assert_eq!(cpu.reg(3), 0);
+    }
+
+    #[test]
+    fn sc_after_trap_test() {
+        let (mut cpu, mut mem) = machine();
+        cpu.set_reg(1, DATA);
+        step(&mut cpu, &mut mem, amo_inst(0b00010, 0, 1, 3));
+        assert_eq!(
+            step(&mut cpu, &mut mem, 0x00000073),
+            Some(Exception::EcallFromM)
+        );
+        step(&mut cpu, &mut mem, amo_inst(0b00011, 2, 1, 3));
+        assert_eq!(cpu.reg(3), 1);
+    }
+
+    #[test]
+    fn amo_aq_rl_test() {
+        let (mut cpu, mut mem) = machine();
+        cpu.set_reg(1, DATA);
+        cpu.set_reg(2, 5);
+        step(
+            &mut cpu,
+            &mut mem,
+            amo_inst(0b00000, 2, 1, 3) | (0b11 << 25),
+        );
+        assert_eq!(mem.paddr_read(DATA, 4), 5);
+    }
+
+    #[test]
+    fn amoswap_test() {
+        assert_eq!(amo_op(0b00001, 1, 2), (1, 2));
+    }
+
+    #[test]
+    fn amoadd_test() {
+        assert_eq!(amo_op(0b00000, Word::MAX, 2), (Word::MAX, 1));
+    }
+
+    #[test]
+    fn amoxor_test() {
+        assert_eq!(amo_op(0b00100, 0b1100, 0b1010), (0b1100, 0b0110));
+    }
+
+    #[test]
+    fn amoand_test() {
+        assert_eq!(amo_op(0b01100, 0b1100, 0b1010), (0b1100, 0b1000));
+    }
+
+    #[test]
+    fn amoor_test() {
+        assert_eq!(amo_op(0b01000, 0b1100, 0b1010), (0b1100, 0b1110));
+    }
+
+    #[test]
+    fn amomin_test() {
+        assert_eq!(amo_op(0b10000, 1, NEG1), (1, NEG1));
+    }
+
+    #[test]
+    fn amomax_test() {
+        assert_eq!(amo_op(0b10100, 1, NEG1), (1, 1));
+    }
+
+    #[test]
+    fn amominu_test() {
+        assert_eq!(amo_op(0b11000, 1, NEG1), (1, 1));
+    }
+
+    #[test]
+    fn amomaxu_test() {
+        assert_eq!(amo_op(0b11100, 1, NEG1), (1, NEG1));
+    }
+
+    #[test]
+    fn amo_misaligned_test() {
+        let (mut cpu, mut mem) = machine();
+        cpu.set_reg(1, DATA + 2);
+        cpu.set_reg(2, 5);
+        cpu.set_reg(3, 0x55);
+        let trap = step(&mut cpu, &mut mem, amo_inst(0b00000, 2, 1, 3));
+        assert_eq!(trap, Some(Exception::StoreMisaligned(DATA + 2)));
+        assert_eq!(cpu.reg(3), 0x55);
+        assert_eq!(mem.paddr_read(DATA, 4), 0);
+
+        let (mut cpu, mut mem) = machine();
+        cpu.set_reg(1, DATA + 1);
+        let trap = step(&mut cpu, &mut mem, amo_inst(0b00010, 0, 1, 3));
+        assert_eq!(trap, Some(Exception::LoadMisaligned(DATA + 1)));
+        let trap = step(&mut cpu, &mut mem, amo_inst(0b00011, 0, 1, 3));
+        assert_eq!(trap, Some(Exception::StoreMisaligned(DATA + 1)));
+    }
+
+    /// place a 16-bit `inst` at the current pc and execute it
+    fn step_c(cpu: &mut Riscv32, mem: &mut MemoryBank, inst: Word) -> Option<Exception> {
+        let pc = cpu.pc();
+        mem.paddr_write(pc, 2, inst);
+        let mut executer = Executer::new();
+        executer.set_pc(pc);
+        executer.set_snpc(pc);
+        executer.exec_once(cpu, mem);
+        cpu.set_pc(executer.dnpc());
+        executer.take_exception()
+    }
+
+    #[test]
+    fn rvc_test() {
+        let (mut cpu, mut mem) = machine();
+        // c.li t0, 31
+        step_c(&mut cpu, &mut mem, 0x42fd);
+        assert_eq!(cpu.reg(5), 31);
+        assert_eq!(cpu.pc(), PC + 2);
+        // c.add a0, a1
+        cpu.set_reg(10, 1);
+        cpu.set_reg(11, 2);
+        step_c(&mut cpu, &mut mem, 0x952e);
+        assert_eq!(cpu.reg(10), 3);
+        assert_eq!(cpu.pc(), PC + 4);
+    }
+
+    #[test]
+    fn rvc_jump_test() {
+        // c.jal -2048 links to pc + 2
+        let (mut cpu, mut mem) = machine();
+        step_c(&mut cpu, &mut mem, 0x3001);
+        assert_eq!(cpu.reg(1), PC + 2);
+        assert_eq!(cpu.pc(), PC - 2048);
+
+        // c.jalr t0
+        let (mut cpu, mut mem) = machine();
+        cpu.set_reg(5, PC + 0x42);
+        step_c(&mut cpu, &mut mem, 0x9282);
+        assert_eq!(cpu.reg(1), PC + 2);
+        assert_eq!(cpu.pc(), PC + 0x42);
+
+        // c.bnez a5, 254
+        let (mut cpu, mut mem) = machine();
+        cpu.set_reg(15, 1);
+        step_c(&mut cpu, &mut mem, 0xeffd);
+        assert_eq!(cpu.pc(), PC + 254);
+    }
+
+    #[test]
+    fn rvc_ebreak_test() {
+        let (mut cpu, mut mem) = machine();
+        assert_eq!(
+            step_c(&mut cpu, &mut mem, 0x9002),
+            Some(Exception::Breakpoint)
+        );
+    }
+
+    #[test]
+    fn straddle_test() {
+        let (mut cpu, mut mem) = machine();
+        // c.nop, then addi a0, zero, 5 across the word boundary at PC + 4
+        mem.paddr_write(PC, 2, 0x0001);
+        mem.paddr_write(PC + 2, 4, i(5, 0, 0, 10, 0x13));
+        let mut executer = Executer::new();
+        for _ in 0..2 {
+            executer.set_pc(cpu.pc());
+            executer.set_snpc(cpu.pc());
+            executer.exec_once(&mut cpu, &mut mem);
+            cpu.set_pc(executer.dnpc());
+        }
+        assert_eq!(cpu.reg(10), 5);
+        assert_eq!(cpu.pc(), PC + 6);
+    }
+
+    #[test]
+    fn rvc_invalid_test() {
+        let (mut cpu, mut mem) = machine();
+        // c.fld is not supported
+        let trap = step_c(&mut cpu, &mut mem, 0x2000);
+        assert_eq!(trap, Some(Exception::IllegalInstruction(0x2000)));
+    }
+
+    fn csr_inst(csr: usize, rs1: u32, f3: u32, rd: u32) -> Word {
+        i(csr as i32, rs1, f3, rd, 0x73)
+    }
+
+    #[test]
+    fn csrrw_test() {
+        let (mut cpu, mut mem) = machine();
+        cpu.set_csr(MSCRATCH, 0x11);
+        cpu.set_reg(1, 0x22);
+        step(&mut cpu, &mut mem, csr_inst(MSCRATCH, 1, 1, 2));
+        assert_eq!(cpu.reg(2), 0x11);
+        assert_eq!(cpu.csr(MSCRATCH), Some(0x22));
+    }
+
+    #[test]
+    fn csrrs_test() {
+        let (mut cpu, mut mem) = machine();
+        cpu.set_csr(MSCRATCH, 0b0101);
+        cpu.set_reg(1, 0b0011);
+        step(&mut cpu, &mut mem, csr_inst(MSCRATCH, 1, 2, 2));
+        assert_eq!(cpu.reg(2), 0b0101);
+        assert_eq!(cpu.csr(MSCRATCH), Some(0b0111));
+
+        // csrr of a read-only CSR does not write it
+        assert_eq!(step(&mut cpu, &mut mem, csr_inst(MHARTID, 0, 2, 2)), None);
+        assert_eq!(cpu.reg(2), 0);
+    }
+
+    #[test]
+    fn csrrc_test() {
+        let (mut cpu, mut mem) = machine();
+        cpu.set_csr(MSCRATCH, 0b0101);
+        cpu.set_reg(1, 0b0011);
+        step(&mut cpu, &mut mem, csr_inst(MSCRATCH, 1, 3, 2));
+        assert_eq!(cpu.reg(2), 0b0101);
+        assert_eq!(cpu.csr(MSCRATCH), Some(0b0100));
+    }
+
+    #[test]
+    fn csrrwi_test() {
+        let (mut cpu, mut mem) = machine();
+        step(&mut cpu, &mut mem, csr_inst(MSCRATCH, 31, 5, 2));
+        assert_eq!(cpu.csr(MSCRATCH), Some(31));
+    }
+
+    #[test]
+    fn csrrsi_test() {
+        let (mut cpu, mut mem) = machine();
+        cpu.set_csr(MSCRATCH, 0b0101);
+        step(&mut cpu, &mut mem, csr_inst(MSCRATCH, 0b0011, 6, 2));
+        assert_eq!(cpu.reg(2), 0b0101);
+        assert_eq!(cpu.csr(MSCRATCH), Some(0b0111));
+    }
+
+    #[test]
+    fn csrrci_test() {
+        let (mut cpu, mut mem) = machine();
+        cpu.set_csr(MSCRATCH, 0b0101);
+        step(&mut cpu, &mut mem, csr_inst(MSCRATCH, 0b0011, 7, 2));
+        assert_eq!(cpu.reg(2), 0b0101);
+        assert_eq!(cpu.csr(MSCRATCH), Some(0b0100));
+    }
+
+    #[test]
+    fn csr_warl_test() {
+        let (mut cpu, mut mem) = machine();
+        cpu.set_reg(1, 0x8000_0003);
+        step(&mut cpu, &mut mem, csr_inst(MEPC, 1, 1, 0));
+        assert_eq!(cpu.csr(MEPC), Some(0x8000_0002));
+    }
+
+    #[test]
+    fn csr_illegal_test() {
+        let (mut cpu, mut mem) = machine();
+        cpu.set_reg(2, 0x55);
+        // write to a read-only CSR
+        let inst = csr_inst(MHARTID, 1, 1, 2);
+        assert_eq!(
+            step(&mut cpu, &mut mem, inst),
+            Some(Exception::IllegalInstruction(inst))
+        );
+        assert_eq!(cpu.reg(2), 0x55);
+        // CSR that does not exist
+        let inst = csr_inst(0x7ff, 0, 2, 2);
+        assert_eq!(
+            step(&mut cpu, &mut mem, inst),
+            Some(Exception::IllegalInstruction(inst))
+        );
+        assert_eq!(cpu.reg(2), 0x55);
+    }
+
+    #[test]
+    fn fence_test() {
+        let (mut cpu, mut mem) = machine();
+        assert_eq!(step(&mut cpu, &mut mem, 0x0ff0000f), None);
+        assert_eq!(cpu.pc(), PC + 4);
+    }
+
+    #[test]
+    fn ecall_test() {
+        let (mut cpu, mut mem) = machine();
+        assert_eq!(
+            step(&mut cpu, &mut mem, 0x00000073),
+            Some(Exception::EcallFromM)
+        );
+    }
+
+    #[test]
+    fn ebreak_test() {
+        let (mut cpu, mut mem) = machine();
+        assert_eq!(
+            step(&mut cpu, &mut mem, 0x00100073),
+            Some(Exception::Breakpoint)
+        );
+    }
+
+    #[test]
+    fn load_exception_test() {
+        let (mut cpu, mut mem) = machine();
+        cpu.set_reg(1, DATA + 1);
+        cpu.set_reg(3, 0x55);
+        // lw from a misaligned address leaves rd alone
+        let trap = step(&mut cpu, &mut mem, i(0, 1, 2, 3, 0x03));
+        assert_eq!(trap, Some(Exception::LoadMisaligned(DATA + 1)));
+        assert_eq!(cpu.reg(3), 0x55);
+        // lbu below physical memory
+        cpu.set_reg(1, 0x1000);
+        let trap = step(&mut cpu, &mut mem, i(0, 1, 4, 3, 0x03));
+        assert_eq!(trap, Some(Exception::LoadAccessFault(0x1000)));
+        assert_eq!(cpu.reg(3), 0x55);
+    }
+
+    #[test]
+    fn store_exception_test() {
+        let (mut cpu, mut mem) = machine();
+        cpu.set_reg(1, DATA + 2);
+        cpu.set_reg(2, Word::MAX);
+        let trap = step(&mut cpu, &mut mem, s(0, 2, 1, 2));
+        assert_eq!(trap, Some(Exception::StoreMisaligned(DATA + 2)));
+        assert_eq!(mem.paddr_read(DATA, 4), 0);
+        cpu.set_reg(1, 0);
+        let trap = step(&mut cpu, &mut mem, s(0, 2, 1, 1));
+        assert_eq!(trap, Some(Exception::StoreAccessFault(0)));
+    }
+
+    #[test]
+    fn fetch_exception_test() {
+        let (mut cpu, mut mem) = machine();
+        let mut executer = Executer::new();
+        executer.set_pc(0x1000);
+        executer.set_snpc(0x1000);
+        executer.exec_once(&mut cpu, &mut mem);
+        assert_eq!(
+            executer.take_exception(),
+            Some(Exception::InstAccessFault(0x1000))
+        );
+
+        executer.set_pc(PC + 1);
+        executer.set_snpc(PC + 1);
+        executer.exec_once(&mut cpu, &mut mem);
+        assert_eq!(
+            executer.take_exception(),
+            Some(Exception::InstMisaligned(PC + 1))
+        );
+    }
+
+    #[test]
+    fn mret_test() {
+        let (mut cpu, mut mem) = machine();
+        cpu.set_csr(MEPC, PC + 0x100);
+        step(&mut cpu, &mut mem, 0x30200073);
+        assert_eq!(cpu.pc(), PC + 0x100);
+    }
+
+    #[test]
+    fn ecall_privilege_test() {
+        let (mut cpu, mut mem) = machine();
+        cpu.privilege = Privilege::Supervisor;
+        assert_eq!(
+            step(&mut cpu, &mut mem, 0x00000073),
+            Some(Exception::EcallFromS)
+        );
+        cpu.privilege = Privilege::User;
+        assert_eq!(
+            step(&mut cpu, &mut mem, 0x00000073),
+            Some(Exception::EcallFromU)
+        );
+    }
+
+    #[test]
+    fn sret_test() {
+        let (mut cpu, mut mem) = machine();
+        cpu.privilege = Privilege::Supervisor;
+        cpu.set_csr(SEPC, 0x1000);
+        // SPP = U
+        step(&mut cpu, &mut mem, 0x10200073);
+        assert_eq!(cpu.pc(), 0x1000);
+        assert_eq!(cpu.privilege, Privilege::User);
+
+        // TSR traps it in S-mode, U-mode never has it
+        let (mut cpu, mut mem) = machine();
+        cpu.set_csr(MSTATUS, MSTATUS_TSR);
+        cpu.privilege = Privilege::Supervisor;
+        assert_eq!(
+            step(&mut cpu, &mut mem, 0x10200073),
+            Some(Exception::IllegalInstruction(0x10200073))
+        );
+        cpu.privilege = Privilege::User;
+        assert_eq!(
+            step(&mut cpu, &mut mem, 0x10200073),
+            Some(Exception::IllegalInstruction(0x10200073))
+        );
+    }
+
+    #[test]
+    fn mret_privilege_test() {
+        let (mut cpu, mut mem) = machine();
+        // MPP = S
+        cpu.set_csr(MSTATUS, 1 << 11);
+        cpu.set_csr(MEPC, PC + 0x100);
+        step(&mut cpu, &mut mem, 0x30200073);
+        assert_eq!(cpu.privilege, Privilege::Supervisor);
+        assert_eq!(cpu.pc(), PC + 0x100);
+        assert_eq!(
+            step(&mut cpu, &mut mem, 0x30200073),
+            Some(Exception::IllegalInstruction(0x30200073))
+        );
+    }
+
+    #[test]
+    fn sfence_vma_test() {
+        let inst = r(0b0001001, 2, 1, 0, 0, 0x73);
+        let (mut cpu, mut mem) = machine();
+        cpu.privilege = Privilege::Supervisor;
+        assert_eq!(step(&mut cpu, &mut mem, inst), None);
+        assert_eq!(cpu.pc(), PC + 4);
+        cpu.privilege = Privilege::User;
+        assert_eq!(
+            step(&mut cpu, &mut mem, inst),
+            Some(Exception::IllegalInstruction(inst))
+        );
+        cpu.set_csr(MSTATUS, MSTATUS_TVM);
+        cpu.privilege = Privilege::Supervisor;
+        assert_eq!(
+            step(&mut cpu, &mut mem, inst),
+            Some(Exception::IllegalInstruction(inst))
+        );
+    }
+
+    #[test]
+    fn csr_privilege_test() {
+        let (mut cpu, mut mem) = machine();
+        cpu.privilege = Privilege::Supervisor;
+        let inst = csr_inst(MSCRATCH, 0, 2, 2);
+        assert_eq!(
+            step(&mut cpu, &mut mem, inst),
+            Some(Exception::IllegalInstruction(inst))
+        );
+        cpu.set_csr(SEPC, 0x44);
+        assert_eq!(step(&mut cpu, &mut mem, csr_inst(SEPC, 0, 2, 2)), None);
+        assert_eq!(cpu.reg(2), 0x44);
+    }
+
+    #[test]
+    fn translation_test() {
+        // VA 0 -> DATA, VA 0x1000 -> PC, with a megapage identity map of the bank
+        let (mut cpu, mut mem) = machine();
+        let root = PC + 0x10_0000;
+        let l0 = root + 0x1000;
+        let flags = 0xcf; // D A X W R V
+        mem.paddr_write(root, 4, (l0 >> 12) << 10 | 1);
+        mem.paddr_write(l0, 4, (DATA >> 12) << 10 | flags);
+        mem.paddr_write(l0 + 4, 4, (PC >> 12) << 10 | flags);
+        mem.paddr_write(root + 4 * (PC >> 22), 4, (PC >> 12) << 10 | flags);
+        cpu.set_csr(SATP, 1 << 31 | root >> 12);
+        cpu.privilege = Privilege::Supervisor;
+
+        mem.paddr_write(DATA + 8, 4, 0x1234);
+        mem.paddr_write(PC, 4, i(8, 0, 2, 3, 0x03));
+        let mut executer = Executer::new();
+        executer.set_pc(0x1000);
+        executer.set_snpc(0x1000);
+        executer.exec_once(&mut cpu, &mut mem);
+        assert_eq!(executer.take_exception(), None);
+        assert_eq!(cpu.reg(3), 0x1234);
+        assert_eq!(executer.dnpc(), 0x1004);
+
+        // unmapped fetch and store
+        executer.set_pc(0x2000);
+        executer.set_snpc(0x2000);
+        executer.exec_once(&mut cpu, &mut mem);
+        assert_eq!(
+            executer.take_exception(),
+            Some(Exception::InstPageFault(0x2000))
+        );
+        cpu.set_reg(1, 0x3000);
+        mem.paddr_write(PC, 4, s(0, 0, 1, 2));
+        executer.set_pc(0x1000);
+        executer.set_snpc(0x1000);
+        executer.exec_once(&mut cpu, &mut mem);
+        assert_eq!(
+            executer.take_exception(),
+            Some(Exception::StorePageFault(0x3000))
+        );
+    }
+
+    #[test]
+    fn sfence_flush_test() {
+        // VA 0x1000 -> PC, VA 0 -> DATA then DATA + 0x1000
+        let (mut cpu, mut mem) = machine();
+        let root = PC + 0x10_0000;
+        let l0 = root + 0x1000;
+        let flags = 0xcf;
+        mem.paddr_write(root, 4, (l0 >> 12) << 10 | 1);
+        mem.paddr_write(l0, 4, (DATA >> 12) << 10 | flags);
+        mem.paddr_write(l0 + 4, 4, (PC >> 12) << 10 | flags);
+        mem.paddr_write(DATA, 4, 1);
+        mem.paddr_write(DATA + 0x1000, 4, 2);
+        cpu.set_csr(SATP, 1 << 31 | root >> 12);
+        cpu.privilege = Privilege::Supervisor;
+
+        let run = |cpu: &mut Riscv32, mem: &mut MemoryBank, inst: Word| {
+            mem.paddr_write(PC, 4, inst);
+            let mut executer = Executer::new();
+            executer.set_pc(0x1000);
+            executer.set_snpc(0x1000);
+            executer.exec_once(cpu, mem);
+            executer.take_exception()
+        };
+        let lw = i(0, 0, 2, 3, 0x03);
+        run(&mut cpu, &mut mem, lw);
+        assert_eq!(cpu.reg(3), 1);
+        // the remapping is not seen until the fence
+        mem.paddr_write(l0, 4, ((DATA + 0x1000) >> 12) << 10 | flags);
+        run(&mut cpu, &mut mem, lw);
+        assert_eq!(cpu.reg(3), 1);
+        // sfence.vma zero, zero
+        assert_eq!(
+            run(&mut cpu, &mut mem, r(0b0001001, 0, 0, 0, 0, 0x73)),
+            None
+        );
+        run(&mut cpu, &mut mem, lw);
+        assert_eq!(cpu.reg(3), 2);
+        let (_, dtlb) = cpu.tlbs()[1];
+        assert_eq!((dtlb.hit(), dtlb.miss()), (1, 2));
+    }
+
+    #[test]
+    fn pmp_test() {
+        let (mut cpu, mut mem) = machine();
+        // NAPOT rx over the first 64 KiB of the bank, nothing else for S/U-mode
+        cpu.set_csr(PMPADDR0, (PC >> 2) | 0x1fff);
+        cpu.set_csr(PMPCFG0, 0x1d);
+        cpu.set_reg(1, DATA);
+        assert_eq!(step(&mut cpu, &mut mem, s(0, 0, 1, 2)), None);
+        cpu.privilege = Privilege::Supervisor;
+        let trap = step(&mut cpu, &mut mem, s(0, 0, 1, 2));
+        assert_eq!(trap, Some(Exception::StoreAccessFault(DATA)));
+        assert_eq!(step(&mut cpu, &mut mem, i(0, 1, 2, 3, 0x03)), None);
+        cpu.set_reg(1, PC + 0x1_0000);
+        let trap = step(&mut cpu, &mut mem, i(0, 1, 2, 3, 0x03));
+        assert_eq!(trap, Some(Exception::LoadAccessFault(PC + 0x1_0000)));
+    }
+
+    #[test]
+    fn exception_test() {
+        assert_eq!(Exception::IllegalInstruction(0x13).cause(), 2);
+        assert_eq!(Exception::IllegalInstruction(0x13).tval(PC), 0x13);
+        assert_eq!(Exception::Breakpoint.tval(PC), PC);
+        assert_eq!(Exception::EcallFromM.cause(), 11);
+        assert_eq!(Exception::EcallFromM.tval(PC), 0);
+        assert_eq!(Exception::StoreAccessFault(DATA).cause(), 7);
+        assert_eq!(Exception::EcallFromU.cause(), 8);
+        assert_eq!(Exception::StorePageFault(DATA).cause(), 15);
+        assert_eq!(Exception::StorePageFault(DATA).tval(PC), DATA);
+    }
+
+    #[test]
+    fn x0_test() {
+        let (mut cpu, mut mem) = machine();
+        step(&mut cpu, &mut mem, i(5, 0, 0, 0, 0x13));
+        assert_eq!(cpu.reg(0), 0);
+    }
+
+    #[test]
+    fn invalid_test() {
+        let (mut cpu, mut mem) = machine();
+        let inst = r(0, 0, 0, 0, 0, 0x0b);
+        assert_eq!(
+            step(&mut cpu, &mut mem, inst),
+            Some(Exception::IllegalInstruction(inst))
+        );
+        // c.unimp, the all zero parcel
+        assert_eq!(
+            step(&mut cpu, &mut mem, 0),
+            Some(Exception::IllegalInstruction(0))
+        );
+    }
+
+    const EBREAK: Word = 0x00100073;
+
+    /// write `code` at physical address `addr`
+    fn load_code(mem: &mut MemoryBank, addr: Paddr, code: &[Word]) {
+        for (n, inst) in code.iter().enumerate() {
+            mem.paddr_write(addr + 4 * n as Paddr, 4, *inst);
+        }
+    }
+
+    /// run from `pc` a block at a time until an exception, which is left at the pc raising it
+    fn run_blocks(cpu: &mut Riscv32, mem: &mut MemoryBank, pc: Vaddr) -> Exception {
+        let mut executer = Executer::new();
+        cpu.set_pc(pc);
+        for _ in 0..100 {
+            executer.exec_block(cpu, mem, u64::MAX);
+            if let Some(e) = executer.take_exception() {
+                cpu.set_pc(executer.pc());
+                return e;
+            }
+        }
+        panic!("no exception");
+    }
+
+    #[test]
+    fn block_test() {
+        // a1 counts the iterations of a loop until a0 is down to zero
+        let (mut cpu, mut mem) = machine();
+        let code = [
+            i(3, 0, 0, 10, 0x13),
+            i(-1, 10, 0, 10, 0x13),
+            i(1, 11, 0, 11, 0x13),
+            b(-8, 0, 10, 1),
+            EBREAK,
+        ];
+        load_code(&mut mem, PC, &code);
+        assert_eq!(run_blocks(&mut cpu, &mut mem, PC), Exception::Breakpoint);
+        assert_eq!((cpu.reg(10), cpu.reg(11)), (0, 3));
+        assert_eq!(cpu.pc(), PC + 16);
+        // the blocks at PC, at the loop and at the ebreak, the loop is run once more
+        assert_eq!((cpu.blocks.hit(), cpu.blocks.miss()), (1, 3));
+
+        let mut executer = Executer::new();
+        cpu.set_pc(PC);
+        assert_eq!(executer.exec_block(&mut cpu, &mut mem, u64::MAX), 4);
+        assert_eq!(cpu.pc(), PC + 4);
+        assert_eq!(executer.exec_block(&mut cpu, &mut mem, 2), 2);
+        assert_eq!(cpu.pc(), PC + 12);
+        assert_eq!(executer.take_exception(), None);
+    }
+
+    #[test]
+    fn block_exception_test() {
+        // the faulting load ends the block, pc is left on it
+        let (mut cpu, mut mem) = machine();
+        load_code(
+            &mut mem,
+            PC,
+            &[i(1, 0, 0, 10, 0x13), i(0, 0, 2, 5, 0x03), EBREAK],
+        );
+        let mut executer = Executer::new();
+        cpu.set_pc(PC);
+        assert_eq!(executer.exec_block(&mut cpu, &mut mem, u64::MAX), 2);
+        assert_eq!(
+            executer.take_exception(),
+            Some(Exception::LoadAccessFault(0))
+        );
+        assert_eq!(executer.pc(), PC + 4);
+        assert_eq!(cpu.pc(), PC + 8);
+        // so is an instruction that can not be decoded, from the slow path
+        cpu.set_pc(DATA);
+        assert_eq!(executer.exec_block(&mut cpu, &mut mem, u64::MAX), 1);
+        assert_eq!(
+            executer.take_exception(),
+            Some(Exception::IllegalInstruction(0))
+        );
+        assert_eq!(executer.pc(), DATA);
+    }
+
+    #[test]
+    fn self_modifying_test() {
+        // sw x2, 8(x1) rewrites the addi behind it in the same block
+        let (mut cpu, mut mem) = machine();
+        let code = [s(8, 2, 1, 2), 0x13, i(1, 0, 0, 10, 0x13), EBREAK];
+        load_code(&mut mem, PC, &code);
+        cpu.set_reg(1, PC);
+        cpu.set_reg(2, i(2, 0, 0, 10, 0x13));
+        assert_eq!(run_blocks(&mut cpu, &mut mem, PC), Exception::Breakpoint);
+        assert_eq!(cpu.reg(10), 2);
+        // the store also drops the block cached at the rewritten instruction
+        assert_eq!(
+            run_blocks(&mut cpu, &mut mem, PC + 8),
+            Exception::Breakpoint
+        );
+        cpu.set_reg(2, i(3, 0, 0, 10, 0x13));
+        assert_eq!(run_blocks(&mut cpu, &mut mem, PC), Exception::Breakpoint);
+        assert_eq!(cpu.reg(10), 3);
+        assert_eq!(
+            run_blocks(&mut cpu, &mut mem, PC + 8),
+            Exception::Breakpoint
+        );
+        assert_eq!(cpu.reg(10), 3);
+    }
+
+    #[test]
+    fn fence_i_test() {
+        // code written behind the back of the hart runs once it executes FENCE.I
+        let (mut cpu, mut mem) = machine();
+        load_code(&mut mem, PC, &[i(1, 0, 0, 10, 0x13), EBREAK]);
+        load_code(&mut mem, DATA, &[0x0000100f, EBREAK]);
+        run_blocks(&mut cpu, &mut mem, PC);
+        mem.paddr_write(PC, 4, i(2, 0, 0, 10, 0x13));
+        run_blocks(&mut cpu, &mut mem, PC);
+        assert_eq!(cpu.reg(10), 1);
+        assert_eq!(run_blocks(&mut cpu, &mut mem, DATA), Exception::Breakpoint);
+        run_blocks(&mut cpu, &mut mem, PC);
+        assert_eq!(cpu.reg(10), 2);
+    }
+
+    #[test]
+    fn block_remap_test() {
+        // VA 0x1000 -> PC + 0x2000, then PC + 0x3000, each holding its own code
+        let (mut cpu, mut mem) = machine();
+        let root = PC + 0x10_0000;
+        let l0 = root + 0x1000;
+        let flags = 0xcf;
+        mem.paddr_write(root, 4, (l0 >> 12) << 10 | 1);
+        mem.paddr_write(l0 + 4, 4, ((PC + 0x2000) >> 12) << 10 | flags);
+        load_code(&mut mem, PC + 0x2000, &[i(1, 0, 0, 10, 0x13), EBREAK]);
+        load_code(&mut mem, PC + 0x3000, &[i(2, 0, 0, 10, 0x13), EBREAK]);
+        cpu.set_csr(SATP, 1 << 31 | root >> 12);
+        cpu.privilege = Privilege::Supervisor;
+        assert_eq!(
+            run_blocks(&mut cpu, &mut mem, 0x1000),
+            Exception::Breakpoint
+        );
+        assert_eq!(cpu.reg(10), 1);
+        mem.paddr_write(l0 + 4, 4, ((PC + 0x3000) >> 12) << 10 | flags);
+        cpu.flush_tlb(None, None);
+        assert_eq!(
+            run_blocks(&mut cpu, &mut mem, 0x1000),
+            Exception::Breakpoint
+        );
+        assert_eq!(cpu.reg(10), 2);
+    }
+
+    #[test]
+    fn block_pmp_test() {
+        // a write to the PMP drops the blocks decoded under the old one
+        let (mut cpu, mut mem) = machine();
+        load_code(&mut mem, PC, &[EBREAK]);
+        run_blocks(&mut cpu, &mut mem, PC);
+        run_blocks(&mut cpu, &mut mem, PC);
+        assert_eq!((cpu.blocks.hit(), cpu.blocks.miss()), (1, 1));
+        cpu.set_csr(PMPADDR0, 0);
+        run_blocks(&mut cpu, &mut mem, PC);
+        assert_eq!((cpu.blocks.hit(), cpu.blocks.miss()), (1, 2));
+    }
 }
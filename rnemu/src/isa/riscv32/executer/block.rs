@@ -0,0 +1,184 @@
+use std::{collections::HashMap, fmt, sync::Arc};
+
+use super::{operand::Operands, rvc, Decode, DECODERS};
+use crate::{
+    common::{Paddr, Vaddr, Word},
+    isa::riscv32::{
+        csr::Privilege,
+        mmu::{self, Access, PAGE_SHIFT},
+        Riscv32,
+    },
+    memory::{MemoryBank, Perm},
+};
+
+/// longest block decoded in one go
+const MAX_BLOCK: usize = 64;
+
+/// An instruction decoded once and run from the cache after that.
+pub struct DecodedInst {
+    /// the 32-bit instruction, expanded if it was compressed
+    pub(super) inst: Word,
+    /// 2 for a compressed instruction, 4 otherwise
+    pub(super) len: Vaddr,
+    pub(super) decoder: &'static dyn Decode,
+    pub(super) operands: Operands,
+}
+
+/// Straight-line code starting at `start`, it ends at the first instruction that can leave the
+/// straight line or change how the next one is fetched, and never crosses a page.
+pub struct Block {
+    start: Paddr,
+    /// one past the last byte
+    end: Paddr,
+    pub(super) insts: Vec<DecodedInst>,
+}
+
+impl Block {
+    /// decode the block at `paddr`, fetched by `cpu` at its current privilege. It stops short
+    /// of an instruction it can not fetch or decode, which leaves it empty if the first one is.
+    pub fn decode(cpu: &Riscv32, mem: &MemoryBank, paddr: Paddr) -> Self {
+        let privilege = mmu::effective_privilege(cpu, Access::Fetch);
+        let page = paddr >> PAGE_SHIFT;
+        let fetch = |addr: Paddr, len: usize| {
+            let in_page = (addr + len as Paddr - 1) >> PAGE_SHIFT == page;
+            (in_page
+                && mem.allows(addr, len, Perm::X)
+                && cpu.csr.pmp().check(addr, len, privilege, Access::Fetch))
+            .then(|| mem.peek(addr, len))
+        };
+        let mut insts = vec![];
+        let mut addr = paddr;
+        while insts.len() < MAX_BLOCK {
+            let Some(lo) = fetch(addr, 2) else {
+                break;
+            };
+            let (inst, len) = if bits!(lo, 1, 0) == 0b11 {
+                match fetch(addr + 2, 2) {
+                    Some(hi) => (lo | (hi << 16), 4),
+                    None => break,
+                }
+            } else {
+                match rvc::expand(lo) {
+                    Some(inst) => (inst, 2),
+                    None => break,
+                }
+            };
+            let Some(decoder) = DECODERS.lookup(inst) else {
+                break;
+            };
+            let decoder = decoder.as_ref();
+            insts.push(DecodedInst {
+                inst,
+                len,
+                decoder,
+                operands: decoder.operands(inst),
+            });
+            addr += len;
+            if ends_block(inst) {
+                break;
+            }
+        }
+        Self {
+            start: paddr,
+            end: addr,
+            insts,
+        }
+    }
+
+    /// whether the `len` bytes at `addr` hold some of its instructions
+    fn overlaps(&self, addr: Paddr, len: usize) -> bool {
+        addr < self.end && self.start < addr.wrapping_add(len as Paddr)
+    }
+}
+
+/// Branches, jumps and SYSTEM instructions may go anywhere, the latter and FENCE.I may also
+/// change the translation, the protection or the code of what follows.
+fn ends_block(inst: Word) -> bool {
+    matches!(
+        bits!(inst, 6, 0),
+        0b1100011 | 0b1101111 | 0b1100111 | 0b1110011 | 0b0001111
+    )
+}
+
+type BlockKey = (Paddr, Privilege);
+
+/// Decoded blocks by their physical address and the privilege they were fetched at. Being
+/// physically tagged, a block stays right whatever the page tables map to it, only a write to
+/// its bytes or a change of the PMP makes it stale.
+#[derive(Default)]
+pub struct BlockCache {
+    blocks: HashMap<BlockKey, Arc<Block>>,
+    /// the blocks in each physical page
+    pages: HashMap<Paddr, Vec<BlockKey>>,
+    /// some block was dropped since the last `take_stale`
+    stale: bool,
+    hit: u64,
+    miss: u64,
+}
+
+impl BlockCache {
+    /// the block at `paddr` fetched at `privilege`
+    pub fn get(&mut self, paddr: Paddr, privilege: Privilege) -> Option<Arc<Block>> {
+        let block = self.blocks.get(&(paddr, privilege)).cloned();
+        match block {
+            Some(_) => self.hit += 1,
+            None => self.miss += 1,
+        }
+        block
+    }
+
+    pub fn insert(&mut self, privilege: Privilege, block: Block) -> Arc<Block> {
+        let key = (block.start, privilege);
+        let block = Arc::new(block);
+        self.blocks.insert(key, block.clone());
+        self.pages.entry(key.0 >> PAGE_SHIFT).or_default().push(key);
+        block
+    }
+
+    /// drop the blocks the `len` bytes written at `addr` overlap
+    pub fn invalidate(&mut self, addr: Paddr, len: usize) {
+        let Some(keys) = self.pages.get_mut(&(addr >> PAGE_SHIFT)) else {
+            return;
+        };
+        let blocks = &mut self.blocks;
+        let stale = &mut self.stale;
+        keys.retain(|key| match blocks.get(key) {
+            Some(block) if block.overlaps(addr, len) => {
+                blocks.remove(key);
+                *stale = true;
+                false
+            }
+            _ => true,
+        });
+    }
+
+    /// drop every block
+    pub fn flush(&mut self) {
+        self.blocks.clear();
+        self.pages.clear();
+        self.stale = true;
+    }
+
+    /// whether some block was dropped since the last call, the one running may be among them
+    pub fn take_stale(&mut self) -> bool {
+        std::mem::take(&mut self.stale)
+    }
+
+    pub fn hit(&self) -> u64 {
+        self.hit
+    }
+
+    pub fn miss(&self) -> u64 {
+        self.miss
+    }
+}
+
+impl fmt::Debug for BlockCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BlockCache")
+            .field("blocks", &self.blocks.len())
+            .field("hit", &self.hit)
+            .field("miss", &self.miss)
+            .finish()
+    }
+}
@@ -0,0 +1,217 @@
+use super::{operand::OperandType, rvc, DECODERS};
+use crate::{
+    common::{SWord, Vaddr, Word},
+    isa::riscv32::csr::csr_name,
+};
+
+/// ABI names of the integer registers
+pub const REG_NAMES: [&str; 32] = [
+    "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1", "a0", "a1", "a2", "a3", "a4",
+    "a5", "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4",
+    "t5", "t6",
+];
+
+/// Disassemble the instruction at `pc` whose bytes are the low ones of `inst`, the way objdump
+/// does: ABI register names, its aliases and absolute branch targets. A compressed instruction
+/// shows as the one it expands to. Return its length in bytes with the text, `None` if the
+/// executer does not know it.
+pub fn disassemble(inst: Word, pc: Vaddr) -> Option<(usize, String)> {
+    let (inst, len) = if bits!(inst, 1, 0) == 0b11 {
+        (inst, 4)
+    } else {
+        (rvc::expand(inst & 0xffff)?, 2)
+    };
+    let (pattern, decoder) = DECODERS.entry(inst)?;
+    let op = decoder.operands(inst);
+    let name = pattern.name.replace('_', ".");
+    let (rd, rs1, rs2) = (REG_NAMES[op.rd], REG_NAMES[op.rs1], REG_NAMES[op.rs2]);
+    let imm = op.imm as SWord;
+    let target = pc.wrapping_add(op.imm);
+    let csr = || {
+        let addr = bits!(inst, 31, 20) as usize;
+        csr_name(addr).map_or_else(|| format!("0x{:x}", addr), String::from)
+    };
+
+    // csrw, csrwi and the like drop the rd of csrrw and csrrwi
+    let alias = format!("csr{}", name.get(4..).unwrap_or_default());
+    let (mnemonic, operands) = match (decoder.operand_type(), pattern.name) {
+        (OperandType::U, _) => (name.as_str(), format!("{rd},0x{:x}", op.imm >> 12)),
+        (OperandType::J, _) if op.rd == 0 => ("j", format!("{target:x}")),
+        (OperandType::J, _) if op.rd == 1 => ("jal", format!("{target:x}")),
+        (OperandType::J, _) => (name.as_str(), format!("{rd},{target:x}")),
+        (OperandType::B, "beq" | "bne") if op.rs2 == 0 => {
+            let alias = if pattern.name == "beq" {
+                "beqz"
+            } else {
+                "bnez"
+            };
+            (alias, format!("{rs1},{target:x}"))
+        }
+        (OperandType::B, "bge") if op.rs1 == 0 => ("blez", format!("{rs2},{target:x}")),
+        (OperandType::B, "bge") if op.rs2 == 0 => ("bgez", format!("{rs1},{target:x}")),
+        (OperandType::B, "blt") if op.rs2 == 0 => ("bltz", format!("{rs1},{target:x}")),
+        (OperandType::B, "blt") if op.rs1 == 0 => ("bgtz", format!("{rs2},{target:x}")),
+        (OperandType::B, _) => (name.as_str(), format!("{rs1},{rs2},{target:x}")),
+        (OperandType::S, _) => (name.as_str(), format!("{rs2},{imm}({rs1})")),
+        (OperandType::I, "jalr") => match (op.rd, op.rs1, imm) {
+            (0, 1, 0) => ("ret", String::new()),
+            (0, _, 0) => ("jr", rs1.to_string()),
+            (1, _, 0) => ("jalr", rs1.to_string()),
+            _ => (name.as_str(), format!("{rd},{imm}({rs1})")),
+        },
+        (OperandType::I, "lb" | "lh" | "lw" | "lbu" | "lhu") => {
+            (name.as_str(), format!("{rd},{imm}({rs1})"))
+        }
+        (OperandType::I, "slli" | "srli" | "srai") => {
+            (name.as_str(), format!("{rd},{rs1},0x{:x}", op.imm & 0x1f))
+        }
+        (OperandType::I, "addi") if op.rd == 0 && op.rs1 == 0 && imm == 0 => ("nop", String::new()),
+        (OperandType::I, "addi") if op.rs1 == 0 => ("li", format!("{rd},{imm}")),
+        (OperandType::I, "addi") if imm == 0 => ("mv", format!("{rd},{rs1}")),
+        (OperandType::I, "xori") if imm == -1 => ("not", format!("{rd},{rs1}")),
+        (OperandType::I, "sltiu") if imm == 1 => ("seqz", format!("{rd},{rs1}")),
+        (OperandType::I, "csrrs") if op.rs1 == 0 => ("csrr", format!("{rd},{}", csr())),
+        (OperandType::I, "csrrw" | "csrrs" | "csrrc") if op.rd == 0 => {
+            (alias.as_str(), format!("{},{rs1}", csr()))
+        }
+        (OperandType::I, "csrrw" | "csrrs" | "csrrc") => {
+            (name.as_str(), format!("{rd},{},{rs1}", csr()))
+        }
+        // the rs1 field is the immediate
+        (OperandType::I, "csrrwi" | "csrrsi" | "csrrci") if op.rd == 0 => {
+            (alias.as_str(), format!("{},{}", csr(), op.rs1))
+        }
+        (OperandType::I, "csrrwi" | "csrrsi" | "csrrci") => {
+            (name.as_str(), format!("{rd},{},{}", csr(), op.rs1))
+        }
+        (OperandType::I, _) => (name.as_str(), format!("{rd},{rs1},{imm}")),
+        (OperandType::R, "lr_w") => (name.as_str(), format!("{rd},({rs1})")),
+        (OperandType::R, n) if n == "sc_w" || n.starts_with("amo") => {
+            (name.as_str(), format!("{rd},{rs2},({rs1})"))
+        }
+        (OperandType::R, "sub") if op.rs1 == 0 => ("neg", format!("{rd},{rs2}")),
+        (OperandType::R, "sltu") if op.rs1 == 0 => ("snez", format!("{rd},{rs2}")),
+        (OperandType::R, "slt") if op.rs2 == 0 => ("sltz", format!("{rd},{rs1}")),
+        (OperandType::R, "slt") if op.rs1 == 0 => ("sgtz", format!("{rd},{rs2}")),
+        (OperandType::R, _) => (name.as_str(), format!("{rd},{rs1},{rs2}")),
+        (OperandType::N, "fence") => fence(inst),
+        (OperandType::N, "sfence_vma") => {
+            let (rs1, rs2) = (bits!(inst, 19, 15) as usize, bits!(inst, 24, 20) as usize);
+            let operands = match (rs1, rs2) {
+                (0, 0) => String::new(),
+                (_, 0) => REG_NAMES[rs1].to_string(),
+                _ => format!("{},{}", REG_NAMES[rs1], REG_NAMES[rs2]),
+            };
+            (name.as_str(), operands)
+        }
+        (OperandType::N, _) => (name.as_str(), String::new()),
+    };
+    let text = match operands.is_empty() {
+        true => mnemonic.to_string(),
+        false => format!("{mnemonic}\t{operands}"),
+    };
+    Some((len, text))
+}
+
+/// `fence pred,succ`, plain `fence` when both are every kind of access
+fn fence(inst: Word) -> (&'static str, String) {
+    let set = |bits: Word| -> String {
+        "iorw"
+            .chars()
+            .enumerate()
+            .filter(|(n, _)| bits & (0b1000 >> n) != 0)
+            .map(|(_, c)| c)
+            .collect()
+    };
+    let (fm, pred, succ) = (
+        bits!(inst, 31, 28),
+        bits!(inst, 27, 24),
+        bits!(inst, 23, 20),
+    );
+    match (fm, pred, succ) {
+        (0b1000, 0b0011, 0b0011) => ("fence.tso", String::new()),
+        (_, 0b1111, 0b1111) => ("fence", String::new()),
+        _ => ("fence", format!("{},{}", set(pred), set(succ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PC: Vaddr = 0x8000_0000;
+
+    fn dis(inst: Word) -> String {
+        disassemble(inst, PC).expect("unknown instruction").1
+    }
+
+    #[test]
+    fn disassemble_test() {
+        let cases = [
+            (0x00000297, "auipc\tt0,0x0"),
+            (0x00028823, "sb\tzero,16(t0)"),
+            (0x0102c503, "lbu\ta0,16(t0)"),
+            (0x00100073, "ebreak"),
+            (0xff010113, "addi\tsp,sp,-16"),
+            (0x02b50533, "mul\ta0,a0,a1"),
+            (0x00251513, "slli\ta0,a0,0x2"),
+            (0x40355513, "srai\ta0,a0,0x3"),
+            (0x1005a52f, "lr.w\ta0,(a1)"),
+            (0x08c5a52f, "amoswap.w\ta0,a2,(a1)"),
+            (0x30200073, "mret"),
+            (0x0000100f, "fence.i"),
+            (0x12000073, "sfence.vma"),
+            (0x800000b7, "lui\tra,0x80000"),
+            (0x00b50463, "beq\ta0,a1,80000008"),
+            (0xfe0548e3, "bltz\ta0,7ffffff0"),
+            (0x34151073, "csrw\tmepc,a0"),
+            (0x7c002573, "csrr\ta0,0x7c0"),
+            (0x7c0595f3, "csrrw\ta1,0x7c0,a1"),
+        ];
+        for (inst, text) in cases {
+            assert_eq!(dis(inst), text, "{:08x}", inst);
+        }
+        assert_eq!(disassemble(0x0b, PC), None);
+    }
+
+    #[test]
+    fn alias_test() {
+        let cases = [
+            (0x00000013, "nop"),
+            (0x00100513, "li\ta0,1"),
+            (0x00050593, "mv\ta1,a0"),
+            (0x00008067, "ret"),
+            (0x008000ef, "jal\t80000008"),
+            (0x0000006f, "j\t80000000"),
+            (0x00050463, "beqz\ta0,80000008"),
+            (0x30002573, "csrr\ta0,mstatus"),
+            (0x30046073, "csrsi\tmstatus,8"),
+            (0x40a00533, "neg\ta0,a0"),
+            (0x0ff0000f, "fence"),
+            (0x0330000f, "fence\trw,rw"),
+        ];
+        for (inst, text) in cases {
+            assert_eq!(dis(inst), text, "{:08x}", inst);
+        }
+    }
+
+    #[test]
+    fn compressed_test() {
+        // c.li a0,1 and c.jr ra, in the low half of what was read at pc
+        assert_eq!(disassemble(0x1234_4505, PC), Some((2, "li\ta0,1".into())));
+        assert_eq!(disassemble(0x8082, PC), Some((2, "ret".into())));
+        // c.unimp
+        assert_eq!(disassemble(0, PC), None);
+    }
+
+    #[test]
+    fn coverage_test() {
+        // every pattern of the executer, with its operand fields zero, disassembles
+        for pattern in DECODERS.patterns() {
+            let (len, text) =
+                disassemble(pattern.key, PC).unwrap_or_else(|| panic!("{}", pattern.name));
+            assert_eq!(len, 4);
+            assert!(!text.is_empty());
+        }
+    }
+}
@@ -3,9 +3,10 @@ use crate::{
     isa::{Riscv32, ISA},
 };
 
-pub trait OperandHelper: Send {
-    fn decode_operand(&self, inst: Word, isa: &Riscv32) -> Args;
+pub trait OperandHelper: Send + Sync {
+    fn decode_operand(&self, inst: Word) -> Operands;
 }
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum OperandType {
     I,
     U,
@@ -23,6 +24,32 @@ pub struct Args {
     pub imm: Word,
 }
 
+/// The register numbers and immediate of an instruction, all that decoding it yields. An
+/// operand the type does not have reads x0, which is always zero.
+#[derive(Debug, Clone, Copy)]
+pub struct Operands {
+    pub rd: usize,
+    pub rs1: usize,
+    pub rs2: usize,
+    pub imm: Word,
+}
+
+impl Operands {
+    pub fn new(rd: Word, rs1: Word, rs2: Word, imm: Word) -> Self {
+        Self {
+            rd: rd as usize,
+            rs1: rs1 as usize,
+            rs2: rs2 as usize,
+            imm,
+        }
+    }
+
+    /// read the source registers
+    pub fn read(&self, isa: &Riscv32) -> Args {
+        Args::new(self.rd, isa.reg(self.rs1), isa.reg(self.rs2), self.imm)
+    }
+}
+
 impl Args {
     pub fn new(rd: usize, src1: Word, src2: Word, imm: Word) -> Self {
         Self {
@@ -36,79 +63,67 @@ impl Args {
 
 pub struct ROperand;
 impl OperandHelper for ROperand {
-    fn decode_operand(&self, inst: Word, isa: &Riscv32) -> Args {
+    fn decode_operand(&self, inst: Word) -> Operands {
         let rs1 = bits!(inst, 19, 15);
         let rs2 = bits!(inst, 24, 20);
         let rd = bits!(inst, 11, 7);
-        let src1 = isa.reg(rs1 as usize);
-        let src2 = isa.reg(rs2 as usize);
-        let imm = 0;
-        Args::new(rd as usize, src1, src2, imm)
+        Operands::new(rd, rs1, rs2, 0)
     }
 }
 pub struct BOperand;
 impl OperandHelper for BOperand {
-    fn decode_operand(&self, inst: Word, isa: &Riscv32) -> Args {
+    fn decode_operand(&self, inst: Word) -> Operands {
         let rs1 = bits!(inst, 19, 15);
         let rs2 = bits!(inst, 24, 20);
         let rd = bits!(inst, 11, 7);
-        let src1 = isa.reg(rs1 as usize);
-        let src2 = isa.reg(rs2 as usize);
-        let imm = (sext!(bits!(inst, 31, 31), 1) << 20)
-            | (bits!(inst, 30, 21) << 1)
-            | (bits!(inst, 20, 20) << 11)
-            | (bits!(inst, 19, 12) << 12);
-        Args::new(rd as usize, src1, src2, imm)
+        let imm = (sext!(bits!(inst, 31, 31), 1) << 12)
+            | (bits!(inst, 30, 25) << 5)
+            | (bits!(inst, 11, 8) << 1)
+            | (bits!(inst, 7, 7) << 11);
+        Operands::new(rd, rs1, rs2, imm)
     }
 }
 pub struct UOperand;
 impl OperandHelper for UOperand {
-    fn decode_operand(&self, inst: Word, isa: &Riscv32) -> Args {
-        let rs1 = bits!(inst, 19, 15);
-        let rs2 = bits!(inst, 24, 20);
+    fn decode_operand(&self, inst: Word) -> Operands {
         let rd = bits!(inst, 11, 7);
         let imm = sext!(bits!(inst, 31, 12), 20) << 12;
-        Args::new(rd as usize, 0, 0, imm)
+        Operands::new(rd, 0, 0, imm)
     }
 }
 pub struct JOperand;
 impl OperandHelper for JOperand {
-    fn decode_operand(&self, inst: Word, isa: &Riscv32) -> Args {
+    fn decode_operand(&self, inst: Word) -> Operands {
         let imm = (sext!(bits!(inst, 31, 31), 1) << 20)
             | (bits!(inst, 30, 21) << 1)
             | (bits!(inst, 20, 20) << 11)
             | (bits!(inst, 19, 12) << 12);
-        Args::new(0, 0, 0, imm)
+        let rd = bits!(inst, 11, 7);
+        Operands::new(rd, 0, 0, imm)
     }
 }
 pub struct SOperand;
 impl OperandHelper for SOperand {
-    fn decode_operand(&self, inst: Word, isa: &Riscv32) -> Args {
+    fn decode_operand(&self, inst: Word) -> Operands {
         let rs1 = bits!(inst, 19, 15);
         let rs2 = bits!(inst, 24, 20);
         let rd = bits!(inst, 11, 7);
-        let src1 = isa.reg(rs1 as usize);
-        let src2 = isa.reg(rs2 as usize);
         let imm = (sext!(bits!(inst, 31, 25), 7) << 5) | bits!(inst, 11, 7);
-
-        Args::new(rd as usize, src1, src2, imm)
+        Operands::new(rd, rs1, rs2, imm)
     }
 }
 pub struct NOperand;
 impl OperandHelper for NOperand {
-    fn decode_operand(&self, inst: Word, isa: &Riscv32) -> Args {
-        Args::new(0, 0, 0, 0)
+    fn decode_operand(&self, inst: Word) -> Operands {
+        Operands::new(0, 0, 0, 0)
     }
 }
 pub struct IOperand;
 impl OperandHelper for IOperand {
-    fn decode_operand(&self, inst: Word, isa: &Riscv32) -> Args {
+    fn decode_operand(&self, inst: Word) -> Operands {
         let rs1 = bits!(inst, 19, 15);
-        let rs2 = bits!(inst, 24, 20);
         let rd = bits!(inst, 11, 7);
-        let src1 = isa.reg(rs1 as usize);
-        let src2 = 0;
         let imm = sext!(bits!(inst, 31, 20), 12);
-        Args::new(rd as usize, src1, src2, imm)
+        Operands::new(rd, rs1, 0, imm)
     }
 }
@@ -0,0 +1,253 @@
+use crate::common::Word;
+
+const OP_LOAD: Word = 0x03;
+const OP_STORE: Word = 0x23;
+const OP_IMM: Word = 0x13;
+const OP_LUI: Word = 0x37;
+const OP_REG: Word = 0x33;
+const OP_BRANCH: Word = 0x63;
+const OP_JALR: Word = 0x67;
+const OP_JAL: Word = 0x6f;
+const EBREAK: Word = 0x0010_0073;
+
+fn r_type(funct7: Word, rs2: Word, rs1: Word, funct3: Word, rd: Word) -> Word {
+    (funct7 << 25) | (rs2 << 20) | (rs1 << 15) | (funct3 << 12) | (rd << 7) | OP_REG
+}
+
+fn i_type(imm: Word, rs1: Word, funct3: Word, rd: Word, opcode: Word) -> Word {
+    (bits!(imm, 11, 0) << 20) | (rs1 << 15) | (funct3 << 12) | (rd << 7) | opcode
+}
+
+fn s_type(imm: Word, rs2: Word, rs1: Word) -> Word {
+    (bits!(imm, 11, 5) << 25)
+        | (rs2 << 20)
+        | (rs1 << 15)
+        | (0b010 << 12)
+        | (bits!(imm, 4, 0) << 7)
+        | OP_STORE
+}
+
+fn b_type(imm: Word, rs1: Word, funct3: Word) -> Word {
+    (bits!(imm, 12, 12) << 31)
+        | (bits!(imm, 10, 5) << 25)
+        | (rs1 << 15)
+        | (funct3 << 12)
+        | (bits!(imm, 4, 1) << 8)
+        | (bits!(imm, 11, 11) << 7)
+        | OP_BRANCH
+}
+
+fn j_type(imm: Word, rd: Word) -> Word {
+    (bits!(imm, 20, 20) << 31)
+        | (bits!(imm, 10, 1) << 21)
+        | (bits!(imm, 11, 11) << 20)
+        | (bits!(imm, 19, 12) << 12)
+        | (rd << 7)
+        | OP_JAL
+}
+
+/// offset of C.J/C.JAL, `imm[11|4|9:8|10|6|7|3:1|5]`
+fn cj_imm(inst: Word) -> Word {
+    let imm = (bits!(inst, 12, 12) << 11)
+        | (bits!(inst, 11, 11) << 4)
+        | (bits!(inst, 10, 9) << 8)
+        | (bits!(inst, 8, 8) << 10)
+        | (bits!(inst, 7, 7) << 6)
+        | (bits!(inst, 6, 6) << 7)
+        | (bits!(inst, 5, 3) << 1)
+        | (bits!(inst, 2, 2) << 5);
+    sext!(imm, 12)
+}
+
+/// offset of C.BEQZ/C.BNEZ, `imm[8|4:3]` and `imm[7:6|2:1|5]`
+fn cb_imm(inst: Word) -> Word {
+    let imm = (bits!(inst, 12, 12) << 8)
+        | (bits!(inst, 11, 10) << 3)
+        | (bits!(inst, 6, 5) << 6)
+        | (bits!(inst, 4, 3) << 1)
+        | (bits!(inst, 2, 2) << 5);
+    sext!(imm, 9)
+}
+
+/// Expand a 16-bit RVC instruction to the 32-bit instruction it stands for,
+/// `None` for reserved encodings and the F/D/RV64 only ones.
+pub fn expand(inst: Word) -> Option<Word> {
+    let rd = bits!(inst, 11, 7);
+    let rs2 = bits!(inst, 6, 2);
+    // the 3-bit register fields rd'/rs1'/rs2' name x8..x15
+    let rs1p = bits!(inst, 9, 7) + 8;
+    let rs2p = bits!(inst, 4, 2) + 8;
+    let bit12 = bits!(inst, 12, 12);
+    let imm6 = sext!((bit12 << 5) | bits!(inst, 6, 2), 6);
+
+    let ret = match (bits!(inst, 1, 0), bits!(inst, 15, 13)) {
+        // c.addi4spn
+        (0b00, 0b000) => {
+            let nzuimm = (bits!(inst, 12, 11) << 4)
+                | (bits!(inst, 10, 7) << 6)
+                | (bits!(inst, 6, 6) << 2)
+                | (bits!(inst, 5, 5) << 3);
+            if nzuimm == 0 {
+                return None;
+            }
+            i_type(nzuimm, 2, 0b000, rs2p, OP_IMM)
+        }
+        // c.lw
+        (0b00, 0b010) => {
+            let uimm =
+                (bits!(inst, 12, 10) << 3) | (bits!(inst, 6, 6) << 2) | (bits!(inst, 5, 5) << 6);
+            i_type(uimm, rs1p, 0b010, rs2p, OP_LOAD)
+        }
+        // c.sw
+        (0b00, 0b110) => {
+            let uimm =
+                (bits!(inst, 12, 10) << 3) | (bits!(inst, 6, 6) << 2) | (bits!(inst, 5, 5) << 6);
+            s_type(uimm, rs2p, rs1p)
+        }
+        // c.addi, c.nop
+        (0b01, 0b000) => i_type(imm6, rd, 0b000, rd, OP_IMM),
+        // c.jal
+        (0b01, 0b001) => j_type(cj_imm(inst), 1),
+        // c.li
+        (0b01, 0b010) => i_type(imm6, 0, 0b000, rd, OP_IMM),
+        // c.addi16sp
+        (0b01, 0b011) if rd == 2 => {
+            let nzimm = (bit12 << 9)
+                | (bits!(inst, 6, 6) << 4)
+                | (bits!(inst, 5, 5) << 6)
+                | (bits!(inst, 4, 3) << 7)
+                | (bits!(inst, 2, 2) << 5);
+            if nzimm == 0 {
+                return None;
+            }
+            i_type(sext!(nzimm, 10), 2, 0b000, 2, OP_IMM)
+        }
+        // c.lui
+        (0b01, 0b011) => {
+            if imm6 == 0 {
+                return None;
+            }
+            (imm6 << 12) | (rd << 7) | OP_LUI
+        }
+        (0b01, 0b100) => match bits!(inst, 11, 10) {
+            // shamt[5] must be zero on RV32
+            0b00 | 0b01 if bit12 == 1 => return None,
+            // c.srli
+            0b00 => i_type(rs2, rs1p, 0b101, rs1p, OP_IMM),
+            // c.srai
+            0b01 => i_type(0x400 | rs2, rs1p, 0b101, rs1p, OP_IMM),
+            // c.andi
+            0b10 => i_type(imm6, rs1p, 0b111, rs1p, OP_IMM),
+            // c.subw/c.addw are RV64 only
+            _ if bit12 == 1 => return None,
+            // c.sub, c.xor, c.or, c.and
+            _ => match bits!(inst, 6, 5) {
+                0b00 => r_type(0b0100000, rs2p, rs1p, 0b000, rs1p),
+                0b01 => r_type(0, rs2p, rs1p, 0b100, rs1p),
+                0b10 => r_type(0, rs2p, rs1p, 0b110, rs1p),
+                _ => r_type(0, rs2p, rs1p, 0b111, rs1p),
+            },
+        },
+        // c.j
+        (0b01, 0b101) => j_type(cj_imm(inst), 0),
+        // c.beqz
+        (0b01, 0b110) => b_type(cb_imm(inst), rs1p, 0b000),
+        // c.bnez
+        (0b01, 0b111) => b_type(cb_imm(inst), rs1p, 0b001),
+        // c.slli
+        (0b10, 0b000) => {
+            if bit12 == 1 {
+                return None;
+            }
+            i_type(rs2, rd, 0b001, rd, OP_IMM)
+        }
+        // c.lwsp
+        (0b10, 0b010) => {
+            if rd == 0 {
+                return None;
+            }
+            let uimm = (bit12 << 5) | (bits!(inst, 6, 4) << 2) | (bits!(inst, 3, 2) << 6);
+            i_type(uimm, 2, 0b010, rd, OP_LOAD)
+        }
+        (0b10, 0b100) => match (bit12, rd, rs2) {
+            (0, 0, 0) => return None,
+            // c.jr
+            (0, _, 0) => i_type(0, rd, 0b000, 0, OP_JALR),
+            // c.mv
+            (0, _, _) => r_type(0, rs2, 0, 0b000, rd),
+            // c.ebreak
+            (_, 0, 0) => EBREAK,
+            // c.jalr
+            (_, _, 0) => i_type(0, rd, 0b000, 1, OP_JALR),
+            // c.add
+            _ => r_type(0, rs2, rd, 0b000, rd),
+        },
+        // c.swsp
+        (0b10, 0b110) => {
+            let uimm = (bits!(inst, 12, 9) << 2) | (bits!(inst, 8, 7) << 6);
+            s_type(uimm, rs2, 2)
+        }
+        _ => return None,
+    };
+    Some(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expand_test() {
+        // pairs assembled with llvm-mc, with and without +c
+        let cases: [(Word, Word); 27] = [
+            (0x1fe0, 0x3fc10413), // c.addi4spn s0, sp, 1020
+            (0x5fe8, 0x07c7a503), // c.lw a0, 124(a5)
+            (0xc0ac, 0x04b4a023), // c.sw a1, 64(s1)
+            (0x0001, 0x00000013), // c.nop
+            (0x1501, 0xfe050513), // c.addi a0, -32
+            (0x3001, 0x801ff0ef), // c.jal -2048
+            (0x42fd, 0x01f00293), // c.li t0, 31
+            (0x7101, 0xe0010113), // c.addi16sp sp, -512
+            (0x7785, 0xfffe17b7), // c.lui a5, 0xfffe1
+            (0x80fd, 0x01f4d493), // c.srli s1, 31
+            (0x8685, 0x4016d693), // c.srai a3, 1
+            (0x9b7d, 0xfff77713), // c.andi a4, -1
+            (0x8c1d, 0x40f40433), // c.sub s0, a5
+            (0x8c3d, 0x00f44433), // c.xor s0, a5
+            (0x8c5d, 0x00f46433), // c.or s0, a5
+            (0x8c7d, 0x00f47433), // c.and s0, a5
+            (0xaffd, 0x7fe0006f), // c.j 2046
+            (0xd001, 0xf00400e3), // c.beqz s0, -256
+            (0xeffd, 0x0e079f63), // c.bnez a5, 254
+            (0x037e, 0x01f31313), // c.slli t1, 31
+            (0x50fe, 0x0fc12083), // c.lwsp ra, 252(sp)
+            (0x8082, 0x00008067), // c.jr ra
+            (0x852e, 0x00b00533), // c.mv a0, a1
+            (0x9002, 0x00100073), // c.ebreak
+            (0x9282, 0x000280e7), // c.jalr t0
+            (0x952e, 0x00b50533), // c.add a0, a1
+            (0xdf86, 0x0e112e23), // c.swsp ra, 252(sp)
+        ];
+        for (c, e) in cases {
+            assert_eq!(expand(c), Some(e), "{c:#06x}");
+        }
+    }
+
+    #[test]
+    fn reserved_test() {
+        // all zero is defined illegal
+        assert_eq!(expand(0x0000), None);
+        // c.addi16sp/c.lui with zero immediate
+        assert_eq!(expand(0x6101), None);
+        assert_eq!(expand(0x6781), None);
+        // c.lwsp to x0, c.jr x0
+        assert_eq!(expand(0x4002), None);
+        assert_eq!(expand(0x8002), None);
+        // c.srli with shamt[5] set, c.slli with shamt[5] set
+        assert_eq!(expand(0x9005), None);
+        assert_eq!(expand(0x1002), None);
+        // c.subw and c.fld
+        assert_eq!(expand(0x9c1d), None);
+        assert_eq!(expand(0x2000), None);
+    }
+}
@@ -0,0 +1,436 @@
+use super::{
+    csr::{Privilege, MSTATUS_MPRV, MSTATUS_MXR, MSTATUS_SUM},
+    pmp::Pmp,
+    tlb::TlbEntry,
+    Exception, Riscv32,
+};
+use crate::{
+    common::{Paddr, Vaddr, Word},
+    memory::{MemoryBank, Perm},
+};
+
+pub const PAGE_SHIFT: usize = 12;
+const VPN_BITS: usize = 10;
+const LEVELS: usize = 2;
+const PTE_SIZE: u64 = 4;
+
+pub const SATP_MODE: Word = 1 << 31;
+
+const PTE_V: Word = 1 << 0;
+const PTE_R: Word = 1 << 1;
+const PTE_W: Word = 1 << 2;
+const PTE_X: Word = 1 << 3;
+const PTE_U: Word = 1 << 4;
+const PTE_G: Word = 1 << 5;
+const PTE_A: Word = 1 << 6;
+const PTE_D: Word = 1 << 7;
+
+/// Kind of memory access, selecting the permission checked and the exception raised.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Fetch,
+    Load,
+    /// stores and AMOs
+    Store,
+}
+
+impl Access {
+    /// what the region accessed has to allow, AMOs read what they store to
+    pub fn perm(self) -> Perm {
+        match self {
+            Access::Fetch => Perm::X,
+            Access::Load => Perm::R,
+            Access::Store => Perm::R | Perm::W,
+        }
+    }
+
+    pub fn misaligned(self, addr: Vaddr) -> Exception {
+        match self {
+            Access::Fetch => Exception::InstMisaligned(addr),
+            Access::Load => Exception::LoadMisaligned(addr),
+            Access::Store => Exception::StoreMisaligned(addr),
+        }
+    }
+
+    pub fn access_fault(self, addr: Vaddr) -> Exception {
+        match self {
+            Access::Fetch => Exception::InstAccessFault(addr),
+            Access::Load => Exception::LoadAccessFault(addr),
+            Access::Store => Exception::StoreAccessFault(addr),
+        }
+    }
+
+    pub fn page_fault(self, addr: Vaddr) -> Exception {
+        match self {
+            Access::Fetch => Exception::InstPageFault(addr),
+            Access::Load => Exception::LoadPageFault(addr),
+            Access::Store => Exception::StorePageFault(addr),
+        }
+    }
+}
+
+/// privilege level `access` is translated and protected with
+pub fn effective_privilege(cpu: &Riscv32, access: Access) -> Privilege {
+    // MPRV makes M-mode loads and stores use the translation and protection of MPP
+    match access {
+        Access::Load | Access::Store
+            if cpu.privilege == Privilege::Machine && cpu.csr.mstatus() & MSTATUS_MPRV != 0 =>
+        {
+            cpu.csr.mpp()
+        }
+        _ => cpu.privilege,
+    }
+}
+
+/// Translate `vaddr` for an `access` by `cpu` through the Sv32 page tables in `mem`,
+/// the page or access fault to raise if it can not be done.
+pub fn translate(
+    cpu: &mut Riscv32,
+    mem: &MemoryBank,
+    vaddr: Vaddr,
+    access: Access,
+) -> Result<Paddr, Exception> {
+    let privilege = effective_privilege(cpu, access);
+    let satp = cpu.csr.satp();
+    if privilege == Privilege::Machine || satp & SATP_MODE == 0 {
+        return Ok(vaddr);
+    }
+
+    // the TLB caches the leaf, permissions and A/D are checked again on every hit since
+    // privilege, SUM and MXR change without a fence
+    let asid = bits!(satp, 30, 22);
+    let pmp = cpu.csr.pmp();
+    let tlb = match access {
+        Access::Fetch => &mut cpu.itlb,
+        _ => &mut cpu.dtlb,
+    };
+    let entry = match tlb.lookup(vaddr, asid) {
+        Some(entry) => entry,
+        None => {
+            // the walker reads the tables as S-mode loads as far as PMP is concerned
+            let read_pte = |addr: Paddr| {
+                let ok = mem.allows(addr, PTE_SIZE as usize, Perm::R)
+                    && pmp.check(addr, PTE_SIZE as usize, Privilege::Supervisor, Access::Load);
+                ok.then(|| mem.paddr_read(addr, PTE_SIZE as usize))
+            };
+            let entry = walk(read_pte, satp, vaddr, access)?;
+            tlb.insert(entry);
+            entry
+        }
+    };
+    leaf(cpu, entry, privilege, vaddr, access)
+}
+
+/// `translate` as the debugger and the traces see it: the TLB is only looked in and the
+/// tables are read around the memory bank, `None` where the access would fault
+pub fn probe(cpu: &Riscv32, mem: &MemoryBank, vaddr: Vaddr, access: Access) -> Option<Paddr> {
+    let privilege = effective_privilege(cpu, access);
+    let satp = cpu.csr.satp();
+    if privilege == Privilege::Machine || satp & SATP_MODE == 0 {
+        return Some(vaddr);
+    }
+    let asid = bits!(satp, 30, 22);
+    let tlb = match access {
+        Access::Fetch => &cpu.itlb,
+        _ => &cpu.dtlb,
+    };
+    let entry = match tlb.peek(vaddr, asid) {
+        Some(entry) => entry,
+        None => {
+            let read_pte = |addr: Paddr| {
+                mem.in_pmem(addr, PTE_SIZE as usize)
+                    .then(|| mem.peek(addr, PTE_SIZE as usize))
+            };
+            walk(read_pte, satp, vaddr, access).ok()?
+        }
+    };
+    leaf(cpu, entry, privilege, vaddr, access).ok()
+}
+
+/// the physical address of `vaddr` in the page of `entry`, if `privilege` may make `access`
+fn leaf(
+    cpu: &Riscv32,
+    entry: TlbEntry,
+    privilege: Privilege,
+    vaddr: Vaddr,
+    access: Access,
+) -> Result<Paddr, Exception> {
+    let pte = entry.pte;
+    if !permitted(pte, privilege, cpu.csr.mstatus(), access) {
+        return Err(access.page_fault(vaddr));
+    }
+    // A/D are not updated by hardware, software sets them and takes the page fault otherwise
+    if pte & PTE_A == 0 || (access == Access::Store && pte & PTE_D == 0) {
+        return Err(access.page_fault(vaddr));
+    }
+    let offset_bits = PAGE_SHIFT + VPN_BITS * entry.level;
+    let paddr = (((pte >> 10) as u64) << PAGE_SHIFT) | bits!(vaddr as u64, offset_bits - 1, 0);
+    // Sv32 physical addresses are 34 bits, the bank only covers the low 4 GiB
+    if paddr > Paddr::MAX as u64 {
+        return Err(access.access_fault(vaddr));
+    }
+    Ok(paddr as Paddr)
+}
+
+/// walk the page tables rooted at `satp` down to the leaf mapping `vaddr`, reading each
+/// entry with `read_pte`, `None` if it may not be read
+fn walk(
+    read_pte: impl Fn(Paddr) -> Option<Word>,
+    satp: Word,
+    vaddr: Vaddr,
+    access: Access,
+) -> Result<TlbEntry, Exception> {
+    let mut table = (bits!(satp, 21, 0) as u64) << PAGE_SHIFT;
+    for level in (0..LEVELS).rev() {
+        let vpn = bits!(
+            vaddr as u64 >> (PAGE_SHIFT + VPN_BITS * level),
+            VPN_BITS - 1,
+            0
+        );
+        let pte_addr = table + vpn * PTE_SIZE;
+        let pte = match pte_addr <= Paddr::MAX as u64 {
+            true => read_pte(pte_addr as Paddr),
+            false => None,
+        };
+        let Some(pte) = pte else {
+            return Err(access.access_fault(vaddr));
+        };
+        if pte & PTE_V == 0 || (pte & PTE_R == 0 && pte & PTE_W != 0) {
+            return Err(access.page_fault(vaddr));
+        }
+        let ppn = (pte >> 10) as u64;
+        if pte & (PTE_R | PTE_X) == 0 {
+            // pointer to the next level table
+            table = ppn << PAGE_SHIFT;
+            continue;
+        }
+        // a superpage must be aligned to its size
+        if level > 0 && bits!(ppn, VPN_BITS * level - 1, 0) != 0 {
+            return Err(access.page_fault(vaddr));
+        }
+        let asid = bits!(satp, 30, 22);
+        return Ok(TlbEntry::new(vaddr, asid, pte & PTE_G != 0, pte, level));
+    }
+    // a pointer at the last level
+    Err(access.page_fault(vaddr))
+}
+
+/// whether the leaf `pte` allows `access` from `privilege`
+fn permitted(pte: Word, privilege: Privilege, mstatus: Word, access: Access) -> bool {
+    let user_page = pte & PTE_U != 0;
+    let mode_ok = match privilege {
+        Privilege::User => user_page,
+        // S-mode never executes user pages, and only touches their data with SUM
+        _ => !user_page || (access != Access::Fetch && mstatus & MSTATUS_SUM != 0),
+    };
+    // MXR makes executable pages readable
+    let kind_ok = match access {
+        Access::Fetch => pte & PTE_X != 0,
+        Access::Load => pte & PTE_R != 0 || (mstatus & MSTATUS_MXR != 0 && pte & PTE_X != 0),
+        Access::Store => pte & PTE_W != 0,
+    };
+    mode_ok && kind_ok
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::isa::riscv32::csr::{MSTATUS, MSTATUS_MPP, SATP};
+    use crate::isa::ISA;
+    use crate::memory::RESET_VECTOR;
+
+    const ROOT: Paddr = RESET_VECTOR as Paddr + 0x10_0000;
+    const L0: Paddr = ROOT + 0x1000;
+    const PAGE: Paddr = RESET_VECTOR as Paddr + 0x20_0000;
+    const VA: Vaddr = 0x0040_1000;
+
+    const RWX: Word = PTE_V | PTE_R | PTE_W | PTE_X | PTE_A | PTE_D;
+
+    fn pte(paddr: Paddr, flags: Word) -> Word {
+        ((paddr >> PAGE_SHIFT) << 10) | flags
+    }
+
+    /// S-mode hart with Sv32 on, VA mapped to PAGE with `flags`
+    fn machine(flags: Word) -> (Riscv32, MemoryBank) {
+        let mut cpu = Riscv32::new(RESET_VECTOR as Vaddr);
+        let mut mem = MemoryBank::new(&[]);
+        cpu.set_csr(SATP, SATP_MODE | (ROOT >> PAGE_SHIFT));
+        cpu.privilege = Privilege::Supervisor;
+        mem.paddr_write(ROOT + 4 * (VA >> 22), 4, pte(L0, PTE_V));
+        mem.paddr_write(L0 + 4 * bits!(VA >> 12, 9, 0), 4, pte(PAGE, flags));
+        (cpu, mem)
+    }
+
+    #[test]
+    fn bare_test() {
+        let (mut cpu, mem) = machine(RWX);
+        cpu.privilege = Privilege::Machine;
+        assert_eq!(translate(&mut cpu, &mem, VA, Access::Load), Ok(VA));
+        cpu.privilege = Privilege::Supervisor;
+        cpu.set_csr(SATP, 0);
+        assert_eq!(translate(&mut cpu, &mem, VA, Access::Fetch), Ok(VA));
+    }
+
+    #[test]
+    fn page_test() {
+        let (mut cpu, mem) = machine(RWX);
+        for access in [Access::Fetch, Access::Load, Access::Store] {
+            assert_eq!(
+                translate(&mut cpu, &mem, VA + 0x123, access),
+                Ok(PAGE + 0x123)
+            );
+        }
+        // the neighbour page is not mapped
+        assert_eq!(
+            translate(&mut cpu, &mem, VA + 0x1000, Access::Load),
+            Err(Exception::LoadPageFault(VA + 0x1000))
+        );
+    }
+
+    #[test]
+    fn megapage_test() {
+        let (mut cpu, mut mem) = machine(RWX);
+        let va: Vaddr = 0xc000_0000;
+        mem.paddr_write(ROOT + 4 * (va >> 22), 4, pte(RESET_VECTOR as Paddr, RWX));
+        assert_eq!(
+            translate(&mut cpu, &mem, va + 0x12_3456, Access::Store),
+            Ok(0x8012_3456)
+        );
+
+        // misaligned superpage
+        mem.paddr_write(ROOT + 4 * (va >> 22), 4, pte(PAGE + 0x1000, RWX));
+        assert_eq!(
+            translate(&mut cpu, &mem, va, Access::Fetch),
+            Err(Exception::InstPageFault(va))
+        );
+    }
+
+    #[test]
+    fn permission_test() {
+        let (mut cpu, mem) = machine(PTE_V | PTE_R | PTE_A | PTE_D);
+        assert_eq!(translate(&mut cpu, &mem, VA, Access::Load), Ok(PAGE));
+        assert_eq!(
+            translate(&mut cpu, &mem, VA, Access::Store),
+            Err(Exception::StorePageFault(VA))
+        );
+        assert_eq!(
+            translate(&mut cpu, &mem, VA, Access::Fetch),
+            Err(Exception::InstPageFault(VA))
+        );
+
+        // W without R is reserved
+        let (mut cpu, mem) = machine(PTE_V | PTE_W | PTE_A | PTE_D);
+        assert_eq!(
+            translate(&mut cpu, &mem, VA, Access::Store),
+            Err(Exception::StorePageFault(VA))
+        );
+
+        let (mut cpu, mem) = machine(RWX & !PTE_V);
+        assert_eq!(
+            translate(&mut cpu, &mem, VA, Access::Load),
+            Err(Exception::LoadPageFault(VA))
+        );
+    }
+
+    #[test]
+    fn mxr_test() {
+        let (mut cpu, mem) = machine(PTE_V | PTE_X | PTE_A);
+        assert_eq!(
+            translate(&mut cpu, &mem, VA, Access::Load),
+            Err(Exception::LoadPageFault(VA))
+        );
+        cpu.set_csr(MSTATUS, MSTATUS_MXR);
+        assert_eq!(translate(&mut cpu, &mem, VA, Access::Load), Ok(PAGE));
+    }
+
+    #[test]
+    fn user_page_test() {
+        let (mut cpu, mem) = machine(RWX | PTE_U);
+        // S-mode needs SUM for data and never fetches user pages
+        assert_eq!(
+            translate(&mut cpu, &mem, VA, Access::Load),
+            Err(Exception::LoadPageFault(VA))
+        );
+        cpu.set_csr(MSTATUS, MSTATUS_SUM);
+        assert_eq!(translate(&mut cpu, &mem, VA, Access::Load), Ok(PAGE));
+        assert_eq!(
+            translate(&mut cpu, &mem, VA, Access::Fetch),
+            Err(Exception::InstPageFault(VA))
+        );
+        cpu.privilege = Privilege::User;
+        assert_eq!(translate(&mut cpu, &mem, VA, Access::Fetch), Ok(PAGE));
+
+        // U-mode can not touch supervisor pages
+        let (mut cpu, mem) = machine(RWX);
+        cpu.privilege = Privilege::User;
+        assert_eq!(
+            translate(&mut cpu, &mem, VA, Access::Store),
+            Err(Exception::StorePageFault(VA))
+        );
+    }
+
+    #[test]
+    fn accessed_dirty_test() {
+        let (mut cpu, mem) = machine(RWX & !PTE_A);
+        assert_eq!(
+            translate(&mut cpu, &mem, VA, Access::Fetch),
+            Err(Exception::InstPageFault(VA))
+        );
+        let (mut cpu, mem) = machine(RWX & !PTE_D);
+        assert_eq!(translate(&mut cpu, &mem, VA, Access::Load), Ok(PAGE));
+        assert_eq!(
+            translate(&mut cpu, &mem, VA, Access::Store),
+            Err(Exception::StorePageFault(VA))
+        );
+    }
+
+    #[test]
+    fn mprv_test() {
+        let (mut cpu, mem) = machine(RWX);
+        cpu.privilege = Privilege::Machine;
+        // MPP = S
+        cpu.set_csr(MSTATUS, MSTATUS_MPRV | (1 << 11));
+        assert_eq!(translate(&mut cpu, &mem, VA, Access::Load), Ok(PAGE));
+        // fetches ignore MPRV
+        assert_eq!(translate(&mut cpu, &mem, VA, Access::Fetch), Ok(VA));
+        cpu.set_csr(MSTATUS, MSTATUS_MPRV | MSTATUS_MPP);
+        assert_eq!(translate(&mut cpu, &mem, VA, Access::Store), Ok(VA));
+    }
+
+    #[test]
+    fn walk_fault_test() {
+        // root table outside physical memory
+        let (mut cpu, mem) = machine(RWX);
+        cpu.set_csr(SATP, SATP_MODE);
+        assert_eq!(
+            translate(&mut cpu, &mem, VA, Access::Load),
+            Err(Exception::LoadAccessFault(VA))
+        );
+
+        // a pointer at the last level
+        let (mut cpu, mut mem) = machine(RWX);
+        mem.paddr_write(L0 + 4 * bits!(VA >> 12, 9, 0), 4, pte(PAGE, PTE_V));
+        assert_eq!(
+            translate(&mut cpu, &mem, VA, Access::Load),
+            Err(Exception::LoadPageFault(VA))
+        );
+    }
+
+    #[test]
+    fn probe_test() {
+        let (mut cpu, mut mem) = machine(RWX);
+        assert_eq!(
+            probe(&cpu, &mem, VA + 0x10, Access::Fetch),
+            Some(PAGE + 0x10)
+        );
+        assert_eq!(probe(&cpu, &mem, VA + 0x1000, Access::Fetch), None);
+        // nothing cached or counted
+        assert_eq!((cpu.itlb.hit(), cpu.itlb.miss()), (0, 0));
+
+        // a cached leaf is what the hart fetches through until a fence
+        assert_eq!(translate(&mut cpu, &mem, VA, Access::Fetch), Ok(PAGE));
+        mem.paddr_write(L0 + 4 * bits!(VA >> 12, 9, 0), 4, 0);
+        assert_eq!(probe(&cpu, &mem, VA, Access::Fetch), Some(PAGE));
+        assert_eq!(probe(&cpu, &mem, VA, Access::Load), None);
+    }
+}
@@ -1,25 +1,154 @@
-use crate::common::{Vaddr, Word};
+use crate::{
+    common::{Paddr, Vaddr, Word},
+    memory::MemoryBank,
+};
 
-use super::ISA;
+use super::{TlbConfig, ISA};
 
+mod csr;
+#[cfg(feature = "trace")]
+mod etrace;
 mod executer;
-pub use executer::Executer;
-pub const GUEST_ISA: &'static str = "riscv32";
+mod mmu;
+mod pmp;
+mod tlb;
+use csr::CsrFile;
+pub use csr::Privilege;
+use executer::BlockCache;
+pub use executer::{Exception, Executer};
+pub use tlb::Tlb;
+pub const GUEST_ISA: &str = "riscv32";
 
-#[derive(Default, Debug)]
+#[derive(Debug)]
 pub struct Riscv32 {
     gpr: [Word; 32],
     pc: Vaddr,
+    csr: CsrFile,
+    privilege: Privilege,
+    itlb: Tlb,
+    dtlb: Tlb,
+    /// word address reserved by the last LR.W
+    reservation: Option<Paddr>,
+    /// decoded code, run by the executer a block at a time
+    blocks: BlockCache,
+    #[cfg(feature = "trace")]
+    etrace: etrace::ETrace,
 }
 
 impl Riscv32 {
     pub fn new(pc: Vaddr) -> Self {
-        Self { gpr: [0; 32], pc }
+        Self::with_tlb(pc, TlbConfig::default())
+    }
+
+    /// hart whose instruction and data TLBs both have the geometry `tlb`
+    pub fn with_tlb(pc: Vaddr, tlb: TlbConfig) -> Self {
+        Self {
+            gpr: [0; 32],
+            pc,
+            csr: CsrFile::default(),
+            privilege: Privilege::Machine,
+            itlb: Tlb::new(tlb),
+            dtlb: Tlb::new(tlb),
+            reservation: None,
+            blocks: BlockCache::default(),
+            #[cfg(feature = "trace")]
+            etrace: etrace::ETrace::default(),
+        }
+    }
+
+    /// current privilege level
+    pub fn privilege(&self) -> Privilege {
+        self.privilege
+    }
+
+    /// the instruction and data TLBs, for the statistics
+    pub fn tlbs(&self) -> [(&'static str, &Tlb); 2] {
+        [("itlb", &self.itlb), ("dtlb", &self.dtlb)]
+    }
+
+    /// SFENCE.VMA on both TLBs
+    fn flush_tlb(&mut self, vaddr: Option<Vaddr>, asid: Option<Word>) {
+        self.itlb.flush(vaddr, asid);
+        self.dtlb.flush(vaddr, asid);
+    }
+
+    /// drop the reservation if `len` bytes written at `addr` overlap the reserved word, and
+    /// the decoded blocks they overwrite
+    fn snoop_store(&mut self, addr: Paddr, len: usize) {
+        self.blocks.invalidate(addr, len);
+        if let Some(res) = self.reservation {
+            let end = addr.wrapping_add(len as Paddr);
+            if addr < res.wrapping_add(4) && res < end {
+                self.reservation = None;
+            }
+        }
     }
 }
 
 impl ISA for Riscv32 {
     type Executer = executer::Executer;
+    type Exception = executer::Exception;
+    const NAME: &'static str = GUEST_ISA;
+    const LOGO: &'static str = ISA_LOGO;
+    const NR_GPR: usize = 32;
+    const ELF_MACHINE: u16 = 243;
+
+    fn init(pc: Vaddr, tlb: TlbConfig) -> Self {
+        Self::with_tlb(pc, tlb)
+    }
+
+    /// take exception `e` raised by the instruction at `pc`, return the handler address
+    fn raise_exception(&mut self, mem: &mut MemoryBank, pc: Vaddr, e: Exception) -> Vaddr {
+        let (privilege, handler) = self
+            .csr
+            .trap_enter(self.privilege, pc, e.cause(), e.tval(pc));
+        #[cfg(feature = "trace")]
+        self.etrace.trap(
+            e.cause(),
+            e.tval(pc),
+            (self.privilege, privilege),
+            pc,
+            handler,
+        );
+        self.privilege = privilege;
+        handler
+    }
+
+    /// a0 holds the exit code of nemu_trap
+    fn halt_ret(&self) -> Word {
+        self.gpr[10]
+    }
+
+    /// log the hit rate of each TLB and of the block cache that has been looked up, and the
+    /// traps taken by cause
+    fn statistic(&self) {
+        #[cfg(feature = "trace")]
+        self.etrace.statistic();
+        let tlbs = self.tlbs().map(|(name, tlb)| (name, tlb.hit(), tlb.miss()));
+        let blocks = ("block cache", self.blocks.hit(), self.blocks.miss());
+        for (name, hit, miss) in tlbs.into_iter().chain([blocks]) {
+            let total = hit + miss;
+            if total != 0 {
+                log!(
+                    "{} hit = {}, miss = {}, hit rate = {:.2}%",
+                    name,
+                    hit,
+                    miss,
+                    hit as f64 * 100.0 / total as f64
+                );
+            }
+        }
+    }
+
+    #[cfg(feature = "trace")]
+    fn set_etrace(&mut self, on: bool) {
+        self.etrace.set_on(on);
+    }
+
+    fn inst_paddr(&self, mem: &MemoryBank, pc: Vaddr) -> Option<Paddr> {
+        mmu::probe(self, mem, pc, mmu::Access::Fetch)
+    }
+
     fn set_pc(&mut self, next: Vaddr) {
         self.pc = next;
     }
@@ -36,6 +165,33 @@ impl ISA for Riscv32 {
         self.gpr[idx] = val;
     }
 
+    fn csr(&self, addr: usize) -> Option<Word> {
+        self.csr.read(addr)
+    }
+
+    fn set_csr(&mut self, addr: usize, val: Word) -> bool {
+        let old = self.csr.satp();
+        if !self.csr.write(addr, val) {
+            return false;
+        }
+        if csr::is_pmp(addr) {
+            // blocks are decoded under the fetch permission at the time
+            self.blocks.flush();
+        }
+        let new = self.csr.satp();
+        if addr == csr::SATP && new != old {
+            // a new ASID keeps the old entries apart by their tag, anything else means the
+            // entries of the current one can be stale
+            let asid = bits!(new, 30, 22);
+            if (new ^ old) & mmu::SATP_MODE != 0 {
+                self.flush_tlb(None, None);
+            } else if asid == bits!(old, 30, 22) {
+                self.flush_tlb(None, Some(asid));
+            }
+        }
+        true
+    }
+
     fn default_img() -> &'static [u8] {
         let ptr = IMG.as_ptr() as *const u8;
         let len = IMG.len() * std::mem::size_of::<u32>();
@@ -45,10 +201,17 @@ impl ISA for Riscv32 {
     fn executer() -> Self::Executer {
         executer::Executer::new()
     }
+
+    fn disassemble(inst: Word, pc: Vaddr) -> Option<(usize, String)> {
+        executer::disassemble(inst, pc)
+    }
+
+    fn reg_name(idx: usize) -> String {
+        executer::REG_NAMES[idx].to_string()
+    }
 }
 
-pub const ISA_LOGO: &'static str = 
-r"       _                         __  __                         _ 
+pub const ISA_LOGO: &str = r"       _                         __  __                         _ 
       (_)                       |  \/  |                       | |
   _ __ _ ___  ___ ________   __ | \  / | __ _ _ __  _   _  __ _| |
  | '__| / __|/ __|______\ \ / / | |\/| |/ _` | '_ \| | | |/ _` | |
@@ -67,8 +230,68 @@ const IMG: [u32; 5] = [
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::memory::MemoryBank;
     #[test]
     fn t() {
         print!("{ISA_LOGO}");
     }
+
+    #[test]
+    fn raise_exception_test() {
+        let mut cpu = Riscv32::new(0x8000_0000);
+        let mut mem = MemoryBank::new(&[]);
+        cpu.set_csr(csr::MTVEC, 0x8000_0100);
+        let handler =
+            cpu.raise_exception(&mut mem, 0x8000_0004, Exception::IllegalInstruction(0xffff));
+        assert_eq!(handler, 0x8000_0100);
+        assert_eq!(cpu.csr(csr::MEPC), Some(0x8000_0004));
+        assert_eq!(cpu.csr(csr::MCAUSE), Some(2));
+        assert_eq!(cpu.csr(csr::MTVAL), Some(0xffff));
+        assert_eq!(cpu.privilege(), Privilege::Machine);
+    }
+
+    #[cfg(feature = "trace")]
+    #[test]
+    fn etrace_test() {
+        let mut cpu = Riscv32::new(0x8000_0000);
+        let mut mem = MemoryBank::new(&[]);
+        cpu.set_etrace(true);
+        cpu.raise_exception(&mut mem, 0x8000_0004, Exception::EcallFromM);
+        cpu.raise_exception(&mut mem, 0x8000_0008, Exception::EcallFromM);
+        cpu.raise_exception(&mut mem, 0x8000_000c, Exception::Breakpoint);
+        assert_eq!((cpu.etrace.count(11), cpu.etrace.count(3)), (2, 1));
+    }
+
+    #[test]
+    fn satp_flush_test() {
+        let mut cpu = Riscv32::new(0x8000_0000);
+        let entry = tlb::TlbEntry::new(0x1000, 1, false, 0, 0);
+        cpu.set_csr(csr::SATP, mmu::SATP_MODE | (1 << 22));
+        cpu.dtlb.insert(entry);
+        // switching to another ASID keeps the entry under its tag
+        cpu.set_csr(csr::SATP, mmu::SATP_MODE | (2 << 22));
+        assert!(cpu.dtlb.lookup(0x1000, 1).is_some());
+        // a new root under the same ASID drops it
+        cpu.set_csr(csr::SATP, mmu::SATP_MODE | (1 << 22));
+        cpu.set_csr(csr::SATP, mmu::SATP_MODE | (1 << 22) | 0x100);
+        assert!(cpu.dtlb.lookup(0x1000, 1).is_none());
+        // turning translation off drops everything
+        cpu.dtlb.insert(tlb::TlbEntry::new(0x1000, 3, true, 0, 0));
+        cpu.set_csr(csr::SATP, 0);
+        assert!(cpu.dtlb.lookup(0x1000, 3).is_none());
+    }
+
+    #[test]
+    fn delegated_exception_test() {
+        let mut cpu = Riscv32::new(0x8000_0000);
+        let mut mem = MemoryBank::new(&[]);
+        cpu.set_csr(csr::STVEC, 0x8000_0200);
+        cpu.set_csr(csr::MEDELEG, 1 << 13);
+        cpu.privilege = Privilege::User;
+        let handler = cpu.raise_exception(&mut mem, 0x1000, Exception::LoadPageFault(0x4000));
+        assert_eq!(handler, 0x8000_0200);
+        assert_eq!(cpu.privilege(), Privilege::Supervisor);
+        assert_eq!(cpu.csr(csr::SCAUSE), Some(13));
+        assert_eq!(cpu.csr(csr::STVAL), Some(0x4000));
+    }
 }
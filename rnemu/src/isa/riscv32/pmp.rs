@@ -0,0 +1,209 @@
+use super::{csr::Privilege, mmu::Access};
+use crate::common::{Paddr, Word};
+
+pub const ENTRIES: usize = 16;
+
+const PMP_R: u8 = 1 << 0;
+const PMP_W: u8 = 1 << 1;
+const PMP_X: u8 = 1 << 2;
+const PMP_A: u8 = 0b11 << 3;
+const PMP_L: u8 = 1 << 7;
+
+const A_OFF: u8 = 0;
+const A_TOR: u8 = 1;
+const A_NA4: u8 = 2;
+const A_NAPOT: u8 = 3;
+
+/// Physical memory protection unit with 16 entries and a 4-byte grain.
+#[derive(Debug, Default)]
+pub struct Pmp {
+    cfg: [u8; ENTRIES],
+    addr: [Word; ENTRIES],
+}
+
+impl Pmp {
+    /// pmpcfg`n`, the configuration of entries 4n..4n+3
+    pub fn cfg(&self, n: usize) -> Word {
+        let bytes: [u8; 4] = self.cfg[4 * n..4 * n + 4].try_into().unwrap();
+        Word::from_le_bytes(bytes)
+    }
+
+    /// write pmpcfg`n`, locked entries keep their configuration
+    pub fn set_cfg(&mut self, n: usize, val: Word) {
+        for (i, byte) in val.to_le_bytes().into_iter().enumerate() {
+            let entry = 4 * n + i;
+            if self.locked(entry) {
+                continue;
+            }
+            // bits 6:5 are reserved and R = 0, W = 1 is a reserved combination
+            let mut byte = byte & !0b0110_0000;
+            if byte & PMP_R == 0 {
+                byte &= !PMP_W;
+            }
+            self.cfg[entry] = byte;
+        }
+    }
+
+    /// pmpaddr`n`, bits 33:2 of the address
+    pub fn addr(&self, n: usize) -> Word {
+        self.addr[n]
+    }
+
+    /// write pmpaddr`n`, ignored when the entry is locked or is the bottom of a locked TOR range
+    pub fn set_addr(&mut self, n: usize, val: Word) {
+        let top_locked = n + 1 < ENTRIES && self.locked(n + 1) && self.mode(n + 1) == A_TOR;
+        if !self.locked(n) && !top_locked {
+            self.addr[n] = val;
+        }
+    }
+
+    fn locked(&self, n: usize) -> bool {
+        self.cfg[n] & PMP_L != 0
+    }
+
+    fn mode(&self, n: usize) -> u8 {
+        (self.cfg[n] & PMP_A) >> 3
+    }
+
+    /// byte range `[lo, hi)` matched by entry `n`, `None` when it is off
+    fn range(&self, n: usize) -> Option<(u64, u64)> {
+        let addr = self.addr[n] as u64;
+        match self.mode(n) {
+            A_OFF => None,
+            A_TOR => {
+                let lo = if n == 0 {
+                    0
+                } else {
+                    (self.addr[n - 1] as u64) << 2
+                };
+                Some((lo, addr << 2))
+            }
+            A_NA4 => Some((addr << 2, (addr << 2) + 4)),
+            // the trailing ones encode the size, 2^(ones + 3) bytes
+            A_NAPOT => {
+                let ones = self.addr[n].trailing_ones();
+                let base = (addr & !bitmask!(ones as u64)) << 2;
+                Some((base, base + (1u64 << (ones + 3))))
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    /// whether `privilege` may perform `access` on the `len` bytes at `paddr`
+    pub fn check(&self, paddr: Paddr, len: usize, privilege: Privilege, access: Access) -> bool {
+        let (start, end) = (paddr as u64, paddr as u64 + len as u64);
+        let mut active = false;
+        for n in 0..ENTRIES {
+            let Some((lo, hi)) = self.range(n) else {
+                continue;
+            };
+            active = true;
+            if end <= lo || hi <= start {
+                continue;
+            }
+            // the lowest numbered match decides, an access only partially inside it fails
+            if start < lo || hi < end {
+                return false;
+            }
+            // unlocked entries do not constrain M-mode
+            if privilege == Privilege::Machine && !self.locked(n) {
+                return true;
+            }
+            let perm = match access {
+                Access::Fetch => PMP_X,
+                Access::Load => PMP_R,
+                Access::Store => PMP_W,
+            };
+            return self.cfg[n] & perm != 0;
+        }
+        // M-mode passes when nothing matches, S/U-mode only while no entry is programmed
+        privilege == Privilege::Machine || !active
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: Word = 0x8000_0000;
+
+    fn napot(base: Word, size: Word) -> Word {
+        (base >> 2) | ((size >> 3) - 1)
+    }
+
+    #[test]
+    fn empty_test() {
+        let pmp = Pmp::default();
+        assert!(pmp.check(BASE, 4, Privilege::User, Access::Store));
+        assert!(pmp.check(BASE, 4, Privilege::Machine, Access::Fetch));
+    }
+
+    #[test]
+    fn tor_test() {
+        let mut pmp = Pmp::default();
+        pmp.set_addr(0, BASE >> 2);
+        pmp.set_addr(1, (BASE + 0x1000) >> 2);
+        pmp.set_cfg(0, ((A_TOR << 3 | PMP_R | PMP_X) as Word) << 8);
+        assert!(pmp.check(BASE, 4, Privilege::Supervisor, Access::Load));
+        assert!(pmp.check(BASE + 0xffc, 4, Privilege::User, Access::Fetch));
+        assert!(!pmp.check(BASE, 4, Privilege::Supervisor, Access::Store));
+        // outside of every entry
+        assert!(!pmp.check(BASE + 0x1000, 4, Privilege::Supervisor, Access::Load));
+        assert!(pmp.check(BASE + 0x1000, 4, Privilege::Machine, Access::Store));
+        // straddling the top
+        assert!(!pmp.check(BASE + 0xffe, 4, Privilege::Supervisor, Access::Load));
+    }
+
+    #[test]
+    fn na4_napot_test() {
+        let mut pmp = Pmp::default();
+        pmp.set_addr(0, BASE >> 2);
+        pmp.set_addr(1, napot(BASE, 0x1000));
+        pmp.set_cfg(
+            0,
+            (A_NA4 << 3) as Word | ((A_NAPOT << 3 | PMP_R | PMP_W) as Word) << 8,
+        );
+        // entry 0 matches first and grants nothing
+        assert!(!pmp.check(BASE, 4, Privilege::User, Access::Load));
+        assert!(pmp.check(BASE + 4, 4, Privilege::User, Access::Store));
+        assert!(pmp.check(BASE + 0xffc, 4, Privilege::User, Access::Load));
+        assert!(!pmp.check(BASE + 0x1000, 1, Privilege::User, Access::Load));
+        assert!(!pmp.check(BASE + 8, 2, Privilege::User, Access::Fetch));
+    }
+
+    #[test]
+    fn napot_all_test() {
+        let mut pmp = Pmp::default();
+        pmp.set_addr(0, Word::MAX);
+        pmp.set_cfg(0, (A_NAPOT << 3 | PMP_R) as Word);
+        assert!(pmp.check(0, 4, Privilege::User, Access::Load));
+        assert!(pmp.check(Paddr::MAX - 3, 4, Privilege::User, Access::Load));
+    }
+
+    #[test]
+    fn lock_test() {
+        let mut pmp = Pmp::default();
+        pmp.set_addr(0, BASE >> 2);
+        pmp.set_addr(1, (BASE + 0x1000) >> 2);
+        pmp.set_cfg(0, ((PMP_L | A_TOR << 3 | PMP_R) as Word) << 8);
+        // locked entries bind M-mode too
+        assert!(!pmp.check(BASE, 4, Privilege::Machine, Access::Store));
+        assert!(pmp.check(BASE, 4, Privilege::Machine, Access::Load));
+        // and can not be changed, neither can the bottom of the TOR range
+        pmp.set_cfg(0, 0);
+        assert_eq!(pmp.cfg(0), ((PMP_L | A_TOR << 3 | PMP_R) as Word) << 8);
+        pmp.set_addr(1, 0);
+        pmp.set_addr(0, 0);
+        assert_eq!(
+            (pmp.addr(0), pmp.addr(1)),
+            (BASE >> 2, (BASE + 0x1000) >> 2)
+        );
+    }
+
+    #[test]
+    fn warl_test() {
+        let mut pmp = Pmp::default();
+        pmp.set_cfg(3, 0xffff_ff62);
+        assert_eq!(pmp.cfg(3), 0x9f9f_9f00);
+    }
+}
@@ -0,0 +1,203 @@
+use crate::{
+    common::{Vaddr, Word},
+    isa::TlbConfig,
+};
+
+const PAGE_SHIFT: usize = 12;
+const VPN_BITS: usize = 10;
+
+/// Cached leaf of a page-table walk. Megapages are cached per 4 KiB page, `level` keeps the
+/// size of the mapping for SFENCE.VMA.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TlbEntry {
+    vpn: Word,
+    asid: Word,
+    global: bool,
+    pub pte: Word,
+    pub level: usize,
+}
+
+impl TlbEntry {
+    pub fn new(vaddr: Vaddr, asid: Word, global: bool, pte: Word, level: usize) -> Self {
+        Self {
+            vpn: vaddr >> PAGE_SHIFT,
+            asid,
+            global,
+            pte,
+            level,
+        }
+    }
+
+    /// whether the mapping covers `vpn`
+    fn covers(&self, vpn: Word) -> bool {
+        let shift = VPN_BITS * self.level;
+        self.vpn >> shift == vpn >> shift
+    }
+}
+
+/// Set-associative cache of translations tagged with the ASID they were walked under.
+#[derive(Debug)]
+pub struct Tlb {
+    config: TlbConfig,
+    entries: Vec<Option<TlbEntry>>,
+    /// round-robin victim of each set
+    victim: Vec<usize>,
+    hit: u64,
+    miss: u64,
+}
+
+impl Tlb {
+    pub fn new(config: TlbConfig) -> Self {
+        assert!(config.sets > 0 && config.ways > 0, "empty TLB {config:?}");
+        Self {
+            config,
+            entries: vec![None; config.sets * config.ways],
+            victim: vec![0; config.sets],
+            hit: 0,
+            miss: 0,
+        }
+    }
+
+    fn set(&self, vpn: Word) -> usize {
+        vpn as usize % self.config.sets
+    }
+
+    /// look up the translation of `vaddr` in address space `asid`
+    pub fn lookup(&mut self, vaddr: Vaddr, asid: Word) -> Option<TlbEntry> {
+        let entry = self.peek(vaddr, asid);
+        match entry {
+            Some(_) => self.hit += 1,
+            None => self.miss += 1,
+        }
+        entry
+    }
+
+    /// `lookup` without counting it as a hit or a miss
+    pub fn peek(&self, vaddr: Vaddr, asid: Word) -> Option<TlbEntry> {
+        let vpn = vaddr >> PAGE_SHIFT;
+        let start = self.set(vpn) * self.config.ways;
+        self.entries[start..start + self.config.ways]
+            .iter()
+            .flatten()
+            .find(|e| e.vpn == vpn && (e.global || e.asid == asid))
+            .copied()
+    }
+
+    /// cache `entry`, taking a free way of its set or evicting the round-robin victim
+    pub fn insert(&mut self, entry: TlbEntry) {
+        let set = self.set(entry.vpn);
+        let start = set * self.config.ways;
+        let ways = &mut self.entries[start..start + self.config.ways];
+        let way = match ways.iter().position(Option::is_none) {
+            Some(way) => way,
+            None => {
+                let way = self.victim[set];
+                self.victim[set] = (way + 1) % self.config.ways;
+                way
+            }
+        };
+        ways[way] = Some(entry);
+    }
+
+    /// SFENCE.VMA: drop the entries for `vaddr` (all of them if `None`) in address space
+    /// `asid` (every one if `None`), global mappings survive an ASID-specific fence
+    pub fn flush(&mut self, vaddr: Option<Vaddr>, asid: Option<Word>) {
+        for slot in self.entries.iter_mut() {
+            let Some(e) = slot else {
+                continue;
+            };
+            let addr_match = vaddr.is_none_or(|v| e.covers(v >> PAGE_SHIFT));
+            let asid_match = asid.is_none_or(|a| !e.global && e.asid == a);
+            if addr_match && asid_match {
+                *slot = None;
+            }
+        }
+    }
+
+    pub fn hit(&self) -> u64 {
+        self.hit
+    }
+
+    pub fn miss(&self) -> u64 {
+        self.miss
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(vaddr: Vaddr, asid: Word) -> TlbEntry {
+        TlbEntry::new(vaddr, asid, false, 0, 0)
+    }
+
+    #[test]
+    fn lookup_test() {
+        let mut tlb = Tlb::new(TlbConfig::default());
+        assert_eq!(tlb.lookup(0x1000, 1), None);
+        tlb.insert(entry(0x1000, 1));
+        assert_eq!(tlb.lookup(0x1abc, 1), Some(entry(0x1000, 1)));
+        // tagged with another address space
+        assert_eq!(tlb.lookup(0x1000, 2), None);
+        assert_eq!((tlb.hit(), tlb.miss()), (1, 2));
+    }
+
+    #[test]
+    fn global_test() {
+        let mut tlb = Tlb::new(TlbConfig::default());
+        tlb.insert(TlbEntry::new(0x1000, 1, true, 0, 0));
+        assert!(tlb.lookup(0x1000, 2).is_some());
+        tlb.flush(None, Some(2));
+        assert!(tlb.lookup(0x1000, 2).is_some());
+        tlb.flush(Some(0x1000), None);
+        assert!(tlb.lookup(0x1000, 2).is_none());
+    }
+
+    #[test]
+    fn direct_mapped_test() {
+        let mut tlb = Tlb::new(TlbConfig { sets: 4, ways: 1 });
+        tlb.insert(entry(0x1000, 0));
+        // same set, evicts the first one
+        tlb.insert(entry(0x5000, 0));
+        assert!(tlb.lookup(0x1000, 0).is_none());
+        assert!(tlb.lookup(0x5000, 0).is_some());
+    }
+
+    #[test]
+    fn associative_test() {
+        let mut tlb = Tlb::new(TlbConfig { sets: 4, ways: 2 });
+        tlb.insert(entry(0x1000, 0));
+        tlb.insert(entry(0x5000, 0));
+        assert!(tlb.lookup(0x1000, 0).is_some());
+        assert!(tlb.lookup(0x5000, 0).is_some());
+        // the third one replaces the oldest
+        tlb.insert(entry(0x9000, 0));
+        assert!(tlb.lookup(0x1000, 0).is_none());
+        assert!(tlb.lookup(0x5000, 0).is_some());
+    }
+
+    #[test]
+    fn flush_test() {
+        let mut tlb = Tlb::new(TlbConfig::default());
+        tlb.insert(entry(0x1000, 1));
+        tlb.insert(entry(0x2000, 1));
+        tlb.insert(entry(0x3000, 2));
+        tlb.flush(Some(0x1000), Some(1));
+        assert!(tlb.lookup(0x1000, 1).is_none());
+        assert!(tlb.lookup(0x2000, 1).is_some());
+        tlb.flush(None, Some(1));
+        assert!(tlb.lookup(0x2000, 1).is_none());
+        assert!(tlb.lookup(0x3000, 2).is_some());
+        tlb.flush(None, None);
+        assert!(tlb.lookup(0x3000, 2).is_none());
+    }
+
+    #[test]
+    fn megapage_flush_test() {
+        let mut tlb = Tlb::new(TlbConfig::default());
+        tlb.insert(TlbEntry::new(0x0040_1000, 0, false, 0, 1));
+        // any address in the same 4 MiB region hits the megapage
+        tlb.flush(Some(0x007f_f000), None);
+        assert!(tlb.lookup(0x0040_1000, 0).is_none());
+    }
+}
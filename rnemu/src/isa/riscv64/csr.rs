@@ -0,0 +1,225 @@
+use crate::common::Word;
+
+pub const MVENDORID: usize = 0xf11;
+pub const MARCHID: usize = 0xf12;
+pub const MIMPID: usize = 0xf13;
+pub const MHARTID: usize = 0xf14;
+pub const MSTATUS: usize = 0x300;
+pub const MISA: usize = 0x301;
+pub const MIE: usize = 0x304;
+pub const MTVEC: usize = 0x305;
+pub const MSCRATCH: usize = 0x340;
+pub const MEPC: usize = 0x341;
+pub const MCAUSE: usize = 0x342;
+pub const MTVAL: usize = 0x343;
+pub const MIP: usize = 0x344;
+
+pub const MSTATUS_MIE: Word = 1 << 3;
+pub const MSTATUS_MPIE: Word = 1 << 7;
+pub const MSTATUS_MPP: Word = 0b11 << 11;
+
+/// machine software, timer and external interrupt bits of mie/mip
+const MI_MASK: Word = (1 << 3) | (1 << 7) | (1 << 11);
+
+/// MXL = 64, with the I and M extensions
+const MISA_VALUE: Word = (2 << 62) | (1 << 8) | (1 << 12);
+
+pub const CSR_NAMES: [(usize, &str); 13] = [
+    (MVENDORID, "mvendorid"),
+    (MARCHID, "marchid"),
+    (MIMPID, "mimpid"),
+    (MHARTID, "mhartid"),
+    (MSTATUS, "mstatus"),
+    (MISA, "misa"),
+    (MIE, "mie"),
+    (MTVEC, "mtvec"),
+    (MSCRATCH, "mscratch"),
+    (MEPC, "mepc"),
+    (MCAUSE, "mcause"),
+    (MTVAL, "mtval"),
+    (MIP, "mip"),
+];
+
+/// name of the CSR at `addr`
+pub fn csr_name(addr: usize) -> Option<&'static str> {
+    CSR_NAMES.iter().find(|(a, _)| *a == addr).map(|(_, n)| *n)
+}
+
+/// Machine-mode CSR file of a single hart, the U/S-mode fields of mstatus (UXL, SXL, ...)
+/// read as zero.
+#[derive(Debug)]
+pub struct CsrFile {
+    mstatus: Word,
+    mtvec: Word,
+    mepc: Word,
+    mcause: Word,
+    mtval: Word,
+    mscratch: Word,
+    mie: Word,
+    mip: Word,
+}
+
+impl Default for CsrFile {
+    fn default() -> Self {
+        Self {
+            mstatus: MSTATUS_MPP,
+            mtvec: 0,
+            mepc: 0,
+            mcause: 0,
+            mtval: 0,
+            mscratch: 0,
+            mie: 0,
+            mip: 0,
+        }
+    }
+}
+
+impl CsrFile {
+    /// read the CSR at `addr`, `None` if it does not exist
+    pub fn read(&self, addr: usize) -> Option<Word> {
+        let val = match addr {
+            MVENDORID | MARCHID | MIMPID | MHARTID => 0,
+            MSTATUS => self.mstatus,
+            MISA => MISA_VALUE,
+            MIE => self.mie,
+            MTVEC => self.mtvec,
+            MSCRATCH => self.mscratch,
+            MEPC => self.mepc,
+            MCAUSE => self.mcause,
+            MTVAL => self.mtval,
+            MIP => self.mip,
+            _ => return None,
+        };
+        Some(val)
+    }
+
+    /// write `val` to the CSR at `addr` keeping only the legal values of WARL fields,
+    /// `false` if it does not exist or is read-only
+    pub fn write(&mut self, addr: usize, val: Word) -> bool {
+        // addr[11:10] == 0b11 marks the read-only CSRs
+        if bits!(addr, 11, 10) == 0b11 {
+            return false;
+        }
+        match addr {
+            MSTATUS => {
+                // M-mode only hart, MPP is hardwired to M
+                let mask = MSTATUS_MIE | MSTATUS_MPIE;
+                self.mstatus = (val & mask) | MSTATUS_MPP;
+            }
+            // single fixed configuration, writes are ignored
+            MISA => {}
+            MIE => self.mie = val & MI_MASK,
+            MTVEC => {
+                // direct and vectored mode, the reserved modes keep the old one
+                let mode = match bits!(val, 1, 0) {
+                    m @ (0 | 1) => m,
+                    _ => bits!(self.mtvec, 1, 0),
+                };
+                self.mtvec = (val & !0b11) | mode;
+            }
+            MSCRATCH => self.mscratch = val,
+            // IALIGN = 32 without RVC, the two low bits are always zero
+            MEPC => self.mepc = val & !0b11,
+            MCAUSE => self.mcause = val,
+            MTVAL => self.mtval = val,
+            // pending bits are driven by the interrupt sources, not by software
+            MIP => {}
+            _ => return false,
+        }
+        true
+    }
+
+    /// enter the M-mode trap handler from `pc`, return the handler address
+    pub fn trap_enter(&mut self, pc: Word, cause: Word, tval: Word) -> Word {
+        self.mepc = pc;
+        self.mcause = cause;
+        self.mtval = tval;
+        let mpie = if self.mstatus & MSTATUS_MIE != 0 {
+            MSTATUS_MPIE
+        } else {
+            0
+        };
+        self.mstatus = mpie | MSTATUS_MPP;
+        // synchronous exceptions always go to BASE, also in vectored mode
+        self.mtvec & !0b11
+    }
+
+    /// leave the M-mode trap handler through MRET, return the address to resume at
+    pub fn trap_return(&mut self) -> Word {
+        let mie = if self.mstatus & MSTATUS_MPIE != 0 {
+            MSTATUS_MIE
+        } else {
+            0
+        };
+        self.mstatus = mie | MSTATUS_MPIE | MSTATUS_MPP;
+        self.mepc
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_only_test() {
+        let mut csr = CsrFile::default();
+        for addr in [MVENDORID, MARCHID, MIMPID, MHARTID] {
+            assert_eq!(csr.read(addr), Some(0));
+            assert!(!csr.write(addr, 1));
+        }
+        assert_eq!(csr.read(0x7ff), None);
+        assert!(!csr.write(0x7ff, 1));
+    }
+
+    #[test]
+    fn warl_test() {
+        let mut csr = CsrFile::default();
+        assert!(csr.write(MSTATUS, Word::MAX));
+        assert_eq!(
+            csr.read(MSTATUS),
+            Some(MSTATUS_MIE | MSTATUS_MPIE | MSTATUS_MPP)
+        );
+        assert!(csr.write(MSTATUS, 0));
+        assert_eq!(csr.read(MSTATUS), Some(MSTATUS_MPP));
+
+        assert!(csr.write(MISA, 0));
+        assert_eq!(csr.read(MISA), Some(MISA_VALUE));
+
+        assert!(csr.write(MTVEC, 0x8000_0101));
+        assert_eq!(csr.read(MTVEC), Some(0x8000_0101));
+        assert!(csr.write(MTVEC, 0x8000_0202));
+        assert_eq!(csr.read(MTVEC), Some(0x8000_0201));
+
+        assert!(csr.write(MEPC, 0x8000_0003));
+        assert_eq!(csr.read(MEPC), Some(0x8000_0000));
+
+        assert!(csr.write(MIE, Word::MAX));
+        assert_eq!(csr.read(MIE), Some(MI_MASK));
+        assert!(csr.write(MIP, Word::MAX));
+        assert_eq!(csr.read(MIP), Some(0));
+    }
+
+    #[test]
+    fn trap_test() {
+        let mut csr = CsrFile::default();
+        csr.write(MTVEC, 0x8000_1001);
+        csr.write(MSTATUS, MSTATUS_MIE);
+        assert_eq!(csr.trap_enter(0x8000_0010, 2, 0x13), 0x8000_1000);
+        assert_eq!(csr.read(MEPC), Some(0x8000_0010));
+        assert_eq!(csr.read(MCAUSE), Some(2));
+        assert_eq!(csr.read(MTVAL), Some(0x13));
+        assert_eq!(csr.read(MSTATUS), Some(MSTATUS_MPIE | MSTATUS_MPP));
+
+        assert_eq!(csr.trap_return(), 0x8000_0010);
+        assert_eq!(
+            csr.read(MSTATUS),
+            Some(MSTATUS_MIE | MSTATUS_MPIE | MSTATUS_MPP)
+        );
+    }
+
+    #[test]
+    fn name_test() {
+        assert_eq!(csr_name(MEPC), Some("mepc"));
+        assert_eq!(csr_name(0), None);
+    }
+}
@@ -0,0 +1,736 @@
+use operand::{
+    Args, BOperand, IOperand, JOperand, NOperand, OperandHelper, OperandType, ROperand, SOperand,
+    UOperand,
+};
+
+use super::Riscv64;
+use crate::{
+    common::{DWord, SDWord, SWord, Vaddr, Word},
+    isa::{decode::DecodeTable, Execute, GuestException, ISA},
+    memory::{MemoryBank, Perm},
+};
+
+mod operand;
+struct Decoder<A>
+where
+    A: Fn(&mut Executer, &mut Riscv64, &mut MemoryBank, Args),
+{
+    apply: A,
+    helper: Box<dyn OperandHelper>,
+}
+
+impl<A> Decoder<A>
+where
+    A: Fn(&mut Executer, &mut Riscv64, &mut MemoryBank, Args),
+{
+    pub fn new(apply: A, typ: OperandType) -> Self {
+        let helper: Box<dyn OperandHelper> = match typ {
+            OperandType::I => Box::new(IOperand),
+            OperandType::U => Box::new(UOperand),
+            OperandType::S => Box::new(SOperand),
+            OperandType::N => Box::new(NOperand),
+            OperandType::R => Box::new(ROperand),
+            OperandType::J => Box::new(JOperand),
+            OperandType::B => Box::new(BOperand),
+        };
+        Self { apply, helper }
+    }
+}
+trait Decode: Send + Sync {
+    fn apply(&self, s: &mut Executer, cpu: &mut Riscv64, mem: &mut MemoryBank);
+}
+
+impl<A> Decode for Decoder<A>
+where
+    A: Fn(&mut Executer, &mut Riscv64, &mut MemoryBank, Args) + Send + Sync,
+{
+    fn apply(&self, s: &mut Executer, cpu: &mut Riscv64, mem: &mut MemoryBank) {
+        let args = self.helper.decode_operand(s.inst, cpu);
+        (self.apply)(s, cpu, mem, args)
+    }
+}
+
+macro_rules! pat {
+    ($p:literal,$name: ident,$o:expr, $b:expr) => {
+        (
+            $crate::isa::decode::Pattern::new(stringify!($name), $p),
+            Box::new($crate::isa::riscv64::executer::Decoder::new($b, $o))
+                as Box<dyn $crate::isa::riscv64::executer::Decode>,
+        )
+    };
+}
+
+/// Synchronous exceptions raised by an instruction, carrying what goes to mtval.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exception {
+    /// fetch from a pc that is not 4-byte aligned
+    InstMisaligned(Vaddr),
+    /// fetch outside physical memory
+    InstAccessFault(Vaddr),
+    /// illegal instruction, with the instruction
+    IllegalInstruction(Word),
+    /// `ebreak`, also used as nemu_trap
+    Breakpoint,
+    LoadMisaligned(Vaddr),
+    LoadAccessFault(Vaddr),
+    StoreMisaligned(Vaddr),
+    StoreAccessFault(Vaddr),
+    EcallFromM,
+}
+
+impl Exception {
+    /// exception code written to mcause
+    pub fn cause(&self) -> Word {
+        match self {
+            Exception::InstMisaligned(_) => 0,
+            Exception::InstAccessFault(_) => 1,
+            Exception::IllegalInstruction(_) => 2,
+            Exception::Breakpoint => 3,
+            Exception::LoadMisaligned(_) => 4,
+            Exception::LoadAccessFault(_) => 5,
+            Exception::StoreMisaligned(_) => 6,
+            Exception::StoreAccessFault(_) => 7,
+            Exception::EcallFromM => 11,
+        }
+    }
+
+    /// value written to mtval when the exception is taken at `pc`
+    pub fn tval(&self, pc: Vaddr) -> Word {
+        match *self {
+            Exception::InstMisaligned(addr)
+            | Exception::InstAccessFault(addr)
+            | Exception::LoadMisaligned(addr)
+            | Exception::LoadAccessFault(addr)
+            | Exception::StoreMisaligned(addr)
+            | Exception::StoreAccessFault(addr) => addr,
+            Exception::IllegalInstruction(inst) => inst,
+            Exception::Breakpoint => pc,
+            Exception::EcallFromM => 0,
+        }
+    }
+}
+
+impl GuestException for Exception {
+    /// whether the instruction could not even be fetched
+    fn is_fetch(&self) -> bool {
+        matches!(
+            self,
+            Exception::InstMisaligned(_) | Exception::InstAccessFault(_)
+        )
+    }
+
+    fn is_breakpoint(&self) -> bool {
+        matches!(self, Exception::Breakpoint)
+    }
+
+    fn is_illegal(&self) -> bool {
+        matches!(self, Exception::IllegalInstruction(_))
+    }
+
+    fn is_access_fault(&self) -> bool {
+        matches!(
+            self,
+            Exception::InstAccessFault(_)
+                | Exception::LoadAccessFault(_)
+                | Exception::StoreAccessFault(_)
+        )
+    }
+}
+
+pub struct Executer {
+    pc: Vaddr,
+    snpc: Vaddr,
+    dnpc: Vaddr,
+    inst: Word,
+    exception: Option<Exception>,
+    decoders: &'static DecodeTable<Box<dyn Decode>>,
+}
+
+impl Executer {
+    pub fn new() -> Self {
+        Self {
+            pc: 0,
+            snpc: 0,
+            dnpc: 0,
+            inst: 0,
+            exception: None,
+            decoders: &DECODERS,
+        }
+    }
+
+    fn fetch(&mut self, mem: &MemoryBank) -> Option<Word> {
+        // no RVC, every instruction is a 4-byte aligned word
+        if !self.pc.is_multiple_of(4) {
+            self.raise(Exception::InstMisaligned(self.pc));
+            return None;
+        }
+        if !mem.allows(self.snpc, 4, Perm::X) {
+            self.raise(Exception::InstAccessFault(self.snpc));
+            return None;
+        }
+        Some(mem.inst_fetch(&mut self.snpc, 4))
+    }
+
+    fn raise(&mut self, e: Exception) {
+        self.exception = Some(e);
+    }
+}
+
+impl Execute<Riscv64> for Executer {
+    /// execute the instruction at pc, an exception it raises is left for `take_exception`
+    fn exec_once(&mut self, cpu: &mut Riscv64, mem: &mut MemoryBank) {
+        self.dnpc = self.snpc;
+        let Some(inst) = self.fetch(mem) else {
+            return;
+        };
+        self.inst = inst;
+        self.dnpc = self.snpc;
+        match self.decoders.lookup(inst as u32) {
+            Some(d) => {
+                d.apply(self, cpu, mem);
+                cpu.set_reg(0, 0);
+            }
+            None => self.raise(Exception::IllegalInstruction(inst)),
+        }
+    }
+
+    fn pc(&self) -> Vaddr {
+        self.pc
+    }
+
+    fn set_pc(&mut self, pc: Vaddr) {
+        self.pc = pc;
+    }
+
+    fn set_snpc(&mut self, snpc: Vaddr) {
+        self.snpc = snpc;
+    }
+
+    fn dnpc(&self) -> Vaddr {
+        self.dnpc
+    }
+
+    /// take the exception raised by the last executed instruction
+    fn take_exception(&mut self) -> Option<Exception> {
+        self.exception.take()
+    }
+}
+
+/// load `len` bytes at `addr`, `None` after raising the exception if it can not be done
+fn load(s: &mut Executer, mem: &MemoryBank, addr: Vaddr, len: usize) -> Option<Word> {
+    if !addr.is_multiple_of(len as Vaddr) {
+        s.raise(Exception::LoadMisaligned(addr));
+        return None;
+    }
+    if !mem.allows(addr, len, Perm::R) {
+        s.raise(Exception::LoadAccessFault(addr));
+        return None;
+    }
+    Some(mem.paddr_read(addr, len))
+}
+
+fn store(s: &mut Executer, mem: &mut MemoryBank, addr: Vaddr, len: usize, data: Word) {
+    if !addr.is_multiple_of(len as Vaddr) {
+        s.raise(Exception::StoreMisaligned(addr));
+        return;
+    }
+    if !mem.allows(addr, len, Perm::W) {
+        s.raise(Exception::StoreAccessFault(addr));
+        return;
+    }
+    mem.paddr_write(addr, len, data);
+}
+
+/// Zicsr read-modify-write, `rd = csr; csr = op(csr)` where the write is skipped unless `write`
+fn csr_rw<F>(s: &mut Executer, cpu: &mut Riscv64, rd: usize, write: bool, op: F)
+where
+    F: Fn(Word) -> Word,
+{
+    let addr = bits!(s.inst, 31, 20) as usize;
+    let Some(old) = cpu.csr(addr) else {
+        s.raise(Exception::IllegalInstruction(s.inst));
+        return;
+    };
+    if write && !cpu.set_csr(addr, op(old)) {
+        s.raise(Exception::IllegalInstruction(s.inst));
+        return;
+    }
+    cpu.set_reg(rd, old);
+}
+
+/// sign-extend the low 32 bits, the result of every *W instruction
+fn sext_w(val: Word) -> Word {
+    val as u32 as i32 as SWord as Word
+}
+
+lazy_static::lazy_static! {
+    static ref DECODERS: DecodeTable<Box<dyn Decode>> = {
+        let decoders = vec! {
+            pat!("??????? ????? ????? ??? ????? 01101 11", lui, OperandType::U, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, args.imm);
+            }),
+            pat!("??????? ????? ????? ??? ????? 00101 11", auipc, OperandType::U, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, s.pc.wrapping_add(args.imm));
+            }),
+            pat!("??????? ????? ????? ??? ????? 11011 11", jal, OperandType::J, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, s.snpc);
+                s.dnpc = s.pc.wrapping_add(args.imm);
+            }),
+            pat!("??????? ????? ????? 000 ????? 11001 11", jalr, OperandType::I, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, s.snpc);
+                s.dnpc = args.src1.wrapping_add(args.imm) & !1;
+            }),
+            pat!("??????? ????? ????? 000 ????? 11000 11", beq, OperandType::B, |s, cpu, mem, args| {
+                if args.src1 == args.src2 {
+                    s.dnpc = s.pc.wrapping_add(args.imm);
+                }
+            }),
+            pat!("??????? ????? ????? 001 ????? 11000 11", bne, OperandType::B, |s, cpu, mem, args| {
+                if args.src1 != args.src2 {
+                    s.dnpc = s.pc.wrapping_add(args.imm);
+                }
+            }),
+            pat!("??????? ????? ????? 100 ????? 11000 11", blt, OperandType::B, |s, cpu, mem, args| {
+                if (args.src1 as SWord) < (args.src2 as SWord) {
+                    s.dnpc = s.pc.wrapping_add(args.imm);
+                }
+            }),
+            pat!("??????? ????? ????? 101 ????? 11000 11", bge, OperandType::B, |s, cpu, mem, args| {
+                if (args.src1 as SWord) >= (args.src2 as SWord) {
+                    s.dnpc = s.pc.wrapping_add(args.imm);
+                }
+            }),
+            pat!("??????? ????? ????? 110 ????? 11000 11", bltu, OperandType::B, |s, cpu, mem, args| {
+                if args.src1 < args.src2 {
+                    s.dnpc = s.pc.wrapping_add(args.imm);
+                }
+            }),
+            pat!("??????? ????? ????? 111 ????? 11000 11", bgeu, OperandType::B, |s, cpu, mem, args| {
+                if args.src1 >= args.src2 {
+                    s.dnpc = s.pc.wrapping_add(args.imm);
+                }
+            }),
+            pat!("??????? ????? ????? 000 ????? 00000 11", lb, OperandType::I, |s, cpu, mem, args| {
+                let Some(val) = load(s, mem, args.src1.wrapping_add(args.imm), 1) else {
+                    return;
+                };
+                cpu.set_reg(args.rd, sext!(val, 8));
+            }),
+            pat!("??????? ????? ????? 001 ????? 00000 11", lh, OperandType::I, |s, cpu, mem, args| {
+                let Some(val) = load(s, mem, args.src1.wrapping_add(args.imm), 2) else {
+                    return;
+                };
+                cpu.set_reg(args.rd, sext!(val, 16));
+            }),
+            pat!("??????? ????? ????? 010 ????? 00000 11", lw, OperandType::I, |s, cpu, mem, args| {
+                let Some(val) = load(s, mem, args.src1.wrapping_add(args.imm), 4) else {
+                    return;
+                };
+                cpu.set_reg(args.rd, sext_w(val));
+            }),
+            pat!("??????? ????? ????? 011 ????? 00000 11", ld, OperandType::I, |s, cpu, mem, args| {
+                let Some(val) = load(s, mem, args.src1.wrapping_add(args.imm), 8) else {
+                    return;
+                };
+                cpu.set_reg(args.rd, val);
+            }),
+            pat!("??????? ????? ????? 100 ????? 00000 11", lbu, OperandType::I, |s, cpu, mem, args| {
+                let Some(val) = load(s, mem, args.src1.wrapping_add(args.imm), 1) else {
+                    return;
+                };
+                cpu.set_reg(args.rd, val);
+            }),
+            pat!("??????? ????? ????? 101 ????? 00000 11", lhu, OperandType::I, |s, cpu, mem, args| {
+                let Some(val) = load(s, mem, args.src1.wrapping_add(args.imm), 2) else {
+                    return;
+                };
+                cpu.set_reg(args.rd, val);
+            }),
+            pat!("??????? ????? ????? 110 ????? 00000 11", lwu, OperandType::I, |s, cpu, mem, args| {
+                let Some(val) = load(s, mem, args.src1.wrapping_add(args.imm), 4) else {
+                    return;
+                };
+                cpu.set_reg(args.rd, val);
+            }),
+            pat!("??????? ????? ????? 000 ????? 01000 11", sb, OperandType::S, |s, cpu, mem, args| {
+                store(s, mem, args.src1.wrapping_add(args.imm), 1, args.src2);
+            }),
+            pat!("??????? ????? ????? 001 ????? 01000 11", sh, OperandType::S, |s, cpu, mem, args| {
+                store(s, mem, args.src1.wrapping_add(args.imm), 2, args.src2);
+            }),
+            pat!("??????? ????? ????? 010 ????? 01000 11", sw, OperandType::S, |s, cpu, mem, args| {
+                store(s, mem, args.src1.wrapping_add(args.imm), 4, args.src2);
+            }),
+            pat!("??????? ????? ????? 011 ????? 01000 11", sd, OperandType::S, |s, cpu, mem, args| {
+                store(s, mem, args.src1.wrapping_add(args.imm), 8, args.src2);
+            }),
+            pat!("??????? ????? ????? 000 ????? 00100 11", addi, OperandType::I, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, args.src1.wrapping_add(args.imm));
+            }),
+            pat!("??????? ????? ????? 010 ????? 00100 11", slti, OperandType::I, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, ((args.src1 as SWord) < (args.imm as SWord)) as Word);
+            }),
+            pat!("??????? ????? ????? 011 ????? 00100 11", sltiu, OperandType::I, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, (args.src1 < args.imm) as Word);
+            }),
+            pat!("??????? ????? ????? 100 ????? 00100 11", xori, OperandType::I, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, args.src1 ^ args.imm);
+            }),
+            pat!("??????? ????? ????? 110 ????? 00100 11", ori, OperandType::I, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, args.src1 | args.imm);
+            }),
+            pat!("??????? ????? ????? 111 ????? 00100 11", andi, OperandType::I, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, args.src1 & args.imm);
+            }),
+            // RV64 shifts take a 6-bit shamt, imm[5] moves into the funct7 field
+            pat!("000000 ?????? ????? 001 ????? 00100 11", slli, OperandType::I, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, args.src1 << (args.imm & 0x3f));
+            }),
+            pat!("000000 ?????? ????? 101 ????? 00100 11", srli, OperandType::I, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, args.src1 >> (args.imm & 0x3f));
+            }),
+            pat!("010000 ?????? ????? 101 ????? 00100 11", srai, OperandType::I, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, ((args.src1 as SWord) >> (args.imm & 0x3f)) as Word);
+            }),
+            pat!("0000000 ????? ????? 000 ????? 01100 11", add, OperandType::R, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, args.src1.wrapping_add(args.src2));
+            }),
+            pat!("0100000 ????? ????? 000 ????? 01100 11", sub, OperandType::R, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, args.src1.wrapping_sub(args.src2));
+            }),
+            pat!("0000000 ????? ????? 001 ????? 01100 11", sll, OperandType::R, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, args.src1 << (args.src2 & 0x3f));
+            }),
+            pat!("0000000 ????? ????? 010 ????? 01100 11", slt, OperandType::R, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, ((args.src1 as SWord) < (args.src2 as SWord)) as Word);
+            }),
+            pat!("0000000 ????? ????? 011 ????? 01100 11", sltu, OperandType::R, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, (args.src1 < args.src2) as Word);
+            }),
+            pat!("0000000 ????? ????? 100 ????? 01100 11", xor, OperandType::R, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, args.src1 ^ args.src2);
+            }),
+            pat!("0000000 ????? ????? 101 ????? 01100 11", srl, OperandType::R, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, args.src1 >> (args.src2 & 0x3f));
+            }),
+            pat!("0100000 ????? ????? 101 ????? 01100 11", sra, OperandType::R, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, ((args.src1 as SWord) >> (args.src2 & 0x3f)) as Word);
+            }),
+            pat!("0000000 ????? ????? 110 ????? 01100 11", or, OperandType::R, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, args.src1 | args.src2);
+            }),
+            pat!("0000000 ????? ????? 111 ????? 01100 11", and, OperandType::R, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, args.src1 & args.src2);
+            }),
+            // the *W forms operate on the low 32 bits and sign-extend the result
+            pat!("??????? ????? ????? 000 ????? 00110 11", addiw, OperandType::I, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, sext_w(args.src1.wrapping_add(args.imm)));
+            }),
+            pat!("0000000 ????? ????? 001 ????? 00110 11", slliw, OperandType::I, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, sext_w(args.src1 << (args.imm & 0x1f)));
+            }),
+            pat!("0000000 ????? ????? 101 ????? 00110 11", srliw, OperandType::I, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, sext_w((args.src1 as u32 >> (args.imm & 0x1f)) as Word));
+            }),
+            pat!("0100000 ????? ????? 101 ????? 00110 11", sraiw, OperandType::I, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, ((args.src1 as i32) >> (args.imm & 0x1f)) as SWord as Word);
+            }),
+            pat!("0000000 ????? ????? 000 ????? 01110 11", addw, OperandType::R, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, sext_w(args.src1.wrapping_add(args.src2)));
+            }),
+            pat!("0100000 ????? ????? 000 ????? 01110 11", subw, OperandType::R, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, sext_w(args.src1.wrapping_sub(args.src2)));
+            }),
+            pat!("0000000 ????? ????? 001 ????? 01110 11", sllw, OperandType::R, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, sext_w(args.src1 << (args.src2 & 0x1f)));
+            }),
+            pat!("0000000 ????? ????? 101 ????? 01110 11", srlw, OperandType::R, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, sext_w((args.src1 as u32 >> (args.src2 & 0x1f)) as Word));
+            }),
+            pat!("0100000 ????? ????? 101 ????? 01110 11", sraw, OperandType::R, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, ((args.src1 as i32) >> (args.src2 & 0x1f)) as SWord as Word);
+            }),
+            pat!("0000001 ????? ????? 000 ????? 01100 11", mul, OperandType::R, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, args.src1.wrapping_mul(args.src2));
+            }),
+            pat!("0000001 ????? ????? 001 ????? 01100 11", mulh, OperandType::R, |s, cpu, mem, args| {
+                let prod = (args.src1 as SWord as SDWord) * (args.src2 as SWord as SDWord);
+                cpu.set_reg(args.rd, (prod >> Word::BITS) as Word);
+            }),
+            pat!("0000001 ????? ????? 010 ????? 01100 11", mulhsu, OperandType::R, |s, cpu, mem, args| {
+                let prod = (args.src1 as SWord as SDWord).wrapping_mul(args.src2 as SDWord);
+                cpu.set_reg(args.rd, (prod >> Word::BITS) as Word);
+            }),
+            pat!("0000001 ????? ????? 011 ????? 01100 11", mulhu, OperandType::R, |s, cpu, mem, args| {
+                let prod = (args.src1 as DWord) * (args.src2 as DWord);
+                cpu.set_reg(args.rd, (prod >> Word::BITS) as Word);
+            }),
+            // division never traps: x/0 is all ones and INT_MIN/-1 overflows back to INT_MIN
+            pat!("0000001 ????? ????? 100 ????? 01100 11", div, OperandType::R, |s, cpu, mem, args| {
+                let val = match args.src2 {
+                    0 => Word::MAX,
+                    _ => (args.src1 as SWord).wrapping_div(args.src2 as SWord) as Word,
+                };
+                cpu.set_reg(args.rd, val);
+            }),
+            pat!("0000001 ????? ????? 101 ????? 01100 11", divu, OperandType::R, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, args.src1.checked_div(args.src2).unwrap_or(Word::MAX));
+            }),
+            // x%0 is x and INT_MIN%-1 is 0
+            pat!("0000001 ????? ????? 110 ????? 01100 11", rem, OperandType::R, |s, cpu, mem, args| {
+                let val = match args.src2 {
+                    0 => args.src1,
+                    _ => (args.src1 as SWord).wrapping_rem(args.src2 as SWord) as Word,
+                };
+                cpu.set_reg(args.rd, val);
+            }),
+            pat!("0000001 ????? ????? 111 ????? 01100 11", remu, OperandType::R, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, args.src1.checked_rem(args.src2).unwrap_or(args.src1));
+            }),
+            pat!("0000001 ????? ????? 000 ????? 01110 11", mulw, OperandType::R, |s, cpu, mem, args| {
+                cpu.set_reg(args.rd, sext_w(args.src1.wrapping_mul(args.src2)));
+            }),
+            pat!("0000001 ????? ????? 100 ????? 01110 11", divw, OperandType::R, |s, cpu, mem, args| {
+                let val = match args.src2 as i32 {
+                    0 => -1,
+                    b => (args.src1 as i32).wrapping_div(b),
+                };
+                cpu.set_reg(args.rd, val as SWord as Word);
+            }),
+            pat!("0000001 ????? ????? 101 ????? 01110 11", divuw, OperandType::R, |s, cpu, mem, args| {
+                let val = (args.src1 as u32).checked_div(args.src2 as u32).unwrap_or(u32::MAX);
+                cpu.set_reg(args.rd, sext_w(val as Word));
+            }),
+            pat!("0000001 ????? ????? 110 ????? 01110 11", remw, OperandType::R, |s, cpu, mem, args| {
+                let val = match args.src2 as i32 {
+                    0 => args.src1 as i32,
+                    b => (args.src1 as i32).wrapping_rem(b),
+                };
+                cpu.set_reg(args.rd, val as SWord as Word);
+            }),
+            pat!("0000001 ????? ????? 111 ????? 01110 11", remuw, OperandType::R, |s, cpu, mem, args| {
+                let (a, b) = (args.src1 as u32, args.src2 as u32);
+                cpu.set_reg(args.rd, sext_w(a.checked_rem(b).unwrap_or(a) as Word));
+            }),
+            // the immediate forms take the zero-extended rs1 field as uimm, csrrs/csrrc with
+            // rs1 == x0 only read the CSR
+            pat!("??????? ????? ????? 001 ????? 11100 11", csrrw, OperandType::I, |s, cpu, mem, args| {
+                csr_rw(s, cpu, args.rd, true, |_| args.src1);
+            }),
+            pat!("??????? ????? ????? 010 ????? 11100 11", csrrs, OperandType::I, |s, cpu, mem, args| {
+                let write = bits!(s.inst, 19, 15) != 0;
+                csr_rw(s, cpu, args.rd, write, |old| old | args.src1);
+            }),
+            pat!("??????? ????? ????? 011 ????? 11100 11", csrrc, OperandType::I, |s, cpu, mem, args| {
+                let write = bits!(s.inst, 19, 15) != 0;
+                csr_rw(s, cpu, args.rd, write, |old| old & !args.src1);
+            }),
+            pat!("??????? ????? ????? 101 ????? 11100 11", csrrwi, OperandType::I, |s, cpu, mem, args| {
+                let uimm = bits!(s.inst, 19, 15);
+                csr_rw(s, cpu, args.rd, true, |_| uimm);
+            }),
+            pat!("??????? ????? ????? 110 ????? 11100 11", csrrsi, OperandType::I, |s, cpu, mem, args| {
+                let uimm = bits!(s.inst, 19, 15);
+                csr_rw(s, cpu, args.rd, uimm != 0, |old| old | uimm);
+            }),
+            pat!("??????? ????? ????? 111 ????? 11100 11", csrrci, OperandType::I, |s, cpu, mem, args| {
+                let uimm = bits!(s.inst, 19, 15);
+                csr_rw(s, cpu, args.rd, uimm != 0, |old| old & !uimm);
+            }),
+            // single hart without caches, every memory ordering is already satisfied
+            pat!("??????? ????? ????? 000 ????? 00011 11", fence, OperandType::N, |s, cpu, mem, args| {}),
+            pat!("0000000 00000 00000 000 00000 11100 11", ecall, OperandType::N, |s, cpu, mem, args| {
+                s.raise(Exception::EcallFromM);
+            }),
+            pat!("0000000 00001 00000 000 00000 11100 11", ebreak, OperandType::N, |s, cpu, mem, args| {
+                s.raise(Exception::Breakpoint);
+            }),
+            pat!("0011000 00010 00000 000 00000 11100 11", mret, OperandType::N, |s, cpu, mem, args| {
+                s.dnpc = cpu.csr.trap_return();
+            }),
+        };
+        DecodeTable::new(decoders)
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::isa::riscv64::csr::{MEPC, MSCRATCH};
+    use crate::isa::{Riscv64, ISA};
+    use crate::memory::{MemoryBank, RESET_VECTOR};
+
+    use super::*;
+
+    const PC: Vaddr = RESET_VECTOR as Vaddr;
+    const DATA: Vaddr = PC + 0x1000;
+    const NEG1: Word = Word::MAX;
+
+    fn r(f7: u32, rs2: u32, rs1: u32, f3: u32, rd: u32, op: u32) -> Word {
+        ((f7 << 25) | (rs2 << 20) | (rs1 << 15) | (f3 << 12) | (rd << 7) | op) as Word
+    }
+    fn i(imm: i32, rs1: u32, f3: u32, rd: u32, op: u32) -> Word {
+        (((imm as u32 & 0xfff) << 20) | (rs1 << 15) | (f3 << 12) | (rd << 7) | op) as Word
+    }
+    fn s(imm: i32, rs2: u32, rs1: u32, f3: u32) -> Word {
+        let imm = imm as u32;
+        let inst = (bits!(imm, 11, 5) << 25)
+            | (rs2 << 20)
+            | (rs1 << 15)
+            | (f3 << 12)
+            | (bits!(imm, 4, 0) << 7)
+            | 0x23;
+        inst as Word
+    }
+
+    fn machine() -> (Riscv64, MemoryBank) {
+        (Riscv64::new(PC), MemoryBank::new(&[]))
+    }
+
+    /// place `inst` at the current pc and execute it
+    fn step(cpu: &mut Riscv64, mem: &mut MemoryBank, inst: Word) -> Option<Exception> {
+        let pc = cpu.pc();
+        mem.paddr_write(pc, 4, inst);
+        let mut executer = Executer::new();
+        executer.set_pc(pc);
+        executer.set_snpc(pc);
+        executer.exec_once(cpu, mem);
+        cpu.set_pc(executer.dnpc());
+        executer.take_exception()
+    }
+
+    /// run an R-type op with `opcode` on (a, b) and return rd
+    fn alu_r(f7: u32, f3: u32, op: u32, a: Word, b: Word) -> Word {
+        let (mut cpu, mut mem) = machine();
+        cpu.set_reg(1, a);
+        cpu.set_reg(2, b);
+        step(&mut cpu, &mut mem, r(f7, 2, 1, f3, 3, op));
+        cpu.reg(3)
+    }
+
+    /// run an I-type op with `opcode` on (a, imm) and return rd
+    fn alu_i(imm: i32, f3: u32, op: u32, a: Word) -> Word {
+        let (mut cpu, mut mem) = machine();
+        cpu.set_reg(1, a);
+        step(&mut cpu, &mut mem, i(imm, 1, f3, 3, op));
+        cpu.reg(3)
+    }
+
+    #[test]
+    fn default_img_test() {
+        let (mut cpu, mut mem) = machine();
+        let img = Riscv64::default_img();
+        for (n, w) in img.chunks(4).enumerate() {
+            let w = u32::from_le_bytes(w.try_into().unwrap());
+            mem.paddr_write(PC + 4 * n as Vaddr, 4, w as Word);
+        }
+        let mut executer = Executer::new();
+        for _ in 0..4 {
+            executer.set_pc(cpu.pc());
+            executer.set_snpc(cpu.pc());
+            executer.exec_once(&mut cpu, &mut mem);
+            cpu.set_pc(executer.dnpc());
+        }
+        assert_eq!(executer.take_exception(), Some(Exception::Breakpoint));
+        assert_eq!(cpu.reg(5), PC);
+        assert_eq!(cpu.reg(10), 0);
+    }
+
+    #[test]
+    fn wide_register_test() {
+        // addi sign-extends to all 64 bits
+        assert_eq!(alu_i(-1, 0, 0x13, 0), NEG1);
+        assert_eq!(alu_r(0, 0, 0x33, 0xffff_ffff, 1), 0x1_0000_0000);
+        // lui sign-extends imm[31]
+        let (mut cpu, mut mem) = machine();
+        step(&mut cpu, &mut mem, 0x800000b7);
+        assert_eq!(cpu.reg(1), 0xffff_ffff_8000_0000);
+    }
+
+    #[test]
+    fn shift_test() {
+        // 6-bit shamt
+        assert_eq!(alu_i(63, 1, 0x13, 1), 1 << 63);
+        assert_eq!(alu_i(32, 5, 0x13, 1 << 63), 1 << 31);
+        assert_eq!(alu_i(0x400 | 63, 5, 0x13, 1 << 63), NEG1);
+        assert_eq!(alu_r(0, 1, 0x33, 1, 40), 1 << 40);
+        assert_eq!(alu_r(0x20, 5, 0x33, 1 << 63, 63), NEG1);
+    }
+
+    #[test]
+    fn word_op_test() {
+        // addiw wraps at 32 bits and sign-extends
+        assert_eq!(alu_i(1, 0, 0x1b, 0x7fff_ffff), 0xffff_ffff_8000_0000);
+        assert_eq!(alu_r(0, 0, 0x3b, 0x1_0000_0001, 1), 2);
+        assert_eq!(alu_r(0x20, 0, 0x3b, 0, 1), NEG1);
+        assert_eq!(alu_i(31, 1, 0x1b, 1), 0xffff_ffff_8000_0000);
+        assert_eq!(alu_i(4, 5, 0x1b, 0xffff_ffff_8000_0000), 0x0800_0000);
+        assert_eq!(
+            alu_i(0x400 | 4, 5, 0x1b, 0x8000_0000),
+            0xffff_ffff_f800_0000
+        );
+        assert_eq!(alu_r(0, 1, 0x3b, 1, 33), 2);
+        assert_eq!(alu_r(0, 5, 0x3b, 0x8000_0000, 31), 1);
+        assert_eq!(alu_r(0x20, 5, 0x3b, 0x8000_0000, 31), NEG1);
+    }
+
+    #[test]
+    fn mul_div_test() {
+        assert_eq!(alu_r(1, 3, 0x33, NEG1, NEG1), NEG1 - 1);
+        assert_eq!(alu_r(1, 1, 0x33, NEG1, NEG1), 0);
+        assert_eq!(alu_r(1, 4, 0x33, 7, 0), NEG1);
+        assert_eq!(alu_r(1, 4, 0x33, 1 << 63, NEG1), 1 << 63);
+        assert_eq!(alu_r(1, 0, 0x3b, 0x1_0000_0002, 0x8000_0000), 0);
+        assert_eq!(alu_r(1, 4, 0x3b, 0x8000_0000, NEG1), 0xffff_ffff_8000_0000);
+        assert_eq!(alu_r(1, 5, 0x3b, 7, 0), NEG1);
+        assert_eq!(alu_r(1, 6, 0x3b, 0xffff_fff9, 0), NEG1 - 6);
+        assert_eq!(alu_r(1, 7, 0x3b, 0x1_0000_0007, 4), 3);
+    }
+
+    #[test]
+    fn load_store_test() {
+        let (mut cpu, mut mem) = machine();
+        cpu.set_reg(1, DATA);
+        cpu.set_reg(2, 0x8000_0000_ffff_fffe);
+        step(&mut cpu, &mut mem, s(0, 2, 1, 3));
+        assert_eq!(mem.paddr_read(DATA, 8), 0x8000_0000_ffff_fffe);
+        // ld, lw, lwu
+        step(&mut cpu, &mut mem, i(0, 1, 3, 3, 0x03));
+        assert_eq!(cpu.reg(3), 0x8000_0000_ffff_fffe);
+        step(&mut cpu, &mut mem, i(0, 1, 2, 3, 0x03));
+        assert_eq!(cpu.reg(3), NEG1 - 1);
+        step(&mut cpu, &mut mem, i(0, 1, 6, 3, 0x03));
+        assert_eq!(cpu.reg(3), 0xffff_fffe);
+        // doubleword accesses must be 8-byte aligned
+        let trap = step(&mut cpu, &mut mem, i(4, 1, 3, 3, 0x03));
+        assert_eq!(trap, Some(Exception::LoadMisaligned(DATA + 4)));
+        let trap = step(&mut cpu, &mut mem, s(4, 2, 1, 3));
+        assert_eq!(trap, Some(Exception::StoreMisaligned(DATA + 4)));
+    }
+
+    #[test]
+    fn csr_test() {
+        let (mut cpu, mut mem) = machine();
+        cpu.set_reg(1, NEG1);
+        step(&mut cpu, &mut mem, i(MSCRATCH as i32, 1, 1, 0, 0x73));
+        assert_eq!(cpu.csr(MSCRATCH), Some(NEG1));
+        cpu.set_csr(MEPC, PC + 0x100);
+        step(&mut cpu, &mut mem, 0x30200073);
+        assert_eq!(cpu.pc(), PC + 0x100);
+    }
+
+    #[test]
+    fn fetch_test() {
+        let (mut cpu, mut mem) = machine();
+        cpu.set_pc(PC + 2);
+        assert_eq!(
+            step(&mut cpu, &mut mem, 0x13),
+            Some(Exception::InstMisaligned(PC + 2))
+        );
+        // a 16-bit parcel is not an instruction without RVC
+        let (mut cpu, mut mem) = machine();
+        assert_eq!(
+            step(&mut cpu, &mut mem, 0x0001),
+            Some(Exception::IllegalInstruction(0x0001))
+        );
+    }
+}
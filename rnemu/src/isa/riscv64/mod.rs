@@ -0,0 +1,123 @@
+use crate::{
+    common::{Vaddr, Word},
+    memory::MemoryBank,
+};
+
+use super::{TlbConfig, ISA};
+
+mod csr;
+mod executer;
+use csr::CsrFile;
+pub use executer::{Exception, Executer};
+pub const GUEST_ISA: &str = "riscv64";
+
+/// RV64IM hart running bare in M-mode.
+#[derive(Default, Debug)]
+pub struct Riscv64 {
+    gpr: [Word; 32],
+    pc: Vaddr,
+    csr: CsrFile,
+}
+
+impl Riscv64 {
+    pub fn new(pc: Vaddr) -> Self {
+        Self {
+            gpr: [0; 32],
+            pc,
+            csr: CsrFile::default(),
+        }
+    }
+}
+
+impl ISA for Riscv64 {
+    type Executer = executer::Executer;
+    type Exception = executer::Exception;
+    const NAME: &'static str = GUEST_ISA;
+    const LOGO: &'static str = ISA_LOGO;
+    const NR_GPR: usize = 32;
+    const ELF_MACHINE: u16 = 243;
+
+    fn init(pc: Vaddr, tlb: TlbConfig) -> Self {
+        Self::new(pc)
+    }
+
+    /// take exception `e` raised by the instruction at `pc`, return the handler address
+    fn raise_exception(&mut self, mem: &mut MemoryBank, pc: Vaddr, e: Exception) -> Vaddr {
+        self.csr.trap_enter(pc, e.cause(), e.tval(pc))
+    }
+
+    /// a0 holds the exit code of nemu_trap
+    fn halt_ret(&self) -> Word {
+        self.gpr[10]
+    }
+
+    fn set_pc(&mut self, next: Vaddr) {
+        self.pc = next;
+    }
+
+    fn pc(&self) -> Vaddr {
+        self.pc
+    }
+
+    fn reg(&self, idx: usize) -> Word {
+        self.gpr[idx]
+    }
+
+    fn set_reg(&mut self, idx: usize, val: Word) {
+        self.gpr[idx] = val;
+    }
+
+    fn csr(&self, addr: usize) -> Option<Word> {
+        self.csr.read(addr)
+    }
+
+    fn set_csr(&mut self, addr: usize, val: Word) -> bool {
+        self.csr.write(addr, val)
+    }
+
+    fn default_img() -> &'static [u8] {
+        let ptr = IMG.as_ptr() as *const u8;
+        let len = IMG.len() * std::mem::size_of::<u32>();
+        unsafe { std::slice::from_raw_parts(ptr, len) }
+    }
+
+    fn executer() -> Self::Executer {
+        executer::Executer::new()
+    }
+}
+
+pub const ISA_LOGO: &str = r"       _                         __  __                         _
+      (_)                       |  \/  |                       | |
+  _ __ _ ___  ___ ________   __ | \  / | __ _ _ __  _   _  __ _| |
+ | '__| / __|/ __|______\ \ / / | |\/| |/ _` | '_ \| | | |/ _` | |
+ | |  | \__ \ (__        \ V /  | |  | | (_| | | | | |_| | (_| | |
+ |_|  |_|___/\___|        \_/   |_|  |_|\__,_|_| |_|\__,_|\__,_|_|
+";
+
+const IMG: [u32; 6] = [
+    0x00000297, // auipc t0,0
+    0x0002b823, // sd  zero,16(t0)
+    0x0102b503, // ld  a0,16(t0)
+    0x00100073, // ebreak (used as nemu_trap)
+    0xdeadbeef, // some data
+    0xdeadbeef,
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::MemoryBank;
+
+    #[test]
+    fn raise_exception_test() {
+        let mut cpu = Riscv64::new(0x8000_0000);
+        let mut mem = MemoryBank::new(&[]);
+        cpu.set_csr(csr::MTVEC, 0x8000_0100);
+        let handler =
+            cpu.raise_exception(&mut mem, 0x8000_0004, Exception::IllegalInstruction(0xffff));
+        assert_eq!(handler, 0x8000_0100);
+        assert_eq!(cpu.csr(csr::MEPC), Some(0x8000_0004));
+        assert_eq!(cpu.csr(csr::MCAUSE), Some(2));
+        assert_eq!(cpu.csr(csr::MTVAL), Some(0xffff));
+    }
+}
@@ -0,0 +1,1401 @@
+use operand::{decode_operand, Args, Loc, OperandType};
+
+use super::{AF, CF, DF, EAX, EBP, ECX, EDI, EDX, ESI, ESP, IF, OF, PF, SF, X86, ZF};
+use crate::{
+    common::{SWord, Vaddr, Word},
+    isa::{
+        decode::{DecodeTable, Pattern},
+        Execute, GuestException, ISA,
+    },
+    memory::{MemoryBank, Perm},
+};
+
+mod operand;
+
+struct Decoder<A>
+where
+    A: Fn(&mut Executer, &mut X86, &mut MemoryBank, Args) -> Option<()>,
+{
+    typ: OperandType,
+    /// 1 for byte operations, 0 for the operand size
+    width: usize,
+    apply: A,
+}
+
+impl<A> Decoder<A>
+where
+    A: Fn(&mut Executer, &mut X86, &mut MemoryBank, Args) -> Option<()>,
+{
+    pub fn new(typ: OperandType, width: usize, apply: A) -> Self {
+        Self { typ, width, apply }
+    }
+}
+
+trait Decode: Send + Sync {
+    fn apply(&self, s: &mut Executer, cpu: &mut X86, mem: &mut MemoryBank) -> Option<()>;
+}
+
+impl<A> Decode for Decoder<A>
+where
+    A: Fn(&mut Executer, &mut X86, &mut MemoryBank, Args) -> Option<()> + Send + Sync,
+{
+    fn apply(&self, s: &mut Executer, cpu: &mut X86, mem: &mut MemoryBank) -> Option<()> {
+        let width = if self.width == 0 {
+            s.opsize
+        } else {
+            self.width
+        };
+        let args = decode_operand(s, cpu, mem, self.typ, width)?;
+        s.dnpc = s.snpc;
+        (self.apply)(s, cpu, mem, args)
+    }
+}
+
+/// an opcode pattern, one byte or `0x0f` and a second byte. A one-byte pattern also requires
+/// the upper byte to be zero so it never matches a two-byte opcode.
+fn opcode(name: &'static str, pattern: &str) -> Pattern {
+    let mut pat = Pattern::new(name, pattern);
+    match pat.len {
+        8 => pat.mask |= 0xff00,
+        16 => {}
+        _ => panic!("opcode pattern of {name} is neither one nor two bytes"),
+    }
+    pat
+}
+
+macro_rules! pat {
+    ($p:literal, $name:ident, $o:expr, $w:expr, $b:expr) => {
+        (
+            $crate::isa::x86::executer::opcode(stringify!($name), $p),
+            Box::new($crate::isa::x86::executer::Decoder::new($o, $w, $b))
+                as Box<dyn $crate::isa::x86::executer::Decode>,
+        )
+    };
+}
+
+/// Exceptions raised by an instruction, named after their vector.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exception {
+    /// #DE, division by zero or a quotient that does not fit
+    DivideError,
+    /// #BP, `int3`, also raised by nemu_trap
+    Breakpoint,
+    /// #UD, with the opcode
+    IllegalInstruction(Word),
+    /// #GP, data access outside physical memory
+    GeneralProtection(Vaddr),
+    /// fetch outside physical memory, delivered as #GP
+    FetchFault(Vaddr),
+    /// `int imm8`
+    SoftwareInterrupt(u8),
+}
+
+impl Exception {
+    /// index into the IDT
+    pub fn vector(&self) -> u8 {
+        match self {
+            Exception::DivideError => 0,
+            Exception::Breakpoint => 3,
+            Exception::IllegalInstruction(_) => 6,
+            Exception::GeneralProtection(_) | Exception::FetchFault(_) => 13,
+            Exception::SoftwareInterrupt(n) => *n,
+        }
+    }
+
+    /// the %eip pushed for an exception at `pc`, traps return past the 1-byte `int3` or nemu_trap
+    /// and the 2-byte `int imm8`
+    pub fn return_address(&self, pc: Vaddr) -> Vaddr {
+        match self {
+            Exception::Breakpoint => pc.wrapping_add(1),
+            Exception::SoftwareInterrupt(_) => pc.wrapping_add(2),
+            _ => pc,
+        }
+    }
+
+    /// the error code pushed after %eip
+    pub fn error_code(&self) -> Option<Word> {
+        match self {
+            Exception::GeneralProtection(_) | Exception::FetchFault(_) => Some(0),
+            _ => None,
+        }
+    }
+}
+
+impl GuestException for Exception {
+    /// whether the instruction could not even be fetched
+    fn is_fetch(&self) -> bool {
+        matches!(self, Exception::FetchFault(_))
+    }
+
+    fn is_breakpoint(&self) -> bool {
+        matches!(self, Exception::Breakpoint)
+    }
+
+    fn is_illegal(&self) -> bool {
+        matches!(self, Exception::IllegalInstruction(_))
+    }
+
+    fn is_access_fault(&self) -> bool {
+        matches!(
+            self,
+            Exception::FetchFault(_) | Exception::GeneralProtection(_)
+        )
+    }
+}
+
+pub struct Executer {
+    pc: Vaddr,
+    snpc: Vaddr,
+    dnpc: Vaddr,
+    /// the opcode byte, `0x0fxx` for two-byte opcodes
+    opcode: Word,
+    /// operand size, 2 after a 0x66 prefix
+    opsize: usize,
+    /// 0xf3 prefix
+    rep: bool,
+    exception: Option<Exception>,
+    decoders: &'static DecodeTable<Box<dyn Decode>>,
+}
+
+impl Executer {
+    pub fn new() -> Self {
+        Self {
+            pc: 0,
+            snpc: 0,
+            dnpc: 0,
+            opcode: 0,
+            opsize: 4,
+            rep: false,
+            exception: None,
+            decoders: &DECODERS,
+        }
+    }
+
+    fn step(&mut self, cpu: &mut X86, mem: &mut MemoryBank) -> Option<()> {
+        let mut opcode = self.fetch(mem, 1)?;
+        loop {
+            match opcode {
+                0x66 => self.opsize = 2,
+                0xf3 => self.rep = true,
+                // segment overrides and lock mean nothing to a flat single-core machine
+                0x26 | 0x2e | 0x36 | 0x3e | 0x64 | 0x65 | 0xf0 => {}
+                _ => break,
+            }
+            opcode = self.fetch(mem, 1)?;
+        }
+        if opcode == 0x0f {
+            opcode = 0x0f00 | self.fetch(mem, 1)?;
+        }
+        self.opcode = opcode;
+        match self.decoders.lookup(opcode) {
+            Some(d) => d.apply(self, cpu, mem),
+            None => self.illegal(),
+        }
+    }
+
+    /// fetch `len` bytes of the instruction stream
+    fn fetch(&mut self, mem: &MemoryBank, len: usize) -> Option<Word> {
+        if !mem.allows(self.snpc, len, Perm::X) {
+            self.raise(Exception::FetchFault(self.snpc));
+            return None;
+        }
+        Some(mem.inst_fetch(&mut self.snpc, len))
+    }
+
+    /// fetch an immediate that follows the operands decoded by the table, `len` bytes
+    /// sign-extended
+    fn imm(&mut self, mem: &MemoryBank, len: usize) -> Option<Word> {
+        let imm = self.fetch(mem, len)?;
+        self.dnpc = self.snpc;
+        Some(sext!(imm, 8 * len))
+    }
+
+    fn raise(&mut self, e: Exception) {
+        self.exception = Some(e);
+    }
+
+    fn illegal(&mut self) -> Option<()> {
+        self.raise(Exception::IllegalInstruction(self.opcode));
+        None
+    }
+
+    fn load(&mut self, mem: &MemoryBank, addr: Vaddr, len: usize) -> Option<Word> {
+        if !mem.allows(addr, len, Perm::R) {
+            self.raise(Exception::GeneralProtection(addr));
+            return None;
+        }
+        Some(mem.paddr_read(addr, len))
+    }
+
+    fn store(&mut self, mem: &mut MemoryBank, addr: Vaddr, len: usize, val: Word) -> Option<()> {
+        if !mem.allows(addr, len, Perm::W) {
+            self.raise(Exception::GeneralProtection(addr));
+            return None;
+        }
+        mem.paddr_write(addr, len, val);
+        Some(())
+    }
+
+    fn get(&mut self, cpu: &X86, mem: &MemoryBank, loc: Loc, width: usize) -> Option<Word> {
+        match loc {
+            Loc::Reg(r) => Some(cpu.reg_w(r, width)),
+            Loc::Mem(addr) => self.load(mem, addr, width),
+        }
+    }
+
+    fn set(
+        &mut self,
+        cpu: &mut X86,
+        mem: &mut MemoryBank,
+        loc: Loc,
+        width: usize,
+        val: Word,
+    ) -> Option<()> {
+        match loc {
+            Loc::Reg(r) => {
+                cpu.set_reg_w(r, width, val);
+                Some(())
+            }
+            Loc::Mem(addr) => self.store(mem, addr, width, val),
+        }
+    }
+
+    fn push(&mut self, cpu: &mut X86, mem: &mut MemoryBank, width: usize, val: Word) -> Option<()> {
+        let esp = cpu.reg(ESP).wrapping_sub(width as Word);
+        self.store(mem, esp, width, val)?;
+        cpu.set_reg(ESP, esp);
+        Some(())
+    }
+
+    fn pop(&mut self, cpu: &mut X86, mem: &MemoryBank, width: usize) -> Option<Word> {
+        let esp = cpu.reg(ESP);
+        let val = self.load(mem, esp, width)?;
+        cpu.set_reg(ESP, esp.wrapping_add(width as Word));
+        Some(val)
+    }
+}
+
+impl Execute<X86> for Executer {
+    /// execute the instruction at pc, an exception it raises is left for `take_exception`
+    fn exec_once(&mut self, cpu: &mut X86, mem: &mut MemoryBank) {
+        self.dnpc = self.snpc;
+        self.opsize = 4;
+        self.rep = false;
+        self.step(cpu, mem);
+    }
+
+    fn pc(&self) -> Vaddr {
+        self.pc
+    }
+
+    fn set_pc(&mut self, pc: Vaddr) {
+        self.pc = pc;
+    }
+
+    fn set_snpc(&mut self, snpc: Vaddr) {
+        self.snpc = snpc;
+    }
+
+    fn dnpc(&self) -> Vaddr {
+        self.dnpc
+    }
+
+    /// take the exception raised by the last executed instruction
+    fn take_exception(&mut self) -> Option<Exception> {
+        self.exception.take()
+    }
+}
+
+const ADD: usize = 0;
+const OR: usize = 1;
+const ADC: usize = 2;
+const SBB: usize = 3;
+const AND: usize = 4;
+const SUB: usize = 5;
+const XOR: usize = 6;
+const CMP: usize = 7;
+
+fn mask(width: usize) -> Word {
+    Word::MAX >> (32 - 8 * width)
+}
+
+fn msb(val: Word, width: usize) -> bool {
+    (val >> (8 * width - 1)) & 1 != 0
+}
+
+/// `val` as a signed number of `width` bytes
+fn signed(val: Word, width: usize) -> i64 {
+    sext!(val, 8 * width) as SWord as i64
+}
+
+/// ZF, SF and PF of `res`, PF looks at the low byte only
+fn set_szp(cpu: &mut X86, res: Word, width: usize) {
+    cpu.set_flag(ZF, res & mask(width) == 0);
+    cpu.set_flag(SF, msb(res, width));
+    cpu.set_flag(PF, (res as u8).count_ones().is_multiple_of(2));
+}
+
+/// one of the eight ALU operations of the 0x00-0x3f block and group 1, setting the flags
+fn alu(cpu: &mut X86, op: usize, a: Word, b: Word, width: usize) -> Word {
+    let m = mask(width);
+    let (a, b) = (a & m, b & m);
+    let carry = cpu.flag(CF) as Word;
+    let res = match op {
+        ADD | ADC => {
+            let c = if op == ADC { carry } else { 0 };
+            let wide = a as u64 + b as u64 + c as u64;
+            let res = wide as Word & m;
+            cpu.set_flag(CF, wide > m as u64);
+            cpu.set_flag(OF, msb((a ^ res) & (b ^ res), width));
+            res
+        }
+        SBB | SUB | CMP => {
+            let c = if op == SBB { carry } else { 0 };
+            let res = a.wrapping_sub(b).wrapping_sub(c) & m;
+            cpu.set_flag(CF, b as u64 + c as u64 > a as u64);
+            cpu.set_flag(OF, msb((a ^ b) & (a ^ res), width));
+            res
+        }
+        OR | AND | XOR => {
+            cpu.set_flag(CF, false);
+            cpu.set_flag(OF, false);
+            match op {
+                OR => a | b,
+                AND => a & b,
+                _ => a ^ b,
+            }
+        }
+        _ => unreachable!(),
+    };
+    cpu.set_flag(AF, (a ^ b ^ res) & 0x10 != 0);
+    set_szp(cpu, res, width);
+    res
+}
+
+/// the condition `cc` of jcc, setcc and cmovcc, odd conditions are the negation of the even ones
+fn cond(cpu: &X86, cc: Word) -> bool {
+    let f = |flag| cpu.flag(flag);
+    let res = match cc >> 1 {
+        0 => f(OF),
+        1 => f(CF),
+        2 => f(ZF),
+        3 => f(CF) || f(ZF),
+        4 => f(SF),
+        5 => f(PF),
+        6 => f(SF) != f(OF),
+        _ => f(ZF) || f(SF) != f(OF),
+    };
+    res ^ (cc & 1 != 0)
+}
+
+/// group 2 rotate or shift `a` by `count`, a masked count of zero leaves the flags alone
+fn shift(cpu: &mut X86, op: usize, a: Word, count: Word, width: usize) -> Word {
+    let count = count & 0x1f;
+    if count == 0 {
+        return a;
+    }
+    let bits = 8 * width as Word;
+    let m = mask(width);
+    let a = a & m;
+    match op {
+        // rol, ror
+        0 | 1 => {
+            let n = count % bits;
+            let res = match op {
+                0 => ((a << n) | (a >> ((bits - n) % bits))) & m,
+                _ => ((a >> n) | (a << ((bits - n) % bits))) & m,
+            };
+            let cf = match op {
+                0 => res & 1 != 0,
+                _ => msb(res, width),
+            };
+            cpu.set_flag(CF, cf);
+            let of = match op {
+                0 => msb(res, width) != cf,
+                _ => msb(res, width) != msb(res << 1, width),
+            };
+            cpu.set_flag(OF, of);
+            res
+        }
+        // rcl, rcr, through CF one bit at a time
+        2 | 3 => {
+            let mut res = a;
+            let mut cf = cpu.flag(CF);
+            for _ in 0..count % (bits + 1) {
+                let out;
+                if op == 2 {
+                    out = msb(res, width);
+                    res = ((res << 1) | cf as Word) & m;
+                } else {
+                    out = res & 1 != 0;
+                    res = (res >> 1) | ((cf as Word) << (bits - 1));
+                }
+                cf = out;
+            }
+            cpu.set_flag(CF, cf);
+            let of = match op {
+                2 => msb(res, width) != cf,
+                _ => msb(res, width) != msb(res << 1, width),
+            };
+            cpu.set_flag(OF, of);
+            res
+        }
+        // shl, sal
+        4 | 6 => {
+            let wide = (a as u64) << count;
+            let res = wide as Word & m;
+            let cf = (wide >> bits) & 1 != 0;
+            cpu.set_flag(CF, cf);
+            cpu.set_flag(OF, msb(res, width) != cf);
+            set_szp(cpu, res, width);
+            res
+        }
+        5 => {
+            let res = a >> count;
+            cpu.set_flag(CF, (a >> (count - 1)) & 1 != 0);
+            cpu.set_flag(OF, msb(a, width));
+            set_szp(cpu, res, width);
+            res
+        }
+        _ => {
+            let sa = signed(a, width);
+            let res = (sa >> count) as Word & m;
+            cpu.set_flag(CF, (sa >> (count - 1)) & 1 != 0);
+            cpu.set_flag(OF, false);
+            set_szp(cpu, res, width);
+            res
+        }
+    }
+}
+
+/// shld/shrd: shift `dest` by `count` filling in bits from `src`
+fn double_shift(
+    cpu: &mut X86,
+    left: bool,
+    dest: Word,
+    src: Word,
+    count: Word,
+    width: usize,
+) -> Word {
+    let count = count & 0x1f;
+    let bits = 8 * width as Word;
+    let m = mask(width);
+    if count == 0 || count > bits {
+        return dest;
+    }
+    let (dest, src) = ((dest & m) as u64, (src & m) as u64);
+    let (res, cf) = if left {
+        let wide = (dest << bits) | src;
+        (
+            ((wide << count) >> bits) as Word & m,
+            (wide >> (2 * bits - count)) & 1 != 0,
+        )
+    } else {
+        let wide = (src << bits) | dest;
+        ((wide >> count) as Word & m, (wide >> (count - 1)) & 1 != 0)
+    };
+    cpu.set_flag(CF, cf);
+    cpu.set_flag(OF, msb(res, width) != msb(dest as Word, width));
+    set_szp(cpu, res, width);
+    res
+}
+
+/// signed multiply truncated to `width`, CF and OF report whether the product did not fit
+fn imul(cpu: &mut X86, a: Word, b: Word, width: usize) -> Word {
+    let prod = signed(a, width) * signed(b, width);
+    let res = prod as Word & mask(width);
+    let overflow = signed(res, width) != prod;
+    cpu.set_flag(CF, overflow);
+    cpu.set_flag(OF, overflow);
+    res
+}
+
+/// group 3 mul, imul, div and idiv on the accumulator: %al/%ax for bytes, %edx:%eax otherwise
+fn mul_div(s: &mut Executer, cpu: &mut X86, op: usize, src: Word, width: usize) -> Option<()> {
+    let bits = 8 * width as u32;
+    let m = mask(width);
+    let src = src & m;
+    let acc = cpu.reg_w(EAX, width);
+    // the double-width accumulator, %ax for bytes
+    let wide = match width {
+        1 => cpu.reg_w(EAX, 2) as u64,
+        _ => ((cpu.reg_w(EDX, width) as u64) << bits) | acc as u64,
+    };
+    let (lo, hi) = match op {
+        4 | 5 => {
+            let prod = match op {
+                4 => acc as u64 * src as u64,
+                _ => (signed(acc, width) * signed(src, width)) as u64,
+            };
+            let (lo, hi) = (prod as Word & m, (prod >> bits) as Word & m);
+            let overflow = match op {
+                4 => hi != 0,
+                _ => signed(lo, width) != (signed(acc, width) * signed(src, width)),
+            };
+            cpu.set_flag(CF, overflow);
+            cpu.set_flag(OF, overflow);
+            (lo, hi)
+        }
+        6 => {
+            let (Some(q), Some(r)) = (wide.checked_div(src as u64), wide.checked_rem(src as u64))
+            else {
+                s.raise(Exception::DivideError);
+                return None;
+            };
+            if q > m as u64 {
+                s.raise(Exception::DivideError);
+                return None;
+            }
+            (q as Word, r as Word)
+        }
+        _ => {
+            let dividend = (wide << (64 - 2 * bits)) as i64 >> (64 - 2 * bits);
+            let divisor = signed(src, width);
+            let (Some(q), Some(r)) = (dividend.checked_div(divisor), dividend.checked_rem(divisor))
+            else {
+                s.raise(Exception::DivideError);
+                return None;
+            };
+            let limit = 1i64 << (bits - 1);
+            if q < -limit || q >= limit {
+                s.raise(Exception::DivideError);
+                return None;
+            }
+            (q as Word & m, r as Word & m)
+        }
+    };
+    match width {
+        // %al and %ah
+        1 => cpu.set_reg_w(EAX, 2, (hi << 8) | lo),
+        _ => {
+            cpu.set_reg_w(EAX, width, lo);
+            cpu.set_reg_w(EDX, width, hi);
+        }
+    }
+    Some(())
+}
+
+/// movs and stos, repeated %ecx times under a rep prefix
+fn string(
+    s: &mut Executer,
+    cpu: &mut X86,
+    mem: &mut MemoryBank,
+    movs: bool,
+    width: usize,
+) -> Option<()> {
+    let step = if cpu.flag(DF) {
+        (width as Word).wrapping_neg()
+    } else {
+        width as Word
+    };
+    loop {
+        if s.rep && cpu.reg(ECX) == 0 {
+            break;
+        }
+        let val = match movs {
+            true => {
+                let val = s.load(mem, cpu.reg(ESI), width)?;
+                cpu.set_reg(ESI, cpu.reg(ESI).wrapping_add(step));
+                val
+            }
+            false => cpu.reg_w(EAX, width),
+        };
+        s.store(mem, cpu.reg(EDI), width, val)?;
+        cpu.set_reg(EDI, cpu.reg(EDI).wrapping_add(step));
+        if !s.rep {
+            break;
+        }
+        cpu.set_reg(ECX, cpu.reg(ECX).wrapping_sub(1));
+    }
+    Some(())
+}
+
+lazy_static::lazy_static! {
+    static ref DECODERS: DecodeTable<Box<dyn Decode>> = {
+        let decoders = vec! {
+            // add, or, adc, sbb, and, sub, xor and cmp, the operation is in bits 5:3
+            pat!("00?? ?000", alu, OperandType::E, 1, |s, cpu, mem, args| {
+                let op = bits!(s.opcode, 5, 3) as usize;
+                let a = s.get(cpu, mem, args.rm, 1)?;
+                let res = alu(cpu, op, a, cpu.reg_w(args.reg, 1), 1);
+                if op != CMP {
+                    s.set(cpu, mem, args.rm, 1, res)?;
+                }
+                Some(())
+            }),
+            pat!("00?? ?001", alu, OperandType::E, 0, |s, cpu, mem, args| {
+                let op = bits!(s.opcode, 5, 3) as usize;
+                let a = s.get(cpu, mem, args.rm, args.width)?;
+                let res = alu(cpu, op, a, cpu.reg_w(args.reg, args.width), args.width);
+                if op != CMP {
+                    s.set(cpu, mem, args.rm, args.width, res)?;
+                }
+                Some(())
+            }),
+            pat!("00?? ?010", alu, OperandType::E, 1, |s, cpu, mem, args| {
+                let op = bits!(s.opcode, 5, 3) as usize;
+                let b = s.get(cpu, mem, args.rm, 1)?;
+                let res = alu(cpu, op, cpu.reg_w(args.reg, 1), b, 1);
+                if op != CMP {
+                    cpu.set_reg_w(args.reg, 1, res);
+                }
+                Some(())
+            }),
+            pat!("00?? ?011", alu, OperandType::E, 0, |s, cpu, mem, args| {
+                let op = bits!(s.opcode, 5, 3) as usize;
+                let b = s.get(cpu, mem, args.rm, args.width)?;
+                let res = alu(cpu, op, cpu.reg_w(args.reg, args.width), b, args.width);
+                if op != CMP {
+                    cpu.set_reg_w(args.reg, args.width, res);
+                }
+                Some(())
+            }),
+            pat!("00?? ?100", alu, OperandType::Ib, 1, |s, cpu, mem, args| {
+                let op = bits!(s.opcode, 5, 3) as usize;
+                let res = alu(cpu, op, cpu.reg_w(EAX, 1), args.imm, 1);
+                if op != CMP {
+                    cpu.set_reg_w(EAX, 1, res);
+                }
+                Some(())
+            }),
+            pat!("00?? ?101", alu, OperandType::Iv, 0, |s, cpu, mem, args| {
+                let op = bits!(s.opcode, 5, 3) as usize;
+                let res = alu(cpu, op, cpu.reg_w(EAX, args.width), args.imm, args.width);
+                if op != CMP {
+                    cpu.set_reg_w(EAX, args.width, res);
+                }
+                Some(())
+            }),
+            // inc and dec keep CF
+            pat!("0100 0???", inc, OperandType::R, 0, |s, cpu, mem, args| {
+                let cf = cpu.flag(CF);
+                let res = alu(cpu, ADD, cpu.reg_w(args.reg, args.width), 1, args.width);
+                cpu.set_flag(CF, cf);
+                cpu.set_reg_w(args.reg, args.width, res);
+                Some(())
+            }),
+            pat!("0100 1???", dec, OperandType::R, 0, |s, cpu, mem, args| {
+                let cf = cpu.flag(CF);
+                let res = alu(cpu, SUB, cpu.reg_w(args.reg, args.width), 1, args.width);
+                cpu.set_flag(CF, cf);
+                cpu.set_reg_w(args.reg, args.width, res);
+                Some(())
+            }),
+            pat!("0101 0???", push, OperandType::R, 0, |s, cpu, mem, args| {
+                let val = cpu.reg_w(args.reg, args.width);
+                s.push(cpu, mem, args.width, val)
+            }),
+            pat!("0101 1???", pop, OperandType::R, 0, |s, cpu, mem, args| {
+                let val = s.pop(cpu, mem, args.width)?;
+                cpu.set_reg_w(args.reg, args.width, val);
+                Some(())
+            }),
+            pat!("0110 1000", push, OperandType::Iv, 0, |s, cpu, mem, args| {
+                s.push(cpu, mem, args.width, args.imm)
+            }),
+            pat!("0110 1001", imul, OperandType::EIv, 0, |s, cpu, mem, args| {
+                let a = s.get(cpu, mem, args.rm, args.width)?;
+                let res = imul(cpu, a, args.imm, args.width);
+                cpu.set_reg_w(args.reg, args.width, res);
+                Some(())
+            }),
+            pat!("0110 1010", push, OperandType::Ib, 0, |s, cpu, mem, args| {
+                s.push(cpu, mem, args.width, args.imm)
+            }),
+            pat!("0110 1011", imul, OperandType::EIb, 0, |s, cpu, mem, args| {
+                let a = s.get(cpu, mem, args.rm, args.width)?;
+                let res = imul(cpu, a, args.imm, args.width);
+                cpu.set_reg_w(args.reg, args.width, res);
+                Some(())
+            }),
+            pat!("0111 ????", jcc, OperandType::Ib, 0, |s, cpu, mem, args| {
+                if cond(cpu, bits!(s.opcode, 3, 0)) {
+                    s.dnpc = s.snpc.wrapping_add(args.imm);
+                }
+                Some(())
+            }),
+            // group 1, the operation is in the reg field
+            pat!("1000 0000", grp1, OperandType::EIb, 1, |s, cpu, mem, args| {
+                let a = s.get(cpu, mem, args.rm, 1)?;
+                let res = alu(cpu, args.reg, a, args.imm, 1);
+                if args.reg != CMP {
+                    s.set(cpu, mem, args.rm, 1, res)?;
+                }
+                Some(())
+            }),
+            pat!("1000 0001", grp1, OperandType::EIv, 0, |s, cpu, mem, args| {
+                let a = s.get(cpu, mem, args.rm, args.width)?;
+                let res = alu(cpu, args.reg, a, args.imm, args.width);
+                if args.reg != CMP {
+                    s.set(cpu, mem, args.rm, args.width, res)?;
+                }
+                Some(())
+            }),
+            pat!("1000 0011", grp1, OperandType::EIb, 0, |s, cpu, mem, args| {
+                let a = s.get(cpu, mem, args.rm, args.width)?;
+                let res = alu(cpu, args.reg, a, args.imm, args.width);
+                if args.reg != CMP {
+                    s.set(cpu, mem, args.rm, args.width, res)?;
+                }
+                Some(())
+            }),
+            pat!("1000 0100", test, OperandType::E, 1, |s, cpu, mem, args| {
+                let a = s.get(cpu, mem, args.rm, 1)?;
+                alu(cpu, AND, a, cpu.reg_w(args.reg, 1), 1);
+                Some(())
+            }),
+            pat!("1000 0101", test, OperandType::E, 0, |s, cpu, mem, args| {
+                let a = s.get(cpu, mem, args.rm, args.width)?;
+                alu(cpu, AND, a, cpu.reg_w(args.reg, args.width), args.width);
+                Some(())
+            }),
+            pat!("1000 0110", xchg, OperandType::E, 1, |s, cpu, mem, args| {
+                let a = s.get(cpu, mem, args.rm, 1)?;
+                s.set(cpu, mem, args.rm, 1, cpu.reg_w(args.reg, 1))?;
+                cpu.set_reg_w(args.reg, 1, a);
+                Some(())
+            }),
+            pat!("1000 0111", xchg, OperandType::E, 0, |s, cpu, mem, args| {
+                let a = s.get(cpu, mem, args.rm, args.width)?;
+                s.set(cpu, mem, args.rm, args.width, cpu.reg_w(args.reg, args.width))?;
+                cpu.set_reg_w(args.reg, args.width, a);
+                Some(())
+            }),
+            pat!("1000 1000", mov, OperandType::E, 1, |s, cpu, mem, args| {
+                s.set(cpu, mem, args.rm, 1, cpu.reg_w(args.reg, 1))
+            }),
+            pat!("1000 1001", mov, OperandType::E, 0, |s, cpu, mem, args| {
+                s.set(cpu, mem, args.rm, args.width, cpu.reg_w(args.reg, args.width))
+            }),
+            pat!("1000 1010", mov, OperandType::E, 1, |s, cpu, mem, args| {
+                let val = s.get(cpu, mem, args.rm, 1)?;
+                cpu.set_reg_w(args.reg, 1, val);
+                Some(())
+            }),
+            pat!("1000 1011", mov, OperandType::E, 0, |s, cpu, mem, args| {
+                let val = s.get(cpu, mem, args.rm, args.width)?;
+                cpu.set_reg_w(args.reg, args.width, val);
+                Some(())
+            }),
+            pat!("1000 1101", lea, OperandType::E, 0, |s, cpu, mem, args| {
+                let Loc::Mem(addr) = args.rm else {
+                    return s.illegal();
+                };
+                cpu.set_reg_w(args.reg, args.width, addr);
+                Some(())
+            }),
+            pat!("1000 1111", pop, OperandType::E, 0, |s, cpu, mem, args| {
+                if args.reg != 0 {
+                    return s.illegal();
+                }
+                let val = s.pop(cpu, mem, args.width)?;
+                s.set(cpu, mem, args.rm, args.width, val)
+            }),
+            // 0x90 is xchg %eax, %eax, the nop
+            pat!("1001 0???", xchg, OperandType::R, 0, |s, cpu, mem, args| {
+                let a = cpu.reg_w(EAX, args.width);
+                cpu.set_reg_w(EAX, args.width, cpu.reg_w(args.reg, args.width));
+                cpu.set_reg_w(args.reg, args.width, a);
+                Some(())
+            }),
+            // cwtl, cbtw with 0x66
+            pat!("1001 1000", cwtl, OperandType::N, 0, |s, cpu, mem, args| {
+                let half = args.width / 2;
+                cpu.set_reg_w(EAX, args.width, sext!(cpu.reg_w(EAX, half), 8 * half));
+                Some(())
+            }),
+            // cltd, cwtd with 0x66
+            pat!("1001 1001", cltd, OperandType::N, 0, |s, cpu, mem, args| {
+                let sign = if msb(cpu.reg(EAX), args.width) { Word::MAX } else { 0 };
+                cpu.set_reg_w(EDX, args.width, sign);
+                Some(())
+            }),
+            pat!("1001 1100", pushf, OperandType::N, 0, |s, cpu, mem, args| {
+                s.push(cpu, mem, args.width, cpu.eflags())
+            }),
+            pat!("1001 1101", popf, OperandType::N, 0, |s, cpu, mem, args| {
+                let val = s.pop(cpu, mem, args.width)?;
+                cpu.set_eflags(val);
+                Some(())
+            }),
+            pat!("1010 0000", mov, OperandType::O, 1, |s, cpu, mem, args| {
+                let val = s.get(cpu, mem, args.rm, 1)?;
+                cpu.set_reg_w(EAX, 1, val);
+                Some(())
+            }),
+            pat!("1010 0001", mov, OperandType::O, 0, |s, cpu, mem, args| {
+                let val = s.get(cpu, mem, args.rm, args.width)?;
+                cpu.set_reg_w(EAX, args.width, val);
+                Some(())
+            }),
+            pat!("1010 0010", mov, OperandType::O, 1, |s, cpu, mem, args| {
+                s.set(cpu, mem, args.rm, 1, cpu.reg_w(EAX, 1))
+            }),
+            pat!("1010 0011", mov, OperandType::O, 0, |s, cpu, mem, args| {
+                s.set(cpu, mem, args.rm, args.width, cpu.reg_w(EAX, args.width))
+            }),
+            pat!("1010 0100", movs, OperandType::N, 1, |s, cpu, mem, args| {
+                string(s, cpu, mem, true, 1)
+            }),
+            pat!("1010 0101", movs, OperandType::N, 0, |s, cpu, mem, args| {
+                string(s, cpu, mem, true, args.width)
+            }),
+            pat!("1010 1000", test, OperandType::Ib, 1, |s, cpu, mem, args| {
+                alu(cpu, AND, cpu.reg_w(EAX, 1), args.imm, 1);
+                Some(())
+            }),
+            pat!("1010 1001", test, OperandType::Iv, 0, |s, cpu, mem, args| {
+                alu(cpu, AND, cpu.reg_w(EAX, args.width), args.imm, args.width);
+                Some(())
+            }),
+            pat!("1010 1010", stos, OperandType::N, 1, |s, cpu, mem, args| {
+                string(s, cpu, mem, false, 1)
+            }),
+            pat!("1010 1011", stos, OperandType::N, 0, |s, cpu, mem, args| {
+                string(s, cpu, mem, false, args.width)
+            }),
+            pat!("1011 0???", mov, OperandType::RIv, 1, |s, cpu, mem, args| {
+                cpu.set_reg_w(args.reg, 1, args.imm);
+                Some(())
+            }),
+            pat!("1011 1???", mov, OperandType::RIv, 0, |s, cpu, mem, args| {
+                cpu.set_reg_w(args.reg, args.width, args.imm);
+                Some(())
+            }),
+            // group 2, the rotate or shift is in the reg field
+            pat!("1100 0000", grp2, OperandType::EIb, 1, |s, cpu, mem, args| {
+                let a = s.get(cpu, mem, args.rm, 1)?;
+                let res = shift(cpu, args.reg, a, args.imm, 1);
+                s.set(cpu, mem, args.rm, 1, res)
+            }),
+            pat!("1100 0001", grp2, OperandType::EIb, 0, |s, cpu, mem, args| {
+                let a = s.get(cpu, mem, args.rm, args.width)?;
+                let res = shift(cpu, args.reg, a, args.imm, args.width);
+                s.set(cpu, mem, args.rm, args.width, res)
+            }),
+            pat!("1100 0010", ret, OperandType::Iw, 0, |s, cpu, mem, args| {
+                s.dnpc = s.pop(cpu, mem, 4)?;
+                cpu.set_reg(ESP, cpu.reg(ESP).wrapping_add(args.imm));
+                Some(())
+            }),
+            pat!("1100 0011", ret, OperandType::N, 0, |s, cpu, mem, args| {
+                s.dnpc = s.pop(cpu, mem, 4)?;
+                Some(())
+            }),
+            pat!("1100 0110", mov, OperandType::EIv, 1, |s, cpu, mem, args| {
+                s.set(cpu, mem, args.rm, 1, args.imm)
+            }),
+            pat!("1100 0111", mov, OperandType::EIv, 0, |s, cpu, mem, args| {
+                s.set(cpu, mem, args.rm, args.width, args.imm)
+            }),
+            pat!("1100 1001", leave, OperandType::N, 0, |s, cpu, mem, args| {
+                let ebp = cpu.reg(EBP);
+                let val = s.load(mem, ebp, args.width)?;
+                cpu.set_reg(ESP, ebp.wrapping_add(args.width as Word));
+                cpu.set_reg_w(EBP, args.width, val);
+                Some(())
+            }),
+            pat!("1100 1100", int3, OperandType::N, 0, |s, cpu, mem, args| {
+                s.raise(Exception::Breakpoint);
+                None
+            }),
+            pat!("1100 1101", int, OperandType::Ib, 0, |s, cpu, mem, args| {
+                s.raise(Exception::SoftwareInterrupt(args.imm as u8));
+                None
+            }),
+            pat!("1100 1111", iret, OperandType::N, 0, |s, cpu, mem, args| {
+                let eip = s.pop(cpu, mem, 4)?;
+                let _cs = s.pop(cpu, mem, 4)?;
+                let eflags = s.pop(cpu, mem, 4)?;
+                cpu.set_eflags(eflags);
+                s.dnpc = eip;
+                Some(())
+            }),
+            pat!("1101 0000", grp2, OperandType::E, 1, |s, cpu, mem, args| {
+                let a = s.get(cpu, mem, args.rm, 1)?;
+                let res = shift(cpu, args.reg, a, 1, 1);
+                s.set(cpu, mem, args.rm, 1, res)
+            }),
+            pat!("1101 0001", grp2, OperandType::E, 0, |s, cpu, mem, args| {
+                let a = s.get(cpu, mem, args.rm, args.width)?;
+                let res = shift(cpu, args.reg, a, 1, args.width);
+                s.set(cpu, mem, args.rm, args.width, res)
+            }),
+            pat!("1101 0010", grp2, OperandType::E, 1, |s, cpu, mem, args| {
+                let a = s.get(cpu, mem, args.rm, 1)?;
+                let res = shift(cpu, args.reg, a, cpu.reg_w(ECX, 1), 1);
+                s.set(cpu, mem, args.rm, 1, res)
+            }),
+            pat!("1101 0011", grp2, OperandType::E, 0, |s, cpu, mem, args| {
+                let a = s.get(cpu, mem, args.rm, args.width)?;
+                let res = shift(cpu, args.reg, a, cpu.reg_w(ECX, 1), args.width);
+                s.set(cpu, mem, args.rm, args.width, res)
+            }),
+            // an undefined opcode on real hardware, the exit code is in %eax
+            pat!("1101 0110", nemu_trap, OperandType::N, 0, |s, cpu, mem, args| {
+                s.raise(Exception::Breakpoint);
+                None
+            }),
+            pat!("1110 1000", call, OperandType::Iv, 0, |s, cpu, mem, args| {
+                s.push(cpu, mem, 4, s.snpc)?;
+                s.dnpc = s.snpc.wrapping_add(args.imm);
+                Some(())
+            }),
+            pat!("1110 1001", jmp, OperandType::Iv, 0, |s, cpu, mem, args| {
+                s.dnpc = s.snpc.wrapping_add(args.imm);
+                Some(())
+            }),
+            pat!("1110 1011", jmp, OperandType::Ib, 0, |s, cpu, mem, args| {
+                s.dnpc = s.snpc.wrapping_add(args.imm);
+                Some(())
+            }),
+            // group 3, the immediate of test follows the ModR/M operand
+            pat!("1111 0110", grp3, OperandType::E, 1, |s, cpu, mem, args| {
+                let a = s.get(cpu, mem, args.rm, 1)?;
+                match args.reg {
+                    0 | 1 => {
+                        let imm = s.imm(mem, 1)?;
+                        alu(cpu, AND, a, imm, 1);
+                    }
+                    2 => s.set(cpu, mem, args.rm, 1, !a)?,
+                    3 => {
+                        let res = alu(cpu, SUB, 0, a, 1);
+                        s.set(cpu, mem, args.rm, 1, res)?;
+                    }
+                    op => mul_div(s, cpu, op, a, 1)?,
+                }
+                Some(())
+            }),
+            pat!("1111 0111", grp3, OperandType::E, 0, |s, cpu, mem, args| {
+                let a = s.get(cpu, mem, args.rm, args.width)?;
+                match args.reg {
+                    0 | 1 => {
+                        let imm = s.imm(mem, args.width)?;
+                        alu(cpu, AND, a, imm, args.width);
+                    }
+                    2 => s.set(cpu, mem, args.rm, args.width, !a)?,
+                    3 => {
+                        let res = alu(cpu, SUB, 0, a, args.width);
+                        s.set(cpu, mem, args.rm, args.width, res)?;
+                    }
+                    op => mul_div(s, cpu, op, a, args.width)?,
+                }
+                Some(())
+            }),
+            pat!("1111 1010", cli, OperandType::N, 0, |s, cpu, mem, args| {
+                cpu.set_flag(IF, false);
+                Some(())
+            }),
+            pat!("1111 1011", sti, OperandType::N, 0, |s, cpu, mem, args| {
+                cpu.set_flag(IF, true);
+                Some(())
+            }),
+            pat!("1111 1100", cld, OperandType::N, 0, |s, cpu, mem, args| {
+                cpu.set_flag(DF, false);
+                Some(())
+            }),
+            pat!("1111 1101", std, OperandType::N, 0, |s, cpu, mem, args| {
+                cpu.set_flag(DF, true);
+                Some(())
+            }),
+            // group 4, inc and dec of a byte
+            pat!("1111 1110", grp4, OperandType::E, 1, |s, cpu, mem, args| {
+                let op = match args.reg {
+                    0 => ADD,
+                    1 => SUB,
+                    _ => return s.illegal(),
+                };
+                let a = s.get(cpu, mem, args.rm, 1)?;
+                let cf = cpu.flag(CF);
+                let res = alu(cpu, op, a, 1, 1);
+                cpu.set_flag(CF, cf);
+                s.set(cpu, mem, args.rm, 1, res)
+            }),
+            // group 5, inc, dec, indirect call and jmp, push
+            pat!("1111 1111", grp5, OperandType::E, 0, |s, cpu, mem, args| {
+                if matches!(args.reg, 3 | 5 | 7) {
+                    return s.illegal();
+                }
+                let a = s.get(cpu, mem, args.rm, args.width)?;
+                match args.reg {
+                    0 | 1 => {
+                        let op = if args.reg == 0 { ADD } else { SUB };
+                        let cf = cpu.flag(CF);
+                        let res = alu(cpu, op, a, 1, args.width);
+                        cpu.set_flag(CF, cf);
+                        s.set(cpu, mem, args.rm, args.width, res)?;
+                    }
+                    2 => {
+                        s.push(cpu, mem, 4, s.snpc)?;
+                        s.dnpc = a;
+                    }
+                    4 => s.dnpc = a,
+                    _ => s.push(cpu, mem, args.width, a)?,
+                }
+                Some(())
+            }),
+            // group 7, only lidt
+            pat!("0000 1111 0000 0001", lidt, OperandType::E, 0, |s, cpu, mem, args| {
+                let (3, Loc::Mem(addr)) = (args.reg, args.rm) else {
+                    return s.illegal();
+                };
+                let limit = s.load(mem, addr, 2)?;
+                let base = s.load(mem, addr.wrapping_add(2), 4)?;
+                cpu.set_idtr(base, limit);
+                Some(())
+            }),
+            // hint nops, among them the multi-byte nopl and endbr32
+            pat!("0000 1111 0001 1???", nop, OperandType::E, 0, |s, cpu, mem, args| Some(())),
+            pat!("0000 1111 0100 ????", cmovcc, OperandType::E, 0, |s, cpu, mem, args| {
+                let val = s.get(cpu, mem, args.rm, args.width)?;
+                if cond(cpu, bits!(s.opcode, 3, 0)) {
+                    cpu.set_reg_w(args.reg, args.width, val);
+                }
+                Some(())
+            }),
+            pat!("0000 1111 1000 ????", jcc, OperandType::Iv, 0, |s, cpu, mem, args| {
+                if cond(cpu, bits!(s.opcode, 3, 0)) {
+                    s.dnpc = s.snpc.wrapping_add(args.imm);
+                }
+                Some(())
+            }),
+            pat!("0000 1111 1001 ????", setcc, OperandType::E, 1, |s, cpu, mem, args| {
+                let val = cond(cpu, bits!(s.opcode, 3, 0)) as Word;
+                s.set(cpu, mem, args.rm, 1, val)
+            }),
+            pat!("0000 1111 1010 0100", shld, OperandType::EIb, 0, |s, cpu, mem, args| {
+                let a = s.get(cpu, mem, args.rm, args.width)?;
+                let b = cpu.reg_w(args.reg, args.width);
+                let res = double_shift(cpu, true, a, b, args.imm, args.width);
+                s.set(cpu, mem, args.rm, args.width, res)
+            }),
+            pat!("0000 1111 1010 0101", shld, OperandType::E, 0, |s, cpu, mem, args| {
+                let a = s.get(cpu, mem, args.rm, args.width)?;
+                let b = cpu.reg_w(args.reg, args.width);
+                let res = double_shift(cpu, true, a, b, cpu.reg_w(ECX, 1), args.width);
+                s.set(cpu, mem, args.rm, args.width, res)
+            }),
+            pat!("0000 1111 1010 1100", shrd, OperandType::EIb, 0, |s, cpu, mem, args| {
+                let a = s.get(cpu, mem, args.rm, args.width)?;
+                let b = cpu.reg_w(args.reg, args.width);
+                let res = double_shift(cpu, false, a, b, args.imm, args.width);
+                s.set(cpu, mem, args.rm, args.width, res)
+            }),
+            pat!("0000 1111 1010 1101", shrd, OperandType::E, 0, |s, cpu, mem, args| {
+                let a = s.get(cpu, mem, args.rm, args.width)?;
+                let b = cpu.reg_w(args.reg, args.width);
+                let res = double_shift(cpu, false, a, b, cpu.reg_w(ECX, 1), args.width);
+                s.set(cpu, mem, args.rm, args.width, res)
+            }),
+            pat!("0000 1111 1010 1111", imul, OperandType::E, 0, |s, cpu, mem, args| {
+                let b = s.get(cpu, mem, args.rm, args.width)?;
+                let res = imul(cpu, cpu.reg_w(args.reg, args.width), b, args.width);
+                cpu.set_reg_w(args.reg, args.width, res);
+                Some(())
+            }),
+            pat!("0000 1111 1011 0110", movzb, OperandType::E, 0, |s, cpu, mem, args| {
+                let val = s.get(cpu, mem, args.rm, 1)?;
+                cpu.set_reg_w(args.reg, args.width, val);
+                Some(())
+            }),
+            pat!("0000 1111 1011 0111", movzw, OperandType::E, 0, |s, cpu, mem, args| {
+                let val = s.get(cpu, mem, args.rm, 2)?;
+                cpu.set_reg_w(args.reg, args.width, val);
+                Some(())
+            }),
+            pat!("0000 1111 1011 1100", bsf, OperandType::E, 0, |s, cpu, mem, args| {
+                let val = s.get(cpu, mem, args.rm, args.width)?;
+                cpu.set_flag(ZF, val == 0);
+                if val != 0 {
+                    cpu.set_reg_w(args.reg, args.width, val.trailing_zeros());
+                }
+                Some(())
+            }),
+            pat!("0000 1111 1011 1101", bsr, OperandType::E, 0, |s, cpu, mem, args| {
+                let val = s.get(cpu, mem, args.rm, args.width)?;
+                cpu.set_flag(ZF, val == 0);
+                if val != 0 {
+                    cpu.set_reg_w(args.reg, args.width, 31 - val.leading_zeros());
+                }
+                Some(())
+            }),
+            pat!("0000 1111 1011 1110", movsb, OperandType::E, 0, |s, cpu, mem, args| {
+                let val = s.get(cpu, mem, args.rm, 1)?;
+                cpu.set_reg_w(args.reg, args.width, sext!(val, 8));
+                Some(())
+            }),
+            pat!("0000 1111 1011 1111", movsw, OperandType::E, 0, |s, cpu, mem, args| {
+                let val = s.get(cpu, mem, args.rm, 2)?;
+                cpu.set_reg_w(args.reg, args.width, sext!(val, 16));
+                Some(())
+            }),
+            pat!("0000 1111 1100 1???", bswap, OperandType::R, 0, |s, cpu, mem, args| {
+                cpu.set_reg(args.reg, cpu.reg(args.reg).swap_bytes());
+                Some(())
+            }),
+        };
+        DecodeTable::new(decoders)
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::isa::{ISA, X86};
+    use crate::memory::{MemoryBank, RESET_VECTOR};
+
+    use super::super::EBX;
+    use super::*;
+
+    const PC: Vaddr = RESET_VECTOR as Vaddr;
+    const DATA: Vaddr = PC + 0x1000;
+    const STACK: Vaddr = PC + 0x2000;
+
+    /// run `code` from PC until it falls off its end or raises an exception
+    fn run_with(
+        code: &[u8],
+        setup: impl FnOnce(&mut X86, &mut MemoryBank),
+    ) -> (X86, MemoryBank, Option<Exception>) {
+        let mut cpu = X86::new(PC);
+        let mut mem = MemoryBank::new(code);
+        cpu.set_reg(ESP, STACK);
+        setup(&mut cpu, &mut mem);
+        let mut executer = Executer::new();
+        for _ in 0..1000 {
+            let pc = cpu.pc();
+            if pc >= PC + code.len() as Vaddr {
+                return (cpu, mem, None);
+            }
+            executer.set_pc(pc);
+            executer.set_snpc(pc);
+            executer.exec_once(&mut cpu, &mut mem);
+            cpu.set_pc(executer.dnpc());
+            if let Some(e) = executer.take_exception() {
+                return (cpu, mem, Some(e));
+            }
+        }
+        panic!("{code:02x?} does not terminate");
+    }
+
+    fn run(code: &[u8]) -> (X86, MemoryBank, Option<Exception>) {
+        run_with(code, |_, _| {})
+    }
+
+    #[test]
+    fn pattern_test() {
+        let pat = opcode("mov", "1000 1001");
+        assert_eq!((pat.key, pat.mask), (0x89, 0xffff));
+        let pat = opcode("jcc", "0000 1111 1000 ????");
+        assert_eq!((pat.key, pat.mask), (0x0f80, 0xfff0));
+    }
+
+    #[test]
+    #[should_panic]
+    fn bad_pattern_test() {
+        opcode("bad", "1000");
+    }
+
+    #[test]
+    fn default_img_test() {
+        let (cpu, mem, e) = run(X86::default_img());
+        assert_eq!(e, Some(Exception::Breakpoint));
+        assert_eq!(cpu.halt_ret(), 0);
+        assert_eq!(mem.paddr_read(0x8000_0104, 2), 1);
+        assert_eq!(mem.paddr_read(0x8000_0208, 2), 1);
+    }
+
+    #[test]
+    fn alu_test() {
+        // mov $0xffffffff,%eax; add $1,%eax
+        let (cpu, _, _) = run(&[0xb8, 0xff, 0xff, 0xff, 0xff, 0x83, 0xc0, 0x01]);
+        assert_eq!(cpu.reg(EAX), 0);
+        assert!(cpu.flag(CF) && cpu.flag(ZF) && !cpu.flag(OF) && cpu.flag(PF));
+        // mov $0x7fffffff,%eax; add $1,%eax
+        let (cpu, _, _) = run(&[0xb8, 0xff, 0xff, 0xff, 0x7f, 0x83, 0xc0, 0x01]);
+        assert!(cpu.flag(OF) && cpu.flag(SF) && !cpu.flag(CF));
+        // mov $1,%eax; cmp $2,%eax
+        let (cpu, _, _) = run(&[0xb8, 0x01, 0x00, 0x00, 0x00, 0x83, 0xf8, 0x02]);
+        assert_eq!(cpu.reg(EAX), 1);
+        assert!(cpu.flag(CF) && cpu.flag(SF) && !cpu.flag(ZF));
+        // mov $-1,%eax; add $1,%eax; adc $0,%edx
+        let (cpu, _, _) = run(&[
+            0xb8, 0xff, 0xff, 0xff, 0xff, 0x83, 0xc0, 0x01, 0x83, 0xd2, 0x00,
+        ]);
+        assert_eq!(cpu.reg(EDX), 1);
+        // mov $0x80,%al; add %al,%al sets CF and OF on the byte
+        let (cpu, _, _) = run(&[0xb0, 0x80, 0x00, 0xc0]);
+        assert!(cpu.flag(CF) && cpu.flag(OF) && cpu.flag(ZF));
+        // dec keeps CF: mov $1,%eax; cmp $2,%eax; dec %eax
+        let (cpu, _, _) = run(&[0xb8, 0x01, 0x00, 0x00, 0x00, 0x83, 0xf8, 0x02, 0x48]);
+        assert_eq!(cpu.reg(EAX), 0);
+        assert!(cpu.flag(CF) && cpu.flag(ZF));
+    }
+
+    #[test]
+    fn memory_operand_test() {
+        // mov $DATA,%ebx; mov $3,%esi; movl $0x11223344,0x10(%ebx,%esi,4); mov 0x1c(%ebx),%eax
+        let code = [
+            0xbb, 0x00, 0x10, 0x00, 0x80, 0xbe, 0x03, 0x00, 0x00, 0x00, 0xc7, 0x44, 0xb3, 0x10,
+            0x44, 0x33, 0x22, 0x11, 0x8b, 0x43, 0x1c,
+        ];
+        let (cpu, mem, _) = run(&code);
+        assert_eq!(mem.paddr_read(DATA + 0x1c, 4), 0x1122_3344);
+        assert_eq!(cpu.reg(EAX), 0x1122_3344);
+        // lea 0x8(%ebx,%esi,2),%ecx; movzbl 0x1d(%ebx),%edx; movsbl 0x1e(%ebx),%edi
+        let code = [
+            0xbb, 0x00, 0x10, 0x00, 0x80, 0xbe, 0x03, 0x00, 0x00, 0x00, 0x8d, 0x4c, 0x73, 0x08,
+            0x0f, 0xb6, 0x53, 0x1d, 0x0f, 0xbe, 0x7b, 0x1e,
+        ];
+        let (cpu, _, _) = run_with(&code, |_, mem| mem.paddr_write(DATA + 0x1c, 4, 0x0080_ff00));
+        assert_eq!(cpu.reg(ECX), DATA + 14);
+        assert_eq!(cpu.reg(EDX), 0xff);
+        assert_eq!(cpu.reg(EDI), 0xffff_ff80);
+        // mov 0x0,%eax is outside memory
+        let (_, _, e) = run(&[0xa1, 0x00, 0x00, 0x00, 0x00]);
+        assert_eq!(e, Some(Exception::GeneralProtection(0)));
+    }
+
+    #[test]
+    fn stack_test() {
+        // call f; jmp end; f: push %ebp; mov %esp,%ebp; push $0x2a; pop %eax; leave; ret; end:
+        let code = [
+            0xe8, 0x02, 0x00, 0x00, 0x00, 0xeb, 0x08, 0x55, 0x89, 0xe5, 0x6a, 0x2a, 0x58, 0xc9,
+            0xc3,
+        ];
+        let (cpu, mem, e) = run_with(&code, |cpu, _| cpu.set_reg(EBP, 0x1234));
+        assert_eq!(e, None);
+        assert_eq!(cpu.reg(EAX), 0x2a);
+        assert_eq!(cpu.reg(ESP), STACK);
+        assert_eq!(cpu.reg(EBP), 0x1234);
+        assert_eq!(mem.paddr_read(STACK - 4, 4), PC + 5);
+    }
+
+    #[test]
+    fn branch_test() {
+        // xor %eax,%eax; mov $5,%ecx; loop: add %ecx,%eax; dec %ecx; jne loop; setg %bl; cmovg %ecx,%edx
+        let code = [
+            0x31, 0xc0, 0xb9, 0x05, 0x00, 0x00, 0x00, 0x01, 0xc8, 0x49, 0x75, 0xfb, 0x0f, 0x9f,
+            0xc3, 0x0f, 0x4f, 0xd1,
+        ];
+        let (cpu, _, _) = run_with(&code, |cpu, _| {
+            cpu.set_reg(EBX, 0xffff_ffff);
+            cpu.set_reg(EDX, 7);
+        });
+        assert_eq!(cpu.reg(EAX), 15);
+        assert_eq!(cpu.reg(EBX), 0xffff_ff00);
+        assert_eq!(cpu.reg(EDX), 7);
+        // cmp $1,%eax; jl over inc %eax with a rel32
+        let (cpu, _, _) = run(&[
+            0x83, 0xf8, 0x01, 0x0f, 0x8c, 0x01, 0x00, 0x00, 0x00, 0x40, 0x90,
+        ]);
+        assert_eq!(cpu.reg(EAX), 0);
+    }
+
+    #[test]
+    fn mul_div_test() {
+        // mov $-3,%eax; mov $7,%ecx; imul %ecx; mov $0x10001,%ebx; imul $0x10000,%ebx,%esi
+        let code = [
+            0xb8, 0xfd, 0xff, 0xff, 0xff, 0xb9, 0x07, 0x00, 0x00, 0x00, 0xf7, 0xe9, 0xbb, 0x01,
+            0x00, 0x01, 0x00, 0x69, 0xf3, 0x00, 0x00, 0x01, 0x00,
+        ];
+        let (cpu, _, _) = run(&code);
+        assert_eq!((cpu.reg(EAX), cpu.reg(EDX)), (0xffff_ffeb, 0xffff_ffff));
+        assert_eq!(cpu.reg(ESI), 0x0001_0000);
+        assert!(cpu.flag(CF) && cpu.flag(OF));
+        // mov $-1,%eax; mul %eax
+        let (cpu, _, _) = run(&[0xb8, 0xff, 0xff, 0xff, 0xff, 0xf7, 0xe0]);
+        assert_eq!((cpu.reg(EAX), cpu.reg(EDX)), (1, 0xffff_fffe));
+        // mov $-7,%eax; cltd; mov $2,%ecx; idiv %ecx
+        let code = [
+            0xb8, 0xf9, 0xff, 0xff, 0xff, 0x99, 0xb9, 0x02, 0x00, 0x00, 0x00, 0xf7, 0xf9,
+        ];
+        let (cpu, _, _) = run(&code);
+        assert_eq!((cpu.reg(EAX), cpu.reg(EDX)), (0xffff_fffd, 0xffff_ffff));
+        // mov $100,%ax; mov $7,%cl; div %cl
+        let (cpu, _, _) = run(&[0x66, 0xb8, 0x64, 0x00, 0xb1, 0x07, 0xf6, 0xf1]);
+        assert_eq!(cpu.reg_w(EAX, 2), 0x020e);
+        // div %ecx with %ecx = 0
+        let (_, _, e) = run(&[0xf7, 0xf1]);
+        assert_eq!(e, Some(Exception::DivideError));
+        // quotient does not fit: mov $1,%edx; mov $1,%ecx; div %ecx
+        let (_, _, e) = run(&[
+            0xba, 0x01, 0x00, 0x00, 0x00, 0xb9, 0x01, 0x00, 0x00, 0x00, 0xf7, 0xf1,
+        ]);
+        assert_eq!(e, Some(Exception::DivideError));
+    }
+
+    #[test]
+    fn shift_test() {
+        // mov $0x80000001,%eax; shl %eax
+        let (cpu, _, _) = run(&[0xb8, 0x01, 0x00, 0x00, 0x80, 0xd1, 0xe0]);
+        assert_eq!(cpu.reg(EAX), 2);
+        assert!(cpu.flag(CF) && cpu.flag(OF));
+        // mov $0x80000000,%eax; sar $4,%eax
+        let (cpu, _, _) = run(&[0xb8, 0x00, 0x00, 0x00, 0x80, 0xc1, 0xf8, 0x04]);
+        assert_eq!(cpu.reg(EAX), 0xf800_0000);
+        // mov $0x81,%eax; mov $1,%cl; ror %cl,%al
+        let (cpu, _, _) = run(&[0xb8, 0x81, 0x00, 0x00, 0x00, 0xb1, 0x01, 0xd2, 0xc8]);
+        assert_eq!(cpu.reg(EAX), 0xc0);
+        assert!(cpu.flag(CF));
+        // mov $0x80000000,%eax; mov $1,%edx; shld $4,%eax,%edx; shrd $4,%edx,%eax
+        let code = [
+            0xb8, 0x00, 0x00, 0x00, 0x80, 0xba, 0x01, 0x00, 0x00, 0x00, 0x0f, 0xa4, 0xc2, 0x04,
+            0x0f, 0xac, 0xd0, 0x04,
+        ];
+        let (cpu, _, _) = run(&code);
+        assert_eq!(cpu.reg(EDX), 0x18);
+        assert_eq!(cpu.reg(EAX), 0x8800_0000);
+    }
+
+    #[test]
+    fn string_test() {
+        // mov $DATA,%edi; mov $0xab,%al; mov $5,%ecx; rep stos %al,(%edi)
+        let code = [
+            0xbf, 0x00, 0x10, 0x00, 0x80, 0xb0, 0xab, 0xb9, 0x05, 0x00, 0x00, 0x00, 0xf3, 0xaa,
+        ];
+        let (cpu, mem, _) = run(&code);
+        assert_eq!(mem.paddr_read(DATA, 4), 0xabab_abab);
+        assert_eq!(mem.paddr_read(DATA + 4, 2), 0x00ab);
+        assert_eq!((cpu.reg(EDI), cpu.reg(ECX)), (DATA + 5, 0));
+        // mov $DATA,%esi; lea 0x10(%esi),%edi; mov $2,%ecx; rep movsl
+        let code = [
+            0xbe, 0x00, 0x10, 0x00, 0x80, 0x8d, 0x7e, 0x10, 0xb9, 0x02, 0x00, 0x00, 0x00, 0xf3,
+            0xa5,
+        ];
+        let (_, mem, _) = run_with(&code, |_, mem| {
+            mem.paddr_write(DATA, 4, 0x5566_7788);
+            mem.paddr_write(DATA + 4, 4, 0x1122_3344);
+        });
+        assert_eq!(mem.paddr_read(DATA + 0x10, 4), 0x5566_7788);
+        assert_eq!(mem.paddr_read(DATA + 0x14, 4), 0x1122_3344);
+    }
+
+    #[test]
+    fn interrupt_test() {
+        // endbr32; nopl 0x0(%eax,%eax,1); int $0x80
+        let code = [
+            0xf3, 0x0f, 0x1e, 0xfb, 0x0f, 0x1f, 0x44, 0x00, 0x00, 0xcd, 0x80,
+        ];
+        let (_, _, e) = run(&code);
+        assert_eq!(e, Some(Exception::SoftwareInterrupt(0x80)));
+        assert_eq!(e.unwrap().return_address(PC + 9), PC + 11);
+        // push the frame by hand; iret
+        let code = [
+            0x68, 0x00, 0x02, 0x00, 0x00, 0x6a, 0x08, 0x68, 0x00, 0x00, 0x00, 0x90, 0xcf,
+        ];
+        let (cpu, _, e) = run(&code);
+        assert_eq!(e, None);
+        assert_eq!(cpu.pc(), 0x9000_0000);
+        assert!(cpu.flag(IF));
+        assert_eq!(cpu.reg(ESP), STACK);
+    }
+
+    #[test]
+    fn illegal_test() {
+        // ud2
+        let (_, _, e) = run(&[0x0f, 0x0b]);
+        assert_eq!(e, Some(Exception::IllegalInstruction(0x0f0b)));
+        // lea with a register operand
+        let (_, _, e) = run(&[0x8d, 0xc0]);
+        assert_eq!(e, Some(Exception::IllegalInstruction(0x8d)));
+    }
+}
@@ -0,0 +1,119 @@
+use super::Executer;
+use crate::{
+    common::{Vaddr, Word},
+    isa::{ISA, X86},
+    memory::MemoryBank,
+};
+
+/// Operands that follow the opcode, `b` is an imm8 sign-extended to the operand size, `v` an
+/// immediate of the operand size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OperandType {
+    /// nothing
+    N,
+    /// register in the low 3 bits of the opcode
+    R,
+    /// register in the opcode and an immediate, `mov $imm, %reg`
+    RIv,
+    /// ModR/M
+    E,
+    /// ModR/M and imm8
+    EIb,
+    /// ModR/M and an immediate
+    EIv,
+    Ib,
+    Iv,
+    /// imm16, `ret $imm16`
+    Iw,
+    /// 32-bit memory offset, `mov moffs, %eax`
+    O,
+}
+
+/// Where an operand lives.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Loc {
+    Reg(usize),
+    Mem(Vaddr),
+}
+
+pub struct Args {
+    /// operand size in bytes
+    pub width: usize,
+    /// the r/m operand, or the memory operand of `O`
+    pub rm: Loc,
+    /// the reg field of ModR/M, the register encoded in the opcode, or the opcode extension
+    pub reg: usize,
+    pub imm: Word,
+}
+
+pub fn decode_operand(
+    s: &mut Executer,
+    cpu: &X86,
+    mem: &MemoryBank,
+    typ: OperandType,
+    width: usize,
+) -> Option<Args> {
+    let mut args = Args {
+        width,
+        rm: Loc::Reg(0),
+        reg: 0,
+        imm: 0,
+    };
+    if matches!(typ, OperandType::R | OperandType::RIv) {
+        args.reg = bits!(s.opcode, 2, 0) as usize;
+        args.rm = Loc::Reg(args.reg);
+    }
+    if matches!(typ, OperandType::E | OperandType::EIb | OperandType::EIv) {
+        (args.reg, args.rm) = modrm(s, cpu, mem)?;
+    }
+    args.imm = match typ {
+        OperandType::EIb | OperandType::Ib => sext!(s.fetch(mem, 1)?, 8),
+        OperandType::RIv | OperandType::EIv | OperandType::Iv => {
+            sext!(s.fetch(mem, width)?, 8 * width)
+        }
+        OperandType::Iw => s.fetch(mem, 2)?,
+        OperandType::O => {
+            args.rm = Loc::Mem(s.fetch(mem, 4)?);
+            0
+        }
+        _ => 0,
+    };
+    Some(args)
+}
+
+/// decode ModR/M with its SIB and displacement, 32-bit addressing only
+fn modrm(s: &mut Executer, cpu: &X86, mem: &MemoryBank) -> Option<(usize, Loc)> {
+    let modrm = s.fetch(mem, 1)?;
+    let (md, reg, rm) = (
+        bits!(modrm, 7, 6),
+        bits!(modrm, 5, 3) as usize,
+        bits!(modrm, 2, 0),
+    );
+    if md == 3 {
+        return Some((reg, Loc::Reg(rm as usize)));
+    }
+    let mut addr: Word = 0;
+    let mut base = Some(rm as usize);
+    if rm == 4 {
+        let sib = s.fetch(mem, 1)?;
+        let (scale, index) = (bits!(sib, 7, 6), bits!(sib, 5, 3) as usize);
+        // index 4 (%esp) means no index
+        if index != 4 {
+            addr = cpu.reg(index) << scale;
+        }
+        base = Some(bits!(sib, 2, 0) as usize);
+    }
+    // base 5 (%ebp) without a displacement is a bare disp32
+    if md == 0 && base == Some(5) {
+        base = None;
+    }
+    if let Some(base) = base {
+        addr = addr.wrapping_add(cpu.reg(base));
+    }
+    let disp = match (md, base) {
+        (0, None) | (2, _) => s.fetch(mem, 4)?,
+        (1, _) => sext!(s.fetch(mem, 1)?, 8),
+        _ => 0,
+    };
+    Some((reg, Loc::Mem(addr.wrapping_add(disp))))
+}
@@ -1,4 +1,268 @@
 // SPDX-FileCopyrightText: LakeSoul Contributors
-// 
+//
 // SPDX-License-Identifier: Apache-2.0
 
+use crate::{
+    common::{Vaddr, Word},
+    memory::{MemoryBank, Perm},
+};
+
+use super::{TlbConfig, ISA};
+
+mod executer;
+pub use executer::{Exception, Executer};
+pub const GUEST_ISA: &str = "x86";
+
+pub const EAX: usize = 0;
+pub const ECX: usize = 1;
+pub const EDX: usize = 2;
+pub const EBX: usize = 3;
+pub const ESP: usize = 4;
+pub const EBP: usize = 5;
+pub const ESI: usize = 6;
+pub const EDI: usize = 7;
+
+pub const CF: Word = 1 << 0;
+pub const PF: Word = 1 << 2;
+pub const AF: Word = 1 << 4;
+pub const ZF: Word = 1 << 6;
+pub const SF: Word = 1 << 7;
+pub const IF: Word = 1 << 9;
+pub const DF: Word = 1 << 10;
+pub const OF: Word = 1 << 11;
+/// bit 1 of EFLAGS always reads as one
+const EFLAGS_FIXED: Word = 1 << 1;
+const EFLAGS_MASK: Word = CF | PF | AF | ZF | SF | IF | DF | OF;
+
+/// selector of the flat code segment, pushed by interrupts
+const CS: Word = 0x8;
+const GATE_PRESENT: Word = 1 << 7;
+const GATE_TYPE: Word = 0x1f;
+const INTERRUPT_GATE: Word = 0x0e;
+
+/// i386 in flat protected mode, segmentation and paging are not modelled.
+#[derive(Debug)]
+pub struct X86 {
+    gpr: [Word; 8],
+    eip: Vaddr,
+    eflags: Word,
+    /// base and limit of the interrupt descriptor table, loaded by `lidt`
+    idtr: (Vaddr, Word),
+}
+
+impl X86 {
+    pub fn new(pc: Vaddr) -> Self {
+        Self {
+            gpr: [0; 8],
+            eip: pc,
+            eflags: EFLAGS_FIXED,
+            idtr: (0, 0),
+        }
+    }
+
+    /// `width` bytes of register `idx`, for bytes 4..7 are %ah, %ch, %dh and %bh
+    pub fn reg_w(&self, idx: usize, width: usize) -> Word {
+        match width {
+            1 if idx >= 4 => bits!(self.gpr[idx - 4], 15, 8),
+            1 => bits!(self.gpr[idx], 7, 0),
+            2 => bits!(self.gpr[idx], 15, 0),
+            _ => self.gpr[idx],
+        }
+    }
+
+    /// write the low `width` bytes of register `idx`, the rest keeps its value
+    pub fn set_reg_w(&mut self, idx: usize, width: usize, val: Word) {
+        let (idx, shift) = if width == 1 && idx >= 4 {
+            (idx - 4, 8)
+        } else {
+            (idx, 0)
+        };
+        let mask: Word = match width {
+            1 => 0xff << shift,
+            2 => 0xffff,
+            _ => Word::MAX,
+        };
+        self.gpr[idx] = (self.gpr[idx] & !mask) | ((val << shift) & mask);
+    }
+
+    pub fn eflags(&self) -> Word {
+        self.eflags
+    }
+
+    pub fn set_eflags(&mut self, val: Word) {
+        self.eflags = (val & EFLAGS_MASK) | EFLAGS_FIXED;
+    }
+
+    pub fn flag(&self, flag: Word) -> bool {
+        self.eflags & flag != 0
+    }
+
+    pub fn set_flag(&mut self, flag: Word, on: bool) {
+        if on {
+            self.eflags |= flag;
+        } else {
+            self.eflags &= !flag;
+        }
+    }
+
+    pub fn set_idtr(&mut self, base: Vaddr, limit: Word) {
+        self.idtr = (base, limit);
+    }
+}
+
+impl ISA for X86 {
+    type Executer = executer::Executer;
+    type Exception = executer::Exception;
+    const NAME: &'static str = GUEST_ISA;
+    const LOGO: &'static str = ISA_LOGO;
+    const NR_GPR: usize = 8;
+    const ELF_MACHINE: u16 = 3;
+
+    fn init(pc: Vaddr, tlb: TlbConfig) -> Self {
+        Self::new(pc)
+    }
+
+    /// take exception `e` raised by the instruction at `pc` through the IDT, return the handler
+    /// address. A gate that can not be used sends the guest to 0, whose fetch fails in turn.
+    fn raise_exception(&mut self, mem: &mut MemoryBank, pc: Vaddr, e: Exception) -> Vaddr {
+        let vector = e.vector() as Word;
+        let (base, limit) = self.idtr;
+        let gate = base.wrapping_add(8 * vector);
+        if 8 * vector + 7 > limit || !mem.allows(gate, 8, Perm::R) {
+            return 0;
+        }
+        let attr = mem.paddr_read(gate + 5, 1);
+        if attr & GATE_PRESENT == 0 {
+            return 0;
+        }
+        let handler = mem.paddr_read(gate, 2) | (mem.paddr_read(gate + 6, 2) << 16);
+        let mut frame = vec![self.eflags, CS, e.return_address(pc)];
+        if let Some(code) = e.error_code() {
+            frame.push(code);
+        }
+        let esp = self.gpr[ESP].wrapping_sub(4 * frame.len() as Word);
+        if !mem.allows(esp, 4 * frame.len(), Perm::W) {
+            return 0;
+        }
+        // pushed in order, the last one ends up at the new %esp
+        for (n, val) in frame.iter().rev().enumerate() {
+            mem.paddr_write(esp + 4 * n as Word, 4, *val);
+        }
+        self.gpr[ESP] = esp;
+        // trap gates leave interrupts enabled
+        if attr & GATE_TYPE == INTERRUPT_GATE {
+            self.set_flag(IF, false);
+        }
+        handler
+    }
+
+    /// %eax holds the exit code of nemu_trap
+    fn halt_ret(&self) -> Word {
+        self.gpr[EAX]
+    }
+
+    fn set_pc(&mut self, next: Vaddr) {
+        self.eip = next;
+    }
+
+    fn pc(&self) -> Vaddr {
+        self.eip
+    }
+
+    fn reg(&self, idx: usize) -> Word {
+        self.gpr[idx]
+    }
+
+    fn set_reg(&mut self, idx: usize, val: Word) {
+        self.gpr[idx] = val;
+    }
+
+    /// there are no CSRs, x86 keeps its state in EFLAGS and the descriptor tables
+    fn csr(&self, addr: usize) -> Option<Word> {
+        None
+    }
+
+    fn set_csr(&mut self, addr: usize, val: Word) -> bool {
+        false
+    }
+
+    fn default_img() -> &'static [u8] {
+        &IMG
+    }
+
+    fn executer() -> Self::Executer {
+        executer::Executer::new()
+    }
+}
+
+pub const ISA_LOGO: &str = r"       _____    ___   ___
+      |_   _|  |__ \ / _ \
+ __  __ | |     ) | (_) |
+ \ \/ / | |    / / > _ <
+  >  < _| |_  / /_| (_) |
+ /_/\_\_____||____|\___/
+";
+
+const IMG: [u8; 45] = [
+    0xb8, 0x34, 0x12, 0x00, 0x00, // mov   $0x1234,%eax
+    0xb9, 0x00, 0x01, 0x00, 0x80, // mov   $0x80000100,%ecx
+    0x89, 0x01, // mov   %eax,(%ecx)
+    0x66, 0xc7, 0x41, 0x04, 0x01, 0x00, // movw  $0x1,0x4(%ecx)
+    0xbb, 0x02, 0x00, 0x00, 0x00, // mov   $0x2,%ebx
+    0x66, 0xc7, 0x84, 0x99, 0x00, 0x01, 0x00, 0x00, 0x01,
+    0x00, // movw  $0x1,0x100(%ecx,%ebx,4)
+    0x8b, 0x01, // mov   (%ecx),%eax
+    0x2d, 0x34, 0x12, 0x00, 0x00, // sub   $0x1234,%eax
+    0xd6, // nemu_trap
+    0x90, 0x90, 0x90, 0x90, // padding
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn byte_register_test() {
+        let mut cpu = X86::new(0);
+        cpu.set_reg(EAX, 0x1234_5678);
+        assert_eq!(cpu.reg_w(EAX, 1), 0x78);
+        assert_eq!(cpu.reg_w(ESP, 1), 0x56);
+        assert_eq!(cpu.reg_w(EAX, 2), 0x5678);
+        cpu.set_reg_w(ESP, 1, 0xab);
+        cpu.set_reg_w(EAX, 1, 0xcd);
+        assert_eq!(cpu.reg(EAX), 0x1234_abcd);
+        cpu.set_reg_w(EAX, 2, 0xffff_0000);
+        assert_eq!(cpu.reg(EAX), 0x1234_0000);
+    }
+
+    #[test]
+    fn raise_exception_test() {
+        const IDT: Vaddr = 0x8000_1000;
+        const STACK: Vaddr = 0x8000_2000;
+        let mut mem = MemoryBank::new(&[]);
+        let mut cpu = X86::new(0x8000_0000);
+        // no IDT yet
+        assert_eq!(
+            cpu.raise_exception(&mut mem, 0x8000_0000, Exception::DivideError),
+            0
+        );
+        // interrupt gate for #GP pointing at 0x80001234
+        mem.paddr_write(IDT + 8 * 13, 4, 0x0008_1234);
+        mem.paddr_write(IDT + 8 * 13 + 4, 4, 0x8000_8e00);
+        cpu.set_idtr(IDT, 0x7ff);
+        cpu.set_reg(ESP, STACK);
+        cpu.set_flag(IF, true);
+        let eflags = cpu.eflags();
+        let handler = cpu.raise_exception(&mut mem, 0x8000_0010, Exception::GeneralProtection(0));
+        assert_eq!(handler, 0x8000_1234);
+        assert_eq!(cpu.reg(ESP), STACK - 16);
+        assert_eq!(mem.paddr_read(STACK - 16, 4), 0);
+        assert_eq!(mem.paddr_read(STACK - 12, 4), 0x8000_0010);
+        assert_eq!(mem.paddr_read(STACK - 8, 4), CS);
+        assert_eq!(mem.paddr_read(STACK - 4, 4), eflags);
+        assert!(!cpu.flag(IF));
+        // the gate of #UD is not present
+        let handler = cpu.raise_exception(&mut mem, 0x8000_0010, Exception::IllegalInstruction(0));
+        assert_eq!(handler, 0);
+    }
+}
@@ -0,0 +1,131 @@
+//! Intel HEX: `:LLAAAATT<data>CC` records, with the address extended by the segment and linear
+//! address records and the start address given by the start records.
+
+use super::{hex_bytes, LoadError, Records};
+
+const DATA: u8 = 0;
+const EOF: u8 = 1;
+const EXTENDED_SEGMENT: u8 = 2;
+const START_SEGMENT: u8 = 3;
+const EXTENDED_LINEAR: u8 = 4;
+const START_LINEAR: u8 = 5;
+
+/// the records of `text` up to the end of file record, all of them if there is none
+pub(super) fn parse(text: &str) -> Result<Records, LoadError> {
+    let mut records = Records::default();
+    let mut base = 0u64;
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let syntax = |what| LoadError::Syntax { line: n + 1, what };
+        let bytes = line
+            .strip_prefix(':')
+            .ok_or(syntax("a record starts with ':'"))?;
+        let bytes = hex_bytes(bytes).ok_or(syntax("a record is pairs of hex digits"))?;
+        if bytes.len() < 5 || bytes.len() != bytes[0] as usize + 5 {
+            return Err(syntax("the length does not match the record"));
+        }
+        let (body, checksum) = bytes.split_at(bytes.len() - 1);
+        let expected = body
+            .iter()
+            .fold(0u8, |sum, b| sum.wrapping_add(*b))
+            .wrapping_neg();
+        if checksum[0] != expected {
+            return Err(LoadError::Checksum {
+                line: n + 1,
+                expected,
+                found: checksum[0],
+            });
+        }
+        let offset = u16::from_be_bytes([body[1], body[2]]) as u64;
+        let data = &body[4..];
+        let value = || data.iter().fold(0u64, |v, b| v << 8 | *b as u64);
+        match (body[3], data.len()) {
+            (DATA, _) => records.data.push((base + offset, data.to_vec())),
+            (EOF, 0) => break,
+            (EXTENDED_SEGMENT, 2) => base = value() << 4,
+            (EXTENDED_LINEAR, 2) => base = value() << 16,
+            // CS:IP
+            (START_SEGMENT, 4) => records.entry = Some((value() >> 16 << 4) + (value() & 0xffff)),
+            (START_LINEAR, 4) => records.entry = Some(value()),
+            (EOF..=START_LINEAR, _) => return Err(syntax("wrong length for the record type")),
+            _ => return Err(syntax("unknown record type")),
+        }
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_test() {
+        let records = |data: &[(u64, &[u8])], entry| Records {
+            data: data.iter().map(|&(addr, d)| (addr, d.to_vec())).collect(),
+            entry,
+        };
+        let cases = [
+            (
+                ":0400100001020304E2\n",
+                records(&[(0x10, &[1, 2, 3, 4])], None),
+            ),
+            // extended linear address
+            (
+                ":0200000480007A\n:0400100001020304E2\n",
+                records(&[(0x8000_0010, &[1, 2, 3, 4])], None),
+            ),
+            // extended segment address
+            (
+                ":020000020100FB\n:01000000AA55\n",
+                records(&[(0x1000, &[0xaa])], None),
+            ),
+            // start linear address
+            (":040000058000000473\n", records(&[], Some(0x8000_0004))),
+            // start segment address, CS:IP
+            (":0400000300100020C9\n", records(&[], Some(0x120))),
+            // blank lines are skipped, nothing after the end of file is
+            (
+                ":01000000AA55\n\n:00000001FF\n:01000000BB44\n",
+                records(&[(0, &[0xaa])], None),
+            ),
+        ];
+        for (text, records) in cases {
+            assert_eq!(parse(text), Ok(records), "{text}");
+        }
+    }
+
+    #[test]
+    fn error_test() {
+        let syntax = |line, what| LoadError::Syntax { line, what };
+        let cases = [
+            ("0200000480007A", syntax(1, "a record starts with ':'")),
+            (
+                ":0200000480007A\n:020000048000 7A",
+                syntax(2, "a record is pairs of hex digits"),
+            ),
+            (
+                ":0300000480007A",
+                syntax(1, "the length does not match the record"),
+            ),
+            (
+                ":01000004807B",
+                syntax(1, "wrong length for the record type"),
+            ),
+            (":010000068079", syntax(1, "unknown record type")),
+            (
+                ":0200000480007A\n:0400100001020304E3\n",
+                LoadError::Checksum {
+                    line: 2,
+                    expected: 0xe2,
+                    found: 0xe3,
+                },
+            ),
+        ];
+        for (text, e) in cases {
+            assert_eq!(parse(text), Err(e), "{text}");
+        }
+    }
+}
@@ -0,0 +1,502 @@
+use std::fmt;
+
+use crate::{
+    common::{Vaddr, Word},
+    elf::{Elf, ElfError, Symbol},
+    isa::ISA,
+    memory::{MemoryBank, RESET_VECTOR},
+};
+
+mod hex;
+mod srec;
+
+/// Why an image could not be loaded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LoadError {
+    Elf(ElfError),
+    /// the ELF is for another machine than the guest
+    Machine {
+        expected: u16,
+        found: u16,
+    },
+    /// a 32-bit ELF for a 64-bit guest or the other way round
+    Class {
+        is64: bool,
+    },
+    /// the bytes at `[start, end)` are not all in one region, `memory` is the nearest one
+    OutOfMemory {
+        start: u64,
+        end: u64,
+        memory: (usize, usize),
+    },
+    /// record `line`, counted from 1, of a HEX or S-record file is malformed
+    Syntax {
+        line: usize,
+        what: &'static str,
+    },
+    /// the checksum of record `line` is `found`, its bytes sum up to `expected`
+    Checksum {
+        line: usize,
+        expected: u8,
+        found: u8,
+    },
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Elf(e) => write!(f, "{e}"),
+            LoadError::Machine { expected, found } => write!(
+                f,
+                "the ELF is for machine {found}, the guest runs machine {expected}"
+            ),
+            LoadError::Class { is64 } => write!(
+                f,
+                "a {}-bit ELF can not run on a {}-bit guest",
+                if *is64 { 64 } else { 32 },
+                Word::BITS
+            ),
+            LoadError::OutOfMemory {
+                start,
+                end,
+                memory: (mstart, mend),
+            } => write!(
+                f,
+                "[0x{start:x}, 0x{end:x}) is outside of physical memory \
+                [0x{mstart:x}, 0x{mend:x})"
+            ),
+            LoadError::Syntax { line, what } => write!(f, "line {line}: {what}"),
+            LoadError::Checksum {
+                line,
+                expected,
+                found,
+            } => write!(
+                f,
+                "line {line}: checksum 0x{found:02x} does not match 0x{expected:02x}"
+            ),
+        }
+    }
+}
+
+impl From<ElfError> for LoadError {
+    fn from(e: ElfError) -> Self {
+        LoadError::Elf(e)
+    }
+}
+
+/// How an image file is laid out.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    /// tell from the contents
+    Auto,
+    Elf,
+    /// a flat binary copied as it is to its load address
+    Bin,
+    /// Intel HEX
+    Hex,
+    /// Motorola S-record
+    Srec,
+}
+
+impl Format {
+    /// the format of `data`: ELF by its magic, HEX and S-record by the start of the first
+    /// record, a raw binary otherwise
+    pub fn detect(data: &[u8]) -> Self {
+        let text = data.trim_ascii_start();
+        if data.starts_with(b"\x7fELF") {
+            Format::Elf
+        } else if text.starts_with(b":") {
+            Format::Hex
+        } else if text.len() > 1 && text[0] == b'S' && text[1].is_ascii_digit() {
+            Format::Srec
+        } else {
+            Format::Bin
+        }
+    }
+}
+
+/// An image file given on the command line and where to put it if it is a raw binary.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageFile {
+    pub path: String,
+    pub addr: Option<u64>,
+}
+
+/// parse `FILE[@ADDR]`, ADDR in hex with or without 0x
+pub fn parse_image_file(s: &str) -> Result<ImageFile, String> {
+    match s.rsplit_once('@') {
+        Some((path, addr)) => Ok(ImageFile {
+            path: path.into(),
+            addr: Some(parse_addr(addr)?),
+        }),
+        None => Ok(ImageFile {
+            path: s.into(),
+            addr: None,
+        }),
+    }
+}
+
+/// parse an address in hex with or without 0x
+pub fn parse_addr(s: &str) -> Result<u64, String> {
+    u64::from_str_radix(s.trim_start_matches("0x"), 16).map_err(|e| format!("{s}: {e}"))
+}
+
+/// The image files to load and how.
+#[derive(Debug, Clone)]
+pub struct LoadConfig {
+    pub files: Vec<ImageFile>,
+    pub format: Format,
+    /// where raw binaries without an address of their own go
+    pub load_addr: u64,
+}
+
+impl Default for LoadConfig {
+    fn default() -> Self {
+        Self {
+            files: vec![],
+            format: Format::Auto,
+            load_addr: RESET_VECTOR as u64,
+        }
+    }
+}
+
+/// What the guest needs from a loaded image besides memory.
+#[derive(Debug, Default)]
+pub struct Image {
+    /// where the guest starts, `None` to start at the reset vector
+    pub entry: Option<Vaddr>,
+    /// the functions in it, sorted by address
+    pub symbols: Vec<Symbol>,
+}
+
+/// The data records and start address of a HEX or S-record file.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Records {
+    data: Vec<(u64, Vec<u8>)>,
+    entry: Option<u64>,
+}
+
+/// the bytes written in hex digits on `line`
+fn hex_bytes(line: &str) -> Option<Vec<u8>> {
+    let digits = line.as_bytes();
+    if !digits.len().is_multiple_of(2) {
+        return None;
+    }
+    digits
+        .chunks(2)
+        .map(|d| u8::from_str_radix(std::str::from_utf8(d).ok()?, 16).ok())
+        .collect()
+}
+
+/// copy `len` bytes of `data` followed by zeros to `addr`
+fn copy(mem: &mut MemoryBank, addr: u64, data: &[u8], len: u64) -> Result<(), LoadError> {
+    let loaded = usize::try_from(addr)
+        .ok()
+        .zip(usize::try_from(len).ok())
+        .is_some_and(|(addr, len)| mem.load(addr, data, len));
+    if loaded {
+        return Ok(());
+    }
+    let near = mem.region_near(addr as usize);
+    Err(LoadError::OutOfMemory {
+        start: addr,
+        end: addr.saturating_add(len),
+        memory: (near.base, near.end()),
+    })
+}
+
+/// copy the PT_LOAD segments of the ELF `data` for guest `T` to `mem`, zeroing the part of
+/// each past the file like .bss
+pub fn load_elf<T: ISA>(mem: &mut MemoryBank, data: &[u8]) -> Result<Image, LoadError> {
+    let elf = Elf::parse(data)?;
+    if elf.machine != T::ELF_MACHINE {
+        return Err(LoadError::Machine {
+            expected: T::ELF_MACHINE,
+            found: elf.machine,
+        });
+    }
+    if elf.is64() != (Word::BITS == 64) {
+        return Err(LoadError::Class { is64: elf.is64() });
+    }
+    for segment in elf.segments()? {
+        copy(mem, segment.paddr, segment.data, segment.memsz)?;
+    }
+    Ok(Image {
+        entry: Some(elf.entry as Vaddr),
+        symbols: elf.functions()?,
+    })
+}
+
+/// copy the records of a HEX or S-record file to `mem`
+fn load_records(mem: &mut MemoryBank, records: Records) -> Result<Image, LoadError> {
+    for (addr, data) in &records.data {
+        copy(mem, *addr, data, data.len() as u64)?;
+    }
+    Ok(Image {
+        entry: records.entry.map(|e| e as Vaddr),
+        symbols: vec![],
+    })
+}
+
+/// load `data` in `format` for guest `T`, a raw binary at `addr`
+pub fn load_image<T: ISA>(
+    mem: &mut MemoryBank,
+    data: &[u8],
+    format: Format,
+    addr: u64,
+) -> Result<Image, LoadError> {
+    let text = || String::from_utf8_lossy(data);
+    match format {
+        Format::Auto => load_image::<T>(mem, data, Format::detect(data), addr),
+        Format::Elf => load_elf::<T>(mem, data),
+        Format::Bin => {
+            copy(mem, addr, data, data.len() as u64)?;
+            Ok(Image {
+                entry: Some(addr as Vaddr),
+                symbols: vec![],
+            })
+        }
+        Format::Hex => load_records(mem, hex::parse(&text())?),
+        Format::Srec => load_records(mem, srec::parse(&text())?),
+    }
+}
+
+/// load the files of `config` one after the other for guest `T`. The guest starts at the entry
+/// of the first one, the load address for a raw binary, and the symbols of all are kept.
+pub fn load<T: ISA>(mem: &mut MemoryBank, config: &LoadConfig) -> Result<Image, String> {
+    let mut image = Image::default();
+    for file in &config.files {
+        let data =
+            std::fs::read(&file.path).map_err(|e| format!("can not read {}: {}", file.path, e))?;
+        let format = match config.format {
+            Format::Auto => Format::detect(&data),
+            format => format,
+        };
+        if file.addr.is_some() && format != Format::Bin {
+            return Err(format!(
+                "{}: only a raw binary takes a load address",
+                file.path
+            ));
+        }
+        let addr = file.addr.unwrap_or(config.load_addr);
+        let loaded = load_image::<T>(mem, &data, format, addr)
+            .map_err(|e| format!("{}: {}", file.path, e))?;
+        image.entry = image.entry.or(loaded.entry);
+        image.symbols.extend(loaded.symbols);
+    }
+    image.symbols.sort_by_key(|f| f.addr);
+    Ok(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::isa::Guest;
+
+    #[cfg(not(feature = "64bit"))]
+    #[test]
+    fn load_elf_test() {
+        use crate::elf::tests::elf32_with;
+
+        let mut mem = MemoryBank::new(&[0xff; 0x20]);
+        let data = elf32_with(
+            Guest::ELF_MACHINE,
+            0x8000_0004,
+            &[(0x8000_0000, &[1, 2, 3, 4], 4), (0x8000_0010, &[5], 8)],
+            &[("main", 0x8000_0004, 8, 2)],
+        );
+        let image = load_image::<Guest>(&mut mem, &data, Format::Auto, 0).unwrap();
+        assert_eq!(image.entry, Some(0x8000_0004));
+        assert_eq!(image.symbols[0].name, "main");
+        assert_eq!(mem.peek(0x8000_0000, 4), 0x0403_0201);
+        // .bss is zeroed, what lies between the segments is left alone
+        assert_eq!(mem.peek(0x8000_0010, 4), 5);
+        assert_eq!(mem.peek(0x8000_0014, 4), 0);
+        assert_eq!(mem.peek(0x8000_0018, 4), 0xffff_ffff);
+        assert_eq!(mem.peek(0x8000_0008, 4), 0xffff_ffff);
+    }
+
+    #[cfg(not(feature = "64bit"))]
+    #[test]
+    fn elf_error_test() {
+        use crate::elf::tests::elf32_with;
+
+        let elf = |machine, paddr, memsz| {
+            elf32_with(machine, paddr, &[(paddr, &[1, 2, 3, 4], memsz)], &[])
+        };
+        let memory = (0x8000_0000, 0x8800_0000);
+        let cases = [
+            (
+                elf(Guest::ELF_MACHINE + 1, 0x8000_0000, 4),
+                LoadError::Machine {
+                    expected: Guest::ELF_MACHINE,
+                    found: Guest::ELF_MACHINE + 1,
+                },
+            ),
+            (
+                elf(Guest::ELF_MACHINE, 0x1000, 4),
+                LoadError::OutOfMemory {
+                    start: 0x1000,
+                    end: 0x1004,
+                    memory,
+                },
+            ),
+            // the .bss running past the end is out as well
+            (
+                elf(Guest::ELF_MACHINE, 0x87ff_fffc, 8),
+                LoadError::OutOfMemory {
+                    start: 0x87ff_fffc,
+                    end: 0x8800_0004,
+                    memory,
+                },
+            ),
+            (b"\x7fELG".to_vec(), LoadError::Elf(ElfError::NotElf)),
+        ];
+        for (data, e) in cases {
+            let mut mem = MemoryBank::new(&[]);
+            assert_eq!(load_elf::<Guest>(&mut mem, &data).unwrap_err(), e);
+        }
+    }
+
+    #[test]
+    fn detect_test() {
+        let cases: [(&[u8], Format); 5] = [
+            (b"\x7fELF\x01\x01", Format::Elf),
+            (b":0400000001020304F2\n", Format::Hex),
+            (b"\r\nS00600004844521B\n", Format::Srec),
+            (b"\x97\x02\x00\x00", Format::Bin),
+            (b"S", Format::Bin),
+        ];
+        for (data, format) in cases {
+            assert_eq!(Format::detect(data), format, "{data:?}");
+        }
+    }
+
+    #[test]
+    fn load_image_test() {
+        // the image, how it is given, where the guest starts and a word it puts in memory
+        let cases = [
+            (
+                &[1, 2, 3, 4][..],
+                Format::Bin,
+                0x8000_1000,
+                Some(0x8000_1000),
+                (0x8000_1000, 0x0403_0201),
+            ),
+            (
+                &b":0200000480007A\n:0400100005060708D2\n:00000001FF\n"[..],
+                Format::Auto,
+                0,
+                None,
+                (0x8000_0010, 0x0807_0605),
+            ),
+            (
+                &b"S30980000010010203045C\nS7058000000476\n"[..],
+                Format::Srec,
+                0,
+                Some(0x8000_0004),
+                (0x8000_0010, 0x0403_0201),
+            ),
+        ];
+        for (data, format, addr, entry, (paddr, word)) in cases {
+            let mut mem = MemoryBank::new(&[]);
+            let image = load_image::<Guest>(&mut mem, data, format, addr).unwrap();
+            assert_eq!(image.entry, entry, "{format:?}");
+            assert_eq!(mem.peek(paddr, 4), word, "{format:?}");
+        }
+    }
+
+    #[test]
+    fn load_image_error_test() {
+        let memory = (0x8000_0000, 0x8800_0000);
+        let cases: [(&[u8], Format, u64, LoadError); 3] = [
+            (
+                &[1, 2, 3, 4],
+                Format::Bin,
+                0x87ff_fffe,
+                LoadError::OutOfMemory {
+                    start: 0x87ff_fffe,
+                    end: 0x8800_0002,
+                    memory,
+                },
+            ),
+            (
+                b"S1041000AA41\n",
+                Format::Auto,
+                0,
+                LoadError::OutOfMemory {
+                    start: 0x1000,
+                    end: 0x1001,
+                    memory,
+                },
+            ),
+            (
+                b":0400100005060708D3\n",
+                Format::Hex,
+                0,
+                LoadError::Checksum {
+                    line: 1,
+                    expected: 0xd2,
+                    found: 0xd3,
+                },
+            ),
+        ];
+        for (data, format, addr, e) in cases {
+            let mut mem = MemoryBank::new(&[]);
+            let image = load_image::<Guest>(&mut mem, data, format, addr);
+            assert_eq!(image.unwrap_err(), e, "{format:?}");
+        }
+    }
+
+    #[test]
+    fn display_test() {
+        let cases = [
+            (
+                LoadError::OutOfMemory {
+                    start: 0x1000,
+                    end: 0x1004,
+                    memory: (0x8000_0000, 0x8800_0000),
+                },
+                "[0x1000, 0x1004) is outside of physical memory [0x80000000, 0x88000000)",
+            ),
+            (
+                LoadError::Checksum {
+                    line: 2,
+                    expected: 0xe2,
+                    found: 0xe3,
+                },
+                "line 2: checksum 0xe3 does not match 0xe2",
+            ),
+            (
+                LoadError::Machine {
+                    expected: 0xf3,
+                    found: 0x3e,
+                },
+                "the ELF is for machine 62, the guest runs machine 243",
+            ),
+        ];
+        for (e, text) in cases {
+            assert_eq!(e.to_string(), text);
+        }
+    }
+
+    #[test]
+    fn parse_image_file_test() {
+        let file = |path: &str, addr| ImageFile {
+            path: path.into(),
+            addr,
+        };
+        let cases = [
+            ("fw.bin@0x80001000", Ok(file("fw.bin", Some(0x8000_1000)))),
+            ("fw.bin@80001000", Ok(file("fw.bin", Some(0x8000_1000)))),
+            ("kernel.elf", Ok(file("kernel.elf", None))),
+            (
+                "fw.bin@zz",
+                Err("zz: invalid digit found in string".to_string()),
+            ),
+        ];
+        for (s, file) in cases {
+            assert_eq!(parse_image_file(s), file, "{s}");
+        }
+    }
+}
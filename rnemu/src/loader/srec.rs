@@ -0,0 +1,119 @@
+//! Motorola S-record: `STCC<address><data>CC` records, with a 2, 3 or 4-byte address by type.
+
+use super::{hex_bytes, LoadError, Records};
+
+/// the records of `text` up to the termination record, all of them if there is none
+pub(super) fn parse(text: &str) -> Result<Records, LoadError> {
+    let mut records = Records::default();
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let syntax = |what| LoadError::Syntax { line: n + 1, what };
+        let rest = line
+            .strip_prefix('S')
+            .ok_or(syntax("a record starts with 'S'"))?;
+        let (kind, bytes) = rest.split_at_checked(1).ok_or(syntax("no record type"))?;
+        let bytes = hex_bytes(bytes).ok_or(syntax("a record is pairs of hex digits"))?;
+        if bytes.len() < 2 || bytes.len() != bytes[0] as usize + 1 {
+            return Err(syntax("the count does not match the record"));
+        }
+        let (body, checksum) = bytes.split_at(bytes.len() - 1);
+        let expected = !body.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+        if checksum[0] != expected {
+            return Err(LoadError::Checksum {
+                line: n + 1,
+                expected,
+                found: checksum[0],
+            });
+        }
+        let body = &body[1..];
+        // the width of the address, whether it is data or the start address
+        let (width, data) = match kind {
+            "0" | "5" | "6" => continue,
+            "1" => (2, true),
+            "2" => (3, true),
+            "3" => (4, true),
+            "7" => (4, false),
+            "8" => (3, false),
+            "9" => (2, false),
+            _ => return Err(syntax("unknown record type")),
+        };
+        if body.len() < width {
+            return Err(syntax("the address is cut short"));
+        }
+        let (addr, body) = body.split_at(width);
+        let addr = addr.iter().fold(0u64, |v, b| v << 8 | *b as u64);
+        if !data {
+            records.entry = Some(addr);
+            break;
+        }
+        records.data.push((addr, body.to_vec()));
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_test() {
+        let records = |data: &[(u64, &[u8])], entry| Records {
+            data: data.iter().map(|&(addr, d)| (addr, d.to_vec())).collect(),
+            entry,
+        };
+        let cases = [
+            // 2, 3 and 4-byte addresses
+            ("S1041000AA41\n", records(&[(0x1000, &[0xaa])], None)),
+            ("S205801000BBAF\n", records(&[(0x80_1000, &[0xbb])], None)),
+            (
+                "S30980000010010203045C\n",
+                records(&[(0x8000_0010, &[1, 2, 3, 4])], None),
+            ),
+            // the header and the count are skipped, as are blank lines
+            ("S00600004844521B\n\nS5030003F9\n", records(&[], None)),
+            // nothing after the termination is
+            (
+                "S7058000000476\nS1041000AA41\n",
+                records(&[], Some(0x8000_0004)),
+            ),
+            ("S80480000477\n", records(&[], Some(0x80_0004))),
+            ("S9031000EC\n", records(&[], Some(0x1000))),
+        ];
+        for (text, records) in cases {
+            assert_eq!(parse(text), Ok(records), "{text}");
+        }
+    }
+
+    #[test]
+    fn error_test() {
+        let syntax = |line, what| LoadError::Syntax { line, what };
+        let cases = [
+            ("1041000AA41\n", syntax(1, "a record starts with 'S'")),
+            ("S1041000AA41\nS", syntax(2, "no record type")),
+            (
+                "S1041000AA4\n",
+                syntax(1, "a record is pairs of hex digits"),
+            ),
+            (
+                "S1051000AA41\n",
+                syntax(1, "the count does not match the record"),
+            ),
+            ("S4041000AA41\n", syntax(1, "unknown record type")),
+            ("S3041000AA41\n", syntax(1, "the address is cut short")),
+            (
+                "S1041000AA42\n",
+                LoadError::Checksum {
+                    line: 1,
+                    expected: 0x41,
+                    found: 0x42,
+                },
+            ),
+        ];
+        for (text, e) in cases {
+            assert_eq!(parse(text), Err(e), "{text}");
+        }
+    }
+}
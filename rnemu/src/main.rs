@@ -7,10 +7,14 @@ mod common;
 mod core;
 #[macro_use]
 mod debug;
+mod elf;
 mod isa;
+mod loader;
 mod memory;
 mod monitor;
 mod time;
+#[cfg(feature = "trace")]
+mod trace;
 
 fn main() {
     init_monitor();
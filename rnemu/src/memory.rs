@@ -1,7 +1,9 @@
 use core::panic;
-use std::io::Write;
+use std::{cell::Cell, fmt, io::Write, ops::Range};
 
 use crate::common::{Paddr, Vaddr, Word};
+#[cfg(feature = "trace")]
+use crate::trace::MTrace;
 
 const MBASE: usize = 0x8000_0000;
 const MSIZE: usize = 0x800_0000;
@@ -10,115 +12,292 @@ const PMEM_RIGHT: usize = MBASE + MSIZE - 1;
 const PC_RESET_OFFSET: usize = 0x0;
 pub const RESET_VECTOR: usize = PMEM_LEFT + PC_RESET_OFFSET;
 
-pub struct MemoryBank {
-    base: usize,
+mod map;
+pub use map::{MemoryMap, Perm, Region};
+
+/// A physical memory access that could not be done, made by the instruction at `pc`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MemError {
+    /// the `len` bytes at `addr` are not all in one region, `range` is the nearest one
+    OutOfBound {
+        addr: Paddr,
+        len: usize,
+        range: Range<usize>,
+        pc: Vaddr,
+    },
+    /// `len` is not 1, 2, 4 or 8, or is wider than a word
+    Width { addr: Paddr, len: usize, pc: Vaddr },
+    /// region `region` allows only `allowed`, not the `access` made
+    Permission {
+        addr: Paddr,
+        len: usize,
+        access: Perm,
+        region: String,
+        allowed: Perm,
+        pc: Vaddr,
+    },
+}
+
+impl fmt::Display for MemError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MemError::OutOfBound {
+                addr,
+                len,
+                range,
+                pc,
+            } => write!(
+                f,
+                "address = 0x{:08x} ({} bytes) is out of bound of pmem [0x{:08x}, 0x{:08x}) at \
+                pc = 0x{:08x}",
+                addr, len, range.start, range.end, pc
+            ),
+            MemError::Width { addr, len, pc } => write!(
+                f,
+                "{}-byte access to address = 0x{:08x} at pc = 0x{:08x}, the widths are 1, 2, 4 \
+                and up to {} bytes",
+                len,
+                addr,
+                pc,
+                std::mem::size_of::<Word>()
+            ),
+            MemError::Permission {
+                addr,
+                len,
+                access,
+                region,
+                allowed,
+                pc,
+            } => write!(
+                f,
+                "address = 0x{:08x} ({} bytes) at pc = 0x{:08x} needs {} but region {} is {}",
+                addr, len, pc, access, region, allowed
+            ),
+        }
+    }
+}
+
+/// A region of the memory map and what it holds.
+struct Bank {
+    region: Region,
     data: Vec<u8>,
 }
 
+pub struct MemoryBank {
+    /// sorted by base
+    banks: Vec<Bank>,
+    /// the first access that failed since `take_error`, as its address, width and what it
+    /// needed
+    error: Cell<Option<(Paddr, usize, Perm)>>,
+    /// the last `allows` check if it failed, the same way
+    denied: Cell<Option<(Paddr, usize, Perm)>>,
+    #[cfg(feature = "trace")]
+    mtrace: Option<MTrace>,
+}
+
 impl MemoryBank {
     pub fn inst_fetch(&self, pc: &mut Vaddr, len: usize) -> Word {
-        let start = (*pc - self.base as u32) as usize;
-        let ret = Word::from_le_bytes((&self.data[start..start + len]).try_into().unwrap());
-        *pc += len as u32;
+        let ret = self.read(*pc, len, Perm::X).unwrap_or(0);
+        *pc = pc.wrapping_add(len as Vaddr);
         ret
     }
 
+    /// index of the bank holding all `len` bytes at physical address `addr` and the offset of
+    /// them in it
+    fn find(&self, addr: Paddr, len: usize) -> Option<(usize, usize)> {
+        let addr = addr as usize;
+        let n = self
+            .banks
+            .iter()
+            .position(|b| addr >= b.region.base && addr.saturating_add(len) <= b.region.end())?;
+        Some((n, addr - self.banks[n].region.base))
+    }
+
+    /// whether `len` bytes at physical address `addr` are backed by one region
+    pub fn in_pmem(&self, addr: Paddr, len: usize) -> bool {
+        self.find(addr, len).is_some()
+    }
+
+    /// whether `len` bytes at physical address `addr` are backed by one region that allows
+    /// `perm`, kept for `take_denied` if not
+    pub fn allows(&self, addr: Paddr, len: usize, perm: Perm) -> bool {
+        let allowed = self
+            .find(addr, len)
+            .is_some_and(|(n, _)| self.banks[n].region.perm.contains(perm));
+        self.denied.set((!allowed).then_some((addr, len, perm)));
+        allowed
+    }
+
+    /// the region `addr` is in or the nearest one below it, the lowest if there is none
+    pub fn region_near(&self, addr: usize) -> &Region {
+        let n = self.banks.partition_point(|b| b.region.base <= addr);
+        &self.banks[n.saturating_sub(1)].region
+    }
+
+    /// the regions, sorted by base
+    pub fn regions(&self) -> impl Iterator<Item = &Region> {
+        self.banks.iter().map(|b| &b.region)
+    }
+
+    /// copy `data` to physical address `addr` and zero the rest of the `len` bytes from it
+    /// whatever the region allows, `false` and nothing written if they are not all in one
+    pub fn load(&mut self, addr: usize, data: &[u8], len: usize) -> bool {
+        let len = len.max(data.len());
+        let Some(bank) = self.banks.iter_mut().find(|b| {
+            addr >= b.region.base && addr.checked_add(len).is_some_and(|e| e <= b.region.end())
+        }) else {
+            return false;
+        };
+        let start = addr - bank.region.base;
+        bank.data[start..start + data.len()].copy_from_slice(data);
+        bank.data[start + data.len()..start + len].fill(0);
+        true
+    }
+
+    /// the bank and offset of the `len` bytes at `addr`, `None` after keeping the access for
+    /// `take_error` if it can not be done or its region does not allow `perm`
+    fn access(&self, addr: Paddr, len: usize, perm: Perm) -> Option<(usize, usize)> {
+        let width = matches!(len, 1 | 2 | 4 | 8) && len <= std::mem::size_of::<Word>();
+        let found = self.find(addr, len);
+        if width && found.is_some_and(|(n, _)| self.banks[n].region.perm.contains(perm)) {
+            return found;
+        }
+        if self.error.get().is_none() {
+            self.error.set(Some((addr, len, perm)));
+        }
+        None
+    }
+
+    /// read `len` bytes at `addr` that the region allows `perm` on, `None` if it can not
+    fn read(&self, addr: Paddr, len: usize, perm: Perm) -> Option<Word> {
+        let (bank, start) = self.access(addr, len, perm)?;
+        let mut buf = [0u8; std::mem::size_of::<Word>()];
+        buf[..len].copy_from_slice(&self.banks[bank].data[start..start + len]);
+        Some(Word::from_le_bytes(buf))
+    }
+
+    /// read `len` bytes, 1, 2, 4 or, on 64-bit guests, 8, at physical address `addr`, little
+    /// endian. An access outside of memory or to a region that can not be read reads 0, is
+    /// kept for `take_error` and left out of the memory trace
+    pub fn paddr_read(&self, addr: Paddr, len: usize) -> Word {
+        let data = self.read(addr, len, Perm::R);
+        #[cfg(feature = "trace")]
+        if let (Some(mtrace), Some(data)) = (&self.mtrace, data) {
+            mtrace.trace(false, addr, len, data);
+        }
+        data.unwrap_or(0)
+    }
+
+    /// read like `paddr_read` but out of the memory trace and whatever the region allows,
+    /// for the debugger and the traces
+    pub fn peek(&self, addr: Paddr, len: usize) -> Word {
+        self.read(addr, len, Perm::NONE).unwrap_or(0)
+    }
+
+    /// write the low `len` bytes, 1, 2, 4 or, on 64-bit guests, 8, of `data` to physical
+    /// address `addr`, little endian. An access outside of memory or to a region that can not
+    /// be written writes nothing, is kept for `take_error` and left out of the memory trace
+    pub fn paddr_write(&mut self, addr: Paddr, len: usize, data: Word) {
+        let Some((bank, start)) = self.access(addr, len, Perm::W) else {
+            return;
+        };
+        #[cfg(feature = "trace")]
+        if let Some(mtrace) = &self.mtrace {
+            mtrace.trace(true, addr, len, data);
+        }
+        self.banks[bank].data[start..start + len].copy_from_slice(&data.to_le_bytes()[..len]);
+    }
+
+    /// whether an access could not be done since the last `take_error`
+    pub fn has_error(&self) -> bool {
+        self.error.get().is_some()
+    }
+
+    /// the first access that could not be done since the last call, made by the instruction
+    /// at `pc`
+    pub fn take_error(&mut self, pc: Vaddr) -> Option<MemError> {
+        let error = self.error.take()?;
+        Some(self.report(error, pc))
+    }
+
+    /// the last `allows` check if it failed, made by the instruction at `pc`, for the guest
+    /// exception it turned into
+    pub fn take_denied(&mut self, pc: Vaddr) -> Option<MemError> {
+        let denied = self.denied.take()?;
+        Some(self.report(denied, pc))
+    }
+
+    /// why the `len`-byte access at `addr` that needed `access` could not be done
+    fn report(&self, (addr, len, access): (Paddr, usize, Perm), pc: Vaddr) -> MemError {
+        match self.find(addr, len) {
+            None => {
+                let near = self.region_near(addr as usize);
+                MemError::OutOfBound {
+                    addr,
+                    len,
+                    range: near.base..near.end(),
+                    pc,
+                }
+            }
+            Some((n, _)) if !self.banks[n].region.perm.contains(access) => MemError::Permission {
+                addr,
+                len,
+                access,
+                region: self.banks[n].region.name.clone(),
+                allowed: self.banks[n].region.perm,
+                pc,
+            },
+            Some(_) => MemError::Width { addr, len, pc },
+        }
+    }
+
+    /// trace the reads and writes from now on
+    #[cfg(feature = "trace")]
+    pub fn set_mtrace(&mut self, mtrace: MTrace) {
+        self.mtrace = Some(mtrace);
+    }
+
+    /// whether the reads and writes are traced
+    #[cfg(feature = "trace")]
+    pub fn mtrace_on(&self) -> bool {
+        self.mtrace.as_ref().is_some_and(MTrace::on)
+    }
+
+    /// the accesses that follow are made by instruction number `n` at `pc`
+    #[cfg(feature = "trace")]
+    pub fn mtrace_begin(&mut self, pc: Vaddr, n: u64) {
+        if let Some(mtrace) = &mut self.mtrace {
+            mtrace.begin(pc, n);
+        }
+    }
+
+    /// the regions of `map`, zeroed
+    pub fn with_map(map: &MemoryMap) -> Self {
+        let mut regions = map.regions.clone();
+        regions.sort_by_key(|r| r.base);
+        Self {
+            banks: regions
+                .into_iter()
+                .map(|region| Bank {
+                    data: vec![0; region.size],
+                    region,
+                })
+                .collect(),
+            error: Cell::new(None),
+            denied: Cell::new(None),
+            #[cfg(feature = "trace")]
+            mtrace: None,
+        }
+    }
+
+    /// the default memory map with `img` at the reset vector
     pub fn new(img: &[u8]) -> Self {
-        let mut data: Vec<u8> = vec![0; MSIZE];
-        let start = RESET_VECTOR - MBASE;
-        &mut data[start..start + img.len()].copy_from_slice(img);
-        Self { base: MBASE, data }
+        let mut mem = Self::with_map(&MemoryMap::default());
+        assert!(mem.load(RESET_VECTOR, img, img.len()));
+        mem
     }
 }
 
-// fn host_read(addr: usize, len: usize) -> Word {
-//     unsafe {
-//         match len {
-//             1 => {
-//                 let addr = addr as *mut u8;
-//                 (*addr) as Word
-//             }
-//             2 => {
-//                 let addr = addr as *mut u16;
-//                 (*addr) as Word
-//             }
-//             4 => {
-//                 let addr = addr as *mut u32;
-//                 (*addr) as Word
-//             }
-//             _ => panic!(),
-//         }
-//     }
-// }
-
-// // convert the guest physical address in the guest program to host virtual address in NEMU
-// fn host_write(addr: usize, len: usize, data: Word) {
-//     unsafe {
-//         match len {
-//             1 => {
-//                 let addr = addr as *mut u8;
-//                 *addr = data as u8;
-//             }
-//             2 => {
-//                 let addr = addr as *mut u16;
-//                 *addr = data as u16;
-//             }
-//             4 => {
-//                 let addr = addr as *mut u32;
-//                 *addr = data;
-//             }
-//             _ => {
-//                 panic!()
-//             }
-//         }
-//     }
-// }
-
-// pub fn guest_to_host(paddr: Paddr) -> usize {
-//     unsafe { return PMEM.as_ptr() as usize + paddr as usize - MBASE }
-// }
-// // convert the host virtual address in NEMU to guest physical address in the guest program
-// pub fn host_to_guest(haddr: *const u8) -> Paddr {
-//     unsafe { (haddr as usize - PMEM.as_ptr() as usize + MBASE as usize) as Paddr }
-// }
-
-// fn pmem_read(addr: Paddr, len: usize) -> Word {
-//     host_read(guest_to_host(addr), len)
-// }
-
-// fn pmem_write(addr: Paddr, len: usize, data: Word) {
-//     host_write(guest_to_host(addr), len, data);
-// }
-
-// fn out_of_bound(addr: Paddr) {
-//     //   panic("address =  {}  is out of bound of pmem [ {} ,  {} ] at pc =  {}" ,
-//     //       addr, PMEM_LEFT, PMEM_RIGHT, 0x0000);
-// }
-
-// fn init_mem() {
-//     // info!("physical memory area [" {} ", " {} "]", PMEM_LEFT, PMEM_RIGHT);
-// }
-
-// fn paddr_read(addr: Paddr, len: usize) {
-//     //   if (likely(in_pmem(addr))) return pmem_read(addr, len);
-//     //   IFDEF(CONFIG_DEVICE, return mmio_read(addr, len));
-//     //   out_of_bound(addr);
-//     //   return 0;
-// }
-
-// fn in_pmem(addr: Paddr) -> bool {
-//     addr as usize - MBASE < MSIZE
-// }
-
-// fn paddr_write(addr: Paddr, len: usize, data: Word) {
-//     if in_pmem(addr) {
-//         pmem_write(addr, len, data);
-//         return;
-//     }
-//     //   IFDEF(CONFIG_DEVICE, mmio_write(addr, len, data); return);
-//     out_of_bound(addr);
-// }
-
 #[derive(Default)]
 pub struct Memory {
     /// Memory content
@@ -145,7 +324,7 @@ impl Memory {
     /// * `address`
     /// * `width` up to eight
     pub fn read_bytes(&self, address: u64, width: u64) -> u64 {
-        let mut data = 0 as u64;
+        let mut data = 0_u64;
         for i in 0..width {
             data |= (self.read_byte(address.wrapping_add(i)) as u64) << (i * 8);
         }
@@ -159,7 +338,7 @@ impl Memory {
     /// * `value`
     pub fn write_byte(&mut self, address: u64, value: u8) {
         let index = (address >> 3) as usize;
-        let pos = ((address % 8) as u64) * 8;
+        let pos = (address % 8) * 8;
         self.data[index] = (self.data[index] & !(0xff << pos)) | ((value as u64) << pos);
     }
 
@@ -169,9 +348,9 @@ impl Memory {
     /// * `address`
     /// * `value`
     pub fn write_halfword(&mut self, address: u64, value: u16) {
-        if (address % 2) == 0 {
+        if address.is_multiple_of(2) {
             let index = (address >> 3) as usize;
-            let pos = ((address % 8) as u64) * 8;
+            let pos = (address % 8) * 8;
             self.data[index] = (self.data[index] & !(0xffff << pos)) | ((value as u64) << pos);
         } else {
             self.write_bytes(address, value as u64, 2);
@@ -184,9 +363,9 @@ impl Memory {
     /// * `address`
     /// * `value`
     pub fn write_word(&mut self, address: u64, value: u32) {
-        if (address % 4) == 0 {
+        if address.is_multiple_of(4) {
             let index = (address >> 3) as usize;
-            let pos = ((address % 8) as u64) * 8;
+            let pos = (address % 8) * 8;
             self.data[index] = (self.data[index] & !(0xffffffff << pos)) | ((value as u64) << pos);
         } else {
             self.write_bytes(address, value as u64, 4);
@@ -199,10 +378,10 @@ impl Memory {
     /// * `address`
     /// * `value`
     pub fn write_doubleword(&mut self, address: u64, value: u64) {
-        if (address % 8) == 0 {
+        if address.is_multiple_of(8) {
             let index = (address >> 3) as usize;
             self.data[index] = value;
-        } else if (address % 4) == 0 {
+        } else if address.is_multiple_of(4) {
             self.write_word(address, (value & 0xffffffff) as u32);
             self.write_word(address.wrapping_add(4), (value >> 32) as u32);
         } else {
@@ -227,6 +406,197 @@ impl Memory {
     /// # Arguments
     /// * `address`
     pub fn validate_address(&self, address: u64) -> bool {
-        return (address as usize) < self.data.len();
+        (address as usize) < self.data.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: Paddr = MBASE as Paddr;
+    const END: Paddr = (MBASE + MSIZE) as Paddr;
+
+    #[test]
+    fn access_test() {
+        let mut mem = MemoryBank::new(&[]);
+        mem.paddr_write(BASE, 4, 0x4433_2211);
+        mem.paddr_write(BASE + 4, 2, 0x6655);
+        mem.paddr_write(END - 1, 1, 0x77);
+        assert_eq!(mem.paddr_read(BASE, 1), 0x11);
+        assert_eq!(mem.paddr_read(BASE + 2, 2), 0x4433);
+        assert_eq!(mem.paddr_read(BASE + 2, 4), 0x6655_4433);
+        assert_eq!(mem.paddr_read(END - 1, 1), 0x77);
+        if std::mem::size_of::<Word>() == 8 {
+            mem.paddr_write(BASE + 8, 8, Word::MAX);
+            assert_eq!(mem.paddr_read(BASE + 8, 8), Word::MAX);
+        }
+        assert_eq!(mem.take_error(0), None);
+    }
+
+    #[test]
+    fn error_test() {
+        let pc = 0x8000_0010;
+        let out = |addr, len| MemError::OutOfBound {
+            addr,
+            len,
+            range: MBASE..MBASE + MSIZE,
+            pc,
+        };
+        let cases = [
+            (END - 2, 4, out(END - 2, 4)),
+            (BASE - 4, 4, out(BASE - 4, 4)),
+            (0, 1, out(0, 1)),
+            (
+                BASE,
+                3,
+                MemError::Width {
+                    addr: BASE,
+                    len: 3,
+                    pc,
+                },
+            ),
+        ];
+        for (addr, len, e) in cases {
+            // reads 0 and writes nothing
+            let mut mem = MemoryBank::new(&[]);
+            assert_eq!(mem.paddr_read(addr, len), 0);
+            assert_eq!(mem.take_error(pc).as_ref(), Some(&e));
+            mem.paddr_write(addr, len, Word::MAX);
+            assert_eq!(mem.take_error(pc), Some(e));
+            assert_eq!(mem.peek(BASE, 4), 0);
+        }
+    }
+
+    #[test]
+    fn first_error_test() {
+        let mut mem = MemoryBank::new(&[]);
+        mem.paddr_read(END - 2, 4);
+        mem.paddr_write(BASE - 4, 4, 1);
+        assert!(mem.has_error());
+        assert!(matches!(
+            mem.take_error(0),
+            Some(MemError::OutOfBound { addr, .. }) if addr == END - 2
+        ));
+        assert!(!mem.has_error());
+    }
+
+    #[test]
+    fn display_test() {
+        let cases = [
+            (
+                MemError::OutOfBound {
+                    addr: 0,
+                    len: 1,
+                    range: MBASE..MBASE + MSIZE,
+                    pc: 0x8000_0000,
+                },
+                "address = 0x00000000 (1 bytes) is out of bound of pmem [0x80000000, 0x88000000) \
+                at pc = 0x80000000",
+            ),
+            (
+                MemError::Permission {
+                    addr: 0x1000,
+                    len: 4,
+                    access: Perm::W,
+                    region: "rom".into(),
+                    allowed: Perm::R | Perm::X,
+                    pc: 0x1004,
+                },
+                "address = 0x00001000 (4 bytes) at pc = 0x00001004 needs -w- but region rom is r-x",
+            ),
+        ];
+        for (e, text) in cases {
+            assert_eq!(e.to_string(), text);
+        }
+    }
+
+    #[test]
+    fn region_test() {
+        let map = MemoryMap::new(
+            vec![
+                Region::parse("rom:1000:1000:rx").unwrap(),
+                Region::parse("ram:80000000:1000:rw").unwrap(),
+            ],
+            Some(0x1000),
+        )
+        .unwrap();
+        let mut mem = MemoryBank::with_map(&map);
+        // images go into read-only regions all the same
+        assert!(mem.load(0x1000, &[0x13, 0, 0, 0], 8));
+        assert!(!mem.load(0x1ffc, &[], 8));
+        assert_eq!(mem.paddr_read(0x1000, 4), 0x13);
+        mem.paddr_write(0x8000_0ffc, 4, 7);
+        assert_eq!(mem.paddr_read(0x8000_0ffc, 4), 7);
+        assert_eq!(mem.take_error(0), None);
+        // a write to the rom writes nothing
+        mem.paddr_write(0x1000, 4, 0);
+        assert_eq!(mem.peek(0x1000, 4), 0x13);
+        assert!(matches!(
+            mem.take_error(0x1004),
+            Some(MemError::Permission {
+                addr: 0x1000,
+                access: Perm::W,
+                ..
+            })
+        ));
+        assert_eq!(mem.region_near(0).name, "rom");
+        assert_eq!(mem.region_near(0x9000_0000).name, "ram");
+    }
+
+    #[test]
+    fn allows_test() {
+        let map = MemoryMap::new(
+            vec![
+                Region::parse("rom:1000:1000:rx").unwrap(),
+                Region::parse("ram:2000:1000:rw").unwrap(),
+            ],
+            Some(0x1000),
+        )
+        .unwrap();
+        let mut mem = MemoryBank::with_map(&map);
+        let denied = |addr, len, access, region: &str, allowed| MemError::Permission {
+            addr,
+            len,
+            access,
+            region: region.into(),
+            allowed,
+            pc: 0x1000,
+        };
+        // the region below is the nearest
+        let out = |addr, len, range| MemError::OutOfBound {
+            addr,
+            len,
+            range,
+            pc: 0x1000,
+        };
+        // a check and what `take_denied` reports after it
+        let cases = [
+            (0x1000, 4, Perm::R | Perm::X, None),
+            (0x2ffc, 4, Perm::R | Perm::W, None),
+            (
+                0x1000,
+                4,
+                Perm::W,
+                Some(denied(0x1000, 4, Perm::W, "rom", Perm::R | Perm::X)),
+            ),
+            (
+                0x2000,
+                4,
+                Perm::X,
+                Some(denied(0x2000, 4, Perm::X, "ram", Perm::R | Perm::W)),
+            ),
+            // an access may not straddle two regions
+            (0x1ffe, 4, Perm::NONE, Some(out(0x1ffe, 4, 0x1000..0x2000))),
+            (0x3000, 1, Perm::NONE, Some(out(0x3000, 1, 0x2000..0x3000))),
+        ];
+        for (addr, len, perm, e) in cases {
+            assert_eq!(mem.allows(addr, len, perm), e.is_none(), "0x{addr:x}");
+            assert_eq!(mem.take_denied(0x1000), e, "0x{addr:x}");
+        }
+        // only the last check is kept
+        mem.allows(0x1000, 4, Perm::W);
+        mem.allows(0x1000, 4, Perm::X);
+        assert_eq!(mem.take_denied(0x1000), None);
     }
 }
@@ -0,0 +1,291 @@
+use std::{fmt, ops::BitOr};
+
+use super::{MBASE, MSIZE, RESET_VECTOR};
+
+/// What the guest may do with a region, any of read, write and execute.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Perm(u8);
+
+impl Perm {
+    pub const NONE: Perm = Perm(0);
+    pub const R: Perm = Perm(1);
+    pub const W: Perm = Perm(2);
+    pub const X: Perm = Perm(4);
+    pub const RWX: Perm = Perm(7);
+
+    /// whether all of `other` is allowed
+    pub fn contains(self, other: Perm) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// parse any of `r`, `w` and `x` in any order, `-` standing for none of them
+    pub fn parse(s: &str) -> Result<Perm, String> {
+        s.chars().try_fold(Perm::NONE, |perm, c| match c {
+            'r' => Ok(perm | Perm::R),
+            'w' => Ok(perm | Perm::W),
+            'x' => Ok(perm | Perm::X),
+            '-' => Ok(perm),
+            _ => Err(format!("{s}: permissions are made of r, w and x")),
+        })
+    }
+}
+
+impl BitOr for Perm {
+    type Output = Perm;
+
+    fn bitor(self, rhs: Perm) -> Perm {
+        Perm(self.0 | rhs.0)
+    }
+}
+
+impl fmt::Display for Perm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (perm, c) in [(Perm::R, 'r'), (Perm::W, 'w'), (Perm::X, 'x')] {
+            write!(f, "{}", if self.contains(perm) { c } else { '-' })?;
+        }
+        Ok(())
+    }
+}
+
+/// A named region of physical memory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Region {
+    pub name: String,
+    pub base: usize,
+    pub size: usize,
+    pub perm: Perm,
+}
+
+impl Region {
+    /// parse `NAME:BASE:SIZE:PERM`, BASE and SIZE in hex with or without 0x, PERM like `rx`
+    pub fn parse(s: &str) -> Result<Region, String> {
+        let fields: Vec<_> = s.split(':').collect();
+        Region::from_fields(&fields).map_err(|e| format!("{s}: {e}"))
+    }
+
+    fn from_fields(fields: &[&str]) -> Result<Region, String> {
+        let [name, base, size, perm] = fields else {
+            return Err("expected NAME BASE SIZE PERM".into());
+        };
+        let num = |n: &str| {
+            usize::from_str_radix(n.trim_start_matches("0x"), 16).map_err(|e| format!("{n}: {e}"))
+        };
+        Ok(Region {
+            name: name.to_string(),
+            base: num(base)?,
+            size: num(size)?,
+            perm: Perm::parse(perm)?,
+        })
+    }
+
+    pub fn end(&self) -> usize {
+        self.base + self.size
+    }
+}
+
+/// The regions of physical memory and where the hart comes out of reset.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryMap {
+    pub regions: Vec<Region>,
+    pub reset_vector: usize,
+    /// whether the reset vector was given rather than the default one, the entry point of
+    /// an image does not move it then
+    pub reset_given: bool,
+}
+
+impl Default for MemoryMap {
+    /// the one `pmem` region nemu has always had
+    fn default() -> Self {
+        Self {
+            regions: vec![Region {
+                name: "pmem".into(),
+                base: MBASE,
+                size: MSIZE,
+                perm: Perm::RWX,
+            }],
+            reset_vector: RESET_VECTOR,
+            reset_given: false,
+        }
+    }
+}
+
+impl MemoryMap {
+    /// parse a memory map file: a region per line as `NAME BASE SIZE PERM` and the reset
+    /// vector as `reset_vector ADDR`, in hex. `#` starts a comment.
+    ///
+    /// ```text
+    /// # name  base        size       perm
+    /// rom     0x1000      0x1000     rx
+    /// sram    0x0f000000  0x10000    rw
+    /// dram    0x80000000  0x8000000  rwx
+    /// reset_vector 0x1000
+    /// ```
+    pub fn parse(text: &str) -> Result<(Vec<Region>, Option<usize>), String> {
+        let mut regions = vec![];
+        let mut reset_vector = None;
+        for (n, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default();
+            let fields: Vec<_> = line.split_whitespace().collect();
+            let line = |e| format!("line {}: {}", n + 1, e);
+            match fields[..] {
+                [] => {}
+                ["reset_vector", addr] => {
+                    let addr = usize::from_str_radix(addr.trim_start_matches("0x"), 16)
+                        .map_err(|e| line(format!("{addr}: {e}")))?;
+                    reset_vector = Some(addr);
+                }
+                _ => regions.push(Region::from_fields(&fields).map_err(line)?),
+            }
+        }
+        Ok((regions, reset_vector))
+    }
+
+    /// the map of `regions`, the default one if there is none, with the hart out of reset at
+    /// `reset_vector`, the default one if there is none. Regions must not be empty or overlap
+    /// and the reset vector has to be in an executable one.
+    pub fn new(regions: Vec<Region>, reset_vector: Option<usize>) -> Result<Self, String> {
+        let mut map = MemoryMap::default();
+        if !regions.is_empty() {
+            map.regions = regions;
+        }
+        map.reset_vector = reset_vector.unwrap_or(RESET_VECTOR);
+        map.reset_given = reset_vector.is_some();
+        map.regions.sort_by_key(|r| r.base);
+        for r in &map.regions {
+            if r.size == 0 || r.base.checked_add(r.size).is_none() {
+                return Err(format!(
+                    "region {} at 0x{:x} is empty or too large",
+                    r.name, r.base
+                ));
+            }
+        }
+        for w in map.regions.windows(2) {
+            if w[0].end() > w[1].base {
+                return Err(format!(
+                    "region {} overlaps region {}",
+                    w[0].name, w[1].name
+                ));
+            }
+        }
+        let reset = map
+            .regions
+            .iter()
+            .find(|r| (r.base..r.end()).contains(&map.reset_vector));
+        if !reset.is_some_and(|r| r.perm.contains(Perm::X)) {
+            return Err(format!(
+                "reset vector 0x{:x} is not in an executable region",
+                map.reset_vector
+            ));
+        }
+        Ok(map)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_test() {
+        let rom = Region {
+            name: "rom".into(),
+            base: 0x1000,
+            size: 0x1000,
+            perm: Perm::R | Perm::X,
+        };
+        let cases = [
+            ("rom:0x1000:1000:rx", Ok(rom.clone())),
+            ("rom:1000:0x1000:xr", Ok(rom)),
+            (
+                "rom:0x1000:1000",
+                Err("rom:0x1000:1000: expected NAME BASE SIZE PERM"),
+            ),
+            (
+                "rom:0x1000:1000:rq",
+                Err("rom:0x1000:1000:rq: rq: permissions are made of r, w and x"),
+            ),
+            (
+                "rom:zz:1000:rx",
+                Err("rom:zz:1000:rx: zz: invalid digit found in string"),
+            ),
+        ];
+        for (s, region) in cases {
+            assert_eq!(Region::parse(s), region.map_err(String::from), "{s}");
+        }
+        assert_eq!(Perm::parse("r-x").unwrap().to_string(), "r-x");
+    }
+
+    #[test]
+    fn parse_map_test() {
+        let text = "\
+            # boot rom first\n\
+            rom  0x1000 0x1000 rx\n\
+            \n\
+            dram 0x80000000 0x8000000 rwx # main memory\n\
+            reset_vector 0x1000\n";
+        let (regions, reset_vector) = MemoryMap::parse(text).unwrap();
+        let regions: Vec<_> = regions.iter().map(|r| (r.name.as_str(), r.perm)).collect();
+        assert_eq!(regions, [("rom", Perm::R | Perm::X), ("dram", Perm::RWX)]);
+        assert_eq!(reset_vector, Some(0x1000));
+        let cases = [
+            (
+                "rom 0x1000 zz rx",
+                "line 1: zz: invalid digit found in string",
+            ),
+            (
+                "\nrom 0x1000 0x1000",
+                "line 2: expected NAME BASE SIZE PERM",
+            ),
+            (
+                "reset_vector 0xq",
+                "line 1: 0xq: invalid digit found in string",
+            ),
+        ];
+        for (text, e) in cases {
+            assert_eq!(MemoryMap::parse(text), Err(e.into()), "{text}");
+        }
+    }
+
+    #[test]
+    fn new_test() {
+        let region = |s| Region::parse(s).unwrap();
+        assert_eq!(MemoryMap::new(vec![], None), Ok(MemoryMap::default()));
+        let map = MemoryMap::new(
+            vec![region("dram:80000000:1000:rwx"), region("rom:1000:1000:rx")],
+            Some(0x1000),
+        )
+        .unwrap();
+        assert_eq!(map.regions[0].name, "rom");
+    }
+
+    #[test]
+    fn new_error_test() {
+        let region = |s| Region::parse(s).unwrap();
+        let cases = [
+            // the default reset vector is not in the map
+            (
+                vec![region("rom:1000:1000:rx")],
+                None,
+                "reset vector 0x80000000 is not in an executable region",
+            ),
+            (
+                vec![region("ram:1000:1000:rw")],
+                Some(0x1000),
+                "reset vector 0x1000 is not in an executable region",
+            ),
+            (
+                vec![region("a:1000:1000:rx"), region("b:1fff:10:rw")],
+                Some(0x1000),
+                "region a overlaps region b",
+            ),
+            (
+                vec![region("a:1000:0:rx")],
+                Some(0x1000),
+                "region a at 0x1000 is empty or too large",
+            ),
+        ];
+        for (regions, reset_vector, e) in cases {
+            assert_eq!(MemoryMap::new(regions, reset_vector), Err(e.into()));
+        }
+    }
+}
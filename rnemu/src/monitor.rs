@@ -1,15 +1,27 @@
-use std::{sync::OnceLock, u64};
+use std::sync::OnceLock;
 
 use clap::Parser;
 use colored::Colorize;
 use sdb::{init_sdb, main_loop};
 
 use crate::{
-    core::{init_nemu, nemu_exec},
+    core::{init_nemu, nemu_exec, TrapMode},
     debug::init_log,
-    isa::GUEST_ISA,
+    isa::{Guest, TlbConfig, ISA},
+    loader::{parse_addr, parse_image_file, Format, ImageFile, LoadConfig},
+    memory::{MemoryMap, Region},
     time::now,
 };
+#[cfg(feature = "trace")]
+use std::ops::Range;
+
+#[cfg(feature = "trace")]
+use crate::{
+    common::Vaddr,
+    core::{init_etrace, init_ftrace, init_itrace, init_mtrace},
+    elf::Elf,
+    trace::{parse_addr_range, parse_count_range, ITraceConfig, MTraceConfig},
+};
 
 static PORT: OnceLock<usize> = OnceLock::new();
 
@@ -26,11 +38,44 @@ fn welcome() {
         );
     }
     log!("Build time: {}", now().format("%H:%M:%S %Y-%m-%d"));
-    println!("Welcome to {}-NEMU!", GUEST_ISA.yellow().on_red());
+    println!("Welcome to {}-NEMU!", Guest::NAME.yellow().on_red());
     println!("For help, type \"help\"");
 }
 
-fn load_img() {}
+/// the memory map of the map `file` with `regions` added and the reset vector moved to
+/// `reset_vector`
+fn memory_map(
+    file: Option<&str>,
+    mut regions: Vec<Region>,
+    reset_vector: Option<u64>,
+) -> Result<MemoryMap, String> {
+    let mut reset_vector = reset_vector.map(|addr| addr as usize);
+    if let Some(file) = file {
+        let text =
+            std::fs::read_to_string(file).map_err(|e| format!("can not read {}: {}", file, e))?;
+        let (mut file_regions, file_reset_vector) =
+            MemoryMap::parse(&text).map_err(|e| format!("{}: {}", file, e))?;
+        file_regions.append(&mut regions);
+        regions = file_regions;
+        reset_vector = reset_vector.or(file_reset_vector);
+    }
+    MemoryMap::new(regions, reset_vector)
+}
+
+/// the functions in the symbol table of the ELF `file`
+#[cfg(feature = "trace")]
+fn load_symbols(file: &str) -> Result<Vec<crate::elf::Symbol>, String> {
+    let data = std::fs::read(file).map_err(|e| format!("can not read {}: {}", file, e))?;
+    Elf::parse(&data)
+        .and_then(|elf| elf.functions())
+        .map_err(|e| format!("{}: {}", file, e))
+}
+
+/// report why nemu can not start and leave
+fn exit_with(e: String) -> ! {
+    log!("{}", e.red().bold());
+    std::process::exit(1);
+}
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
@@ -47,15 +92,115 @@ struct Args {
     /// run DiffTtest with port PORT
     #[arg(short)]
     port: Option<String>,
-    #[arg(short)]
-    /// img file
-    image_file: Option<String>,
+    /// image to run, FILE@ADDR puts a raw binary at ADDR; repeat it to load several, the
+    /// guest starts at the first. The built-in test image by default
+    #[arg(short, value_parser = parse_image_file)]
+    image_file: Vec<ImageFile>,
+    /// format of the images
+    #[arg(long, value_enum, default_value_t = Format::Auto)]
+    format: Format,
+    /// where raw binaries without an address of their own go, in hex, the reset vector by
+    /// default
+    #[arg(long, value_parser = parse_addr)]
+    load_addr: Option<u64>,
+    /// memory map file, a region per line as `NAME BASE SIZE PERM` and `reset_vector ADDR`
+    #[arg(long)]
+    memory_map: Option<String>,
+    /// region of physical memory as NAME:BASE:SIZE:PERM in hex, e.g. rom:1000:1000:rx; repeat
+    /// it for more. Added to the memory map file, a single rwx pmem region by default
+    #[arg(long, value_parser = Region::parse)]
+    region: Vec<Region>,
+    /// where the hart comes out of reset, in hex, over the one of the memory map file. Either
+    /// of them wins over the entry point of an ELF image
+    #[arg(long, value_parser = parse_addr)]
+    reset_vector: Option<u64>,
+    /// how guest exceptions are handled
+    #[arg(long, value_enum, default_value_t = TrapMode::Abort)]
+    trap: TrapMode,
+    /// number of sets of the instruction and data TLBs
+    #[arg(long, default_value_t = TlbConfig::default().sets as u32, value_parser = clap::value_parser!(u32).range(1..))]
+    tlb_sets: u32,
+    /// ways per TLB set, 1 for a direct-mapped TLB
+    #[arg(long, default_value_t = TlbConfig::default().ways as u32, value_parser = clap::value_parser!(u32).range(1..))]
+    tlb_ways: u32,
+    /// log only the instructions with a pc in START-END, in hex
+    #[cfg(feature = "trace")]
+    #[arg(long, value_name = "START-END", value_parser = parse_addr_range)]
+    itrace_pc: Option<Range<Vaddr>>,
+    /// log only the instructions numbered FIRST up to LAST, counted from 0
+    #[cfg(feature = "trace")]
+    #[arg(long, value_name = "FIRST-LAST", value_parser = parse_count_range)]
+    itrace_window: Option<Range<u64>>,
+    /// number of the last instructions shown when the guest fails, 0 and no log run the
+    /// guest block by block untraced
+    #[cfg(feature = "trace")]
+    #[arg(long, default_value_t = ITraceConfig::default().ring)]
+    itrace_ring: usize,
+    /// trace the reads and writes of memory
+    #[cfg(feature = "trace")]
+    #[arg(long)]
+    mtrace: bool,
+    /// log only the accesses that touch START-END, in hex, may be given more than once
+    #[cfg(feature = "trace")]
+    #[arg(long, value_name = "START-END", value_parser = parse_addr_range)]
+    mtrace_range: Vec<Range<Vaddr>>,
+    /// log only the accesses of the instructions numbered FIRST up to LAST, counted from 0
+    #[cfg(feature = "trace")]
+    #[arg(long, value_name = "FIRST-LAST", value_parser = parse_count_range)]
+    mtrace_window: Option<Range<u64>>,
+    /// log each trap the guest takes and each return from a handler
+    #[cfg(feature = "trace")]
+    #[arg(long)]
+    etrace: bool,
+    /// ELF whose symbols name the functions in the function trace, those of the image by default
+    #[cfg(feature = "trace")]
+    #[arg(long)]
+    elf: Option<String>,
+    /// start with the function trace on, `ftrace on` turns it on later
+    #[cfg(feature = "trace")]
+    #[arg(long)]
+    ftrace: bool,
 }
 
 pub fn init_monitor() {
     let args = Args::parse();
     init_log(args.log);
-    init_nemu(args.image_file);
+    let tlb = TlbConfig {
+        sets: args.tlb_sets as usize,
+        ways: args.tlb_ways as usize,
+    };
+    let nemu =
+        memory_map(args.memory_map.as_deref(), args.region, args.reset_vector).and_then(|map| {
+            let images = LoadConfig {
+                files: args.image_file,
+                format: args.format,
+                load_addr: args.load_addr.unwrap_or(map.reset_vector as u64),
+            };
+            init_nemu(&map, &images, args.trap, tlb)
+        });
+    if let Err(e) = nemu {
+        exit_with(e);
+    }
+    #[cfg(feature = "trace")]
+    init_itrace(ITraceConfig {
+        pc: args.itrace_pc,
+        window: args.itrace_window,
+        ring: args.itrace_ring,
+    });
+    #[cfg(feature = "trace")]
+    if args.mtrace {
+        init_mtrace(MTraceConfig {
+            ranges: args.mtrace_range,
+            window: args.mtrace_window,
+        });
+    }
+    #[cfg(feature = "trace")]
+    init_etrace(args.etrace);
+    #[cfg(feature = "trace")]
+    match args.elf.map(|f| load_symbols(&f)).transpose() {
+        Ok(funcs) => init_ftrace(funcs, args.ftrace),
+        Err(e) => exit_with(e),
+    }
     init_sdb(args.batch);
     welcome();
 }
@@ -1,3 +1,5 @@
+use crate::common::SWord;
+
 #[derive(Debug, Clone)]
 pub enum Expr {
     Literal(Literal),
@@ -41,6 +43,6 @@ pub struct BinaryOp {
 
 #[derive(Debug, Clone)]
 pub enum Literal {
-    Number(i32),
+    Number(SWord),
     Register(String),
 }
@@ -30,7 +30,7 @@ fn interpret_binary(lhs: &Expr, op: BinaryOp, rhs: &Expr) -> Value {
         (Value::Number(n1), BinaryOpTy::Mul, Value::Number(n2)) => Value::Number(n1 * n2),
         (Value::Number(n1), BinaryOpTy::Div, Value::Number(n2)) => {
             if *n2 == 0 {
-                return Value::Bool(false);
+                Value::Bool(false)
             } else {
                 Value::Number(n1 / n2)
             }
@@ -1,4 +1,4 @@
-use std::{sync::OnceLock, u64};
+use std::sync::OnceLock;
 
 use interpreter::interpret_expr;
 use lazy_static::lazy_static;
@@ -7,7 +7,10 @@ use rustyline::error::ReadlineError;
 use spin::mutex::SpinMutex;
 use tokenizer::tokenize;
 
-use crate::core::nemu_exec;
+use crate::{
+    common::Vaddr,
+    core::{nemu_disas, nemu_exec},
+};
 
 mod expr;
 mod interpreter;
@@ -72,6 +75,9 @@ impl Debugger {
             }
             "si" => cmd_si(args),
             "p" => self.cmd_p(args),
+            "disas" => cmd_disas(args),
+            #[cfg(feature = "trace")]
+            "ftrace" => cmd_ftrace(args),
             "help" => cmd_help(args),
             "q" => {
                 return -1;
@@ -94,17 +100,22 @@ impl Debugger {
 
 lazy_static::lazy_static! {
     static ref CMD_TABLE:Vec<(&'static str,&'static str)> = {
-        vec!{
+        let mut table = vec!{
             ("help","Display information about all supported commands"),
             ("c","Continue the execution of the program"),
             ("si","si [N] 让程序单步执行N条指令后暂停执行,当N没有给出时, 缺省为1"),
             ("info","info r/w 打印寄存器状态,打印监视点信息"),
             ("p","p EXPR 求出表达式EXPR的值"),
+            ("disas","disas [N] [ADDR] 反汇编从ADDR开始的N条指令, ADDR缺省为当前PC, N缺省为10"),
             ("x","x N EXPR 求出表达式EXPR的值, 将结果作为起始内存地址, 以十六进制形式输出连续的N个4字节"),
             ("w","w EXPR 当表达式EXPR的值发生变化时, 暂停程序执行"),
             ("d","d N 删除序号为N的监视点"),
             ("q","Exit NEMU"),
+        };
+        if cfg!(feature = "trace") {
+            table.push(("ftrace","ftrace [on/off] 打开或关闭函数调用跟踪, 缺省时显示其状态"));
         }
+        table
     };
 }
 
@@ -125,13 +136,53 @@ fn cmd_help(arg: &str) {
 }
 
 fn cmd_si(arg: &str) {
-    if let Ok(step) = arg.parse::<u64>() {
+    if arg.is_empty() {
+        nemu_exec(1);
+    } else if let Ok(step) = arg.parse::<u64>() {
         nemu_exec(step);
     } else {
         println!("Parse Number Failed: {}", arg);
     }
 }
 
+fn cmd_disas(arg: &str) {
+    let mut args = arg.split_whitespace();
+    let n = match args.next().map(str::parse::<usize>) {
+        None => 10,
+        Some(Ok(n)) => n,
+        Some(Err(_)) => {
+            println!("Parse Number Failed: {}", arg);
+            return;
+        }
+    };
+    let addr = match args.next() {
+        None => None,
+        Some(a) => match Vaddr::from_str_radix(a.trim_start_matches("0x"), 16) {
+            Ok(addr) => Some(addr),
+            Err(_) => {
+                println!("Parse Address Failed: {}", a);
+                return;
+            }
+        },
+    };
+    nemu_disas(addr, n);
+}
+
+#[cfg(feature = "trace")]
+fn cmd_ftrace(arg: &str) {
+    let on = match arg {
+        "" => None,
+        "on" => Some(true),
+        "off" => Some(false),
+        _ => {
+            println!("Unknown Argument: {}", arg);
+            return;
+        }
+    };
+    let on = crate::core::nemu_ftrace(on);
+    println!("ftrace is {}", if on { "on" } else { "off" });
+}
+
 fn cmd_p(arg: &str) {}
 
 pub fn init_sdb(b: bool) {
@@ -53,7 +53,7 @@ pub enum TokenType {
 #[derive(Debug, Clone, PartialEq)]
 pub enum Literal {
     Identifier(String),
-    Number(i32),
+    Number(SWord),
 }
 
 #[derive(Clone, PartialEq)]
@@ -97,7 +97,7 @@ fn hex_value(input: &str) -> IResult<&str, Token> {
             ))),
         ),
         |out: &str| {
-            SWord::from_str_radix(&str::replace(&out, "_", ""), 16)
+            SWord::from_str_radix(&str::replace(out, "_", ""), 16)
                 .map(|v| Token::new(TokenType::Number, Some(Literal::Number(v))))
         },
     )
@@ -108,7 +108,7 @@ fn decimal_value(input: &str) -> IResult<&str, Token> {
     map_res(
         recognize(many1(terminated(one_of("_0123456789"), many0(char('_'))))),
         |out: &str| {
-            SWord::from_str_radix(&str::replace(&out, "_", ""), 10)
+            SWord::from_str_radix(&str::replace(out, "_", ""), 10)
                 .map(|v| Token::new(TokenType::Number, Some(Literal::Number(v))))
         },
     )
@@ -187,14 +187,12 @@ mod tests {
     #[test]
     fn tokenize_test() {
         let input = "3 + 5";
-        match tokenize(input) {
+        match many0(token)(input) {
             Ok((remain, o)) => {
-                println!("Tokens: {:?}", o);
-                if !remain.trim().is_empty() {
-                    println!("remain: {:?}", remain)
-                }
+                assert_eq!(o.len(), 3, "Tokens: {:?}", o);
+                assert!(remain.trim().is_empty(), "remain: {:?}", remain);
             }
-            Err(e) => println!("Failed: {:?}", e),
+            Err(e) => panic!("Failed: {:?}", e),
         }
     }
 }
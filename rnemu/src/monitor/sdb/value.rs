@@ -1,14 +1,15 @@
 use core::fmt;
 
+use crate::common::SWord;
 pub enum Value {
-    Number(i32),
+    Number(SWord),
     Bool(bool),
 }
 impl fmt::Display for Value {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Value::Number(n) => write!(f,"{}",n),
-            Value::Bool(b) => write!(f,"{}",b),
+            Value::Number(n) => write!(f, "{}", n),
+            Value::Bool(b) => write!(f, "{}", b),
         }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,130 @@
+use crate::{common::Vaddr, elf::Symbol, isa::Jump, log};
+
+/// The function trace: each call and return as the function it goes to or leaves, indented by
+/// how deep the calls are nested.
+#[derive(Default)]
+pub struct FTrace {
+    on: bool,
+    /// sorted by address
+    funcs: Vec<Symbol>,
+    depth: usize,
+}
+
+impl FTrace {
+    pub fn new(funcs: Vec<Symbol>, on: bool) -> Self {
+        Self {
+            on,
+            funcs,
+            depth: 0,
+        }
+    }
+
+    pub fn on(&self) -> bool {
+        self.on
+    }
+
+    pub fn set_on(&mut self, on: bool) {
+        self.on = on;
+    }
+
+    /// name of the function `addr` is in, a symbol without a size covers its address alone
+    fn func(&self, addr: Vaddr) -> &str {
+        let addr = addr as u64;
+        let n = self.funcs.partition_point(|f| f.addr <= addr);
+        self.funcs[..n]
+            .iter()
+            .rev()
+            .find(|f| addr < f.addr + f.size.max(1))
+            .map_or("???", |f| f.name.as_str())
+    }
+
+    /// the line for `jump` made at `pc`, nesting is updated past it
+    fn line(&mut self, pc: Vaddr, jump: Jump) -> String {
+        match jump {
+            Jump::Call(target) => {
+                let line = format!(
+                    "0x{:08x}: {:indent$}call [{}@0x{:08x}]",
+                    pc,
+                    "",
+                    self.func(target),
+                    target,
+                    indent = self.depth * 2
+                );
+                self.depth += 1;
+                line
+            }
+            Jump::Return(_) => {
+                self.depth = self.depth.saturating_sub(1);
+                format!(
+                    "0x{:08x}: {:indent$}ret  [{}]",
+                    pc,
+                    "",
+                    self.func(pc),
+                    indent = self.depth * 2
+                )
+            }
+        }
+    }
+
+    /// log `jump` made by the instruction at `pc` when the trace is on
+    pub fn trace(&mut self, pc: Vaddr, jump: Jump) {
+        if self.on {
+            log!("{}", self.line(pc, jump));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ftrace() -> FTrace {
+        let func = |name: &str, addr, size| Symbol {
+            name: name.into(),
+            addr,
+            size,
+        };
+        FTrace::new(
+            vec![
+                func("_start", 0x8000_0000, 0x10),
+                func("main", 0x8000_0100, 0x40),
+                func("f", 0x8000_0200, 0),
+            ],
+            true,
+        )
+    }
+
+    #[test]
+    fn func_test() {
+        let ftrace = ftrace();
+        assert_eq!(ftrace.func(0x8000_0000), "_start");
+        assert_eq!(ftrace.func(0x8000_013c), "main");
+        assert_eq!(ftrace.func(0x8000_0140), "???");
+        assert_eq!(ftrace.func(0x8000_0200), "f");
+        assert_eq!(ftrace.func(0x8000_0204), "???");
+        assert_eq!(ftrace.func(0), "???");
+    }
+
+    #[test]
+    fn line_test() {
+        let mut ftrace = ftrace();
+        let lines = [
+            ftrace.line(0x8000_0004, Jump::Call(0x8000_0100)),
+            ftrace.line(0x8000_0110, Jump::Call(0x8000_0200)),
+            ftrace.line(0x8000_0200, Jump::Return(0x8000_0114)),
+            ftrace.line(0x8000_0120, Jump::Return(0x8000_0008)),
+        ];
+        assert_eq!(
+            lines,
+            [
+                "0x80000004: call [main@0x80000100]",
+                "0x80000110:   call [f@0x80000200]",
+                "0x80000200:   ret  [f]",
+                "0x80000120: ret  [main]",
+            ]
+        );
+        // a return with nothing called does not go below the top
+        ftrace.line(0x8000_0008, Jump::Return(0));
+        assert_eq!(ftrace.depth, 0);
+    }
+}
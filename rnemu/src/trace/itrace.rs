@@ -0,0 +1,188 @@
+use std::{collections::VecDeque, ops::Range};
+
+use crate::{
+    common::{Vaddr, Word},
+    debug::LOG_FILE,
+    isa::{disas_line, ISA},
+    log,
+};
+
+/// Which instructions the instruction trace logs and how many it keeps.
+#[derive(Debug, Clone)]
+pub struct ITraceConfig {
+    /// log only the instructions with a pc in it
+    pub pc: Option<Range<Vaddr>>,
+    /// log only the instructions whose number, counted from 0, is in it
+    pub window: Option<Range<u64>>,
+    /// how many of the last instructions are kept for `dump`, whatever the filters
+    pub ring: usize,
+}
+
+impl Default for ITraceConfig {
+    fn default() -> Self {
+        Self {
+            pc: None,
+            window: None,
+            ring: 16,
+        }
+    }
+}
+
+/// An executed instruction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Entry {
+    pc: Vaddr,
+    /// the bytes at pc, `None` if they could not be fetched
+    inst: Option<Word>,
+    /// the registers it changed and their new values
+    writes: Vec<(usize, Word)>,
+}
+
+impl Entry {
+    fn format<T: ISA>(&self) -> String {
+        let mut line = match self.inst {
+            Some(inst) => disas_line::<T>(self.pc, inst).0,
+            None => format!("{:8x}:\t<not mapped or outside of memory>", self.pc),
+        };
+        let writes: Vec<String> = self
+            .writes
+            .iter()
+            .map(|&(idx, val)| format!("{} <- 0x{:x}", T::reg_name(idx), val))
+            .collect();
+        if !writes.is_empty() {
+            line += &format!("\t# {}", writes.join(", "));
+        }
+        line
+    }
+}
+
+/// The instruction trace: each instruction executed as its pc, bytes, disassembly and the
+/// registers it changed, logged when it passes the filters. The last few are kept in a ring
+/// to be shown when the guest fails.
+pub struct ITrace {
+    config: ITraceConfig,
+    ring: VecDeque<Entry>,
+    /// the registers before the instruction being traced
+    regs: Vec<Word>,
+}
+
+impl ITrace {
+    pub fn new(config: ITraceConfig) -> Self {
+        Self {
+            ring: VecDeque::with_capacity(config.ring),
+            config,
+            regs: vec![],
+        }
+    }
+
+    /// whether instructions have to be traced, to be logged or kept in the ring
+    pub fn on(&self) -> bool {
+        self.config.ring > 0 || LOG_FILE.get().is_some()
+    }
+
+    /// take note of the registers of `cpu` before it executes an instruction
+    pub fn begin<T: ISA>(&mut self, cpu: &T) {
+        self.regs.clear();
+        self.regs.extend((0..T::NR_GPR).map(|idx| cpu.reg(idx)));
+    }
+
+    /// trace instruction number `n` executed at `pc` from `inst`, `cpu` is past it
+    pub fn end<T: ISA>(&mut self, n: u64, pc: Vaddr, inst: Option<Word>, cpu: &T) {
+        let writes = self
+            .regs
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, &old)| {
+                let new = cpu.reg(idx);
+                (new != old).then_some((idx, new))
+            })
+            .collect();
+        let entry = Entry { pc, inst, writes };
+        if LOG_FILE.get().is_some() && self.filter(n, pc) {
+            _log_file!(entry.format::<T>());
+        }
+        if self.config.ring == 0 {
+            return;
+        }
+        if self.ring.len() == self.config.ring {
+            self.ring.pop_front();
+        }
+        self.ring.push_back(entry);
+    }
+
+    fn filter(&self, n: u64, pc: Vaddr) -> bool {
+        self.config.pc.as_ref().is_none_or(|r| r.contains(&pc))
+            && self.config.window.as_ref().is_none_or(|w| w.contains(&n))
+    }
+
+    /// show the instructions in the ring, oldest first and the last one marked
+    pub fn dump<T: ISA>(&self) {
+        if self.ring.is_empty() {
+            return;
+        }
+        log!("the last {} instructions:", self.ring.len());
+        for (i, entry) in self.ring.iter().enumerate() {
+            let mark = if i + 1 == self.ring.len() {
+                "--> "
+            } else {
+                "    "
+            };
+            let line = format!("{}{}", mark, entry.format::<T>());
+            println!("{}", line);
+            _log_file!(line);
+        }
+    }
+}
+
+impl Default for ITrace {
+    fn default() -> Self {
+        Self::new(ITraceConfig::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        isa::{Guest, TlbConfig},
+        memory::RESET_VECTOR,
+    };
+
+    const PC: Vaddr = RESET_VECTOR as Vaddr;
+
+    #[test]
+    fn ring_test() {
+        let mut itrace = ITrace::new(ITraceConfig {
+            ring: 2,
+            ..Default::default()
+        });
+        let mut cpu = Guest::init(PC, TlbConfig::default());
+        for n in 0..3 {
+            itrace.begin(&cpu);
+            cpu.set_reg(1, n as Word + 1);
+            itrace.end(n, PC + 4 * n as Vaddr, None, &cpu);
+        }
+        let pcs: Vec<Vaddr> = itrace.ring.iter().map(|e| e.pc).collect();
+        assert_eq!(pcs, [PC + 4, PC + 8]);
+        assert_eq!(itrace.ring[1].writes, [(1, 3)]);
+        // nothing changed
+        itrace.begin(&cpu);
+        itrace.end(3, PC, None, &cpu);
+        assert!(itrace.ring[1].writes.is_empty());
+    }
+
+    #[test]
+    fn filter_test() {
+        let itrace = ITrace::new(ITraceConfig {
+            pc: Some(PC..PC + 0x10),
+            window: Some(5..10),
+            ring: 0,
+        });
+        assert!(itrace.filter(5, PC));
+        assert!(itrace.filter(9, PC + 0xc));
+        assert!(!itrace.filter(10, PC));
+        assert!(!itrace.filter(4, PC));
+        assert!(!itrace.filter(5, PC + 0x10));
+        assert!(ITrace::default().filter(u64::MAX, 0));
+    }
+}
@@ -0,0 +1,58 @@
+// traces of what the guest does, written to the `-l` log file. They are only built with the
+// `trace` feature as they slow every instruction down.
+
+use std::ops::Range;
+
+use crate::common::Vaddr;
+
+mod ftrace;
+mod itrace;
+mod mtrace;
+pub use ftrace::FTrace;
+pub use itrace::{ITrace, ITraceConfig};
+pub use mtrace::{MTrace, MTraceConfig};
+
+/// parse `START-END` in hex, with or without 0x, into `START..END`
+pub fn parse_addr_range(s: &str) -> Result<Range<Vaddr>, String> {
+    let addr = |a: &str| {
+        let a = a.trim();
+        Vaddr::from_str_radix(a.trim_start_matches("0x"), 16).map_err(|e| format!("{a}: {e}"))
+    };
+    parse_range(s, addr)
+}
+
+/// parse `FIRST-LAST` in decimal into `FIRST..LAST`
+pub fn parse_count_range(s: &str) -> Result<Range<u64>, String> {
+    parse_range(s, |n| n.trim().parse().map_err(|e| format!("{n}: {e}")))
+}
+
+fn parse_range<N: PartialOrd>(
+    s: &str,
+    parse: impl Fn(&str) -> Result<N, String>,
+) -> Result<Range<N>, String> {
+    let (start, end) = s
+        .split_once('-')
+        .ok_or_else(|| format!("{s}: expected START-END"))?;
+    let range = parse(start)?..parse(end)?;
+    match range.start < range.end {
+        true => Ok(range),
+        false => Err(format!("{s}: the range is empty")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_range_test() {
+        assert_eq!(
+            parse_addr_range("0x80000000-80000100"),
+            Ok(0x8000_0000..0x8000_0100)
+        );
+        assert_eq!(parse_count_range("10-20"), Ok(10..20));
+        assert!(parse_count_range("20-10").is_err());
+        assert!(parse_count_range("10").is_err());
+        assert!(parse_addr_range("0x8000000g-0x80000010").is_err());
+    }
+}
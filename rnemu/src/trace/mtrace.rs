@@ -0,0 +1,114 @@
+use std::ops::Range;
+
+use crate::{
+    common::{Paddr, Vaddr, Word},
+    debug::LOG_FILE,
+};
+
+/// Which accesses the memory trace logs.
+#[derive(Debug, Clone, Default)]
+pub struct MTraceConfig {
+    /// log only the accesses that touch one of them, all when there is none
+    pub ranges: Vec<Range<Paddr>>,
+    /// log only the accesses of the instructions whose number, counted from 0, is in it
+    pub window: Option<Range<u64>>,
+}
+
+/// The memory trace: each read and write of physical memory as the pc of the instruction that
+/// made it, the address, width, direction and value. Instruction fetches are left to the
+/// instruction trace.
+pub struct MTrace {
+    config: MTraceConfig,
+    /// the instruction making the accesses
+    pc: Vaddr,
+    n: u64,
+}
+
+impl MTrace {
+    pub fn new(config: MTraceConfig) -> Self {
+        Self {
+            config,
+            pc: 0,
+            n: 0,
+        }
+    }
+
+    /// whether accesses have to be traced, the log is where they go
+    pub fn on(&self) -> bool {
+        LOG_FILE.get().is_some()
+    }
+
+    /// the accesses that follow are made by instruction number `n` at `pc`
+    pub fn begin(&mut self, pc: Vaddr, n: u64) {
+        self.pc = pc;
+        self.n = n;
+    }
+
+    fn filter(&self, addr: Paddr, len: usize) -> bool {
+        let end = addr.wrapping_add(len as Paddr);
+        (self.config.ranges.is_empty()
+            || self
+                .config
+                .ranges
+                .iter()
+                .any(|r| addr < r.end && r.start < end))
+            && self
+                .config
+                .window
+                .as_ref()
+                .is_none_or(|w| w.contains(&self.n))
+    }
+
+    fn line(&self, write: bool, addr: Paddr, len: usize, val: Word) -> String {
+        let dir = if write { "write" } else { "read " };
+        format!(
+            "0x{:08x}: {} 0x{:08x} {} = 0x{:x}",
+            self.pc, dir, addr, len, val
+        )
+    }
+
+    /// log the `len`-byte access at `addr` that read or is writing the low bytes of `val`
+    pub fn trace(&self, write: bool, addr: Paddr, len: usize, val: Word) {
+        if LOG_FILE.get().is_some() && self.filter(addr, len) {
+            let val = val & (Word::MAX >> (Word::BITS as usize).saturating_sub(len * 8));
+            _log_file!(self.line(write, addr, len, val));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filter_test() {
+        let mut mtrace = MTrace::new(MTraceConfig {
+            ranges: vec![0x8000_1000..0x8000_1010, 0x8000_2000..0x8000_2004],
+            window: Some(10..20),
+        });
+        mtrace.begin(0x8000_0000, 10);
+        assert!(mtrace.filter(0x8000_1000, 4));
+        assert!(mtrace.filter(0x8000_2003, 1));
+        // straddling the start of a range
+        assert!(mtrace.filter(0x8000_0ffe, 4));
+        assert!(!mtrace.filter(0x8000_1010, 4));
+        assert!(!mtrace.filter(0x8000_0ffc, 4));
+        mtrace.begin(0x8000_0000, 20);
+        assert!(!mtrace.filter(0x8000_1000, 4));
+        assert!(MTrace::new(MTraceConfig::default()).filter(0, 1));
+    }
+
+    #[test]
+    fn line_test() {
+        let mut mtrace = MTrace::new(MTraceConfig::default());
+        mtrace.begin(0x8000_0008, 0);
+        assert_eq!(
+            mtrace.line(false, 0x8000_0010, 1, 0xef),
+            "0x80000008: read  0x80000010 1 = 0xef"
+        );
+        assert_eq!(
+            mtrace.line(true, 0x8000_0010, 4, 0),
+            "0x80000008: write 0x80000010 4 = 0x0"
+        );
+    }
+}